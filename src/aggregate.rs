@@ -0,0 +1,61 @@
+//! Aggregation operators for folding series values into a scalar.
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub enum Agg {
+    #[default]
+    Sum,
+    Avg,
+    Min,
+    Max,
+    Count,
+}
+
+impl Agg {
+    /// Folds `values`; `Sum` and `Count` of nothing are zero, the others have no value.
+    pub fn apply(self, values: impl IntoIterator<Item = f64>) -> Option<f64> {
+        let values = values.into_iter();
+        match self {
+            Self::Sum => Some(values.fold(0_f64, |sum, value| sum + value)),
+            Self::Count => Some(values.count() as f64),
+            Self::Min => values.reduce(f64::min),
+            Self::Max => values.reduce(f64::max),
+            Self::Avg => {
+                let (sum, count) = values.fold((0_f64, 0_usize), |(sum, count), value| (sum + value, count + 1));
+                (0 < count).then(|| sum / count as f64)
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{labels, MetricFamily};
+
+    const VALUES: [f64; 4] = [4_f64, 1_f64, 3_f64, 2_f64];
+
+    #[test]
+    fn folds_values() {
+        let folded: Vec<_> = [Agg::Sum, Agg::Avg, Agg::Min, Agg::Max, Agg::Count].map(|agg| agg.apply(VALUES)).into();
+        assert_eq!(folded, vec![Some(10_f64), Some(2.5), Some(1_f64), Some(4_f64), Some(4_f64)]);
+    }
+
+    #[test]
+    fn folds_nothing_into_zero_or_no_value() {
+        let folded: Vec<_> = [Agg::Sum, Agg::Avg, Agg::Min, Agg::Max, Agg::Count].map(|agg| agg.apply([])).into();
+        assert_eq!(folded, vec![Some(0_f64), None, None, None, Some(0_f64)]);
+    }
+
+    #[test]
+    fn aggregates_the_values_of_a_family() {
+        let family = MetricFamily::builder("queue_depth")
+            .gauge(labels! {"queue" => "a"}, 3_f64)
+            .gauge(labels! {"queue" => "b"}, 5_f64)
+            .build();
+        assert_eq!((family.aggregate(Agg::default()), family.aggregate(Agg::Avg)), (Some(8_f64), Some(4_f64)));
+
+        let latency = MetricFamily::builder("latency_seconds").histogram(vec![], &[(f64::INFINITY, 2)], 1_f64).build();
+        assert_eq!((latency.aggregate(Agg::Sum), latency.aggregate(Agg::Max)), (Some(0_f64), None));
+    }
+}
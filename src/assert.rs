@@ -0,0 +1,1151 @@
+//! Test assertions on a [`MetricState`] whose failure messages list the series that do exist.
+//!
+//! ```
+//! use inspect_prometheus::{assert_counter, labels, parse, MetricState};
+//!
+//! let text = "# TYPE http_requests_total counter\nhttp_requests_total{method=\"GET\"} 3\n";
+//! let state = MetricState::from(parse::parse_text(text).unwrap());
+//! assert_counter!(state, "http_requests_total", labels! {"method" => "GET"}, 3);
+//! ```
+
+use std::fmt::Write;
+
+use crate::diff::{counted, is_reset};
+use crate::export::{format_float, format_labels};
+use crate::render::display_value;
+use crate::{Agg, HistogramValue, Metric, MetricFamily, MetricKind, MetricLabel, MetricState};
+
+/// How many series of a family a failure message lists.
+const NEARBY_SERIES: usize = 10;
+
+/// Asserts the counter series of family `name` with exactly `labels` has the `expected` value,
+/// exactly or within an optional [`Tolerance`].
+#[macro_export]
+macro_rules! assert_counter {
+    ($state:expr, $name:expr, $labels:expr, $expected:expr $(,)?) => {
+        $crate::assert_counter!($state, $name, $labels, $expected, $crate::assert::Tolerance::Exact)
+    };
+    ($state:expr, $name:expr, $labels:expr, $expected:expr, $tolerance:expr $(,)?) => {
+        $crate::assert::assert_counter(&$state, $name, &$labels, $expected as f64, $tolerance)
+    };
+}
+
+/// Asserts the gauge series of family `name` with exactly `labels` has the `expected` value,
+/// exactly or within an optional [`Tolerance`].
+#[macro_export]
+macro_rules! assert_gauge {
+    ($state:expr, $name:expr, $labels:expr, $expected:expr $(,)?) => {
+        $crate::assert_gauge!($state, $name, $labels, $expected, $crate::assert::Tolerance::Exact)
+    };
+    ($state:expr, $name:expr, $labels:expr, $expected:expr, $tolerance:expr $(,)?) => {
+        $crate::assert::assert_gauge(&$state, $name, &$labels, $expected as f64, $tolerance)
+    };
+}
+
+/// Asserts the histogram series of family `name` with exactly `labels` has observed `count`
+/// samples, and optionally that they sum to `sum`, exactly or within a [`Tolerance`].
+#[macro_export]
+macro_rules! assert_histogram {
+    ($state:expr, $name:expr, $labels:expr, $count:expr $(,)?) => {
+        $crate::assert::assert_histogram(
+            &$state,
+            $name,
+            &$labels,
+            $count as u64,
+            None,
+            $crate::assert::Tolerance::Exact,
+        )
+    };
+    ($state:expr, $name:expr, $labels:expr, $count:expr, $sum:expr $(,)?) => {
+        $crate::assert_histogram!($state, $name, $labels, $count, $sum, $crate::assert::Tolerance::Exact)
+    };
+    ($state:expr, $name:expr, $labels:expr, $count:expr, $sum:expr, $tolerance:expr $(,)?) => {
+        $crate::assert::assert_histogram(&$state, $name, &$labels, $count as u64, Some($sum as f64), $tolerance)
+    };
+}
+
+/// Asserts the values of every counter, gauge or untyped series of family `name`, whatever their
+/// labels, sum to `expected`, exactly or within an optional [`Tolerance`].
+#[macro_export]
+macro_rules! assert_family_total {
+    ($state:expr, $name:expr, $expected:expr $(,)?) => {
+        $crate::assert_family_total!($state, $name, $expected, $crate::assert::Tolerance::Exact)
+    };
+    ($state:expr, $name:expr, $expected:expr, $tolerance:expr $(,)?) => {
+        $crate::assert::assert_family_total(&$state, $name, $expected as f64, $tolerance)
+    };
+}
+
+/// Asserts there is no family `name` or, given `labels`, no series of it carrying at least those
+/// labels.
+#[macro_export]
+macro_rules! assert_absent {
+    ($state:expr, $name:expr $(,)?) => {
+        $crate::assert::assert_absent(&$state, $name, None)
+    };
+    ($state:expr, $name:expr, $labels:expr $(,)?) => {
+        $crate::assert::assert_absent(&$state, $name, Some(&$labels[..]))
+    };
+}
+
+/// Asserts the counter, histogram or summary series of family `name` with exactly `labels`
+/// increased by `expected` from `before` to `after`, exactly or within an optional [`Tolerance`].
+/// The snapshots may be [`MetricState`]s, [`crate::Snapshot`]s or family vectors. A series
+/// missing from `before` counts from zero, and across a reset the increase is the later value.
+#[macro_export]
+macro_rules! assert_increased_by {
+    ($before:expr, $after:expr, $name:expr, $labels:expr, $expected:expr $(,)?) => {
+        $crate::assert_increased_by!($before, $after, $name, $labels, $expected, $crate::assert::Tolerance::Exact)
+    };
+    ($before:expr, $after:expr, $name:expr, $labels:expr, $expected:expr, $tolerance:expr $(,)?) => {
+        $crate::assert::assert_increased_by(
+            ::std::convert::AsRef::<[$crate::MetricFamily]>::as_ref(&$before),
+            ::std::convert::AsRef::<[$crate::MetricFamily]>::as_ref(&$after),
+            $name,
+            &$labels,
+            $expected as f64,
+            $tolerance,
+        )
+    };
+}
+
+/// Asserts `actual` is within `tolerance` of `expected`, exactly when no tolerance is given.
+#[macro_export]
+macro_rules! assert_eq_approx {
+    ($actual:expr, $expected:expr $(,)?) => {
+        $crate::assert::assert_eq_approx($actual as f64, $expected as f64, $crate::assert::Tolerance::Exact)
+    };
+    ($actual:expr, $expected:expr, $tolerance:expr $(,)?) => {
+        $crate::assert::assert_eq_approx($actual as f64, $expected as f64, $tolerance)
+    };
+}
+
+/// How far an actual value may be from the expected one.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum Tolerance {
+    #[default]
+    Exact,
+    /// At most this far apart.
+    Absolute(f64),
+    /// At most this fraction of the larger magnitude apart.
+    Relative(f64),
+}
+
+impl Tolerance {
+    /// Whether `actual` is close enough to `expected`. NaN is only close to NaN.
+    pub fn matches(&self, actual: f64, expected: f64) -> bool {
+        if actual.is_nan() || expected.is_nan() {
+            return actual.is_nan() && expected.is_nan();
+        }
+        if actual == expected {
+            return true;
+        }
+        let difference = (actual - expected).abs();
+        match self {
+            Self::Exact => false,
+            Self::Absolute(epsilon) => difference <= *epsilon,
+            Self::Relative(epsilon) => difference <= epsilon * actual.abs().max(expected.abs()),
+        }
+    }
+}
+
+impl std::fmt::Display for Tolerance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Exact => f.write_str("exactly"),
+            Self::Absolute(epsilon) => write!(f, "within {epsilon}"),
+            Self::Relative(epsilon) => write!(f, "within {}%", epsilon * 100_f64),
+        }
+    }
+}
+
+#[track_caller]
+pub fn assert_eq_approx(actual: f64, expected: f64, tolerance: Tolerance) {
+    if !tolerance.matches(actual, expected) {
+        panic!("expected {actual} to be {expected}{}", qualifier(tolerance));
+    }
+}
+
+#[track_caller]
+pub fn assert_counter(state: &MetricState, name: &str, labels: &[MetricLabel], expected: f64, tolerance: Tolerance) {
+    let metric = expect_series(state, Some("counter"), name, labels);
+    match metric.counter_value() {
+        Some(actual) if tolerance.matches(actual, expected) => (),
+        Some(actual) => fail(
+            state,
+            name,
+            &format!(
+                "expected counter {} to be {expected}{}, but it was {actual}",
+                series(name, labels),
+                qualifier(tolerance)
+            ),
+        ),
+        None => fail(state, name, &format!("expected {} to be a counter with a value", series(name, labels))),
+    }
+}
+
+#[track_caller]
+pub fn assert_gauge(state: &MetricState, name: &str, labels: &[MetricLabel], expected: f64, tolerance: Tolerance) {
+    let metric = expect_series(state, Some("gauge"), name, labels);
+    match metric.gauge_value() {
+        Some(actual) if tolerance.matches(actual, expected) => (),
+        Some(actual) => fail(
+            state,
+            name,
+            &format!(
+                "expected gauge {} to be {expected}{}, but it was {actual}",
+                series(name, labels),
+                qualifier(tolerance)
+            ),
+        ),
+        None => fail(state, name, &format!("expected {} to be a gauge with a value", series(name, labels))),
+    }
+}
+
+#[track_caller]
+pub fn assert_histogram(
+    state: &MetricState,
+    name: &str,
+    labels: &[MetricLabel],
+    count: u64,
+    sum: Option<f64>,
+    tolerance: Tolerance,
+) {
+    let metric = expect_series(state, Some("histogram"), name, labels);
+    let Some(histogram) = metric.histogram() else {
+        fail(state, name, &format!("expected {} to be a histogram", series(name, labels)))
+    };
+
+    let actual_count = histogram.sample_count.unwrap_or(0);
+    if actual_count != count {
+        let message = format!(
+            "expected histogram {} to have {count} observations, but it had {actual_count}",
+            series(name, labels)
+        );
+        fail(state, name, &message);
+    }
+    if let Some(sum) = sum {
+        let actual_sum = histogram.sample_sum.unwrap_or(0_f64);
+        if !tolerance.matches(actual_sum, sum) {
+            let message = format!(
+                "expected histogram {} to sum to {sum}{}, but it summed to {actual_sum}",
+                series(name, labels),
+                qualifier(tolerance)
+            );
+            fail(state, name, &message);
+        }
+    }
+}
+
+#[track_caller]
+pub fn assert_family_total(state: &MetricState, name: &str, expected: f64, tolerance: Tolerance) {
+    let Some(family) = state.family(name) else {
+        fail(state, name, &format!("expected a family {name} totalling {expected}, but there is none"))
+    };
+    if !matches!(family.kind, MetricKind::Counter | MetricKind::Gauge | MetricKind::Untyped) {
+        let kind = crate::json::kind_name(family.kind);
+        fail(state, name, &format!("expected family {name} to have values to total, but it is a {kind}"));
+    }
+
+    let total = family.aggregate(Agg::Sum).unwrap_or(0_f64);
+    if !tolerance.matches(total, expected) {
+        let message = format!(
+            "expected family {name} to total {expected}{} across {} series, but it totalled {total}",
+            qualifier(tolerance),
+            family.metrics.len()
+        );
+        fail(state, name, &message);
+    }
+}
+
+#[track_caller]
+pub fn assert_absent(state: &MetricState, name: &str, labels: Option<&[MetricLabel]>) {
+    let Some(family) = state.family(name) else { return };
+    let Some(labels) = labels else {
+        let message = format!("expected no family {name}, but it has {} series", family.metrics.len());
+        fail(state, name, &message)
+    };
+
+    let present: Vec<&Metric> = family
+        .metrics
+        .iter()
+        .filter(|metric| {
+            let metric_labels = metric.labels();
+            labels.iter().all(|label| metric_labels.contains(label))
+        })
+        .collect();
+    if !present.is_empty() {
+        let mut message = format!("expected no series {}, but found:", series(name, labels));
+        for metric in present.iter() {
+            let _ = write!(message, "\n  {} {}", series(name, metric.labels()), display_value(metric));
+        }
+        panic!("{message}");
+    }
+}
+
+#[track_caller]
+pub fn assert_increased_by(
+    before: &[MetricFamily],
+    after: &[MetricFamily],
+    name: &str,
+    labels: &[MetricLabel],
+    expected: f64,
+    tolerance: Tolerance,
+) {
+    let (before, after) = (MetricState::from(before.to_vec()), MetricState::from(after.to_vec()));
+    let metric = expect_series(&after, None, name, labels);
+    let Some(value_after) = counted(metric) else {
+        fail(&after, name, &format!("expected {} to be a counter, histogram or summary", series(name, labels)))
+    };
+
+    let pairs: Vec<(&str, &str)> = labels.iter().map(|l| (l.name.as_str(), l.value.as_str())).collect();
+    let (increase, detail) = match before.series(name, &pairs) {
+        Some(previous) if is_reset(previous, metric) => (value_after, format!("reset and rose to {value_after}")),
+        Some(previous) => {
+            let value_before = counted(previous).unwrap_or(0_f64);
+            (value_after - value_before, format!("went from {value_before} to {value_after}"))
+        },
+        None => (value_after, format!("appeared with {value_after}")),
+    };
+    if !tolerance.matches(increase, expected) {
+        let message = format!(
+            "expected {} to increase by {expected}{}, but it increased by {increase} (it {detail})",
+            series(name, labels),
+            qualifier(tolerance)
+        );
+        fail(&after, name, &message);
+    }
+}
+
+/// Asserts `actual` includes `expected`, tolerating extra families, series and labels; see
+/// [`MetricState::includes`].
+#[track_caller]
+pub fn assert_includes(actual: &MetricState, expected: &MetricState) {
+    let missing = actual.missing(expected);
+    if !missing.is_empty() {
+        panic!("expected metrics are missing:\n  {}", missing.join("\n  "));
+    }
+}
+
+/// Asserts no value, sum or quantile in `state` is NaN or infinite; see
+/// [`crate::validate::non_finite`].
+#[track_caller]
+pub fn assert_finite(state: &MetricState) {
+    let violations = crate::validate::non_finite(state.families());
+    if !violations.is_empty() {
+        let mut message = "expected all values to be finite, but:".to_string();
+        for violation in violations.iter() {
+            let _ = write!(message, "\n  {violation}");
+        }
+        panic!("{message}");
+    }
+}
+
+/// Starts a chainable assertion on `state`, the non-macro alternative to [`assert_counter!`] and
+/// friends:
+///
+/// ```
+/// # use inspect_prometheus::{assert::expect, parse, MetricState};
+/// # let text = "# TYPE job_latency_seconds histogram\njob_latency_seconds_bucket{stage=\"ingest\",le=\"+Inf\"} 12\njob_latency_seconds_sum{stage=\"ingest\"} 3\njob_latency_seconds_count{stage=\"ingest\"} 12\n";
+/// # let state = MetricState::from(parse::parse_text(text).unwrap());
+/// expect(&state).family("job_latency_seconds").with_labels(&[("stage", "ingest")]).histogram_count_at_least(10);
+/// ```
+pub fn expect(state: &MetricState) -> Expect<'_> {
+    Expect { state, finite: false }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Expect<'a> {
+    state: &'a MetricState,
+    finite: bool,
+}
+
+impl<'a> Expect<'a> {
+    /// Fails the value and sum assertions that follow on NaN or infinite values, which otherwise
+    /// compare like any other, e.g. `-Inf` being at most any bound.
+    pub fn finite_values(mut self) -> Self {
+        self.finite = true;
+        self
+    }
+
+    /// Asserts family `name` exists.
+    #[track_caller]
+    pub fn family(self, name: &'a str) -> FamilyExpectation<'a> {
+        match self.state.family(name) {
+            Some(family) => FamilyExpectation { state: self.state, family, finite: self.finite },
+            None => fail(self.state, name, &format!("expected a family {name}, but there is none")),
+        }
+    }
+
+    /// Asserts family `name` is a histogram with an unlabelled series; use
+    /// [`FamilyExpectation::with_labels`] for labelled ones.
+    #[track_caller]
+    pub fn histogram(self, name: &'a str) -> SeriesExpectation<'a> {
+        let series = self.family(name).with_labels(&[]);
+        series.histogram();
+        series
+    }
+
+    /// Asserts there is no family `name`.
+    #[track_caller]
+    pub fn no_family(self, name: &str) -> Self {
+        if self.state.family(name).is_some() {
+            fail(self.state, name, &format!("expected no family {name}"));
+        }
+        self
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct FamilyExpectation<'a> {
+    state: &'a MetricState,
+    family: &'a MetricFamily,
+    finite: bool,
+}
+
+impl<'a> FamilyExpectation<'a> {
+    #[track_caller]
+    pub fn of_kind(self, kind: MetricKind) -> Self {
+        if self.family.kind != kind {
+            let message =
+                format!("expected family {} to be {kind:?}, but it is {:?}", self.family.name, self.family.kind);
+            fail(self.state, &self.family.name, &message);
+        }
+        self
+    }
+
+    #[track_caller]
+    pub fn series_count(self, count: usize) -> Self {
+        let actual = self.family.metrics.len();
+        if actual != count {
+            let message = format!("expected family {} to have {count} series, but it has {actual}", self.family.name);
+            fail(self.state, &self.family.name, &message);
+        }
+        self
+    }
+
+    /// Asserts the family has a series with exactly `labels`, in any order.
+    #[track_caller]
+    pub fn with_labels(self, labels: &[(&str, &str)]) -> SeriesExpectation<'a> {
+        let labels: Vec<MetricLabel> = labels
+            .iter()
+            .map(|(name, value)| MetricLabel { name: name.to_string(), value: value.to_string() })
+            .collect();
+        let metric = expect_series(self.state, None, &self.family.name, &labels);
+        SeriesExpectation {
+            state: self.state,
+            family: self.family,
+            metric,
+            tolerance: Tolerance::Exact,
+            finite: self.finite,
+        }
+    }
+
+    pub fn family(&self) -> &'a MetricFamily {
+        self.family
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct SeriesExpectation<'a> {
+    state: &'a MetricState,
+    family: &'a MetricFamily,
+    metric: &'a Metric,
+    tolerance: Tolerance,
+    finite: bool,
+}
+
+impl<'a> SeriesExpectation<'a> {
+    /// Compares the equality assertions that follow within `tolerance`.
+    pub fn within(mut self, tolerance: Tolerance) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    #[track_caller]
+    pub fn counter_eq(self, expected: f64) -> Self {
+        let (tolerance, expectation) = (self.tolerance, format!("to be {expected}{}", qualifier(self.tolerance)));
+        self.check_value("counter", self.metric.counter_value(), &expectation, |actual| {
+            tolerance.matches(actual, expected)
+        })
+    }
+
+    #[track_caller]
+    pub fn counter_at_least(self, min: f64) -> Self {
+        self.check_value("counter", self.metric.counter_value(), &format!("to be at least {min}"), |actual| {
+            min <= actual
+        })
+    }
+
+    #[track_caller]
+    pub fn gauge_eq(self, expected: f64) -> Self {
+        let (tolerance, expectation) = (self.tolerance, format!("to be {expected}{}", qualifier(self.tolerance)));
+        self.check_value("gauge", self.metric.gauge_value(), &expectation, |actual| tolerance.matches(actual, expected))
+    }
+
+    #[track_caller]
+    pub fn gauge_at_least(self, min: f64) -> Self {
+        self.check_value("gauge", self.metric.gauge_value(), &format!("to be at least {min}"), |actual| min <= actual)
+    }
+
+    #[track_caller]
+    pub fn gauge_at_most(self, max: f64) -> Self {
+        self.check_value("gauge", self.metric.gauge_value(), &format!("to be at most {max}"), |actual| actual <= max)
+    }
+
+    #[track_caller]
+    pub fn histogram_count_eq(self, count: u64) -> Self {
+        self.check_count("to have", |actual| actual == count, count)
+    }
+
+    #[track_caller]
+    pub fn histogram_count_at_least(self, min: u64) -> Self {
+        self.check_count("to have at least", |actual| min <= actual, min)
+    }
+
+    #[track_caller]
+    pub fn histogram_count_at_most(self, max: u64) -> Self {
+        self.check_count("to have at most", |actual| actual <= max, max)
+    }
+
+    #[track_caller]
+    pub fn histogram_sum_eq(self, sum: f64) -> Self {
+        let actual = self.histogram().sample_sum.unwrap_or(0_f64);
+        self.check_finite("histogram", actual);
+        if !self.tolerance.matches(actual, sum) {
+            let message = format!(
+                "expected histogram {} to sum to {sum}{}, but it summed to {actual}",
+                self.describe(),
+                qualifier(self.tolerance)
+            );
+            self.fail(&message);
+        }
+        self
+    }
+
+    /// Asserts the histogram has a bucket with upper bound `le`, for assertions on how many
+    /// observations fell at or below it.
+    #[track_caller]
+    pub fn bucket_le(self, le: f64) -> BucketExpectation<'a> {
+        let histogram = self.histogram();
+        let count = match histogram.buckets.iter().find(|bucket| bucket.upper_bound == le) {
+            Some(bucket) => bucket.cumulative_count,
+            None if le == f64::INFINITY => histogram.sample_count.unwrap_or(0),
+            None => {
+                let bounds: Vec<String> =
+                    histogram.buckets.iter().map(|bucket| format_float(bucket.upper_bound)).collect();
+                let message = format!(
+                    "expected histogram {} to have a bucket le=\"{}\", but its buckets are [{}]",
+                    self.describe(),
+                    format_float(le),
+                    bounds.join(", ")
+                );
+                self.fail(&message)
+            },
+        };
+        BucketExpectation {
+            series: self,
+            le,
+            count,
+            total: histogram.sample_count.unwrap_or(0),
+        }
+    }
+
+    /// Asserts every observation fell at or below `le`, judged by the largest bucket bound not
+    /// above `le`.
+    #[track_caller]
+    pub fn all_observations_at_most(self, le: f64) -> Self {
+        let histogram = self.histogram();
+        let total = histogram.sample_count.unwrap_or(0);
+        let bucket = histogram.buckets.iter().rfind(|bucket| bucket.upper_bound <= le);
+        let below = match bucket {
+            Some(bucket) => bucket.cumulative_count,
+            None if le == f64::INFINITY => total,
+            None => 0,
+        };
+        if below < total {
+            let message = format!(
+                "expected all {total} observations of histogram {} to be at most {}, but {} were above {}",
+                self.describe(),
+                format_float(le),
+                total - below,
+                bucket.map_or_else(|| format_float(le), |bucket| format_float(bucket.upper_bound)),
+            );
+            self.fail(&message);
+        }
+        self
+    }
+
+    pub fn metric(&self) -> &'a Metric {
+        self.metric
+    }
+
+    #[track_caller]
+    fn check_value(self, kind: &str, value: Option<f64>, expectation: &str, check: impl FnOnce(f64) -> bool) -> Self {
+        if let Some(actual) = value {
+            self.check_finite(kind, actual);
+        }
+        match value {
+            Some(actual) if check(actual) => (),
+            Some(actual) => {
+                self.fail(&format!("expected {kind} {} {expectation}, but it was {actual}", self.describe()))
+            },
+            None => self.fail(&format!("expected {} to be a {kind} with a value", self.describe())),
+        }
+        self
+    }
+
+    #[track_caller]
+    fn check_finite(&self, kind: &str, actual: f64) {
+        if self.finite && !actual.is_finite() {
+            self.fail(&format!(
+                "expected {kind} {} to be finite, but it was {}",
+                self.describe(),
+                format_float(actual)
+            ));
+        }
+    }
+
+    #[track_caller]
+    fn check_count(self, relation: &str, check: impl FnOnce(u64) -> bool, bound: u64) -> Self {
+        let actual = self.histogram().sample_count.unwrap_or(0);
+        if !check(actual) {
+            let message =
+                format!("expected histogram {} {relation} {bound} observations, but it had {actual}", self.describe());
+            self.fail(&message);
+        }
+        self
+    }
+
+    #[track_caller]
+    fn histogram(&self) -> HistogramValue<'a> {
+        match self.metric.histogram() {
+            Some(histogram) => histogram,
+            None => self.fail(&format!("expected {} to be a histogram", self.describe())),
+        }
+    }
+
+    fn describe(&self) -> String {
+        series(&self.family.name, self.metric.labels())
+    }
+
+    #[track_caller]
+    fn fail(&self, message: &str) -> ! {
+        fail(self.state, &self.family.name, message)
+    }
+}
+
+/// The observations of a histogram series at or below one bucket bound.
+#[derive(Debug, Copy, Clone)]
+pub struct BucketExpectation<'a> {
+    series: SeriesExpectation<'a>,
+    le: f64,
+    count: u64,
+    total: u64,
+}
+
+impl<'a> BucketExpectation<'a> {
+    #[track_caller]
+    pub fn count_eq(self, count: u64) -> Self {
+        self.check("exactly", |actual| actual == count, count)
+    }
+
+    #[track_caller]
+    pub fn count_at_least(self, min: u64) -> Self {
+        self.check("at least", |actual| min <= actual, min)
+    }
+
+    #[track_caller]
+    pub fn count_at_most(self, max: u64) -> Self {
+        self.check("at most", |actual| actual <= max, max)
+    }
+
+    /// Asserts at least `ratio` of all observations fell in the bucket, e.g. `0.99` for a
+    /// latency SLO.
+    #[track_caller]
+    pub fn fraction_at_least(self, ratio: f64) -> Self {
+        let fraction = if self.total == 0 { 1_f64 } else { self.count as f64 / self.total as f64 };
+        if fraction < ratio {
+            let message = format!(
+                "expected at least {ratio} of the observations of histogram {} to be at most {}, but only {fraction} \
+                 ({} of {}) were",
+                self.series.describe(),
+                format_float(self.le),
+                self.count,
+                self.total
+            );
+            self.series.fail(&message);
+        }
+        self
+    }
+
+    /// Back to the series, for further assertions on it.
+    pub fn series(self) -> SeriesExpectation<'a> {
+        self.series
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    #[track_caller]
+    fn check(self, relation: &str, check: impl FnOnce(u64) -> bool, bound: u64) -> Self {
+        if !check(self.count) {
+            let message = format!(
+                "expected {relation} {bound} observations of histogram {} to be at most {}, but {} were",
+                self.series.describe(),
+                format_float(self.le),
+                self.count
+            );
+            self.series.fail(&message);
+        }
+        self
+    }
+}
+
+/// The series of family `name` with exactly `labels`, or a failure naming what was expected.
+#[track_caller]
+pub(crate) fn expect_series<'a>(
+    state: &'a MetricState,
+    kind: Option<&str>,
+    name: &str,
+    labels: &[MetricLabel],
+) -> &'a Metric {
+    let pairs: Vec<(&str, &str)> = labels.iter().map(|l| (l.name.as_str(), l.value.as_str())).collect();
+    match state.series(name, &pairs) {
+        Some(metric) => metric,
+        None => {
+            let kind = kind.map(|kind| format!("{kind} ")).unwrap_or_default();
+            fail(state, name, &format!("expected a {kind}series {}, but there is none", series(name, labels)))
+        },
+    }
+}
+
+/// Panics with `message` followed by the series of family `name`, or similarly named families
+/// when it does not exist.
+#[track_caller]
+pub(crate) fn fail(state: &MetricState, name: &str, message: &str) -> ! {
+    panic!("{message}\n{}", nearby(state, name).trim_end())
+}
+
+fn nearby(state: &MetricState, name: &str) -> String {
+    let mut out = String::new();
+    match state.family(name) {
+        Some(family) if family.metrics.is_empty() => {
+            let _ = writeln!(out, "family {name} has no series");
+        },
+        Some(family) => {
+            let _ = writeln!(out, "series of {name} ({}):", crate::json::kind_name(family.kind));
+            for metric in family.metrics.iter().take(NEARBY_SERIES) {
+                let _ = writeln!(out, "  {} {}", series(name, metric.labels()), display_value(metric));
+            }
+            if NEARBY_SERIES < family.metrics.len() {
+                let _ = writeln!(out, "  ... and {} more", family.metrics.len() - NEARBY_SERIES);
+            }
+        },
+        None => {
+            let stem = name.split('_').next().unwrap_or(name);
+            let similar: Vec<&str> = state
+                .families()
+                .iter()
+                .map(|family| family.name.as_str())
+                .filter(|family| family.contains(name) || name.contains(family) || family.starts_with(stem))
+                .collect();
+            if similar.is_empty() {
+                let _ = writeln!(out, "there is no family {name}; {} families are present", state.families().len());
+            } else {
+                let _ = writeln!(out, "there is no family {name}; similar families: {}", similar.join(", "));
+            }
+        },
+    }
+    out
+}
+
+fn series(name: &str, labels: &[MetricLabel]) -> String {
+    if labels.is_empty() {
+        name.to_string()
+    } else {
+        format!("{name}{{{}}}", format_labels(labels))
+    }
+}
+
+/// How a failure message qualifies an expected value compared within `tolerance`.
+fn qualifier(tolerance: Tolerance) -> String {
+    match tolerance {
+        Tolerance::Exact => String::new(),
+        tolerance => format!(" {tolerance}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::labels;
+
+    fn state() -> MetricState {
+        MetricState::from(vec![
+            MetricFamily::builder("requests_total")
+                .counter(labels! {"method" => "GET"}, 3_f64)
+                .counter(labels! {"method" => "POST"}, 1.5)
+                .build(),
+            MetricFamily::builder("temperature").gauge(vec![], 21.5).build(),
+            MetricFamily::builder("latency_seconds")
+                .histogram(labels! {"path" => "/"}, &[(0.1, 2), (0.5, 8), (f64::INFINITY, 10)], 2.5)
+                .build(),
+        ])
+    }
+
+    #[test]
+    fn passes_on_matching_series() {
+        let state = state();
+        assert_counter!(state, "requests_total", labels! {"method" => "GET"}, 3);
+        assert_gauge!(state, "temperature", [], 21.5);
+        assert_histogram!(state, "latency_seconds", labels! {"path" => "/"}, 10);
+        assert_histogram!(state, "latency_seconds", labels! {"path" => "/"}, 10, 2.5);
+    }
+
+    #[test]
+    #[should_panic(expected = "expected counter requests_total{method=\"GET\"} to be 4, but it was 3\nseries of \
+                               requests_total (counter):\n  requests_total{method=\"GET\"} 3\n  \
+                               requests_total{method=\"POST\"} 1.5")]
+    fn counter_mismatches_list_the_family_series() {
+        assert_counter!(state(), "requests_total", labels! {"method" => "GET"}, 4);
+    }
+
+    #[test]
+    #[should_panic(expected = "expected a counter series requests_total{method=\"PUT\"}, but there is none")]
+    fn missing_series_fail() {
+        assert_counter!(state(), "requests_total", labels! {"method" => "PUT"}, 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "expected temperature to be a counter with a value")]
+    fn counters_must_be_counters() {
+        assert_counter!(state(), "temperature", [], 21.5);
+    }
+
+    #[test]
+    #[should_panic(expected = "expected gauge temperature to be 20, but it was 21.5")]
+    fn gauge_mismatches_fail() {
+        assert_gauge!(state(), "temperature", [], 20);
+    }
+
+    #[test]
+    #[should_panic(expected = "there is no family temperature_celsius; similar families: temperature")]
+    fn missing_families_suggest_similar_ones() {
+        assert_gauge!(state(), "temperature_celsius", [], 21.5);
+    }
+
+    #[test]
+    #[should_panic(expected = "expected histogram latency_seconds{path=\"/\"} to have 9 observations, but it had 10")]
+    fn histogram_count_mismatches_fail() {
+        assert_histogram!(state(), "latency_seconds", labels! {"path" => "/"}, 9);
+    }
+
+    #[test]
+    #[should_panic(expected = "expected histogram latency_seconds{path=\"/\"} to sum to 3, but it summed to 2.5")]
+    fn histogram_sum_mismatches_fail() {
+        assert_histogram!(state(), "latency_seconds", labels! {"path" => "/"}, 10, 3);
+    }
+
+    #[test]
+    fn chains_fluent_assertions() {
+        let state = state();
+        let requests = expect(&state).no_family("errors_total").family("requests_total");
+        let requests = requests.of_kind(MetricKind::Counter).series_count(2);
+        requests.with_labels(&[("method", "GET")]).counter_eq(3_f64).counter_at_least(3_f64);
+        let temperature = expect(&state).family("temperature").with_labels(&[]);
+        temperature.gauge_eq(21.5).gauge_at_least(21_f64).gauge_at_most(21.5);
+        let latency = expect(&state).family("latency_seconds").with_labels(&[("path", "/")]);
+        latency.histogram_count_eq(10).histogram_count_at_least(10).histogram_count_at_most(10).histogram_sum_eq(2.5);
+        assert_eq!(latency.metric().count(), 10);
+    }
+
+    #[test]
+    #[should_panic(expected = "expected a family errors_total, but there is none")]
+    fn fluent_missing_families_fail() {
+        expect(&state()).family("errors_total");
+    }
+
+    #[test]
+    #[should_panic(expected = "expected no family temperature")]
+    fn fluent_present_families_fail_no_family() {
+        expect(&state()).no_family("temperature");
+    }
+
+    #[test]
+    #[should_panic(expected = "expected family temperature to be Counter, but it is Gauge")]
+    fn fluent_kind_mismatches_fail() {
+        expect(&state()).family("temperature").of_kind(MetricKind::Counter);
+    }
+
+    #[test]
+    #[should_panic(expected = "expected family requests_total to have 1 series, but it has 2")]
+    fn fluent_series_count_mismatches_fail() {
+        expect(&state()).family("requests_total").series_count(1);
+    }
+
+    #[test]
+    #[should_panic(expected = "expected a series requests_total{method=\"PUT\"}, but there is none")]
+    fn fluent_missing_series_fail() {
+        expect(&state()).family("requests_total").with_labels(&[("method", "PUT")]);
+    }
+
+    #[test]
+    #[should_panic(expected = "expected counter requests_total{method=\"POST\"} to be at least 2, but it was 1.5")]
+    fn fluent_counter_bounds_fail() {
+        expect(&state()).family("requests_total").with_labels(&[("method", "POST")]).counter_at_least(2_f64);
+    }
+
+    #[test]
+    #[should_panic(expected = "expected gauge temperature to be at most 20, but it was 21.5")]
+    fn fluent_gauge_bounds_fail() {
+        expect(&state()).family("temperature").with_labels(&[]).gauge_at_most(20_f64);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "expected histogram latency_seconds{path=\"/\"} to have at least 11 observations, but it \
+                               had 10"
+    )]
+    fn fluent_histogram_counts_fail() {
+        expect(&state()).family("latency_seconds").with_labels(&[("path", "/")]).histogram_count_at_least(11);
+    }
+
+    #[test]
+    #[should_panic(expected = "expected temperature to be a histogram")]
+    fn fluent_histogram_assertions_need_histograms() {
+        expect(&state()).family("temperature").with_labels(&[]).histogram_sum_eq(1_f64);
+    }
+
+    #[test]
+    fn tolerances_include_their_boundaries() {
+        assert!(Tolerance::Exact.matches(1_f64, 1_f64));
+        assert!(!Tolerance::Exact.matches(1_f64, 1_f64 + f64::EPSILON));
+        assert!(Tolerance::Absolute(0.25).matches(1.25, 1_f64));
+        assert!(Tolerance::Absolute(0.25).matches(0.75, 1_f64));
+        assert!(!Tolerance::Absolute(0.25).matches(1.5, 1_f64));
+        // relative to the larger magnitude, so symmetric in actual and expected
+        assert!(Tolerance::Relative(0.5).matches(2_f64, 1_f64));
+        assert!(Tolerance::Relative(0.5).matches(1_f64, 2_f64));
+        assert!(!Tolerance::Relative(0.5).matches(2.5, 1_f64));
+    }
+
+    #[test]
+    fn nan_is_only_close_to_nan() {
+        assert!(Tolerance::Exact.matches(f64::NAN, f64::NAN));
+        assert!(!Tolerance::Absolute(f64::INFINITY).matches(f64::NAN, 1_f64));
+        assert!(!Tolerance::Relative(1_f64).matches(1_f64, f64::NAN));
+        assert!(Tolerance::Exact.matches(f64::INFINITY, f64::INFINITY));
+    }
+
+    #[test]
+    fn displays_tolerances() {
+        let displayed: Vec<_> = [Tolerance::Exact, Tolerance::Absolute(0.25), Tolerance::Relative(0.5)]
+            .iter()
+            .map(Tolerance::to_string)
+            .collect();
+        assert_eq!(displayed, vec!["exactly", "within 0.25", "within 50%"]);
+    }
+
+    #[test]
+    fn compares_within_tolerances() {
+        let state = state();
+        assert_eq_approx!(0.1 + 0.2, 0.3, Tolerance::Absolute(1e-9));
+        assert_eq_approx!(4, 4_f64);
+        assert_counter!(state, "requests_total", labels! {"method" => "GET"}, 3.2, Tolerance::Absolute(0.25));
+        assert_gauge!(state, "temperature", [], 20, Tolerance::Relative(0.1));
+        assert_histogram!(state, "latency_seconds", labels! {"path" => "/"}, 10, 2.4, Tolerance::Absolute(0.125));
+        let latency = expect(&state).family("latency_seconds").with_labels(&[("path", "/")]);
+        latency.within(Tolerance::Relative(0.05)).histogram_sum_eq(2.6);
+        expect(&state).family("temperature").with_labels(&[]).within(Tolerance::Absolute(1_f64)).gauge_eq(22_f64);
+    }
+
+    #[test]
+    #[should_panic(expected = "expected 1.5 to be 1 within 0.25")]
+    fn approximate_mismatches_fail() {
+        assert_eq_approx!(1.5, 1, Tolerance::Absolute(0.25));
+    }
+
+    #[test]
+    #[should_panic(expected = "expected gauge temperature to be 20 within 1, but it was 21.5")]
+    fn failure_messages_name_the_tolerance() {
+        assert_gauge!(state(), "temperature", [], 20, Tolerance::Absolute(1_f64));
+    }
+
+    #[test]
+    #[should_panic(expected = "expected counter requests_total{method=\"GET\"} to be 2 within 10%, but it was 3")]
+    fn fluent_failure_messages_name_the_tolerance() {
+        let state = state();
+        let requests = expect(&state).family("requests_total").with_labels(&[("method", "GET")]);
+        requests.within(Tolerance::Relative(0.1)).counter_eq(2_f64);
+    }
+
+    #[test]
+    fn asserts_bucket_counts() {
+        let state = state();
+        let latency = expect(&state).family("latency_seconds").with_labels(&[("path", "/")]);
+        let slo = latency.bucket_le(0.5).count_eq(8).count_at_least(8).count_at_most(8).fraction_at_least(0.8);
+        assert_eq!(slo.count(), 8);
+        slo.series().bucket_le(f64::INFINITY).count_eq(10).fraction_at_least(1_f64);
+        latency.all_observations_at_most(f64::INFINITY);
+    }
+
+    #[test]
+    fn asserts_on_unlabelled_histograms() {
+        let state = MetricState::from(vec![MetricFamily::builder("backoff_seconds")
+            .histogram(vec![], &[(1_f64, 4)], 2_f64)
+            .build()]);
+        // a missing +Inf bucket counts every observation
+        expect(&state)
+            .histogram("backoff_seconds")
+            .all_observations_at_most(1_f64)
+            .bucket_le(f64::INFINITY)
+            .count_eq(4);
+    }
+
+    #[test]
+    #[should_panic(expected = "expected histogram latency_seconds{path=\"/\"} to have a bucket le=\"0.25\", but its \
+                               buckets are [0.1, 0.5, +Inf]")]
+    fn missing_buckets_list_the_bounds() {
+        expect(&state()).family("latency_seconds").with_labels(&[("path", "/")]).bucket_le(0.25);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "expected at least 9 observations of histogram latency_seconds{path=\"/\"} to be at most \
+                               0.5, but 8 were"
+    )]
+    fn bucket_count_mismatches_fail() {
+        expect(&state()).family("latency_seconds").with_labels(&[("path", "/")]).bucket_le(0.5).count_at_least(9);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "expected at least 0.9 of the observations of histogram latency_seconds{path=\"/\"} to be \
+                               at most 0.5, but only 0.8 (8 of 10) were"
+    )]
+    fn bucket_fraction_mismatches_fail() {
+        expect(&state()).family("latency_seconds").with_labels(&[("path", "/")]).bucket_le(0.5).fraction_at_least(0.9);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "expected all 10 observations of histogram latency_seconds{path=\"/\"} to be at most 1, \
+                               but 2 were above 0.5"
+    )]
+    fn observations_above_the_bound_fail() {
+        expect(&state()).family("latency_seconds").with_labels(&[("path", "/")]).all_observations_at_most(1_f64);
+    }
+
+    #[test]
+    #[should_panic(expected = "expected a series latency_seconds, but there is none")]
+    fn histogram_shorthand_expects_an_unlabelled_series() {
+        expect(&state()).histogram("latency_seconds");
+    }
+
+    #[test]
+    fn totals_families_across_labels() {
+        let state = state();
+        assert_family_total!(state, "requests_total", 4.5);
+        assert_family_total!(state, "requests_total", 4, Tolerance::Absolute(0.5));
+        assert_family_total!(state, "temperature", 21.5);
+    }
+
+    #[test]
+    #[should_panic(expected = "expected family requests_total to total 5 across 2 series, but it totalled 4.5")]
+    fn family_total_mismatches_fail() {
+        assert_family_total!(state(), "requests_total", 5);
+    }
+
+    #[test]
+    #[should_panic(expected = "expected family latency_seconds to have values to total, but it is a histogram")]
+    fn family_totals_need_values() {
+        assert_family_total!(state(), "latency_seconds", 10);
+    }
+
+    #[test]
+    #[should_panic(expected = "expected a family errors_total totalling 1, but there is none")]
+    fn family_totals_need_the_family() {
+        assert_family_total!(state(), "errors_total", 1);
+    }
+
+    #[test]
+    fn passes_on_absent_families_and_series() {
+        let state = state();
+        assert_absent!(state, "errors_total");
+        assert_absent!(state, "requests_total", labels! {"method" => "PUT"});
+        assert_absent!(state, "requests_total", labels! {"method" => "GET", "code" => "500"});
+    }
+
+    #[test]
+    #[should_panic(expected = "expected no family temperature, but it has 1 series")]
+    fn present_families_fail() {
+        assert_absent!(state(), "temperature");
+    }
+
+    #[test]
+    #[should_panic(expected = "expected no series requests_total{method=\"GET\"}, but found:\n  \
+                               requests_total{method=\"GET\"} 3")]
+    fn present_series_are_listed() {
+        assert_absent!(state(), "requests_total", labels! {"method" => "GET"});
+    }
+
+    fn after() -> Vec<MetricFamily> {
+        vec![
+            MetricFamily::builder("requests_total")
+                .counter(labels! {"method" => "GET"}, 5_f64)
+                .counter(labels! {"method" => "PUT"}, 2_f64)
+                .counter(labels! {"method" => "POST"}, 1_f64)
+                .build(),
+            MetricFamily::builder("temperature").gauge(vec![], 22_f64).build(),
+            MetricFamily::builder("latency_seconds")
+                .histogram(labels! {"path" => "/"}, &[(0.1, 2), (0.5, 9), (f64::INFINITY, 13)], 3_f64)
+                .build(),
+        ]
+    }
+
+    #[test]
+    fn asserts_increases_between_snapshots() {
+        let (before, after) = (state(), after());
+        assert_increased_by!(before, after, "requests_total", labels! {"method" => "GET"}, 2);
+        assert_increased_by!(before, after, "latency_seconds", labels! {"path" => "/"}, 3);
+        // missing before: counted from zero
+        assert_increased_by!(before, after, "requests_total", labels! {"method" => "PUT"}, 2);
+        // reset from 1.5 to 1: the later value
+        assert_increased_by!(before, after, "requests_total", labels! {"method" => "POST"}, 1);
+        assert_increased_by!(
+            before,
+            after,
+            "requests_total",
+            labels! {"method" => "GET"},
+            2.1,
+            Tolerance::Relative(0.1)
+        );
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "expected requests_total{method=\"GET\"} to increase by 1, but it increased by 2 (it went \
+                               from 3 to 5)"
+    )]
+    fn increase_mismatches_show_both_values() {
+        assert_increased_by!(state(), after(), "requests_total", labels! {"method" => "GET"}, 1);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "expected requests_total{method=\"POST\"} to increase by 0.5, but it increased by 1 (it \
+                               reset and rose to 1)"
+    )]
+    fn increase_mismatches_explain_resets() {
+        assert_increased_by!(state(), after(), "requests_total", labels! {"method" => "POST"}, 0.5);
+    }
+
+    #[test]
+    #[should_panic(expected = "expected temperature to be a counter, histogram or summary")]
+    fn increases_need_counted_series() {
+        assert_increased_by!(state(), after(), "temperature", [], 0.5);
+    }
+}
@@ -0,0 +1,191 @@
+use crate::{HistogramBucket, Metric, MetricFamily, MetricKind, MetricLabel, Quantile};
+
+/// Assembles a [`MetricFamily`] series by series, for authoring expected values in tests:
+///
+/// ```
+/// use inspect_prometheus::{labels, MetricFamily};
+///
+/// let family = MetricFamily::builder("http_requests_total")
+///     .help("Requests served")
+///     .counter(labels! {"method" => "GET"}, 3.0)
+///     .counter(labels! {"method" => "POST"}, 1.0)
+///     .build();
+/// assert_eq!(family.metrics.len(), 2);
+/// ```
+///
+/// The family takes the kind of the series added to it, untyped when there are none.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetricFamilyBuilder {
+    family: MetricFamily,
+    kind: Option<MetricKind>,
+}
+
+impl MetricFamilyBuilder {
+    pub(crate) fn new(name: impl Into<String>) -> Self {
+        Self {
+            family: MetricFamily {
+                name: name.into(),
+                help: None,
+                unit: None,
+                kind: MetricKind::Untyped,
+                metrics: Vec::new(),
+            },
+            kind: None,
+        }
+    }
+
+    pub fn help(mut self, help: impl Into<String>) -> Self {
+        self.family.help = Some(help.into());
+        self
+    }
+
+    pub fn unit(mut self, unit: impl Into<String>) -> Self {
+        self.family.unit = Some(unit.into());
+        self
+    }
+
+    /// Overrides the kind taken from the series.
+    pub fn kind(mut self, kind: MetricKind) -> Self {
+        self.kind = Some(kind);
+        self
+    }
+
+    pub fn counter(self, labels: Vec<MetricLabel>, value: f64) -> Self {
+        self.series(MetricKind::Counter, Metric::counter(labels, value))
+    }
+
+    pub fn gauge(self, labels: Vec<MetricLabel>, value: f64) -> Self {
+        self.series(MetricKind::Gauge, Metric::gauge(labels, value))
+    }
+
+    pub fn untyped(self, labels: Vec<MetricLabel>, value: f64) -> Self {
+        self.series(MetricKind::Untyped, Metric::untyped(labels, value))
+    }
+
+    /// A histogram series from its `(upper_bound, cumulative_count)` buckets; the sample count is
+    /// the `+Inf` bucket's, or else the last bucket's.
+    pub fn histogram(self, labels: Vec<MetricLabel>, buckets: &[(f64, u64)], sum: f64) -> Self {
+        let sample_count = buckets.last().map_or(0, |(_, count)| *count);
+        let metric = Metric::classic_histogram(labels, to_buckets(buckets), sample_count, sum);
+        self.series(MetricKind::Histogram, metric)
+    }
+
+    /// A gauge histogram series, counted like [`MetricFamilyBuilder::histogram`].
+    pub fn gauge_histogram(self, labels: Vec<MetricLabel>, buckets: &[(f64, u64)], sum: f64) -> Self {
+        let sample_count = buckets.last().map_or(0, |(_, count)| *count);
+        let metric = Metric::gauge_histogram(labels, to_buckets(buckets), sample_count, sum);
+        self.series(MetricKind::GaugeHistogram, metric)
+    }
+
+    /// A summary series from its `(quantile, value)` pairs.
+    pub fn summary(self, labels: Vec<MetricLabel>, quantiles: &[(f64, f64)], count: u64, sum: f64) -> Self {
+        let quantiles =
+            quantiles.iter().map(|(quantile, value)| Quantile { quantile: *quantile, value: *value }).collect();
+        self.series(MetricKind::Summary, Metric::summary(labels, quantiles, count, sum))
+    }
+
+    pub fn info(self, labels: Vec<MetricLabel>) -> Self {
+        self.series(MetricKind::Info, Metric::info(labels))
+    }
+
+    pub fn state_set(self, labels: Vec<MetricLabel>, states: &[(&str, bool)]) -> Self {
+        let states = states.iter().map(|(state, enabled)| (state.to_string(), *enabled)).collect();
+        self.series(MetricKind::StateSet, Metric::state_set(labels, states))
+    }
+
+    /// Adds a series as is, leaving the family kind alone.
+    pub fn metric(mut self, metric: Metric) -> Self {
+        self.family.metrics.push(metric);
+        self
+    }
+
+    pub fn build(mut self) -> MetricFamily {
+        if let Some(kind) = self.kind {
+            self.family.kind = kind;
+        }
+        self.family
+    }
+
+    fn series(mut self, kind: MetricKind, metric: Metric) -> Self {
+        self.family.kind = kind;
+        self.family.metrics.push(metric);
+        self
+    }
+}
+
+fn to_buckets(buckets: &[(f64, u64)]) -> Vec<HistogramBucket> {
+    buckets
+        .iter()
+        .map(|(upper_bound, cumulative_count)| HistogramBucket {
+            upper_bound: *upper_bound,
+            cumulative_count: *cumulative_count,
+            exemplar: None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::labels;
+
+    #[test]
+    fn builds_metadata_and_series() {
+        let family = MetricFamily::builder("request_seconds_total")
+            .help("Time spent.")
+            .unit("seconds")
+            .counter(labels! {"method" => "GET"}, 1.5)
+            .build();
+        assert_eq!(
+            family,
+            MetricFamily {
+                name: "request_seconds_total".to_string(),
+                help: Some("Time spent.".to_string()),
+                unit: Some("seconds".to_string()),
+                kind: MetricKind::Counter,
+                metrics: vec![Metric::counter(labels! {"method" => "GET"}, 1.5)],
+            }
+        );
+    }
+
+    #[test]
+    fn takes_the_kind_of_the_series() {
+        let kind = |builder: MetricFamilyBuilder| builder.build().kind;
+        let builder = || MetricFamily::builder("x");
+        assert_eq!(kind(builder()), MetricKind::Untyped);
+        assert_eq!(kind(builder().gauge(vec![], 1_f64)), MetricKind::Gauge);
+        assert_eq!(kind(builder().untyped(vec![], 1_f64)), MetricKind::Untyped);
+        assert_eq!(kind(builder().gauge_histogram(vec![], &[], 0_f64)), MetricKind::GaugeHistogram);
+        assert_eq!(kind(builder().info(vec![])), MetricKind::Info);
+        assert_eq!(kind(builder().state_set(vec![], &[("on", true)])), MetricKind::StateSet);
+        assert_eq!(kind(builder().metric(Metric::gauge(vec![], 1_f64))), MetricKind::Untyped);
+        assert_eq!(kind(builder().kind(MetricKind::Counter).gauge(vec![], 1_f64)), MetricKind::Counter);
+    }
+
+    #[test]
+    fn counts_histograms_from_their_last_bucket() {
+        let family = MetricFamily::builder("latency_seconds")
+            .histogram(vec![], &[(0.5, 2), (f64::INFINITY, 5)], 2.5)
+            .histogram(labels! {"path" => "/"}, &[(0.5, 3)], 1_f64)
+            .histogram(labels! {"path" => "/empty"}, &[], 0_f64)
+            .build();
+        let totals: Vec<_> = family.metrics.iter().map(|m| (m.count(), m.sum())).collect();
+        assert_eq!(totals, vec![(5, 2.5), (3, 1_f64), (0, 0_f64)]);
+        let bounds: Vec<_> = family.metrics[0].histogram().unwrap().buckets.iter().map(|b| b.upper_bound).collect();
+        assert_eq!(bounds, vec![0.5, f64::INFINITY]);
+    }
+
+    #[test]
+    fn builds_summaries_and_state_sets() {
+        let family = MetricFamily::builder("rpc_seconds").summary(vec![], &[(0.5, 0.1), (0.99, 0.4)], 7, 1.25).build();
+        assert_eq!((family.kind, family.metrics[0].count(), family.metrics[0].sum()), (MetricKind::Summary, 7, 1.25));
+        assert_eq!(
+            family.metrics[0].quantiles(),
+            Some(&[Quantile { quantile: 0.5, value: 0.1 }, Quantile { quantile: 0.99, value: 0.4 }][..])
+        );
+
+        let family = MetricFamily::builder("mode").state_set(vec![], &[("on", true), ("off", false)]).build();
+        assert_eq!(family.metrics[0].states(), Some(&[("on".to_string(), true), ("off".to_string(), false)][..]));
+    }
+}
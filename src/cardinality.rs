@@ -0,0 +1,176 @@
+//! Series and label value counts per family, to bound cardinality in soak tests and to spot
+//! labels that leak unbounded values such as request ids:
+//!
+//! ```
+//! use inspect_prometheus::cardinality::cardinality_report;
+//!
+//! let report = cardinality_report(&inspect_prometheus::distill_default_registry());
+//! assert!(report.exceeding(1_000).is_empty(), "{report}");
+//! ```
+//!
+//! A series is counted as exposed, so a histogram counts once however many buckets it has.
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+
+use crate::MetricFamily;
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct CardinalityReport {
+    /// One entry per family, in exposition order.
+    pub families: Vec<FamilyCardinality>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FamilyCardinality {
+    pub family: String,
+    pub series: usize,
+    /// The family's label names, those with the most distinct values first.
+    pub labels: Vec<LabelCardinality>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LabelCardinality {
+    pub label: String,
+    pub distinct_values: usize,
+}
+
+pub fn cardinality_report(families: &[MetricFamily]) -> CardinalityReport {
+    let families = families
+        .iter()
+        .map(|family| {
+            let mut values: BTreeMap<String, HashSet<String>> = BTreeMap::new();
+            for label in family.metrics.iter().flat_map(|metric| metric.labels()) {
+                values.entry(label.name.clone()).or_default().insert(label.value.clone());
+            }
+            let mut labels: Vec<LabelCardinality> = values
+                .into_iter()
+                .map(|(label, values)| LabelCardinality { label, distinct_values: values.len() })
+                .collect();
+            labels.sort_by_key(|label| Reverse(label.distinct_values));
+            FamilyCardinality {
+                family: family.name.clone(),
+                series: family.metrics.len(),
+                labels,
+            }
+        })
+        .collect();
+    CardinalityReport { families }
+}
+
+impl CardinalityReport {
+    pub fn total_series(&self) -> usize {
+        self.families.iter().map(|family| family.series).sum()
+    }
+
+    /// The families with more than `max_series` series, the largest first.
+    pub fn exceeding(&self, max_series: usize) -> Vec<&FamilyCardinality> {
+        self.by_series().into_iter().filter(|family| max_series < family.series).collect()
+    }
+
+    /// The `n` families with the most series, the largest first.
+    pub fn top_families(&self, n: usize) -> Vec<&FamilyCardinality> {
+        self.by_series().into_iter().take(n).collect()
+    }
+
+    /// The `n` labels with the most distinct values across all families, as `(family, label)`.
+    pub fn top_labels(&self, n: usize) -> Vec<(&str, &LabelCardinality)> {
+        let mut labels: Vec<(&str, &LabelCardinality)> = self
+            .families
+            .iter()
+            .flat_map(|family| family.labels.iter().map(move |label| (family.family.as_str(), label)))
+            .collect();
+        labels.sort_by_key(|(_, label)| Reverse(label.distinct_values));
+        labels.truncate(n);
+        labels
+    }
+
+    fn by_series(&self) -> Vec<&FamilyCardinality> {
+        let mut families: Vec<&FamilyCardinality> = self.families.iter().collect();
+        families.sort_by_key(|family| Reverse(family.series));
+        families
+    }
+}
+
+/// A line per family, the largest first, listing its labels by distinct values.
+impl fmt::Display for CardinalityReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} series in {} families", self.total_series(), self.families.len())?;
+        for family in self.by_series() {
+            write!(f, "\n{}: {} series", family.family, family.series)?;
+            for (idx, label) in family.labels.iter().enumerate() {
+                let separator = if idx == 0 { " (" } else { ", " };
+                write!(f, "{separator}{} values of {}", label.distinct_values, label.label)?;
+            }
+            if !family.labels.is_empty() {
+                f.write_str(")")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::labels;
+
+    fn report() -> CardinalityReport {
+        let mut requests = MetricFamily::builder("requests_total");
+        for (method, request_id) in [("GET", "1"), ("GET", "2"), ("POST", "3"), ("POST", "4")] {
+            requests = requests.counter(labels! {"method" => method, "request_id" => request_id}, 1_f64);
+        }
+        cardinality_report(&[
+            MetricFamily::builder("up").gauge(vec![], 1_f64).build(),
+            requests.build(),
+            MetricFamily::builder("latency_seconds")
+                .histogram(labels! {"path" => "/"}, &[(0.1, 1), (1_f64, 2), (f64::INFINITY, 3)], 1_f64)
+                .build(),
+        ])
+    }
+
+    #[test]
+    fn counts_series_and_distinct_label_values() {
+        let report = report();
+        assert_eq!(report.total_series(), 6);
+        assert_eq!(
+            report.families[1],
+            FamilyCardinality {
+                family: "requests_total".to_string(),
+                series: 4,
+                labels: vec![
+                    LabelCardinality { label: "request_id".to_string(), distinct_values: 4 },
+                    LabelCardinality { label: "method".to_string(), distinct_values: 2 },
+                ],
+            }
+        );
+        // buckets are not series of their own
+        assert_eq!(report.families[2].series, 1);
+    }
+
+    #[test]
+    fn ranks_families_and_labels() {
+        let report = report();
+        let names = |families: Vec<&FamilyCardinality>| -> Vec<String> {
+            families.into_iter().map(|family| family.family.clone()).collect()
+        };
+        assert_eq!(names(report.exceeding(1)), vec!["requests_total"]);
+        assert_eq!(names(report.exceeding(4)), Vec::<String>::new());
+        assert_eq!(names(report.top_families(2)), vec!["requests_total", "up"]);
+
+        let labels: Vec<_> =
+            report.top_labels(2).into_iter().map(|(family, label)| (family, label.label.as_str())).collect();
+        assert_eq!(labels, vec![("requests_total", "request_id"), ("requests_total", "method")]);
+    }
+
+    #[test]
+    fn displays_the_largest_families_first() {
+        let expected = "6 series in 3 families
+requests_total: 4 series (4 values of request_id, 2 values of method)
+up: 1 series
+latency_seconds: 1 series (1 values of path)";
+        assert_eq!(report().to_string(), expected);
+    }
+}
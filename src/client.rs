@@ -0,0 +1,65 @@
+//! Support for registries of the `prometheus-client` crate, which exposes its registry only
+//! through its OpenMetrics text encoder. The crate isn't a dependency, so the encoder is passed in.
+
+use std::fmt;
+
+use crate::parse::{self, ParseError};
+use crate::MetricFamily;
+
+/// Distills a `prometheus_client::registry::Registry` by running its text encoder and parsing the
+/// OpenMetrics output, with families and series in [`crate::normalize`] order:
+///
+/// ```ignore
+/// let families = client::distill_client_registry(|out| prometheus_client::encoding::text::encode(out, &registry))?;
+/// ```
+pub fn distill_client_registry(
+    encode: impl FnOnce(&mut String) -> fmt::Result,
+) -> Result<Vec<MetricFamily>, ParseError> {
+    let mut encoded = String::new();
+    encode(&mut encoded).map_err(|err| ParseError::Io(err.to_string()))?;
+    let mut families = parse::parse_openmetrics(&encoded)?;
+    crate::normalize(&mut families);
+    Ok(families)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fmt::Write;
+
+    use crate::{labels, MetricKind};
+
+    /// Output as `prometheus_client::encoding::text::encode` writes it for a counter family and a
+    /// histogram registered in that order.
+    const ENCODED: &str = r#"# HELP requests Handled requests.
+# TYPE requests counter
+requests_total{path="/b"} 2
+requests_total{path="/a"} 1
+# HELP latency_seconds Request latency.
+# TYPE latency_seconds histogram
+# UNIT latency_seconds seconds
+latency_seconds_sum 0.75
+latency_seconds_count 2
+latency_seconds_bucket{le="0.5"} 1
+latency_seconds_bucket{le="+Inf"} 2
+# EOF
+"#;
+
+    #[test]
+    fn distills_encoded_registries_in_normal_order() {
+        let families = distill_client_registry(|out| out.write_str(ENCODED)).unwrap();
+        let names: Vec<_> = families.iter().map(|family| (family.name.as_str(), family.kind)).collect();
+        assert_eq!(names, vec![("latency_seconds", MetricKind::Histogram), ("requests_total", MetricKind::Counter)]);
+        assert_eq!(families[0].unit.as_deref(), Some("seconds"));
+        assert_eq!(families[0].metrics[0].count(), 2);
+        let labels: Vec<_> = families[1].metrics.iter().map(|m| m.labels().to_vec()).collect();
+        assert_eq!(labels, vec![labels! {"path" => "/a"}, labels! {"path" => "/b"}]);
+    }
+
+    #[test]
+    fn reports_encoder_and_parse_failures() {
+        assert!(matches!(distill_client_registry(|_| Err(fmt::Error)), Err(ParseError::Io(_))));
+        let unterminated = distill_client_registry(|out| out.write_str("# TYPE up gauge\nup 1\n"));
+        assert_eq!(unterminated, Err(ParseError::MissingEof));
+    }
+}
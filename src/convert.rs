@@ -0,0 +1,248 @@
+//! Conversion of distilled families back into `prometheus::proto` types, the reverse of
+//! [`crate::distill_metric_state`].
+
+use std::convert::TryFrom;
+
+use prometheus::proto;
+
+use crate::proto_ext::{self, NativeHistogram};
+use crate::{HistogramBucket, Metric, MetricFamily, MetricKind, MetricLabel};
+
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum ConvertError {
+    #[error("{kind:?} family {family} cannot be represented in prometheus::proto")]
+    Unrepresentable { family: String, kind: MetricKind },
+
+    #[error("family {family} of kind {kind:?} holds a metric of another kind")]
+    KindMismatch { family: String, kind: MetricKind },
+}
+
+/// Converts families back into `prometheus::proto` families, e.g. to hand edited state to the
+/// `prometheus` crate's encoders or push clients.
+pub fn to_proto_families(families: &[MetricFamily]) -> Result<Vec<proto::MetricFamily>, ConvertError> {
+    families.iter().map(proto::MetricFamily::try_from).collect()
+}
+
+/// Exemplars, created timestamps, units, native histograms and gauge histograms are written into
+/// the newer `io.prometheus.client` fields, which `distill_metric_state` recovers again.
+impl TryFrom<&MetricFamily> for proto::MetricFamily {
+    type Error = ConvertError;
+
+    fn try_from(family: &MetricFamily) -> Result<Self, Self::Error> {
+        let mut result = proto::MetricFamily::new();
+        result.set_name(family.name.clone());
+        if let Some(help) = family.help.as_ref() {
+            result.set_help(help.clone());
+        }
+        if let Some(unit) = family.unit.as_ref() {
+            proto_ext::set_family_unit(&mut result, unit);
+        }
+
+        let unrepresentable = || ConvertError::Unrepresentable { family: family.name.clone(), kind: family.kind };
+        match family.kind {
+            MetricKind::Counter => result.set_field_type(proto::MetricType::COUNTER),
+            MetricKind::Gauge => result.set_field_type(proto::MetricType::GAUGE),
+            MetricKind::Histogram => result.set_field_type(proto::MetricType::HISTOGRAM),
+            MetricKind::GaugeHistogram => proto_ext::set_gauge_histogram(&mut result),
+            MetricKind::Summary => result.set_field_type(proto::MetricType::SUMMARY),
+            MetricKind::Untyped => result.set_field_type(proto::MetricType::UNTYPED),
+            MetricKind::Info | MetricKind::StateSet => return Err(unrepresentable()),
+        }
+
+        for metric in family.metrics.iter() {
+            let converted = convert_metric(family.kind, metric)
+                .ok_or_else(|| ConvertError::KindMismatch { family: family.name.clone(), kind: family.kind })?;
+            result.mut_metric().push(converted);
+        }
+        Ok(result)
+    }
+}
+
+fn convert_metric(kind: MetricKind, metric: &Metric) -> Option<proto::Metric> {
+    let mut result = proto::Metric::new();
+    result.set_label(metric.labels().iter().map(label_pair).collect());
+    if let Some(timestamp_ms) = metric.timestamp_ms() {
+        result.set_timestamp_ms(timestamp_ms);
+    }
+
+    match (kind, metric) {
+        (MetricKind::Counter, Metric::Counter { value, exemplar, created, .. }) => {
+            let counter = result.mut_counter();
+            if let Some(value) = value {
+                counter.set_value(*value);
+            }
+            if let Some(exemplar) = exemplar {
+                proto_ext::set_counter_exemplar(counter, exemplar);
+            }
+            if let Some(created) = created {
+                proto_ext::set_counter_created(counter, *created);
+            }
+        },
+        (MetricKind::Gauge, Metric::Gauge { value, .. }) => {
+            if let Some(value) = value {
+                result.mut_gauge().set_value(*value);
+            }
+        },
+        (MetricKind::Untyped, Metric::Untyped { value, .. }) => {
+            if let Some(value) = value {
+                result.mut_untyped().set_value(*value);
+            }
+        },
+        (MetricKind::Histogram, Metric::Histogram { sample_count, sample_sum, buckets, created, .. }) => {
+            set_histogram(result.mut_histogram(), *sample_count, *sample_sum, buckets, *created)
+        },
+        (MetricKind::GaugeHistogram, Metric::GaugeHistogram { sample_count, sample_sum, buckets, .. }) => {
+            set_histogram(result.mut_histogram(), *sample_count, *sample_sum, buckets, None)
+        },
+        (
+            MetricKind::Histogram,
+            Metric::NativeHistogram {
+                sample_count,
+                sample_sum,
+                schema,
+                zero_threshold,
+                zero_count,
+                positive_spans,
+                positive_deltas,
+                negative_spans,
+                negative_deltas,
+                buckets,
+                created,
+                ..
+            },
+        ) => {
+            let histogram = result.mut_histogram();
+            set_histogram(histogram, *sample_count, *sample_sum, buckets, *created);
+            proto_ext::set_native_histogram(
+                histogram,
+                &NativeHistogram {
+                    schema: *schema,
+                    zero_threshold: *zero_threshold,
+                    zero_count: *zero_count,
+                    positive_spans: positive_spans.clone(),
+                    positive_deltas: positive_deltas.clone(),
+                    negative_spans: negative_spans.clone(),
+                    negative_deltas: negative_deltas.clone(),
+                },
+            );
+        },
+        (MetricKind::Summary, Metric::Summary { sample_count, sample_sum, quantiles, created, .. }) => {
+            let summary = result.mut_summary();
+            if let Some(sample_count) = sample_count {
+                summary.set_sample_count(*sample_count);
+            }
+            if let Some(sample_sum) = sample_sum {
+                summary.set_sample_sum(*sample_sum);
+            }
+            summary.set_quantile(
+                quantiles
+                    .iter()
+                    .map(|q| {
+                        let mut quantile = proto::Quantile::new();
+                        quantile.set_quantile(q.quantile);
+                        quantile.set_value(q.value);
+                        quantile
+                    })
+                    .collect(),
+            );
+            if let Some(created) = created {
+                proto_ext::set_summary_created(summary, *created);
+            }
+        },
+        _ => return None,
+    }
+
+    Some(result)
+}
+
+fn set_histogram(
+    histogram: &mut proto::Histogram,
+    sample_count: Option<u64>,
+    sample_sum: Option<f64>,
+    buckets: &[HistogramBucket],
+    created: Option<f64>,
+) {
+    set_count_and_sum(histogram, sample_count, sample_sum);
+    histogram.set_bucket(buckets.iter().map(bucket).collect());
+    if let Some(created) = created {
+        proto_ext::set_histogram_created(histogram, created);
+    }
+}
+
+fn set_count_and_sum(histogram: &mut proto::Histogram, sample_count: Option<u64>, sample_sum: Option<f64>) {
+    if let Some(sample_count) = sample_count {
+        histogram.set_sample_count(sample_count);
+    }
+    if let Some(sample_sum) = sample_sum {
+        histogram.set_sample_sum(sample_sum);
+    }
+}
+
+fn bucket(bucket: &HistogramBucket) -> proto::Bucket {
+    let mut result = proto::Bucket::new();
+    result.set_upper_bound(bucket.upper_bound);
+    result.set_cumulative_count(bucket.cumulative_count);
+    if let Some(exemplar) = bucket.exemplar.as_ref() {
+        proto_ext::set_bucket_exemplar(&mut result, exemplar);
+    }
+    result
+}
+
+fn label_pair(label: &MetricLabel) -> proto::LabelPair {
+    let mut pair = proto::LabelPair::new();
+    pair.set_name(label.name.clone());
+    pair.set_value(label.value.clone());
+    pair
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BucketSpan;
+
+    fn mixed_histogram() -> MetricFamily {
+        MetricFamily::builder("request_seconds")
+            .metric(Metric::NativeHistogram {
+                labels: vec![MetricLabel { name: "route".to_string(), value: "a".to_string() }],
+                sample_count: Some(4),
+                sample_sum: Some(1.5),
+                schema: 3,
+                zero_threshold: 1e-128,
+                zero_count: 1,
+                positive_spans: vec![BucketSpan { offset: -2, length: 2 }],
+                positive_deltas: vec![2, -1],
+                negative_spans: vec![],
+                negative_deltas: vec![],
+                buckets: vec![
+                    HistogramBucket { upper_bound: 0.5, cumulative_count: 3, exemplar: None },
+                    HistogramBucket { upper_bound: 1_f64, cumulative_count: 4, exemplar: None },
+                ],
+                created: Some(1_700_000_000.5),
+                timestamp_ms: None,
+            })
+            .kind(MetricKind::Histogram)
+            .build()
+    }
+
+    #[test]
+    fn round_trips_mixed_histograms_through_proto() {
+        let families = vec![mixed_histogram()];
+        let distilled = crate::distill_metric_state(to_proto_families(&families).unwrap());
+        assert_eq!(distilled, families);
+        let histogram = distilled[0].metrics[0].histogram().unwrap();
+        assert_eq!(histogram.buckets.len(), 2);
+    }
+
+    #[test]
+    fn renders_the_classic_buckets_of_mixed_histograms() {
+        let text = crate::render::text(&[mixed_histogram()]);
+        assert!(text.contains("request_seconds_bucket{route=\"a\",le=\"0.5\"} 3\n"), "{text}");
+        assert!(text.contains("request_seconds_bucket{route=\"a\",le=\"+Inf\"} 4\n"), "{text}");
+    }
+
+    #[test]
+    fn rejects_metrics_of_another_kind() {
+        let family = MetricFamily::builder("up").gauge(vec![], 1_f64).kind(MetricKind::Counter).build();
+        assert!(matches!(to_proto_families(&[family]), Err(ConvertError::KindMismatch { .. })));
+    }
+}
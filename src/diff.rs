@@ -0,0 +1,385 @@
+//! Differences between two snapshots of distilled families.
+
+use std::collections::{HashMap, HashSet};
+use std::time::SystemTime;
+
+use crate::{label_key, label_key_of, LabelKey, Metric, MetricFamily, MetricLabel};
+
+/// What changed from one snapshot to the next. Series are paired by family name and label set,
+/// regardless of label order; series of added or removed families are listed as added or removed
+/// series as well. Counters, histograms and summaries that went backwards (or whose created
+/// timestamp moved) were reset, e.g. by a process restart, and are listed in `resets` instead of
+/// `changed`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct MetricDelta {
+    pub added_families: Vec<String>,
+    pub removed_families: Vec<String>,
+    pub added_series: Vec<SeriesId>,
+    pub removed_series: Vec<SeriesId>,
+    pub changed: Vec<SeriesDelta>,
+    pub resets: Vec<SeriesDelta>,
+}
+
+impl MetricDelta {
+    pub fn is_empty(&self) -> bool {
+        self.added_families.is_empty()
+            && self.removed_families.is_empty()
+            && self.added_series.is_empty()
+            && self.removed_series.is_empty()
+            && self.changed.is_empty()
+            && self.resets.is_empty()
+    }
+
+    /// The change of the series of family `name` with exactly `labels`, if it changed or was
+    /// reset.
+    pub fn series(&self, name: &str, labels: &[(&str, &str)]) -> Option<&SeriesDelta> {
+        let key = label_key_of(labels.iter().copied());
+        self.changed
+            .iter()
+            .chain(self.resets.iter())
+            .find(|delta| delta.family == name && label_key(&delta.labels) == key)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SeriesId {
+    pub family: String,
+    pub labels: Vec<MetricLabel>,
+}
+
+/// The change of one series present in both snapshots. `before` and `after` are the sample value
+/// of counters, gauges and untyped series and the sample sum of histograms and summaries, whose
+/// observation count change is `count_delta`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SeriesDelta {
+    pub family: String,
+    pub labels: Vec<MetricLabel>,
+    pub before: f64,
+    pub after: f64,
+    pub delta: f64,
+    pub count_delta: i64,
+}
+
+/// Distilled families captured at a point in time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub captured_at: SystemTime,
+    pub families: Vec<MetricFamily>,
+}
+
+impl Snapshot {
+    pub fn new(families: Vec<MetricFamily>) -> Self {
+        Self { captured_at: SystemTime::now(), families }
+    }
+}
+
+impl AsRef<[MetricFamily]> for Snapshot {
+    fn as_ref(&self) -> &[MetricFamily] {
+        &self.families
+    }
+}
+
+/// The per-second rate of one series between two snapshots; for histograms and summaries it is
+/// the rate of observations. Across a `reset` the series is taken to have restarted from zero.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SeriesRate {
+    pub family: String,
+    pub labels: Vec<MetricLabel>,
+    pub rate: f64,
+    pub reset: bool,
+}
+
+/// The growth of one monotonic series between two snapshots; see [`increase`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct SeriesIncrease {
+    pub family: String,
+    pub labels: Vec<MetricLabel>,
+    pub increase: f64,
+    pub reset: bool,
+}
+
+pub fn diff(before: &[MetricFamily], after: &[MetricFamily]) -> MetricDelta {
+    let before_index: HashMap<&str, &MetricFamily> =
+        before.iter().map(|family| (family.name.as_str(), family)).collect();
+    let after_index: HashMap<&str, &MetricFamily> = after.iter().map(|family| (family.name.as_str(), family)).collect();
+
+    let mut delta = MetricDelta::default();
+    for family in after.iter() {
+        match before_index.get(family.name.as_str()) {
+            Some(previous) => diff_family(previous, family, &mut delta),
+            None => {
+                delta.added_families.push(family.name.clone());
+                delta.added_series.extend(family.metrics.iter().map(|metric| series_id(family, metric)));
+            },
+        }
+    }
+    for family in before.iter().filter(|family| !after_index.contains_key(family.name.as_str())) {
+        delta.removed_families.push(family.name.clone());
+        delta.removed_series.extend(family.metrics.iter().map(|metric| series_id(family, metric)));
+    }
+    delta
+}
+
+/// Per-second rates of the counter, histogram and summary series present in both snapshots,
+/// from their [`increase`]. Snapshots not captured in order yield none.
+pub fn rate(before: &Snapshot, after: &Snapshot) -> Vec<SeriesRate> {
+    let Some(seconds) = after.captured_at.duration_since(before.captured_at).ok().map(|d| d.as_secs_f64()) else {
+        return Vec::new();
+    };
+    if seconds <= 0_f64 {
+        return Vec::new();
+    }
+
+    increase(&before.families, &after.families)
+        .into_iter()
+        .map(|increase| SeriesRate {
+            family: increase.family,
+            labels: increase.labels,
+            rate: increase.increase / seconds,
+            reset: increase.reset,
+        })
+        .collect()
+}
+
+/// How much each counter, histogram and summary series present in both snapshots grew. Like
+/// Prometheus' `increase()`, a series that was reset in between is taken to have restarted from
+/// zero, so its increase is its later value. Series missing from either side are skipped.
+pub fn increase(before: &[MetricFamily], after: &[MetricFamily]) -> Vec<SeriesIncrease> {
+    let before_index: HashMap<&str, &MetricFamily> =
+        before.iter().map(|family| (family.name.as_str(), family)).collect();
+    let mut increases = Vec::new();
+    for family in after.iter() {
+        let Some(previous) = before_index.get(family.name.as_str()) else { continue };
+        let before_series: HashMap<LabelKey, &Metric> =
+            previous.metrics.iter().map(|metric| (label_key(metric.labels()), metric)).collect();
+        for metric in family.metrics.iter() {
+            let Some(previous) = before_series.get(&label_key(metric.labels())) else { continue };
+            let Some((value_after, value_before)) = counted(metric).zip(counted(previous)) else { continue };
+            let reset = is_reset(previous, metric);
+            increases.push(SeriesIncrease {
+                family: family.name.clone(),
+                labels: metric.labels().to_vec(),
+                increase: if reset { value_after } else { value_after - value_before },
+                reset,
+            });
+        }
+    }
+    increases
+}
+
+/// Why a series counts as stale in a later snapshot.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Staleness {
+    /// The series is no longer exported, which Prometheus marks with a staleness marker.
+    Missing,
+    /// The series is still exported with its own timestamp, but that timestamp did not advance.
+    NotUpdated,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StaleSeries {
+    pub family: String,
+    pub labels: Vec<MetricLabel>,
+    pub staleness: Staleness,
+}
+
+/// The series of `before` that went stale by `after`: no longer exported, or exported with an
+/// explicit timestamp that did not move forward.
+pub fn stale(before: &[MetricFamily], after: &[MetricFamily]) -> Vec<StaleSeries> {
+    let after_index: HashMap<&str, &MetricFamily> = after.iter().map(|family| (family.name.as_str(), family)).collect();
+    let mut stale = Vec::new();
+    for family in before.iter() {
+        let after_series: HashMap<LabelKey, &Metric> = after_index
+            .get(family.name.as_str())
+            .map(|later| later.metrics.iter().map(|metric| (label_key(metric.labels()), metric)).collect())
+            .unwrap_or_default();
+        for metric in family.metrics.iter() {
+            let staleness = match after_series.get(&label_key(metric.labels())) {
+                None => Staleness::Missing,
+                Some(later) => match metric.timestamp_ms().zip(later.timestamp_ms()) {
+                    Some((before_ms, after_ms)) if after_ms <= before_ms => Staleness::NotUpdated,
+                    _ => continue,
+                },
+            };
+            stale.push(StaleSeries {
+                family: family.name.clone(),
+                labels: metric.labels().to_vec(),
+                staleness,
+            });
+        }
+    }
+    stale
+}
+
+/// The monotonic quantity of a series: a counter's value or the observation count of a
+/// histogram or summary.
+pub(crate) fn counted(metric: &Metric) -> Option<f64> {
+    match metric {
+        Metric::Counter { value, .. } => *value,
+        Metric::Histogram { .. } | Metric::NativeHistogram { .. } | Metric::Summary { .. } => {
+            Some(metric.count() as f64)
+        },
+        _ => None,
+    }
+}
+
+/// Whether a monotonic series went backwards or was recreated between two snapshots.
+pub(crate) fn is_reset(before: &Metric, after: &Metric) -> bool {
+    let recreated = before.created().zip(after.created()).is_some_and(|(before, after)| before != after);
+    let decreased = counted(before).zip(counted(after)).is_some_and(|(before, after)| after < before);
+    recreated || decreased
+}
+
+fn diff_family(before: &MetricFamily, after: &MetricFamily, delta: &mut MetricDelta) {
+    let before_series: HashMap<LabelKey, &Metric> =
+        before.metrics.iter().map(|metric| (label_key(metric.labels()), metric)).collect();
+    let after_keys: Vec<LabelKey> = after.metrics.iter().map(|metric| label_key(metric.labels())).collect();
+
+    for (metric, key) in after.metrics.iter().zip(after_keys.iter()) {
+        let Some(previous) = before_series.get(key) else {
+            delta.added_series.push(series_id(after, metric));
+            continue;
+        };
+
+        let (value_before, value_after) = (previous.sum(), metric.sum());
+        let count_delta = metric.count() as i64 - previous.count() as i64;
+        let same_value = value_before == value_after || (value_before.is_nan() && value_after.is_nan());
+        let reset = is_reset(previous, metric);
+        if !same_value || count_delta != 0 || reset {
+            let series = SeriesDelta {
+                family: after.name.clone(),
+                labels: metric.labels().to_vec(),
+                before: value_before,
+                after: value_after,
+                delta: value_after - value_before,
+                count_delta,
+            };
+            if reset {
+                delta.resets.push(series);
+            } else {
+                delta.changed.push(series);
+            }
+        }
+    }
+
+    let after_keys: HashSet<LabelKey> = after_keys.into_iter().collect();
+    for metric in before.metrics.iter() {
+        if !after_keys.contains(&label_key(metric.labels())) {
+            delta.removed_series.push(series_id(before, metric));
+        }
+    }
+}
+
+fn series_id(family: &MetricFamily, metric: &Metric) -> SeriesId {
+    SeriesId {
+        family: family.name.clone(),
+        labels: metric.labels().to_vec(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{labels, MetricFamilyBuilder};
+
+    fn counters(name: &str, series: &[(&str, f64)]) -> MetricFamily {
+        series
+            .iter()
+            .fold(MetricFamily::builder(name), |builder, (code, value)| {
+                builder.counter(labels! {"code" => *code}, *value)
+            })
+            .build()
+    }
+
+    #[test]
+    fn pairs_series_by_family_and_labels() {
+        let before =
+            vec![counters("hits_total", &[("200", 1_f64), ("404", 2_f64), ("500", 3_f64)]), counters("old", &[])];
+        let after =
+            vec![counters("hits_total", &[("500", 3_f64), ("200", 4_f64), ("201", 1_f64)]), counters("new", &[])];
+        let delta = diff(&before, &after);
+
+        assert_eq!(delta.added_families, ["new"]);
+        assert_eq!(delta.removed_families, ["old"]);
+        assert_eq!(
+            delta.added_series,
+            [SeriesId {
+                family: "hits_total".to_string(),
+                labels: labels! {"code" => "201"}
+            }]
+        );
+        assert_eq!(
+            delta.removed_series,
+            [SeriesId {
+                family: "hits_total".to_string(),
+                labels: labels! {"code" => "404"}
+            }]
+        );
+        assert_eq!(delta.changed.len(), 1);
+        assert_eq!(delta.series("hits_total", &[("code", "200")]).map(|series| series.delta), Some(3_f64));
+        assert_eq!(delta.series("hits_total", &[("code", "500")]), None);
+        assert!(delta.resets.is_empty());
+        assert!(diff(&after, &after).is_empty());
+    }
+
+    #[test]
+    fn ignores_label_order() {
+        let before = vec![MetricFamily::builder("up").gauge(labels! {"a" => "1", "b" => "2"}, 1_f64).build()];
+        let after = vec![MetricFamily::builder("up").gauge(labels! {"b" => "2", "a" => "1"}, 0_f64).build()];
+        let delta = diff(&before, &after);
+        assert!(delta.added_series.is_empty() && delta.removed_series.is_empty());
+        assert_eq!(delta.series("up", &[("b", "2"), ("a", "1")]).map(|series| series.after), Some(0_f64));
+    }
+
+    #[test]
+    fn lists_and_finds_resets() {
+        let delta = diff(&[counters("hits_total", &[("200", 10_f64)])], &[counters("hits_total", &[("200", 2_f64)])]);
+        assert!(delta.changed.is_empty());
+        assert_eq!(delta.resets.len(), 1);
+        assert_eq!(delta.series("hits_total", &[("code", "200")]).map(|series| series.delta), Some(-8_f64));
+    }
+
+    #[test]
+    fn increases_restart_from_zero_after_a_reset() {
+        let before = [counters("hits_total", &[("200", 10_f64), ("500", 1_f64)])];
+        let after = [counters("hits_total", &[("200", 2_f64), ("500", 4_f64)])];
+        let increases: Vec<(f64, bool)> = increase(&before, &after).iter().map(|i| (i.increase, i.reset)).collect();
+        assert_eq!(increases, [(2_f64, true), (3_f64, false)]);
+    }
+
+    #[test]
+    fn rates_per_second_of_captured_time() {
+        let captured_at = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000);
+        let before = Snapshot {
+            captured_at,
+            families: vec![counters("hits_total", &[("200", 10_f64)])],
+        };
+        let after = Snapshot {
+            captured_at: captured_at + Duration::from_secs(4),
+            families: vec![counters("hits_total", &[("200", 30_f64)])],
+        };
+
+        let rates = rate(&before, &after);
+        assert_eq!(rates.iter().map(|rate| rate.rate).collect::<Vec<_>>(), [5_f64]);
+        assert!(rate(&after, &before).is_empty());
+        assert!(rate(&before, &before).is_empty());
+    }
+
+    #[test]
+    fn finds_stale_series() {
+        let series = |code: &str, timestamp_ms: i64| {
+            Metric::gauge(labels! {"code" => code}, 1_f64).with_timestamp_ms(timestamp_ms)
+        };
+        let family = |metrics: Vec<Metric>| {
+            metrics.into_iter().fold(MetricFamily::builder("up"), MetricFamilyBuilder::metric).build()
+        };
+        let before = [family(vec![series("a", 10), series("b", 10), series("c", 10)])];
+        let after = [family(vec![series("a", 20), series("b", 10)])];
+
+        let stale = stale(&before, &after);
+        let stale: Vec<(&str, Staleness)> = stale.iter().map(|s| (s.labels[0].value.as_str(), s.staleness)).collect();
+        assert_eq!(stale, [("b", Staleness::NotUpdated), ("c", Staleness::Missing)]);
+    }
+}
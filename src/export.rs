@@ -0,0 +1,368 @@
+//! Flat exports of distilled families for tools outside Rust.
+
+use std::borrow::Cow;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+
+use crate::validate::{is_valid_label_name, is_valid_metric_name};
+#[cfg(feature = "yaml")]
+pub use crate::yaml::{from_yaml, to_yaml, YamlError};
+use crate::{Metric, MetricFamily, MetricLabel};
+
+const CSV_HEADER: [&str; 7] = ["family", "type", "labels", "value", "count", "sum", "timestamp"];
+
+/// Writes one row per series, `family,type,labels,value,count,sum,timestamp`, under a header row.
+/// Labels are written as `name="value"` pairs joined by `,`; state sets get a row per state.
+pub fn to_csv(families: &[MetricFamily], writer: impl Write) -> io::Result<()> {
+    to_delimited(families, writer, b',')
+}
+
+/// Tab separated variant of [`to_csv`].
+pub fn to_tsv(families: &[MetricFamily], writer: impl Write) -> io::Result<()> {
+    to_delimited(families, writer, b'\t')
+}
+
+fn to_delimited(families: &[MetricFamily], mut writer: impl Write, delimiter: u8) -> io::Result<()> {
+    write_row(&mut writer, delimiter, &CSV_HEADER.map(String::from))?;
+    for family in families {
+        let kind = crate::json::kind_name(family.kind).to_string();
+        for metric in family.metrics.iter() {
+            let timestamp = metric.timestamp_ms().map(|ts| ts.to_string()).unwrap_or_default();
+            let row = |labels: &[MetricLabel], value: String, count: String, sum: String| {
+                [family.name.clone(), kind.clone(), format_labels(labels), value, count, sum, timestamp.clone()]
+            };
+
+            match metric {
+                Metric::Counter { labels, value, .. }
+                | Metric::Gauge { labels, value, .. }
+                | Metric::Untyped { labels, value, .. } => {
+                    let value = value.map(format_float).unwrap_or_default();
+                    write_row(&mut writer, delimiter, &row(labels, value, String::new(), String::new()))?
+                },
+                Metric::Histogram { labels, .. }
+                | Metric::GaugeHistogram { labels, .. }
+                | Metric::NativeHistogram { labels, .. }
+                | Metric::Summary { labels, .. } => {
+                    let (count, sum) = (metric.count().to_string(), format_float(metric.sum()));
+                    write_row(&mut writer, delimiter, &row(labels, String::new(), count, sum))?
+                },
+                Metric::Info { labels, .. } => {
+                    write_row(&mut writer, delimiter, &row(labels, "1".to_string(), String::new(), String::new()))?
+                },
+                Metric::StateSet { labels, states, .. } => {
+                    for (state, enabled) in states {
+                        let mut labels = labels.clone();
+                        labels.push(MetricLabel { name: family.name.clone(), value: state.clone() });
+                        let value = if *enabled { "1" } else { "0" }.to_string();
+                        write_row(&mut writer, delimiter, &row(&labels, value, String::new(), String::new()))?;
+                    }
+                },
+                Metric::UNSUPPORTED(_) => (),
+            }
+        }
+    }
+    writer.flush()
+}
+
+fn write_row(writer: &mut impl Write, delimiter: u8, fields: &[String]) -> io::Result<()> {
+    for (idx, field) in fields.iter().enumerate() {
+        if 0 < idx {
+            writer.write_all(&[delimiter])?;
+        }
+        let needs_quotes = field.bytes().any(|b| b == delimiter || b == b'"' || b == b'\n' || b == b'\r');
+        if needs_quotes {
+            write!(writer, "\"{}\"", field.replace('"', "\"\""))?;
+        } else {
+            writer.write_all(field.as_bytes())?;
+        }
+    }
+    writer.write_all(b"\n")
+}
+
+/// Formats a sample value the way the text exposition format spells it.
+pub(crate) fn format_float(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value.is_infinite() {
+        if value > 0_f64 { "+Inf" } else { "-Inf" }.to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+/// Formats labels as the comma separated `name="value"` pairs of the text exposition format.
+/// Names that are not valid legacy label names are quoted, as in Prometheus 3.
+pub(crate) fn format_labels(labels: &[MetricLabel]) -> String {
+    labels
+        .iter()
+        .map(|l| format!("{}=\"{}\"", format_name(&l.name, is_valid_label_name), MetricLabel::escape_value(&l.value)))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// A metric name for HELP, TYPE and UNIT lines, quoted unless it is a valid legacy name.
+pub(crate) fn format_metric_name(name: &str) -> Cow<'_, str> {
+    format_name(name, is_valid_metric_name)
+}
+
+/// Formats a sample's `name{labels}`, moving a name that is not a valid legacy metric name into
+/// the braces as a quoted string.
+pub(crate) fn format_series(name: &str, labels: &[MetricLabel]) -> String {
+    match (is_valid_metric_name(name), labels.is_empty()) {
+        (true, true) => name.to_string(),
+        (true, false) => format!("{name}{{{}}}", format_labels(labels)),
+        (false, true) => format!("{{{}}}", format_metric_name(name)),
+        (false, false) => format!("{{{},{}}}", format_metric_name(name), format_labels(labels)),
+    }
+}
+
+fn format_name(name: &str, is_valid: fn(&str) -> bool) -> Cow<'_, str> {
+    if is_valid(name) {
+        Cow::Borrowed(name)
+    } else {
+        Cow::Owned(format!("\"{}\"", MetricLabel::escape_value(name)))
+    }
+}
+
+/// Encodes families in the InfluxDB line protocol: one line per series, measured by family name
+/// with the labels as tags. Value series write a `value` field, histograms and summaries `count`,
+/// `sum` and a field per bucket bound or quantile, and state sets a boolean field per state.
+/// Values the protocol cannot carry (NaN, infinities) are left out.
+pub fn to_influx_line_protocol(families: &[MetricFamily]) -> String {
+    let mut out = String::new();
+    for family in families {
+        for metric in family.metrics.iter() {
+            let mut fields: Vec<(String, String)> = Vec::new();
+            match metric {
+                Metric::Counter { value, .. } | Metric::Gauge { value, .. } | Metric::Untyped { value, .. } => {
+                    if let Some(value) = value {
+                        push_float(&mut fields, "value".to_string(), *value);
+                    }
+                },
+                Metric::Histogram { buckets, .. }
+                | Metric::GaugeHistogram { buckets, .. }
+                | Metric::NativeHistogram { buckets, .. } => {
+                    fields.push(("count".to_string(), format!("{}i", metric.count())));
+                    push_float(&mut fields, "sum".to_string(), metric.sum());
+                    for bucket in buckets.iter() {
+                        fields.push((format_float(bucket.upper_bound), format!("{}i", bucket.cumulative_count)));
+                    }
+                },
+                Metric::Summary { quantiles, .. } => {
+                    fields.push(("count".to_string(), format!("{}i", metric.count())));
+                    push_float(&mut fields, "sum".to_string(), metric.sum());
+                    for q in quantiles.iter() {
+                        push_float(&mut fields, format_float(q.quantile), q.value);
+                    }
+                },
+                Metric::Info { .. } => fields.push(("value".to_string(), "1".to_string())),
+                Metric::StateSet { states, .. } => {
+                    fields.extend(states.iter().map(|(state, enabled)| (state.clone(), enabled.to_string())));
+                },
+                Metric::UNSUPPORTED(_) => (),
+            }
+            if fields.is_empty() {
+                continue;
+            }
+
+            out.push_str(&influx_escape(&family.name, &[',', ' ']));
+            let mut tags: Vec<MetricLabel> = metric.labels().iter().filter(|l| !l.value.is_empty()).cloned().collect();
+            tags.sort_by(|lhs, rhs| lhs.name.cmp(&rhs.name));
+            for tag in tags.iter() {
+                let _ = write!(
+                    out,
+                    ",{}={}",
+                    influx_escape(&tag.name, &[',', '=', ' ']),
+                    influx_escape(&tag.value, &[',', '=', ' '])
+                );
+            }
+            let fields: Vec<String> = fields
+                .iter()
+                .map(|(name, value)| format!("{}={value}", influx_escape(name, &[',', '=', ' '])))
+                .collect();
+            let _ = write!(out, " {}", fields.join(","));
+            if let Some(timestamp_ms) = metric.timestamp_ms() {
+                let _ = write!(out, " {}", i128::from(timestamp_ms) * 1_000_000);
+            }
+            out.push('\n');
+        }
+    }
+    out
+}
+
+fn push_float(fields: &mut Vec<(String, String)>, name: String, value: f64) {
+    if value.is_finite() {
+        fields.push((name, value.to_string()));
+    }
+}
+
+fn influx_escape(value: &str, special: &[char]) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if c == '\\' || special.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(if c == '\n' { ' ' } else { c });
+    }
+    escaped
+}
+
+/// Encodes families as Graphite plaintext `path value timestamp` lines.
+///
+/// Paths are `prefix.family` followed by `label.value` segments in label name order, with every
+/// character outside `[A-Za-z0-9_-]` replaced by `_` within a segment. Histograms and summaries
+/// add `count`, `sum` and `le.<bound>` / `quantile.<q>` leaves, and state sets one leaf per state.
+/// A series' own timestamp (in seconds) is used when it has one, otherwise `timestamp`.
+/// Non-finite values are left out.
+pub fn to_graphite(families: &[MetricFamily], prefix: &str, timestamp: i64) -> String {
+    let mut out = String::new();
+    for family in families {
+        for metric in family.metrics.iter() {
+            let mut labels = metric.labels().to_vec();
+            labels.sort_by(|lhs, rhs| lhs.name.cmp(&rhs.name));
+            let mut path: Vec<String> = prefix.split('.').filter(|s| !s.is_empty()).map(graphite_segment).collect();
+            path.push(graphite_segment(&family.name));
+            for label in labels.iter() {
+                path.push(graphite_segment(&label.name));
+                path.push(graphite_segment(&label.value));
+            }
+            let path = path.join(".");
+            let timestamp = metric.timestamp_ms().map(|ts| ts.div_euclid(1000)).unwrap_or(timestamp);
+
+            let mut line = |leaf: &[&str], value: f64| {
+                if value.is_finite() {
+                    let leaf: String = leaf.iter().map(|segment| format!(".{}", graphite_segment(segment))).collect();
+                    let _ = writeln!(out, "{path}{leaf} {value} {timestamp}");
+                }
+            };
+
+            match metric {
+                Metric::Counter { value, .. } | Metric::Gauge { value, .. } | Metric::Untyped { value, .. } => {
+                    if let Some(value) = value {
+                        line(&[], *value);
+                    }
+                },
+                Metric::Histogram { buckets, .. }
+                | Metric::GaugeHistogram { buckets, .. }
+                | Metric::NativeHistogram { buckets, .. } => {
+                    line(&["count"], metric.count() as f64);
+                    line(&["sum"], metric.sum());
+                    for bucket in buckets.iter() {
+                        line(
+                            &["le", format_float(bucket.upper_bound).trim_start_matches('+')],
+                            bucket.cumulative_count as f64,
+                        );
+                    }
+                },
+                Metric::Summary { quantiles, .. } => {
+                    line(&["count"], metric.count() as f64);
+                    line(&["sum"], metric.sum());
+                    for q in quantiles.iter() {
+                        line(&["quantile", &format_float(q.quantile)], q.value);
+                    }
+                },
+                Metric::Info { .. } => line(&[], 1_f64),
+                Metric::StateSet { states, .. } => {
+                    for (state, enabled) in states.iter() {
+                        line(&[state], if *enabled { 1_f64 } else { 0_f64 });
+                    }
+                },
+                Metric::UNSUPPORTED(_) => (),
+            }
+        }
+    }
+    out
+}
+
+fn graphite_segment(segment: &str) -> String {
+    segment.chars().map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '-' { c } else { '_' }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::labels;
+
+    fn families() -> Vec<MetricFamily> {
+        let mut requests = MetricFamily::builder("requests_total")
+            .counter(labels! {"path" => "/a,b", "agent" => "say \"hi\""}, 3_f64)
+            .counter(labels! {"path" => "/c d"}, 1.5)
+            .build();
+        requests.metrics[0] = requests.metrics[0].clone().with_timestamp_ms(1_000);
+        vec![
+            requests,
+            MetricFamily::builder("latency_seconds").histogram(vec![], &[(0.5, 1), (f64::INFINITY, 2)], 0.75).build(),
+            MetricFamily::builder("mode").state_set(vec![], &[("on", true), ("off", false)]).build(),
+        ]
+    }
+
+    fn csv(families: &[MetricFamily]) -> String {
+        let mut out = Vec::new();
+        to_csv(families, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn writes_csv_rows_quoting_delimiters_and_quotes() {
+        let expected = r#"family,type,labels,value,count,sum,timestamp
+requests_total,counter,"agent=""say \""hi\"""",path=""/a,b""",3,,,1000
+requests_total,counter,"path=""/c d""",1.5,,,
+latency_seconds,histogram,,,2,0.75,
+mode,state_set,"mode=""on""",1,,,
+mode,state_set,"mode=""off""",0,,,
+"#;
+        assert_eq!(csv(&families()), expected);
+    }
+
+    #[test]
+    fn writes_tsv_rows_quoting_only_what_tabs_require() {
+        let mut out = Vec::new();
+        to_tsv(&families()[1..2], &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "family\ttype\tlabels\tvalue\tcount\tsum\ttimestamp\nlatency_seconds\thistogram\t\t\t2\t0.75\t\n"
+        );
+
+        let family = MetricFamily::builder("up").gauge(labels! {"job" => "a\tb"}, 1_f64).build();
+        let mut out = Vec::new();
+        to_tsv(&[family], &mut out).unwrap();
+        assert!(String::from_utf8(out).unwrap().ends_with("up\tgauge\t\"job=\"\"a\tb\"\"\"\t1\t\t\t\n"));
+    }
+
+    #[test]
+    fn writes_influx_lines_escaping_tags_and_measurements() {
+        let expected = r#"requests_total,agent=say\ "hi",path=/a\,b value=3 1000000000
+requests_total,path=/c\ d value=1.5
+latency_seconds count=2i,sum=0.75,0.5=1i,+Inf=2i
+mode on=true,off=false
+"#;
+        assert_eq!(to_influx_line_protocol(&families()), expected);
+
+        let families = vec![
+            MetricFamily::builder("queue depth,max").gauge(labels! {"a=b" => "x", "empty" => ""}, 1_f64).build(),
+            MetricFamily::builder("ratio").gauge(vec![], f64::NAN).build(),
+        ];
+        assert_eq!(to_influx_line_protocol(&families), "queue\\ depth\\,max,a\\=b=x value=1\n");
+    }
+
+    #[test]
+    fn writes_graphite_paths_replacing_special_characters() {
+        let expected = "app.prod.requests_total.agent.say__hi_.path._a_b 3 1
+app.prod.requests_total.path._c_d 1.5 50
+app.prod.latency_seconds.count 2 50
+app.prod.latency_seconds.sum 0.75 50
+app.prod.latency_seconds.le.0_5 1 50
+app.prod.latency_seconds.le.Inf 2 50
+app.prod.mode.on 1 50
+app.prod.mode.off 0 50
+";
+        assert_eq!(to_graphite(&families(), "app.prod", 50), expected);
+
+        let families = vec![
+            MetricFamily::builder("rpc_seconds").summary(vec![], &[(0.99, f64::NAN)], 1, 0.5).build(),
+            MetricFamily::builder("up").gauge(vec![], 1_f64).build(),
+        ];
+        assert_eq!(to_graphite(&families, "", 50), "rpc_seconds.count 1 50\nrpc_seconds.sum 0.5 50\nup 1 50\n");
+    }
+}
@@ -0,0 +1,179 @@
+//! Handling of the target labels Prometheus attaches to series served from `/federate`.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::{label_key, LabelKey, MetricFamily, MetricLabel};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TargetLabels {
+    /// Keep target labels as exposed, as `honor_labels: true` would.
+    Keep,
+    /// Rename target labels to `exported_<name>`, as `honor_labels: false` would.
+    Rename,
+    /// Drop target labels; series that only differed by target collapse into the first seen.
+    Strip,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FederationOptions {
+    pub target_labels: TargetLabels,
+    pub label_names: Vec<String>,
+}
+
+impl Default for FederationOptions {
+    fn default() -> Self {
+        Self {
+            target_labels: TargetLabels::Keep,
+            label_names: vec!["instance".to_string(), "job".to_string()],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Target {
+    pub labels: Vec<MetricLabel>,
+    pub families: Vec<MetricFamily>,
+}
+
+pub fn apply(families: Vec<MetricFamily>, options: &FederationOptions) -> Vec<MetricFamily> {
+    let is_target = |label: &MetricLabel| options.label_names.iter().any(|name| name == &label.name);
+
+    families
+        .into_iter()
+        .map(|mut family| {
+            match options.target_labels {
+                TargetLabels::Keep => (),
+                TargetLabels::Rename => {
+                    for labels in family.metrics.iter_mut().filter_map(|m| m.labels_mut()) {
+                        for label in labels.iter_mut().filter(|l| is_target(l)) {
+                            label.name = format!("exported_{}", label.name);
+                        }
+                    }
+                },
+                TargetLabels::Strip => {
+                    let mut seen = HashSet::new();
+                    family.metrics.retain_mut(|m| match m.labels_mut() {
+                        Some(labels) => {
+                            labels.retain(|l| !is_target(l));
+                            seen.insert(label_key(labels))
+                        },
+                        None => true,
+                    });
+                },
+            }
+            family
+        })
+        .collect()
+}
+
+/// Splits federated families per target, identified by the values of the target labels, which
+/// are removed from the series. Targets are returned in the order they first appear.
+pub fn group_by_target(families: Vec<MetricFamily>, options: &FederationOptions) -> Vec<Target> {
+    let mut targets: Vec<Target> = Vec::new();
+    let mut positions: HashMap<LabelKey, usize> = HashMap::new();
+
+    for family in families {
+        let MetricFamily { name, help, unit, kind, metrics } = family;
+        for mut metric in metrics {
+            let target_labels: Vec<MetricLabel> = match metric.labels_mut() {
+                Some(labels) => {
+                    let (target, series): (Vec<MetricLabel>, Vec<MetricLabel>) =
+                        labels.drain(..).partition(|l| options.label_names.iter().any(|name| name == &l.name));
+                    *labels = series;
+                    target
+                },
+                None => Vec::new(),
+            };
+
+            let pos = *positions.entry(label_key(&target_labels)).or_insert_with(|| {
+                targets.push(Target { labels: target_labels, families: Vec::new() });
+                targets.len() - 1
+            });
+
+            let families = &mut targets[pos].families;
+            match families.iter_mut().find(|f| f.name == name) {
+                Some(family) => family.metrics.push(metric),
+                None => families.push(MetricFamily {
+                    name: name.clone(),
+                    help: help.clone(),
+                    unit: unit.clone(),
+                    kind,
+                    metrics: vec![metric],
+                }),
+            }
+        }
+    }
+
+    targets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Labels in the given order, unlike the sorted `labels!`.
+    fn labels(pairs: &[(&str, &str)]) -> Vec<MetricLabel> {
+        pairs.iter().map(|(name, value)| MetricLabel { name: name.to_string(), value: value.to_string() }).collect()
+    }
+
+    fn federated() -> Vec<MetricFamily> {
+        vec![MetricFamily::builder("up")
+            .gauge(labels(&[("job", "api"), ("instance", "a:80"), ("a", "1"), ("b", "2")]), 1_f64)
+            .gauge(labels(&[("b", "2"), ("a", "1"), ("instance", "b:80"), ("job", "api")]), 0_f64)
+            .gauge(labels(&[("job", "db"), ("instance", "c:80"), ("a", "3")]), 1_f64)
+            .build()]
+    }
+
+    fn options(target_labels: TargetLabels) -> FederationOptions {
+        FederationOptions { target_labels, ..FederationOptions::default() }
+    }
+
+    #[test]
+    fn keeps_target_labels() {
+        assert_eq!(apply(federated(), &options(TargetLabels::Keep)), federated());
+    }
+
+    #[test]
+    fn renames_target_labels() {
+        let families = apply(federated(), &options(TargetLabels::Rename));
+        assert_eq!(
+            families[0].metrics[2].labels(),
+            labels(&[("exported_job", "db"), ("exported_instance", "c:80"), ("a", "3")])
+        );
+    }
+
+    #[test]
+    fn strips_target_labels_collapsing_series_in_any_label_order() {
+        let families = apply(federated(), &options(TargetLabels::Strip));
+        let series: Vec<(Vec<MetricLabel>, Option<f64>)> =
+            families[0].metrics.iter().map(|metric| (metric.labels().to_vec(), metric.value())).collect();
+        assert_eq!(series, [(labels(&[("a", "1"), ("b", "2")]), Some(1_f64)), (labels(&[("a", "3")]), Some(1_f64))]);
+    }
+
+    #[test]
+    fn groups_series_by_target() {
+        let targets = group_by_target(federated(), &options(TargetLabels::Strip));
+        let target_labels: Vec<Vec<MetricLabel>> = targets.iter().map(|target| target.labels.clone()).collect();
+        assert_eq!(
+            target_labels,
+            [
+                labels(&[("job", "api"), ("instance", "a:80")]),
+                labels(&[("instance", "b:80"), ("job", "api")]),
+                labels(&[("job", "db"), ("instance", "c:80")]),
+            ]
+        );
+        assert!(targets.iter().all(|target| target.families[0].metrics.len() == 1));
+        assert_eq!(targets[1].families[0].metrics[0].labels(), labels(&[("b", "2"), ("a", "1")]));
+    }
+
+    #[test]
+    fn groups_targets_regardless_of_label_order() {
+        let families = vec![MetricFamily::builder("up")
+            .gauge(labels(&[("job", "api"), ("instance", "a:80")]), 1_f64)
+            .gauge(labels(&[("instance", "a:80"), ("job", "api"), ("a", "1")]), 1_f64)
+            .build()];
+        let targets = group_by_target(families, &FederationOptions::default());
+        assert_eq!(targets.len(), 1);
+        assert_eq!(targets[0].families[0].metrics.len(), 2);
+    }
+}
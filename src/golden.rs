@@ -0,0 +1,304 @@
+//! Golden-file comparison of metric state.
+//!
+//! A golden file holds a normalized state in the [`crate::json`] format. [`compare`] normalizes the
+//! saved and the actual state the same way and, on mismatch, reports a line diff of their
+//! [`render::snapshot_string`]s. Set `UPDATE_GOLDEN=1` to rewrite the golden files instead:
+//!
+//! ```no_run
+//! use inspect_prometheus::golden::{self, Normalize};
+//! use inspect_prometheus::matcher::NamePattern;
+//!
+//! let state = inspect_prometheus::MetricState::from(inspect_prometheus::distill_default_registry());
+//! let normalize = Normalize { ignore_families: vec![NamePattern::prefix("process_")], ..Normalize::default() };
+//! golden::compare("tests/golden/metrics.json", &state, &normalize).unwrap();
+//! ```
+
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+
+use crate::assert::Tolerance;
+use crate::matcher::NamePattern;
+use crate::state::LoadError;
+use crate::{json, render, Metric, MetricFamily, MetricState};
+
+/// The environment variable that, when set to anything but `0` or empty, makes [`compare`] write
+/// the actual state to the golden file instead of comparing against it.
+pub const UPDATE_ENV: &str = "UPDATE_GOLDEN";
+
+/// How many unchanged lines a diff shows around each change.
+const CONTEXT_LINES: usize = 2;
+
+/// The largest line diff table built, about 128 MiB; differences beyond it are listed as every
+/// expected line removed and every actual line added.
+const MAX_TABLE_CELLS: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, thiserror::Error)]
+pub enum GoldenError {
+    #[error("failed to load golden file {}: {source} (set {UPDATE_ENV}=1 to create it)", path.display())]
+    Load { path: PathBuf, source: LoadError },
+
+    #[error("failed to write golden file {}: {source}", path.display())]
+    Write { path: PathBuf, source: std::io::Error },
+
+    #[error("metrics do not match golden file {} (set {UPDATE_ENV}=1 to update it):\n{diff}", path.display())]
+    Mismatch { path: PathBuf, diff: String },
+}
+
+/// What to disregard on both sides of a comparison. Timestamps, created timestamps and exemplar
+/// timestamps are always disregarded.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Normalize {
+    /// Label names dropped from every series, e.g. `instance` or `pid`.
+    pub ignore_labels: Vec<String>,
+    /// Families left out entirely.
+    pub ignore_families: Vec<NamePattern>,
+    /// How far sample values may drift from the golden ones.
+    pub float_tolerance: Tolerance,
+}
+
+impl Normalize {
+    /// The families without ignored families and labels or timestamps, in canonical order.
+    pub fn apply(&self, families: &[MetricFamily]) -> Vec<MetricFamily> {
+        let mut families: Vec<MetricFamily> = families
+            .iter()
+            .filter(|family| !self.ignore_families.iter().any(|pattern| pattern.matches(&family.name)))
+            .cloned()
+            .collect();
+        for metric in families.iter_mut().flat_map(|family| family.metrics.iter_mut()) {
+            if let Some(labels) = metric.labels_mut() {
+                labels.retain(|label| !self.ignore_labels.contains(&label.name));
+            }
+            strip_timestamps(metric);
+        }
+        crate::normalize(&mut families);
+        families
+    }
+}
+
+/// Compares `state` against the golden file at `path` after normalizing both, or rewrites the
+/// file when [`UPDATE_ENV`] is set.
+pub fn compare(path: impl AsRef<Path>, state: &MetricState, normalize: &Normalize) -> Result<(), GoldenError> {
+    let path = path.as_ref();
+    let actual = normalize.apply(state.families());
+
+    if update_requested() {
+        let write = || -> std::io::Result<()> {
+            if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+                std::fs::create_dir_all(dir)?;
+            }
+            std::fs::write(path, json::to_json_pretty(&actual))
+        };
+        return write().map_err(|source| GoldenError::Write { path: path.to_path_buf(), source });
+    }
+
+    let golden =
+        MetricState::from_path(path).map_err(|source| GoldenError::Load { path: path.to_path_buf(), source })?;
+    let expected = normalize.apply(golden.families());
+
+    let (expected, actual) = (render::snapshot_string(&expected), render::snapshot_string(&actual));
+    match diff_lines(&expected, &actual, normalize.float_tolerance) {
+        None => Ok(()),
+        Some(diff) => Err(GoldenError::Mismatch { path: path.to_path_buf(), diff }),
+    }
+}
+
+fn update_requested() -> bool {
+    std::env::var_os(UPDATE_ENV).is_some_and(|value| !value.is_empty() && value != "0")
+}
+
+fn strip_timestamps(metric: &mut Metric) {
+    match metric {
+        Metric::Counter { created, timestamp_ms, exemplar, .. } => {
+            (*created, *timestamp_ms) = (None, None);
+            if let Some(exemplar) = exemplar {
+                exemplar.timestamp = None;
+            }
+        },
+        Metric::Histogram { created, timestamp_ms, buckets, .. }
+        | Metric::NativeHistogram { created, timestamp_ms, buckets, .. } => {
+            (*created, *timestamp_ms) = (None, None);
+            buckets.iter_mut().filter_map(|bucket| bucket.exemplar.as_mut()).for_each(|e| e.timestamp = None);
+        },
+        Metric::GaugeHistogram { timestamp_ms, buckets, .. } => {
+            *timestamp_ms = None;
+            buckets.iter_mut().filter_map(|bucket| bucket.exemplar.as_mut()).for_each(|e| e.timestamp = None);
+        },
+        Metric::Summary { created, timestamp_ms, .. } => {
+            (*created, *timestamp_ms) = (None, None);
+        },
+        Metric::Gauge { timestamp_ms, .. }
+        | Metric::Untyped { timestamp_ms, .. }
+        | Metric::Info { timestamp_ms, .. }
+        | Metric::StateSet { timestamp_ms, .. } => *timestamp_ms = None,
+        Metric::UNSUPPORTED(_) => (),
+    }
+}
+
+/// Whether two snapshot lines agree, their whitespace separated numbers within `tolerance`.
+fn lines_match(expected: &str, actual: &str, tolerance: Tolerance) -> bool {
+    if expected == actual {
+        return true;
+    }
+    let (expected, actual): (Vec<&str>, Vec<&str>) = (expected.split(' ').collect(), actual.split(' ').collect());
+    expected.len() == actual.len()
+        && expected.iter().zip(actual.iter()).all(|(expected, actual)| {
+            expected == actual
+                || match (parse_float(expected), parse_float(actual)) {
+                    (Some(expected), Some(actual)) => tolerance.matches(actual, expected),
+                    _ => false,
+                }
+        })
+}
+
+fn parse_float(token: &str) -> Option<f64> {
+    match token {
+        "+Inf" => Some(f64::INFINITY),
+        "-Inf" => Some(f64::NEG_INFINITY),
+        "NaN" => Some(f64::NAN),
+        token => token.parse().ok(),
+    }
+}
+
+/// A `-`/`+` line diff of `expected` against `actual` with a little context, or `None` when they
+/// match.
+fn diff_lines(expected: &str, actual: &str, tolerance: Tolerance) -> Option<String> {
+    let (expected, actual): (Vec<&str>, Vec<&str>) = (expected.lines().collect(), actual.lines().collect());
+
+    // only the lines between the common prefix and suffix need the quadratic table
+    let matching = |(expected, actual): (&&str, &&str)| lines_match(expected, actual, tolerance);
+    let prefix = expected.iter().zip(actual.iter()).take_while(|pair| matching(*pair)).count();
+    let suffix =
+        expected[prefix..].iter().rev().zip(actual[prefix..].iter().rev()).take_while(|pair| matching(*pair)).count();
+
+    let mut edits: Vec<(char, &str)> = actual[..prefix].iter().map(|line| (' ', *line)).collect();
+    edits.extend(edit_script(
+        &expected[prefix..expected.len() - suffix],
+        &actual[prefix..actual.len() - suffix],
+        tolerance,
+    ));
+    edits.extend(actual[actual.len() - suffix..].iter().map(|line| (' ', *line)));
+
+    let changed: Vec<usize> = edits.iter().enumerate().filter(|(_, (op, _))| *op != ' ').map(|(idx, _)| idx).collect();
+    if changed.is_empty() {
+        return None;
+    }
+
+    let mut near_change = vec![false; edits.len()];
+    for idx in changed {
+        let context = idx.saturating_sub(CONTEXT_LINES)..(idx + CONTEXT_LINES + 1).min(edits.len());
+        near_change[context].fill(true);
+    }
+
+    let mut out = String::new();
+    let mut shown_until = 0;
+    for (idx, (op, line)) in edits.iter().enumerate() {
+        if !near_change[idx] {
+            continue;
+        }
+        if shown_until < idx {
+            out.push_str("...\n");
+        }
+        let _ = writeln!(out, "{op} {line}");
+        shown_until = idx + 1;
+    }
+    if shown_until < edits.len() {
+        out.push_str("...\n");
+    }
+    Some(out)
+}
+
+/// The edits turning `expected` into `actual`, from their longest common subsequence unless its
+/// table would exceed [`MAX_TABLE_CELLS`], in which case every line is replaced.
+fn edit_script<'a>(expected: &[&'a str], actual: &[&'a str], tolerance: Tolerance) -> Vec<(char, &'a str)> {
+    if MAX_TABLE_CELLS < (expected.len() + 1).saturating_mul(actual.len() + 1) {
+        let removed = expected.iter().map(|line| ('-', *line));
+        return removed.chain(actual.iter().map(|line| ('+', *line))).collect();
+    }
+
+    // longest common subsequence, from the end so the edit script can be read off front to back
+    let mut common = vec![vec![0_usize; actual.len() + 1]; expected.len() + 1];
+    for i in (0..expected.len()).rev() {
+        for j in (0..actual.len()).rev() {
+            common[i][j] = if lines_match(expected[i], actual[j], tolerance) {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let mut edits = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < expected.len() || j < actual.len() {
+        if i < expected.len() && j < actual.len() && lines_match(expected[i], actual[j], tolerance) {
+            edits.push((' ', actual[j]));
+            (i, j) = (i + 1, j + 1);
+        } else if i < expected.len() && (actual.len() <= j || common[i][j + 1] <= common[i + 1][j]) {
+            edits.push(('-', expected[i]));
+            i += 1;
+        } else {
+            edits.push(('+', actual[j]));
+            j += 1;
+        }
+    }
+    edits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_within_tolerance() {
+        let (expected, actual) = ("a 1\nb 2.0\n", "a 1\nb 2.000001\n");
+        assert_eq!(diff_lines(expected, expected, Tolerance::Exact), None);
+        assert!(diff_lines(expected, actual, Tolerance::Exact).is_some());
+        assert_eq!(diff_lines(expected, actual, Tolerance::Relative(1e-3)), None);
+    }
+
+    #[test]
+    fn shows_changes_with_context() {
+        let expected: String = (0..20).map(|n| format!("line {n}\n")).collect();
+        let actual = expected.replace("line 10\n", "line ten\n").replace("line 15\n", "");
+        let diff = diff_lines(&expected, &actual, Tolerance::Exact).unwrap();
+        assert_eq!(
+            diff,
+            "...\n  line 8\n  line 9\n- line 10\n+ line ten\n  line 11\n  line 12\n  line 13\n  line 14\n- line 15\n  line 16\n  line 17\n...\n"
+        );
+    }
+
+    #[test]
+    fn diffs_large_snapshots_without_a_full_table() {
+        let expected: String = (0..100_000).map(|n| format!("series{n} {n}\n")).collect();
+        let actual = expected.replace("series500 500\n", "series500 501\n");
+        let diff = diff_lines(&expected, &actual, Tolerance::Exact).unwrap();
+        assert_eq!(diff.lines().filter(|line| line.starts_with(['-', '+'])).count(), 2);
+
+        let unrelated: String = (0..5_000).map(|n| format!("other{n} {n}\n")).collect();
+        let diff = diff_lines(&expected, &unrelated, Tolerance::Exact).unwrap();
+        assert_eq!(diff.lines().filter(|line| line.starts_with(['-', '+'])).count(), 105_000);
+    }
+
+    #[test]
+    fn normalizes_ignored_families_labels_and_timestamps() {
+        let family = |name: &str, instance: &str| {
+            MetricFamily::builder(name)
+                .metric(
+                    Metric::counter(crate::labels! {"code" => "200", "instance" => instance}, 1_f64)
+                        .with_timestamp_ms(5),
+                )
+                .kind(crate::MetricKind::Counter)
+                .build()
+        };
+        let normalize = Normalize {
+            ignore_labels: vec!["instance".to_string()],
+            ignore_families: vec![NamePattern::prefix("process_")],
+            ..Normalize::default()
+        };
+        let lhs = normalize.apply(&[family("hits_total", "a"), family("process_cpu", "a")]);
+        let rhs = normalize.apply(&[family("hits_total", "b")]);
+        assert_eq!(lhs, rhs);
+        assert_eq!(lhs[0].metrics[0].timestamp_ms(), None);
+        assert_eq!(lhs[0].metrics[0].labels(), crate::labels! {"code" => "200"});
+    }
+}
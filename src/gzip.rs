@@ -0,0 +1,371 @@
+//! Gzip (RFC 1952) decoding over a small DEFLATE (RFC 1951) inflater, for compressed scrape
+//! responses and `.gz` exposition dumps.
+
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+#[error("{0}")]
+pub(crate) struct GzipError(&'static str);
+
+const MAGIC: [u8; 2] = [0x1f, 0x8b];
+const DEFLATE: u8 = 8;
+
+const FLAG_HCRC: u8 = 0x02;
+const FLAG_EXTRA: u8 = 0x04;
+const FLAG_NAME: u8 = 0x08;
+const FLAG_COMMENT: u8 = 0x10;
+
+/// Decompresses every gzip member in `bytes`, concatenating their contents.
+pub(crate) fn decompress(bytes: &[u8]) -> Result<Vec<u8>, GzipError> {
+    let mut output = Vec::new();
+    let mut pos = 0;
+    while pos < bytes.len() {
+        pos = member(bytes, pos, &mut output)?;
+    }
+    Ok(output)
+}
+
+fn member(bytes: &[u8], start: usize, output: &mut Vec<u8>) -> Result<usize, GzipError> {
+    let header = bytes.get(start..start + 10).ok_or(GzipError("truncated gzip header"))?;
+    if header[..2] != MAGIC {
+        return Err(GzipError("missing gzip magic bytes"));
+    }
+    if header[2] != DEFLATE {
+        return Err(GzipError("unsupported gzip compression method"));
+    }
+
+    let flags = header[3];
+    let mut pos = start + 10;
+    if flags & FLAG_EXTRA != 0 {
+        let extra = bytes.get(pos..pos + 2).ok_or(GzipError("truncated gzip header"))?;
+        pos += 2 + usize::from(u16::from_le_bytes([extra[0], extra[1]]));
+    }
+    for flag in [FLAG_NAME, FLAG_COMMENT] {
+        if flags & flag != 0 {
+            let terminator = bytes.get(pos..).and_then(|rest| rest.iter().position(|b| *b == 0));
+            pos += terminator.ok_or(GzipError("truncated gzip header"))? + 1;
+        }
+    }
+    if flags & FLAG_HCRC != 0 {
+        pos += 2;
+    }
+
+    let body = bytes.get(pos..).ok_or(GzipError("truncated gzip header"))?;
+    let offset = output.len();
+    let consumed = Inflater::new(body, output).inflate()?;
+    pos += consumed;
+
+    let trailer = bytes.get(pos..pos + 8).ok_or(GzipError("truncated gzip trailer"))?;
+    let crc = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+    let size = u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]);
+    let inflated = &output[offset..];
+    if size != inflated.len() as u32 {
+        return Err(GzipError("gzip size check failed"));
+    }
+    if crc != crc32(inflated) {
+        return Err(GzipError("gzip checksum failed"));
+    }
+    Ok(pos + 8)
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let crc = bytes.iter().fold(!0_u32, |crc, byte| {
+        (0..8).fold(crc ^ u32::from(*byte), |crc, _| if crc & 1 == 1 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 })
+    });
+    !crc
+}
+
+const MAX_BITS: usize = 15;
+
+const LENGTH_BASE: [u16; 29] =
+    [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+    8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] =
+    [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+/// Canonical Huffman code, decoded one bit at a time from the per-length code counts.
+struct Huffman {
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0_u16; MAX_BITS + 1];
+        for length in lengths {
+            counts[usize::from(*length)] += 1;
+        }
+        counts[0] = 0;
+
+        let mut offsets = [0_u16; MAX_BITS + 1];
+        for len in 1..MAX_BITS {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0_u16; lengths.len()];
+        for (symbol, length) in lengths.iter().enumerate().filter(|(_, length)| **length != 0) {
+            let offset = &mut offsets[usize::from(*length)];
+            symbols[usize::from(*offset)] = symbol as u16;
+            *offset += 1;
+        }
+        Self { counts, symbols }
+    }
+}
+
+struct Inflater<'a> {
+    input: &'a [u8],
+    pos: usize,
+    bit_buffer: u32,
+    bit_count: u32,
+    output: &'a mut Vec<u8>,
+    start: usize,
+}
+
+impl<'a> Inflater<'a> {
+    fn new(input: &'a [u8], output: &'a mut Vec<u8>) -> Self {
+        let start = output.len();
+        Self { input, pos: 0, bit_buffer: 0, bit_count: 0, output, start }
+    }
+
+    /// Inflates the DEFLATE stream, returning the number of input bytes consumed.
+    fn inflate(mut self) -> Result<usize, GzipError> {
+        loop {
+            let last = self.bits(1)? == 1;
+            match self.bits(2)? {
+                0 => self.stored()?,
+                1 => self.fixed()?,
+                2 => self.dynamic()?,
+                _ => return Err(GzipError("invalid deflate block type")),
+            }
+            if last {
+                return Ok(self.pos);
+            }
+        }
+    }
+
+    fn bits(&mut self, count: u32) -> Result<u32, GzipError> {
+        while self.bit_count < count {
+            let byte = *self.input.get(self.pos).ok_or(GzipError("truncated deflate stream"))?;
+            self.pos += 1;
+            self.bit_buffer |= u32::from(byte) << self.bit_count;
+            self.bit_count += 8;
+        }
+        let value = self.bit_buffer & ((1_u32 << count) - 1);
+        self.bit_buffer >>= count;
+        self.bit_count -= count;
+        Ok(value)
+    }
+
+    fn stored(&mut self) -> Result<(), GzipError> {
+        self.bit_buffer = 0;
+        self.bit_count = 0;
+
+        let header = self.input.get(self.pos..self.pos + 4).ok_or(GzipError("truncated stored block"))?;
+        let length = u16::from_le_bytes([header[0], header[1]]);
+        if length != !u16::from_le_bytes([header[2], header[3]]) {
+            return Err(GzipError("stored block length check failed"));
+        }
+        self.pos += 4;
+
+        let end = self.pos + usize::from(length);
+        let block = self.input.get(self.pos..end).ok_or(GzipError("truncated stored block"))?;
+        self.output.extend_from_slice(block);
+        self.pos = end;
+        Ok(())
+    }
+
+    fn fixed(&mut self) -> Result<(), GzipError> {
+        let mut lengths = [0_u8; 288 + 30];
+        lengths[..144].fill(8);
+        lengths[144..256].fill(9);
+        lengths[256..280].fill(7);
+        lengths[280..288].fill(8);
+        lengths[288..].fill(5);
+        self.codes(&Huffman::new(&lengths[..288]), &Huffman::new(&lengths[288..]))
+    }
+
+    fn dynamic(&mut self) -> Result<(), GzipError> {
+        let literal_count = self.bits(5)? as usize + 257;
+        let distance_count = self.bits(5)? as usize + 1;
+        let code_length_count = self.bits(4)? as usize + 4;
+
+        let mut code_lengths = [0_u8; 19];
+        for idx in CODE_LENGTH_ORDER.iter().take(code_length_count) {
+            code_lengths[*idx] = self.bits(3)? as u8;
+        }
+        let code_length_code = Huffman::new(&code_lengths);
+
+        let mut lengths = Vec::with_capacity(literal_count + distance_count);
+        while lengths.len() < literal_count + distance_count {
+            let (value, repeat) = match self.decode(&code_length_code)? {
+                symbol @ 0..=15 => (symbol as u8, 1),
+                16 => {
+                    let previous = *lengths.last().ok_or(GzipError("repeat with no previous code length"))?;
+                    (previous, 3 + self.bits(2)?)
+                },
+                17 => (0, 3 + self.bits(3)?),
+                _ => (0, 11 + self.bits(7)?),
+            };
+            lengths.extend(std::iter::repeat_n(value, repeat as usize));
+        }
+        if lengths.len() != literal_count + distance_count {
+            return Err(GzipError("code lengths overflow the dynamic block header"));
+        }
+
+        let literal_code = Huffman::new(&lengths[..literal_count]);
+        let distance_code = Huffman::new(&lengths[literal_count..]);
+        self.codes(&literal_code, &distance_code)
+    }
+
+    fn decode(&mut self, huffman: &Huffman) -> Result<u16, GzipError> {
+        let (mut code, mut first, mut index) = (0_i32, 0_i32, 0_i32);
+        for len in 1..=MAX_BITS {
+            code |= self.bits(1)? as i32;
+            let count = i32::from(huffman.counts[len]);
+            if code - first < count {
+                return Ok(huffman.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(GzipError("invalid huffman code"))
+    }
+
+    fn codes(&mut self, literal_code: &Huffman, distance_code: &Huffman) -> Result<(), GzipError> {
+        loop {
+            let symbol = self.decode(literal_code)?;
+            match symbol {
+                0..=255 => self.output.push(symbol as u8),
+                256 => return Ok(()),
+                _ => {
+                    let idx = usize::from(symbol - 257);
+                    let base = *LENGTH_BASE.get(idx).ok_or(GzipError("invalid length symbol"))?;
+                    let length = usize::from(base) + self.bits(u32::from(LENGTH_EXTRA[idx]))? as usize;
+
+                    let idx = usize::from(self.decode(distance_code)?);
+                    let base = *DISTANCE_BASE.get(idx).ok_or(GzipError("invalid distance symbol"))?;
+                    let distance = usize::from(base) + self.bits(u32::from(DISTANCE_EXTRA[idx]))? as usize;
+
+                    if distance > self.output.len() - self.start {
+                        return Err(GzipError("distance reaches before the start of the stream"));
+                    }
+                    let from = self.output.len() - distance;
+                    for offset in 0..length {
+                        let byte = self.output[from + offset];
+                        self.output.push(byte);
+                    }
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `up 1\nup 1\nup 1\n` as gzip writes it, in a fixed huffman block with back references.
+    const FIXED: [u8; 27] =
+        [0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 2, 3, 43, 45, 80, 48, 228, 42, 133, 19, 0, 47, 184, 200, 36, 15, 0, 0, 0];
+
+    /// [`exposition`] as gzip writes it, in a dynamic huffman block.
+    const DYNAMIC: [&str; 8] = [
+        "1f8b080000000000020355d43d4e44310c04e09e53204ee0ff2405674148ac04ed6e89b83b0fa164c6ed68b3f9ecd8ef",
+        "71bb7fdd1e6ff2fdf87cbf7fbcbec8cbcfb33c3dfe53dda95ea9eed4766a571a3bf59dfa95ae9dc64ee3ef1f6ac7b9e3",
+        "bc62cb1dd78eeb8afdfc7a302ece7f4fd6d5812ce6cda35661a00a8ad466349c30566a9c1bd4d9a975441a2cd5752ad0",
+        "64aba1622dd65ae2c460ae4ddc3199eb06d562ae17de4b981ba8dcb4353670c2981bf3dc61cedcb4a3b2606e8e538725",
+        "730b955b31b7e8c460eea03b267307a9167327ea7061ee42e5ae6d5ad12bb7360c82f6bab76910bc88471b07c5237ab6",
+        "79a077f76aab6518151f6c56c774f96c131c18485f4d9d98e190a62eb420b40f313625aca907562bbca9277631a2a917",
+        "9637b20db2a00751ac36c5e72146fb3e98e09ec96a73816db1da424e3d296df9123d486ddb5774c69a7ae09ef4a69eb0",
+        "6534f5a2cf5db60f1bf5208bd5aee85b0e565f7b8e7b26abddf13eb958ed71bde92f1b4b3feee0050000",
+    ];
+
+    fn exposition() -> String {
+        (0..60).map(|i| format!("series_{i}{{shard=\"{}\"}} {}\n", i % 7, i * i)).collect()
+    }
+
+    fn hex(chunks: &[&str]) -> Vec<u8> {
+        let hex = chunks.concat();
+        (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect()
+    }
+
+    /// A gzip member holding `data` in a single stored block, with optional header fields set.
+    fn stored(data: &[u8], flags: u8) -> Vec<u8> {
+        let mut bytes = vec![0x1f, 0x8b, DEFLATE, flags, 0, 0, 0, 0, 0, 255];
+        if flags & FLAG_EXTRA != 0 {
+            bytes.extend([3, 0, b'a', b'b', b'c']);
+        }
+        if flags & FLAG_NAME != 0 {
+            bytes.extend(b"metrics.txt\0");
+        }
+        if flags & FLAG_COMMENT != 0 {
+            bytes.extend(b"a comment\0");
+        }
+        if flags & FLAG_HCRC != 0 {
+            bytes.extend([0, 0]);
+        }
+        let len = data.len() as u16;
+        bytes.push(1);
+        bytes.extend(len.to_le_bytes());
+        bytes.extend((!len).to_le_bytes());
+        bytes.extend(data);
+        bytes.extend(crc32(data).to_le_bytes());
+        bytes.extend((data.len() as u32).to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn decompresses_fixed_huffman_blocks() {
+        assert_eq!(decompress(&FIXED).unwrap(), b"up 1\nup 1\nup 1\n");
+    }
+
+    #[test]
+    fn decompresses_dynamic_huffman_blocks() {
+        assert_eq!(decompress(&hex(&DYNAMIC)).unwrap(), exposition().as_bytes());
+    }
+
+    #[test]
+    fn decompresses_stored_blocks_behind_optional_header_fields() {
+        let flags = FLAG_EXTRA | FLAG_NAME | FLAG_COMMENT | FLAG_HCRC;
+        assert_eq!(decompress(&stored(b"up 1\n", 0)).unwrap(), b"up 1\n");
+        assert_eq!(decompress(&stored(b"up 1\n", flags)).unwrap(), b"up 1\n");
+        assert_eq!(decompress(&stored(b"", 0)).unwrap(), b"");
+    }
+
+    #[test]
+    fn concatenates_members() {
+        let mut bytes = FIXED.to_vec();
+        bytes.extend(stored(b"down 0\n", 0));
+        assert_eq!(decompress(&bytes).unwrap(), b"up 1\nup 1\nup 1\ndown 0\n");
+    }
+
+    #[test]
+    fn checks_the_trailer() {
+        let mut corrupt = FIXED;
+        corrupt[19] ^= 1;
+        assert_eq!(decompress(&corrupt), Err(GzipError("gzip checksum failed")));
+        let mut resized = FIXED;
+        resized[23] += 1;
+        assert_eq!(decompress(&resized), Err(GzipError("gzip size check failed")));
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        assert_eq!(decompress(b"up 1\n"), Err(GzipError("truncated gzip header")));
+        assert_eq!(decompress(b"up 1 and more\n"), Err(GzipError("missing gzip magic bytes")));
+        let mut method = FIXED;
+        method[2] = 7;
+        assert_eq!(decompress(&method), Err(GzipError("unsupported gzip compression method")));
+        assert!(decompress(&FIXED[..16]).is_err());
+        assert_eq!(decompress(&FIXED[..22]), Err(GzipError("truncated gzip trailer")));
+        let mut block_type = FIXED;
+        block_type[10] |= 0b110;
+        assert_eq!(decompress(&block_type), Err(GzipError("invalid deflate block type")));
+        let mut stored_len = stored(b"up 1\n", 0);
+        stored_len[13] ^= 1;
+        assert_eq!(decompress(&stored_len), Err(GzipError("stored block length check failed")));
+    }
+}
@@ -0,0 +1,161 @@
+//! Minimal blocking HTTP/1.1 client over `std::net`, sufficient for scraping plain `http://`
+//! metrics endpoints without pulling an HTTP stack into test builds.
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use crate::scrape::ScrapeError;
+
+pub(crate) const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Url {
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+}
+
+impl Url {
+    pub(crate) fn parse(url: &str) -> Result<Self, ScrapeError> {
+        let invalid = |reason: &str| ScrapeError::InvalidUrl(format!("{}: {}", url, reason));
+
+        let rest = match url.split_once("://") {
+            Some(("http", rest)) => rest,
+            Some((scheme, _)) => return Err(invalid(&format!("unsupported scheme {}", scheme))),
+            None => return Err(invalid("missing http:// scheme")),
+        };
+
+        let (authority, path) = match rest.find(['/', '?']) {
+            Some(pos) => (&rest[..pos], &rest[pos..]),
+            None => (rest, "/"),
+        };
+        let path = if path.starts_with('?') { format!("/{}", path) } else { path.to_string() };
+
+        let port_sep = match authority.rfind(']') {
+            Some(bracket) => authority[bracket..].find(':').map(|pos| bracket + pos),
+            None => authority.rfind(':'),
+        };
+        let (host, port) = match port_sep {
+            Some(pos) => (&authority[..pos], authority[pos + 1..].parse::<u16>().map_err(|_| invalid("invalid port"))?),
+            None => (authority, 80),
+        };
+        if host.is_empty() {
+            return Err(invalid("missing host"));
+        }
+
+        Ok(Self { host: host.to_string(), port, path })
+    }
+
+    fn host_header(&self) -> String {
+        if self.port == 80 {
+            self.host.clone()
+        } else {
+            format!("{}:{}", self.host, self.port)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub(crate) fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str())
+    }
+}
+
+pub(crate) fn get(url: &str, headers: &[(&str, &str)]) -> Result<Response, ScrapeError> {
+    request("GET", url, headers, &[])
+}
+
+pub(crate) fn request(method: &str, url: &str, headers: &[(&str, &str)], body: &[u8]) -> Result<Response, ScrapeError> {
+    let url = Url::parse(url)?;
+
+    let addr = (url.host.trim_start_matches('[').trim_end_matches(']'), url.port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| ScrapeError::InvalidUrl(format!("could not resolve host {}", url.host)))?;
+    let mut stream = TcpStream::connect_timeout(&addr, DEFAULT_TIMEOUT)?;
+    stream.set_read_timeout(Some(DEFAULT_TIMEOUT))?;
+    stream.set_write_timeout(Some(DEFAULT_TIMEOUT))?;
+
+    let mut head = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: inspect_prometheus/{}\r\nConnection: close\r\n",
+        method,
+        url.path,
+        url.host_header(),
+        env!("CARGO_PKG_VERSION")
+    );
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    if !body.is_empty() || method != "GET" {
+        head.push_str(&format!("Content-Length: {}\r\n", body.len()));
+    }
+    head.push_str("\r\n");
+
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(body)?;
+    stream.flush()?;
+
+    read_response(BufReader::new(stream))
+}
+
+fn read_response(mut reader: impl BufRead) -> Result<Response, ScrapeError> {
+    let malformed = |reason: &str| ScrapeError::Http(reason.to_string());
+
+    let status_line = read_line(&mut reader)?;
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse::<u16>().ok())
+        .ok_or_else(|| malformed("malformed status line"))?;
+
+    let mut headers = Vec::new();
+    loop {
+        let line = read_line(&mut reader)?;
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = line.split_once(':').ok_or_else(|| malformed("malformed header line"))?;
+        headers.push((name.trim().to_string(), value.trim().to_string()));
+    }
+
+    let header = |name: &str| headers.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str());
+    let mut body = Vec::new();
+    if header("Transfer-Encoding").map(|te| te.eq_ignore_ascii_case("chunked")).unwrap_or(false) {
+        loop {
+            let size = read_line(&mut reader)?;
+            let size = size.split(';').next().unwrap_or_default().trim();
+            let size = usize::from_str_radix(size, 16).map_err(|_| malformed("malformed chunk size"))?;
+            if size == 0 {
+                break;
+            }
+            let start = body.len();
+            body.resize(start + size, 0);
+            reader.read_exact(&mut body[start..])?;
+            read_line(&mut reader)?;
+        }
+    } else if let Some(length) = header("Content-Length") {
+        let length = length.parse::<usize>().map_err(|_| malformed("malformed Content-Length"))?;
+        body.resize(length, 0);
+        reader.read_exact(&mut body)?;
+    } else {
+        reader.read_to_end(&mut body)?;
+    }
+
+    Ok(Response { status, headers, body })
+}
+
+fn read_line(reader: &mut impl BufRead) -> Result<String, ScrapeError> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(ScrapeError::Http("connection closed before the response completed".to_string()));
+    }
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
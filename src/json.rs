@@ -0,0 +1,895 @@
+//! JSON export and import of distilled families, for tools outside Rust that consume inspection
+//! output.
+//!
+//! The document is an array of families:
+//!
+//! ```json
+//! [
+//!   {
+//!     "name": "http_requests",
+//!     "help": "Requests served",
+//!     "unit": null,
+//!     "kind": "counter",
+//!     "metrics": [
+//!       {
+//!         "type": "counter",
+//!         "labels": { "code": "200" },
+//!         "value": 7,
+//!         "exemplar": null,
+//!         "created": null,
+//!         "timestamp_ms": null
+//!       }
+//!     ]
+//!   }
+//! ]
+//! ```
+//!
+//! - `kind` is one of `counter`, `gauge`, `histogram`, `gauge_histogram`, `summary`, `untyped`, `info` or `state_set`.
+//! - Each metric is tagged by `type`, which is the family kind or `native_histogram` / `unsupported`, and carries the
+//!   fields of the matching [`Metric`] variant under the same names.
+//! - Labels (including exemplar labels) and state set `states` are objects keyed by name, in exposition order.
+//! - Buckets are `{ "upper_bound", "cumulative_count", "exemplar" }`, quantiles `{ "quantile", "value" }`, native
+//!   histogram spans `{ "offset", "length" }` and exemplars `{ "labels", "value", "timestamp" }`.
+//! - Floats that JSON cannot represent are written as the strings `"NaN"`, `"+Inf"` and `"-Inf"`.
+//! - `unsupported` metrics carry the original `metric_type` (`"COUNTER"`, `"GAUGE"`, ...).
+//!
+//! On import, missing optional fields read as `null`, missing label objects and lists as empty, and unknown
+//! fields are ignored.
+
+use prometheus::proto::MetricType;
+
+use crate::{BucketSpan, Exemplar, HistogramBucket, Metric, MetricFamily, MetricKind, MetricLabel, Quantile};
+
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum JsonError {
+    #[error("invalid JSON at byte {offset}: {message}")]
+    Syntax { offset: usize, message: String },
+
+    #[error("JSON does not match the metric family schema: {0}")]
+    Schema(String),
+}
+
+pub fn to_json_pretty(families: &[MetricFamily]) -> String {
+    let document = Value::Array(families.iter().map(encode_family).collect());
+    let mut json = String::new();
+    write_pretty(&document, 0, &mut json);
+    json.push('\n');
+    json
+}
+
+pub fn from_json(json: &str) -> Result<Vec<MetricFamily>, JsonError> {
+    match parse_document(json)? {
+        Value::Array(families) => families.iter().map(decode_family).collect(),
+        _ => Err(schema("document must be an array of families")),
+    }
+}
+
+/// How deeply arrays and objects may nest. The family schema needs 8 levels; without a limit, a
+/// document of nothing but `[`s would overflow the stack.
+const MAX_DEPTH: usize = 64;
+
+pub(crate) fn parse_document(json: &str) -> Result<Value, JsonError> {
+    Parser { bytes: json.as_bytes(), pos: 0, depth: 0 }.document()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Value {
+    Null,
+    Bool(bool),
+    Number(String),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+pub(crate) fn kind_name(kind: MetricKind) -> &'static str {
+    match kind {
+        MetricKind::Counter => "counter",
+        MetricKind::Gauge => "gauge",
+        MetricKind::Histogram => "histogram",
+        MetricKind::GaugeHistogram => "gauge_histogram",
+        MetricKind::Summary => "summary",
+        MetricKind::Untyped => "untyped",
+        MetricKind::Info => "info",
+        MetricKind::StateSet => "state_set",
+    }
+}
+
+fn kind_from_name(name: &str) -> Option<MetricKind> {
+    [
+        MetricKind::Counter,
+        MetricKind::Gauge,
+        MetricKind::Histogram,
+        MetricKind::GaugeHistogram,
+        MetricKind::Summary,
+        MetricKind::Untyped,
+        MetricKind::Info,
+        MetricKind::StateSet,
+    ]
+    .into_iter()
+    .find(|kind| kind_name(*kind) == name)
+}
+
+fn metric_type_from_name(name: &str) -> Option<MetricType> {
+    [MetricType::COUNTER, MetricType::GAUGE, MetricType::SUMMARY, MetricType::UNTYPED, MetricType::HISTOGRAM]
+        .into_iter()
+        .find(|metric_type| format!("{metric_type:?}") == name)
+}
+
+pub(crate) fn encode_family(family: &MetricFamily) -> Value {
+    Value::Object(vec![
+        ("name".to_string(), Value::String(family.name.clone())),
+        ("help".to_string(), family.help.clone().map(Value::String).unwrap_or(Value::Null)),
+        ("unit".to_string(), family.unit.clone().map(Value::String).unwrap_or(Value::Null)),
+        ("kind".to_string(), Value::String(kind_name(family.kind).to_string())),
+        ("metrics".to_string(), Value::Array(family.metrics.iter().map(encode_metric).collect())),
+    ])
+}
+
+fn encode_metric(metric: &Metric) -> Value {
+    let tagged = |tag: &str, fields: Vec<(&str, Value)>| {
+        let mut object = vec![("type".to_string(), Value::String(tag.to_string()))];
+        object.extend(fields.into_iter().map(|(name, value)| (name.to_string(), value)));
+        Value::Object(object)
+    };
+
+    match metric {
+        Metric::Counter { labels, value, exemplar, created, timestamp_ms } => tagged(
+            "counter",
+            vec![
+                ("labels", encode_labels(labels)),
+                ("value", optional(*value, float)),
+                ("exemplar", optional(exemplar.as_ref(), encode_exemplar)),
+                ("created", optional(*created, float)),
+                ("timestamp_ms", optional(*timestamp_ms, integer)),
+            ],
+        ),
+        Metric::Gauge { labels, value, timestamp_ms } => tagged(
+            "gauge",
+            vec![
+                ("labels", encode_labels(labels)),
+                ("value", optional(*value, float)),
+                ("timestamp_ms", optional(*timestamp_ms, integer)),
+            ],
+        ),
+        Metric::Histogram {
+            labels,
+            sample_count,
+            sample_sum,
+            buckets,
+            created,
+            timestamp_ms,
+        } => tagged(
+            "histogram",
+            vec![
+                ("labels", encode_labels(labels)),
+                ("sample_count", optional(*sample_count, integer)),
+                ("sample_sum", optional(*sample_sum, float)),
+                ("buckets", Value::Array(buckets.iter().map(encode_bucket).collect())),
+                ("created", optional(*created, float)),
+                ("timestamp_ms", optional(*timestamp_ms, integer)),
+            ],
+        ),
+        Metric::GaugeHistogram { labels, sample_count, sample_sum, buckets, timestamp_ms } => tagged(
+            "gauge_histogram",
+            vec![
+                ("labels", encode_labels(labels)),
+                ("sample_count", optional(*sample_count, integer)),
+                ("sample_sum", optional(*sample_sum, float)),
+                ("buckets", Value::Array(buckets.iter().map(encode_bucket).collect())),
+                ("timestamp_ms", optional(*timestamp_ms, integer)),
+            ],
+        ),
+        Metric::NativeHistogram {
+            labels,
+            sample_count,
+            sample_sum,
+            schema,
+            zero_threshold,
+            zero_count,
+            positive_spans,
+            positive_deltas,
+            negative_spans,
+            negative_deltas,
+            buckets,
+            created,
+            timestamp_ms,
+        } => tagged(
+            "native_histogram",
+            vec![
+                ("labels", encode_labels(labels)),
+                ("sample_count", optional(*sample_count, integer)),
+                ("sample_sum", optional(*sample_sum, float)),
+                ("schema", integer(*schema)),
+                ("zero_threshold", float(*zero_threshold)),
+                ("zero_count", integer(*zero_count)),
+                ("positive_spans", Value::Array(positive_spans.iter().map(encode_span).collect())),
+                ("positive_deltas", Value::Array(positive_deltas.iter().map(integer).collect())),
+                ("negative_spans", Value::Array(negative_spans.iter().map(encode_span).collect())),
+                ("negative_deltas", Value::Array(negative_deltas.iter().map(integer).collect())),
+                ("buckets", Value::Array(buckets.iter().map(encode_bucket).collect())),
+                ("created", optional(*created, float)),
+                ("timestamp_ms", optional(*timestamp_ms, integer)),
+            ],
+        ),
+        Metric::Summary {
+            labels,
+            sample_count,
+            sample_sum,
+            quantiles,
+            created,
+            timestamp_ms,
+        } => tagged(
+            "summary",
+            vec![
+                ("labels", encode_labels(labels)),
+                ("sample_count", optional(*sample_count, integer)),
+                ("sample_sum", optional(*sample_sum, float)),
+                (
+                    "quantiles",
+                    Value::Array(
+                        quantiles
+                            .iter()
+                            .map(|q| {
+                                Value::Object(vec![
+                                    ("quantile".to_string(), float(q.quantile)),
+                                    ("value".to_string(), float(q.value)),
+                                ])
+                            })
+                            .collect(),
+                    ),
+                ),
+                ("created", optional(*created, float)),
+                ("timestamp_ms", optional(*timestamp_ms, integer)),
+            ],
+        ),
+        Metric::Untyped { labels, value, timestamp_ms } => tagged(
+            "untyped",
+            vec![
+                ("labels", encode_labels(labels)),
+                ("value", optional(*value, float)),
+                ("timestamp_ms", optional(*timestamp_ms, integer)),
+            ],
+        ),
+        Metric::Info { labels, timestamp_ms } => {
+            tagged("info", vec![("labels", encode_labels(labels)), ("timestamp_ms", optional(*timestamp_ms, integer))])
+        },
+        Metric::StateSet { labels, states, timestamp_ms } => tagged(
+            "state_set",
+            vec![
+                ("labels", encode_labels(labels)),
+                (
+                    "states",
+                    Value::Object(
+                        states.iter().map(|(state, enabled)| (state.clone(), Value::Bool(*enabled))).collect(),
+                    ),
+                ),
+                ("timestamp_ms", optional(*timestamp_ms, integer)),
+            ],
+        ),
+        Metric::UNSUPPORTED(metric_type) => {
+            tagged("unsupported", vec![("metric_type", Value::String(format!("{metric_type:?}")))])
+        },
+    }
+}
+
+fn encode_labels(labels: &[MetricLabel]) -> Value {
+    Value::Object(labels.iter().map(|l| (l.name.clone(), Value::String(l.value.clone()))).collect())
+}
+
+fn encode_exemplar(exemplar: &Exemplar) -> Value {
+    Value::Object(vec![
+        ("labels".to_string(), encode_labels(&exemplar.labels)),
+        ("value".to_string(), float(exemplar.value)),
+        ("timestamp".to_string(), optional(exemplar.timestamp, float)),
+    ])
+}
+
+fn encode_bucket(bucket: &HistogramBucket) -> Value {
+    Value::Object(vec![
+        ("upper_bound".to_string(), float(bucket.upper_bound)),
+        ("cumulative_count".to_string(), integer(bucket.cumulative_count)),
+        ("exemplar".to_string(), optional(bucket.exemplar.as_ref(), encode_exemplar)),
+    ])
+}
+
+fn encode_span(span: &BucketSpan) -> Value {
+    Value::Object(vec![("offset".to_string(), integer(span.offset)), ("length".to_string(), integer(span.length))])
+}
+
+fn optional<T>(value: Option<T>, encode: impl FnOnce(T) -> Value) -> Value {
+    value.map(encode).unwrap_or(Value::Null)
+}
+
+fn float(value: f64) -> Value {
+    if value.is_nan() {
+        Value::String("NaN".to_string())
+    } else if value.is_infinite() {
+        Value::String(if value > 0_f64 { "+Inf" } else { "-Inf" }.to_string())
+    } else {
+        Value::Number(value.to_string())
+    }
+}
+
+fn integer(value: impl ToString) -> Value {
+    Value::Number(value.to_string())
+}
+
+fn write_pretty(value: &Value, indent: usize, out: &mut String) {
+    let pad = |out: &mut String, indent: usize| out.extend(std::iter::repeat_n("  ", indent));
+    match value {
+        Value::Null => out.push_str("null"),
+        Value::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+        Value::Number(n) => out.push_str(n),
+        Value::String(s) => write_string(s, out),
+        Value::Array(items) if items.is_empty() => out.push_str("[]"),
+        Value::Object(fields) if fields.is_empty() => out.push_str("{}"),
+        Value::Array(items) => {
+            out.push_str("[\n");
+            for (idx, item) in items.iter().enumerate() {
+                pad(out, indent + 1);
+                write_pretty(item, indent + 1, out);
+                out.push_str(if idx + 1 < items.len() { ",\n" } else { "\n" });
+            }
+            pad(out, indent);
+            out.push(']');
+        },
+        Value::Object(fields) => {
+            out.push_str("{\n");
+            for (idx, (name, field)) in fields.iter().enumerate() {
+                pad(out, indent + 1);
+                write_string(name, out);
+                out.push_str(": ");
+                write_pretty(field, indent + 1, out);
+                out.push_str(if idx + 1 < fields.len() { ",\n" } else { "\n" });
+            }
+            pad(out, indent);
+            out.push('}');
+        },
+    }
+}
+
+pub(crate) fn write_string(s: &str, out: &mut String) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn schema(message: impl Into<String>) -> JsonError {
+    JsonError::Schema(message.into())
+}
+
+/// Field access on a decoded JSON object.
+struct Fields<'a>(&'a [(String, Value)]);
+
+impl<'a> Fields<'a> {
+    fn of(value: &'a Value, what: &str) -> Result<Self, JsonError> {
+        match value {
+            Value::Object(fields) => Ok(Self(fields)),
+            _ => Err(schema(format!("{what} must be an object"))),
+        }
+    }
+
+    fn get(&self, name: &str) -> Option<&'a Value> {
+        self.0.iter().find(|(field, _)| field == name).map(|(_, value)| value).filter(|v| **v != Value::Null)
+    }
+
+    fn string(&self, name: &str) -> Result<String, JsonError> {
+        self.opt_string(name)?.ok_or_else(|| schema(format!("missing `{name}`")))
+    }
+
+    fn opt_string(&self, name: &str) -> Result<Option<String>, JsonError> {
+        match self.get(name) {
+            None => Ok(None),
+            Some(Value::String(s)) => Ok(Some(s.clone())),
+            Some(_) => Err(schema(format!("`{name}` must be a string"))),
+        }
+    }
+
+    fn float(&self, name: &str) -> Result<f64, JsonError> {
+        self.opt_float(name)?.ok_or_else(|| schema(format!("missing `{name}`")))
+    }
+
+    fn opt_float(&self, name: &str) -> Result<Option<f64>, JsonError> {
+        let parsed = match self.get(name) {
+            None => return Ok(None),
+            Some(Value::Number(n)) => n.parse::<f64>().ok(),
+            Some(Value::String(s)) => crate::parse::parse_float(s).ok().filter(|v| !v.is_finite()),
+            Some(_) => None,
+        };
+        parsed.map(Some).ok_or_else(|| schema(format!("`{name}` must be a number")))
+    }
+
+    fn integer<T: TryFrom<i128>>(&self, name: &str) -> Result<T, JsonError> {
+        self.opt_integer(name)?.ok_or_else(|| schema(format!("missing `{name}`")))
+    }
+
+    fn opt_integer<T: TryFrom<i128>>(&self, name: &str) -> Result<Option<T>, JsonError> {
+        match self.get(name) {
+            None => Ok(None),
+            Some(value) => {
+                integer_from(value).map(Some).ok_or_else(|| schema(format!("`{name}` is not a valid integer")))
+            },
+        }
+    }
+
+    fn array(&self, name: &str) -> Result<&'a [Value], JsonError> {
+        match self.get(name) {
+            None => Ok(&[]),
+            Some(Value::Array(items)) => Ok(items),
+            Some(_) => Err(schema(format!("`{name}` must be an array"))),
+        }
+    }
+
+    fn object(&self, name: &str) -> Result<&'a [(String, Value)], JsonError> {
+        match self.get(name) {
+            None => Ok(&[]),
+            Some(Value::Object(fields)) => Ok(fields),
+            Some(_) => Err(schema(format!("`{name}` must be an object"))),
+        }
+    }
+
+    fn labels(&self, name: &str) -> Result<Vec<MetricLabel>, JsonError> {
+        self.object(name)?
+            .iter()
+            .map(|(label, value)| match value {
+                Value::String(value) => Ok(MetricLabel { name: label.clone(), value: value.clone() }),
+                _ => Err(schema(format!("label `{label}` must have a string value"))),
+            })
+            .collect()
+    }
+
+    fn exemplar(&self, name: &str) -> Result<Option<Exemplar>, JsonError> {
+        self.get(name)
+            .map(|value| {
+                let fields = Fields::of(value, "exemplar")?;
+                Ok(Exemplar {
+                    labels: fields.labels("labels")?,
+                    value: fields.float("value")?,
+                    timestamp: fields.opt_float("timestamp")?,
+                })
+            })
+            .transpose()
+    }
+}
+
+fn integer_from<T: TryFrom<i128>>(value: &Value) -> Option<T> {
+    let Value::Number(n) = value else { return None };
+    let wide = n.parse::<i128>().ok().or_else(|| {
+        let float = n.parse::<f64>().ok().filter(|f| f.fract() == 0_f64)?;
+        Some(float as i128)
+    })?;
+    T::try_from(wide).ok()
+}
+
+pub(crate) fn decode_family(value: &Value) -> Result<MetricFamily, JsonError> {
+    let fields = Fields::of(value, "family")?;
+    let name = fields.string("name")?;
+    let kind = fields.string("kind")?;
+    Ok(MetricFamily {
+        name,
+        help: fields.opt_string("help")?,
+        unit: fields.opt_string("unit")?,
+        kind: kind_from_name(&kind).ok_or_else(|| schema(format!("unknown family kind `{kind}`")))?,
+        metrics: fields.array("metrics")?.iter().map(decode_metric).collect::<Result<_, _>>()?,
+    })
+}
+
+fn decode_metric(value: &Value) -> Result<Metric, JsonError> {
+    let f = Fields::of(value, "metric")?;
+    let metric_type = f.string("type")?;
+    let metric = match metric_type.as_str() {
+        "counter" => Metric::Counter {
+            labels: f.labels("labels")?,
+            value: f.opt_float("value")?,
+            exemplar: f.exemplar("exemplar")?,
+            created: f.opt_float("created")?,
+            timestamp_ms: f.opt_integer("timestamp_ms")?,
+        },
+        "gauge" => Metric::Gauge {
+            labels: f.labels("labels")?,
+            value: f.opt_float("value")?,
+            timestamp_ms: f.opt_integer("timestamp_ms")?,
+        },
+        "histogram" => Metric::Histogram {
+            labels: f.labels("labels")?,
+            sample_count: f.opt_integer("sample_count")?,
+            sample_sum: f.opt_float("sample_sum")?,
+            buckets: decode_buckets(&f)?,
+            created: f.opt_float("created")?,
+            timestamp_ms: f.opt_integer("timestamp_ms")?,
+        },
+        "gauge_histogram" => Metric::GaugeHistogram {
+            labels: f.labels("labels")?,
+            sample_count: f.opt_integer("sample_count")?,
+            sample_sum: f.opt_float("sample_sum")?,
+            buckets: decode_buckets(&f)?,
+            timestamp_ms: f.opt_integer("timestamp_ms")?,
+        },
+        "native_histogram" => Metric::NativeHistogram {
+            labels: f.labels("labels")?,
+            sample_count: f.opt_integer("sample_count")?,
+            sample_sum: f.opt_float("sample_sum")?,
+            schema: f.integer("schema")?,
+            zero_threshold: f.opt_float("zero_threshold")?.unwrap_or_default(),
+            zero_count: f.opt_integer("zero_count")?.unwrap_or_default(),
+            positive_spans: decode_spans(&f, "positive_spans")?,
+            positive_deltas: decode_deltas(&f, "positive_deltas")?,
+            negative_spans: decode_spans(&f, "negative_spans")?,
+            negative_deltas: decode_deltas(&f, "negative_deltas")?,
+            buckets: decode_buckets(&f)?,
+            created: f.opt_float("created")?,
+            timestamp_ms: f.opt_integer("timestamp_ms")?,
+        },
+        "summary" => Metric::Summary {
+            labels: f.labels("labels")?,
+            sample_count: f.opt_integer("sample_count")?,
+            sample_sum: f.opt_float("sample_sum")?,
+            quantiles: f
+                .array("quantiles")?
+                .iter()
+                .map(|q| {
+                    let q = Fields::of(q, "quantile")?;
+                    Ok(Quantile { quantile: q.float("quantile")?, value: q.float("value")? })
+                })
+                .collect::<Result<_, JsonError>>()?,
+            created: f.opt_float("created")?,
+            timestamp_ms: f.opt_integer("timestamp_ms")?,
+        },
+        "untyped" => Metric::Untyped {
+            labels: f.labels("labels")?,
+            value: f.opt_float("value")?,
+            timestamp_ms: f.opt_integer("timestamp_ms")?,
+        },
+        "info" => Metric::Info {
+            labels: f.labels("labels")?,
+            timestamp_ms: f.opt_integer("timestamp_ms")?,
+        },
+        "state_set" => Metric::StateSet {
+            labels: f.labels("labels")?,
+            states: f
+                .object("states")?
+                .iter()
+                .map(|(state, enabled)| match enabled {
+                    Value::Bool(enabled) => Ok((state.clone(), *enabled)),
+                    _ => Err(schema(format!("state `{state}` must be a boolean"))),
+                })
+                .collect::<Result<_, _>>()?,
+            timestamp_ms: f.opt_integer("timestamp_ms")?,
+        },
+        "unsupported" => {
+            let name = f.string("metric_type")?;
+            Metric::UNSUPPORTED(
+                metric_type_from_name(&name).ok_or_else(|| schema(format!("unknown metric_type `{name}`")))?,
+            )
+        },
+        other => return Err(schema(format!("unknown metric type `{other}`"))),
+    };
+    Ok(metric)
+}
+
+fn decode_buckets(fields: &Fields) -> Result<Vec<HistogramBucket>, JsonError> {
+    fields
+        .array("buckets")?
+        .iter()
+        .map(|b| {
+            let b = Fields::of(b, "bucket")?;
+            Ok(HistogramBucket {
+                upper_bound: b.float("upper_bound")?,
+                cumulative_count: b.integer("cumulative_count")?,
+                exemplar: b.exemplar("exemplar")?,
+            })
+        })
+        .collect()
+}
+
+fn decode_spans(fields: &Fields, name: &str) -> Result<Vec<BucketSpan>, JsonError> {
+    fields
+        .array(name)?
+        .iter()
+        .map(|s| {
+            let s = Fields::of(s, "span")?;
+            Ok(BucketSpan { offset: s.integer("offset")?, length: s.integer("length")? })
+        })
+        .collect()
+}
+
+fn decode_deltas(fields: &Fields, name: &str) -> Result<Vec<i64>, JsonError> {
+    fields
+        .array(name)?
+        .iter()
+        .map(|delta| integer_from(delta).ok_or_else(|| schema(format!("`{name}` must hold integers"))))
+        .collect()
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn document(mut self) -> Result<Value, JsonError> {
+        let value = self.value()?;
+        self.skip_whitespace();
+        if self.pos < self.bytes.len() {
+            return Err(self.error("trailing characters after the document"));
+        }
+        Ok(value)
+    }
+
+    fn error(&self, message: &str) -> JsonError {
+        JsonError::Syntax { offset: self.pos, message: message.to_string() }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.bytes.get(self.pos), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.pos += 1;
+        }
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        self.skip_whitespace();
+        let matched = self.bytes.get(self.pos) == Some(&byte);
+        if matched {
+            self.pos += 1;
+        }
+        matched
+    }
+
+    fn expect(&mut self, byte: u8, message: &str) -> Result<(), JsonError> {
+        if self.eat(byte) {
+            Ok(())
+        } else {
+            Err(self.error(message))
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, JsonError> {
+        self.skip_whitespace();
+        match self.bytes.get(self.pos) {
+            Some(b'{' | b'[') if MAX_DEPTH <= self.depth => Err(self.error("nesting is too deep")),
+            Some(b'{') => self.nested(Self::object),
+            Some(b'[') => self.nested(Self::array),
+            Some(b'"') => self.string().map(Value::String),
+            Some(b't') => self.literal("true", Value::Bool(true)),
+            Some(b'f') => self.literal("false", Value::Bool(false)),
+            Some(b'n') => self.literal("null", Value::Null),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(_) => Err(self.error("expected a value")),
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    fn nested(&mut self, parse: fn(&mut Self) -> Result<Value, JsonError>) -> Result<Value, JsonError> {
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn literal(&mut self, literal: &str, value: Value) -> Result<Value, JsonError> {
+        if self.bytes[self.pos..].starts_with(literal.as_bytes()) {
+            self.pos += literal.len();
+            Ok(value)
+        } else {
+            Err(self.error("expected a value"))
+        }
+    }
+
+    fn number(&mut self) -> Result<Value, JsonError> {
+        let start = self.pos;
+        while matches!(self.bytes.get(self.pos), Some(b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9')) {
+            self.pos += 1;
+        }
+        let text = std::str::from_utf8(&self.bytes[start..self.pos]).unwrap_or_default();
+        if text.parse::<f64>().is_err() || text.starts_with('+') {
+            self.pos = start;
+            return Err(self.error("invalid number"));
+        }
+        Ok(Value::Number(text.to_string()))
+    }
+
+    fn array(&mut self) -> Result<Value, JsonError> {
+        self.pos += 1;
+        let mut items = Vec::new();
+        if self.eat(b']') {
+            return Ok(Value::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            if self.eat(b']') {
+                return Ok(Value::Array(items));
+            }
+            self.expect(b',', "expected `,` or `]`")?;
+        }
+    }
+
+    fn object(&mut self) -> Result<Value, JsonError> {
+        self.pos += 1;
+        let mut fields = Vec::new();
+        if self.eat(b'}') {
+            return Ok(Value::Object(fields));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.bytes.get(self.pos) != Some(&b'"') {
+                return Err(self.error("expected a field name"));
+            }
+            let name = self.string()?;
+            self.expect(b':', "expected `:`")?;
+            fields.push((name, self.value()?));
+            if self.eat(b'}') {
+                return Ok(Value::Object(fields));
+            }
+            self.expect(b',', "expected `,` or `}`")?;
+        }
+    }
+
+    fn string(&mut self) -> Result<String, JsonError> {
+        self.pos += 1;
+        let mut bytes = Vec::new();
+        loop {
+            let byte = *self.bytes.get(self.pos).ok_or_else(|| self.error("unterminated string"))?;
+            self.pos += 1;
+            match byte {
+                b'"' => break,
+                b'\\' => {
+                    let escape = *self.bytes.get(self.pos).ok_or_else(|| self.error("unterminated string"))?;
+                    self.pos += 1;
+                    let c = match escape {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => self.unicode_escape()?,
+                        _ => return Err(self.error("invalid escape sequence")),
+                    };
+                    bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                },
+                byte if byte < 0x20 => return Err(self.error("control character in string")),
+                byte => bytes.push(byte),
+            }
+        }
+        // the input is a &str and strings are only split at ASCII quotes and escapes
+        String::from_utf8(bytes).map_err(|_| self.error("invalid UTF-8 in string"))
+    }
+
+    fn unicode_escape(&mut self) -> Result<char, JsonError> {
+        let high = self.hex4()?;
+        let code = if (0xd800..0xdc00).contains(&high) {
+            if !self.bytes[self.pos..].starts_with(b"\\u") {
+                return Err(self.error("unpaired surrogate in string"));
+            }
+            self.pos += 2;
+            let low = self.hex4()?;
+            if !(0xdc00..0xe000).contains(&low) {
+                return Err(self.error("unpaired surrogate in string"));
+            }
+            0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)
+        } else {
+            high
+        };
+        char::from_u32(code).ok_or_else(|| self.error("invalid unicode escape"))
+    }
+
+    fn hex4(&mut self) -> Result<u32, JsonError> {
+        let digits = self.bytes.get(self.pos..self.pos + 4).ok_or_else(|| self.error("truncated unicode escape"))?;
+        let code = std::str::from_utf8(digits)
+            .ok()
+            .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+            .ok_or_else(|| self.error("invalid unicode escape"))?;
+        self.pos += 4;
+        Ok(code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::labels;
+
+    fn families() -> Vec<MetricFamily> {
+        vec![
+            MetricFamily::builder("requests_total")
+                .help("Requests \"served\"\n")
+                .counter(labels! {"code" => "200", "path" => "/ü\\"}, 7_f64)
+                .counter(labels! {"code" => "500"}, f64::NAN)
+                .build(),
+            MetricFamily::builder("temperature_celsius").unit("celsius").gauge(vec![], f64::NEG_INFINITY).build(),
+            MetricFamily::builder("latency_seconds")
+                .histogram(labels! {"route" => "a"}, &[(0.1, 2), (1_f64, 5), (f64::INFINITY, 6)], 2.5)
+                .build(),
+            MetricFamily::builder("rpc_seconds").summary(vec![], &[(0.5, 0.2), (0.99, 0.9)], 10, 4_f64).build(),
+            MetricFamily::builder("build").info(labels! {"version" => "1.2.3"}).build(),
+            MetricFamily::builder("mode").state_set(vec![], &[("on", true), ("off", false)]).build(),
+            MetricFamily::builder("native_seconds")
+                .metric(Metric::NativeHistogram {
+                    labels: vec![],
+                    sample_count: Some(4),
+                    sample_sum: Some(1.5),
+                    schema: 3,
+                    zero_threshold: 1e-128,
+                    zero_count: 1,
+                    positive_spans: vec![BucketSpan { offset: -2, length: 2 }],
+                    positive_deltas: vec![2, -1],
+                    negative_spans: vec![],
+                    negative_deltas: vec![],
+                    buckets: vec![HistogramBucket { upper_bound: 1_f64, cumulative_count: 3, exemplar: None }],
+                    created: Some(1_700_000_000.5),
+                    timestamp_ms: Some(1_700_000_001_000),
+                })
+                .kind(MetricKind::Histogram)
+                .build(),
+        ]
+    }
+
+    #[test]
+    fn round_trips_every_kind() {
+        let families = families();
+        let json = to_json_pretty(&families);
+        let decoded = from_json(&json).unwrap();
+        assert_eq!(decoded, families);
+        assert_eq!(to_json_pretty(&decoded), json);
+    }
+
+    #[test]
+    fn writes_the_documented_shape() {
+        let family = MetricFamily::builder("up").gauge(vec![], 1_f64).build();
+        let json = to_json_pretty(&[family]);
+        assert!(json.contains(r#""kind": "gauge""#), "{json}");
+        assert!(json.contains(r#""type": "gauge""#), "{json}");
+        assert!(json.contains(r#""value": 1"#), "{json}");
+    }
+
+    #[test]
+    fn parses_escapes_and_surrogate_pairs() {
+        let value = parse_document(r#"{"s": "a\"b\\c\/\nü😀"}"#).unwrap();
+        assert_eq!(value, Value::Object(vec![("s".to_string(), Value::String("a\"b\\c/\nü😀".to_string()))]));
+    }
+
+    #[test]
+    fn reports_syntax_errors_with_their_offset() {
+        let error = |json: &str| match parse_document(json) {
+            Err(JsonError::Syntax { offset, message }) => (offset, message),
+            other => panic!("expected a syntax error for {json:?}, got {other:?}"),
+        };
+        assert_eq!(error("[1,]").0, 3);
+        assert_eq!(error(r#"{"a" 1}"#).1, "expected `:`");
+        assert_eq!(error("[1] 2").1, "trailing characters after the document");
+        assert_eq!(error(r#""\ud83d""#).1, "unpaired surrogate in string");
+        assert_eq!(error("+1").1, "expected a value");
+        assert!(parse_document("\"\u{1}\"").is_err());
+    }
+
+    #[test]
+    fn limits_nesting() {
+        let nested = |depth: usize| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+        assert!(parse_document(&nested(MAX_DEPTH)).is_ok());
+        assert!(matches!(parse_document(&nested(MAX_DEPTH + 1)), Err(JsonError::Syntax { .. })));
+        assert!(matches!(from_json(&"[".repeat(200_000)), Err(JsonError::Syntax { .. })));
+        assert!(matches!(from_json(&r#"{"a":"#.repeat(200_000)), Err(JsonError::Syntax { .. })));
+    }
+
+    #[test]
+    fn rejects_documents_outside_the_schema() {
+        assert!(matches!(from_json("{}"), Err(JsonError::Schema(_))));
+        assert!(matches!(from_json(r#"[{"name": "up"}]"#), Err(JsonError::Schema(_))));
+        let unknown_kind = r#"[{"name": "up", "help": null, "unit": null, "kind": "meter", "metrics": []}]"#;
+        assert!(matches!(from_json(unknown_kind), Err(JsonError::Schema(_))));
+    }
+}
@@ -1,9 +1,68 @@
-use std::convert::Infallible;
+mod aggregate;
+pub mod assert;
+#[cfg(feature = "scrape-async")]
+mod blocking;
+mod builder;
+pub mod cardinality;
+pub mod client;
+mod convert;
+mod diff;
+pub mod export;
+pub mod federation;
+pub mod golden;
+#[cfg(feature = "gzip")]
+mod gzip;
+#[cfg(feature = "scrape")]
+mod http;
+pub mod json;
+pub mod lint;
+pub mod matcher;
+pub mod metrics_exporter;
+mod order;
+pub mod otel;
+#[cfg(feature = "otlp")]
+pub mod otlp;
+pub mod parse;
+mod proto_ext;
+pub mod proto_fixtures;
+pub mod query;
+pub mod record;
+mod regex;
+pub mod remote_write;
+pub mod render;
+pub mod report;
+#[cfg(feature = "scrape")]
+pub mod scrape;
+mod snappy;
+mod state;
+pub mod validate;
+pub mod watch;
+#[cfg(feature = "yaml")]
+mod yaml;
+
+pub use aggregate::Agg;
+pub use builder::MetricFamilyBuilder;
+pub use convert::{to_proto_families, ConvertError};
+pub use diff::{
+    diff, increase, rate, stale, MetricDelta, SeriesDelta, SeriesId, SeriesIncrease, SeriesRate, Snapshot, StaleSeries,
+    Staleness,
+};
+pub use state::{LoadError, MetricState, Sample};
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
 use std::str::FromStr;
 
-#[derive(Debug, Clone, PartialEq)]
+/// Floats below this hold integers exactly; from it on, neighbouring integers round to the same float.
+const MAX_EXACT_INTEGER: f64 = (1_u64 << f64::MANTISSA_DIGITS) as f64;
+
+/// Equality, ordering and hashing are total, see [`Ord`](#impl-Ord-for-MetricFamily).
+#[derive(Debug, Clone)]
 pub struct MetricFamily {
     pub name: String,
+    pub help: Option<String>,
+    pub unit: Option<String>,
+    pub kind: MetricKind,
     pub metrics: Vec<Metric>,
 }
 
@@ -11,86 +70,688 @@ impl MetricFamily {
     pub fn distill_from(families: impl IntoIterator<Item = prometheus::proto::MetricFamily>) -> Vec<Self> {
         distill_metric_state(families)
     }
+
+    /// Starts building a family by hand; see [`MetricFamilyBuilder`].
+    pub fn builder(name: impl Into<String>) -> MetricFamilyBuilder {
+        MetricFamilyBuilder::new(name)
+    }
+
+    /// PromQL's `sum by (labels)`: collapses the family's values into one gauge series per
+    /// distinct combination of `labels`.
+    pub fn sum_by(&self, labels: &[&str]) -> MetricFamily {
+        self.aggregate_by(labels, Agg::Sum)
+    }
+
+    /// PromQL's `sum without (labels)`: collapses the family's values into one gauge series per
+    /// distinct label set once `labels` are dropped.
+    pub fn sum_without(&self, labels: &[&str]) -> MetricFamily {
+        self.aggregate_without(labels, Agg::Sum)
+    }
+
+    pub fn aggregate_by(&self, labels: &[&str], agg: Agg) -> MetricFamily {
+        self.collapse(|name| labels.contains(&name), agg)
+    }
+
+    pub fn aggregate_without(&self, labels: &[&str], agg: Agg) -> MetricFamily {
+        self.collapse(|name| !labels.contains(&name), agg)
+    }
+
+    fn collapse(&self, keep: impl Fn(&str) -> bool, agg: Agg) -> MetricFamily {
+        let mut groups: Vec<(Vec<MetricLabel>, Vec<f64>)> = Vec::new();
+        let mut index: HashMap<LabelKey, usize> = HashMap::new();
+        for metric in self.metrics.iter() {
+            let Some(value) = metric.value() else { continue };
+            let labels: Vec<MetricLabel> =
+                metric.labels().iter().filter(|l| keep(&l.name) && !l.value.is_empty()).cloned().collect();
+            let idx = *index.entry(label_key(&labels)).or_insert_with(|| {
+                groups.push((labels, Vec::new()));
+                groups.len() - 1
+            });
+            groups[idx].1.push(value);
+        }
+
+        let metrics = groups
+            .into_iter()
+            .map(|(labels, values)| Metric::Gauge { labels, value: agg.apply(values), timestamp_ms: None })
+            .collect();
+        MetricFamily {
+            name: self.name.clone(),
+            help: self.help.clone(),
+            unit: self.unit.clone(),
+            kind: MetricKind::Gauge,
+            metrics,
+        }
+    }
+
+    /// Sorts each series' labels by name and the series by their labels, so output is stable
+    /// regardless of registry iteration order.
+    pub fn normalize(&mut self) {
+        for metric in self.metrics.iter_mut() {
+            if let Some(labels) = metric.labels_mut() {
+                labels.sort_by(|lhs, rhs| (&lhs.name, &lhs.value).cmp(&(&rhs.name, &rhs.value)));
+            }
+            if let Metric::StateSet { states, .. } = metric {
+                states.sort_by(|lhs, rhs| lhs.0.cmp(&rhs.0));
+            }
+        }
+        self.metrics.sort_by_cached_key(|metric| metric.labels().to_vec());
+    }
+
+    /// Aggregates the values of the family's counter, gauge and untyped series.
+    pub fn aggregate(&self, agg: Agg) -> Option<f64> {
+        agg.apply(self.metrics.iter().filter_map(Metric::value))
+    }
+
+    /// The distinct values `label` takes across the family's series.
+    pub fn label_values(&self, label: &str) -> BTreeSet<String> {
+        self.metrics.iter().filter_map(|metric| metric.label(label)).map(str::to_string).collect()
+    }
+
+    /// Every label name used by any of the family's series.
+    pub fn label_names(&self) -> BTreeSet<String> {
+        self.metrics.iter().flat_map(|metric| metric.labels()).map(|l| l.name.clone()).collect()
+    }
+
+    /// The distinct label name sets of the family's series; a family whose series all carry the
+    /// same labels has exactly one.
+    pub fn label_name_sets(&self) -> BTreeSet<BTreeSet<String>> {
+        self.metrics.iter().map(|metric| metric.labels().iter().map(|l| l.name.clone()).collect()).collect()
+    }
+
+    /// Sums the family's values per value of `label`; series without the label group under `""`.
+    pub fn group_by(&self, label: &str) -> BTreeMap<String, f64> {
+        self.group_by_with(label, Agg::Sum)
+    }
+
+    pub fn group_by_with(&self, label: &str, agg: Agg) -> BTreeMap<String, f64> {
+        let mut groups: BTreeMap<String, Vec<f64>> = BTreeMap::new();
+        for metric in self.metrics.iter() {
+            if let Some(value) = metric.value() {
+                let key = metric.label(label).unwrap_or_default().to_string();
+                groups.entry(key).or_default().push(value);
+            }
+        }
+        groups.into_iter().filter_map(|(key, values)| Some((key, agg.apply(values)?))).collect()
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub enum MetricKind {
+    Counter,
+    Gauge,
+    Histogram,
+    GaugeHistogram,
+    Summary,
+    Untyped,
+    Info,
+    StateSet,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+impl From<prometheus::proto::MetricType> for MetricKind {
+    fn from(metric_type: prometheus::proto::MetricType) -> Self {
+        match metric_type {
+            prometheus::proto::MetricType::COUNTER => Self::Counter,
+            prometheus::proto::MetricType::GAUGE => Self::Gauge,
+            prometheus::proto::MetricType::HISTOGRAM => Self::Histogram,
+            prometheus::proto::MetricType::SUMMARY => Self::Summary,
+            prometheus::proto::MetricType::UNTYPED => Self::Untyped,
+        }
+    }
+}
+
+/// Equality, ordering and hashing are total, see [`Ord`](#impl-Ord-for-Metric).
+///
+/// New kinds of series may be added, so outside the crate prefer the constructors and accessors
+/// to matching on the variants.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
 pub enum Metric {
-    Counter(Vec<MetricLabel>, Option<f64>),
-    Gauge(Vec<MetricLabel>, Option<f64>),
+    Counter {
+        labels: Vec<MetricLabel>,
+        value: Option<f64>,
+        exemplar: Option<Exemplar>,
+        created: Option<f64>,
+        timestamp_ms: Option<i64>,
+    },
+    Gauge {
+        labels: Vec<MetricLabel>,
+        value: Option<f64>,
+        timestamp_ms: Option<i64>,
+    },
     Histogram {
         labels: Vec<MetricLabel>,
         sample_count: Option<u64>,
         sample_sum: Option<f64>,
+        buckets: Vec<HistogramBucket>,
+        created: Option<f64>,
+        timestamp_ms: Option<i64>,
+    },
+    GaugeHistogram {
+        labels: Vec<MetricLabel>,
+        sample_count: Option<u64>,
+        sample_sum: Option<f64>,
+        buckets: Vec<HistogramBucket>,
+        timestamp_ms: Option<i64>,
+    },
+    NativeHistogram {
+        labels: Vec<MetricLabel>,
+        sample_count: Option<u64>,
+        sample_sum: Option<f64>,
+        schema: i32,
+        zero_threshold: f64,
+        zero_count: u64,
+        positive_spans: Vec<BucketSpan>,
+        positive_deltas: Vec<i64>,
+        negative_spans: Vec<BucketSpan>,
+        negative_deltas: Vec<i64>,
+        /// The classic buckets a mixed histogram carries alongside its native ones, as
+        /// client_golang exposes by default; empty for a purely native histogram.
+        buckets: Vec<HistogramBucket>,
+        created: Option<f64>,
+        timestamp_ms: Option<i64>,
+    },
+    Summary {
+        labels: Vec<MetricLabel>,
+        sample_count: Option<u64>,
+        sample_sum: Option<f64>,
+        quantiles: Vec<Quantile>,
+        created: Option<f64>,
+        timestamp_ms: Option<i64>,
+    },
+    Untyped {
+        labels: Vec<MetricLabel>,
+        value: Option<f64>,
+        timestamp_ms: Option<i64>,
+    },
+    Info {
+        labels: Vec<MetricLabel>,
+        timestamp_ms: Option<i64>,
+    },
+    StateSet {
+        labels: Vec<MetricLabel>,
+        states: Vec<(String, bool)>,
+        timestamp_ms: Option<i64>,
     },
     UNSUPPORTED(prometheus::proto::MetricType),
 }
 
 impl Metric {
-    pub fn labels(&self) -> Vec<MetricLabel> {
+    pub fn counter(labels: Vec<MetricLabel>, value: f64) -> Self {
+        Self::Counter {
+            labels,
+            value: Some(value),
+            exemplar: None,
+            created: None,
+            timestamp_ms: None,
+        }
+    }
+
+    pub fn gauge(labels: Vec<MetricLabel>, value: f64) -> Self {
+        Self::Gauge { labels, value: Some(value), timestamp_ms: None }
+    }
+
+    pub fn untyped(labels: Vec<MetricLabel>, value: f64) -> Self {
+        Self::Untyped { labels, value: Some(value), timestamp_ms: None }
+    }
+
+    /// A classic histogram from its cumulative buckets.
+    pub fn classic_histogram(labels: Vec<MetricLabel>, buckets: Vec<HistogramBucket>, count: u64, sum: f64) -> Self {
+        Self::Histogram {
+            labels,
+            sample_count: Some(count),
+            sample_sum: Some(sum),
+            buckets,
+            created: None,
+            timestamp_ms: None,
+        }
+    }
+
+    pub fn gauge_histogram(labels: Vec<MetricLabel>, buckets: Vec<HistogramBucket>, count: u64, sum: f64) -> Self {
+        Self::GaugeHistogram {
+            labels,
+            sample_count: Some(count),
+            sample_sum: Some(sum),
+            buckets,
+            timestamp_ms: None,
+        }
+    }
+
+    pub fn summary(labels: Vec<MetricLabel>, quantiles: Vec<Quantile>, count: u64, sum: f64) -> Self {
+        Self::Summary {
+            labels,
+            sample_count: Some(count),
+            sample_sum: Some(sum),
+            quantiles,
+            created: None,
+            timestamp_ms: None,
+        }
+    }
+
+    pub fn info(labels: Vec<MetricLabel>) -> Self {
+        Self::Info { labels, timestamp_ms: None }
+    }
+
+    pub fn state_set(labels: Vec<MetricLabel>, states: Vec<(String, bool)>) -> Self {
+        Self::StateSet { labels, states, timestamp_ms: None }
+    }
+
+    /// The series with its timestamp set, for any kind but unsupported ones.
+    pub fn with_timestamp_ms(mut self, timestamp_ms: i64) -> Self {
+        match &mut self {
+            Self::Counter { timestamp_ms: ts, .. }
+            | Self::Gauge { timestamp_ms: ts, .. }
+            | Self::Histogram { timestamp_ms: ts, .. }
+            | Self::GaugeHistogram { timestamp_ms: ts, .. }
+            | Self::NativeHistogram { timestamp_ms: ts, .. }
+            | Self::Summary { timestamp_ms: ts, .. }
+            | Self::Untyped { timestamp_ms: ts, .. }
+            | Self::Info { timestamp_ms: ts, .. }
+            | Self::StateSet { timestamp_ms: ts, .. } => *ts = Some(timestamp_ms),
+            Self::UNSUPPORTED(_) => (),
+        }
+        self
+    }
+
+    /// The kind of series this is, native histograms being histograms; `None` for types the
+    /// crate does not support.
+    pub fn kind(&self) -> Option<MetricKind> {
+        match self {
+            Self::Counter { .. } => Some(MetricKind::Counter),
+            Self::Gauge { .. } => Some(MetricKind::Gauge),
+            Self::Histogram { .. } | Self::NativeHistogram { .. } => Some(MetricKind::Histogram),
+            Self::GaugeHistogram { .. } => Some(MetricKind::GaugeHistogram),
+            Self::Summary { .. } => Some(MetricKind::Summary),
+            Self::Untyped { .. } => Some(MetricKind::Untyped),
+            Self::Info { .. } => Some(MetricKind::Info),
+            Self::StateSet { .. } => Some(MetricKind::StateSet),
+            Self::UNSUPPORTED(_) => None,
+        }
+    }
+
+    pub fn labels(&self) -> &[MetricLabel] {
         match self {
-            Self::Counter(labels, _) => labels.clone(),
-            Self::Gauge(labels, _) => labels.clone(),
-            Self::Histogram { labels, .. } => labels.clone(),
-            Self::UNSUPPORTED(_) => vec![],
+            Self::Counter { labels, .. } => labels,
+            Self::Gauge { labels, .. } => labels,
+            Self::Histogram { labels, .. } => labels,
+            Self::GaugeHistogram { labels, .. } => labels,
+            Self::NativeHistogram { labels, .. } => labels,
+            Self::Summary { labels, .. } => labels,
+            Self::Untyped { labels, .. } => labels,
+            Self::Info { labels, .. } => labels,
+            Self::StateSet { labels, .. } => labels,
+            Self::UNSUPPORTED(_) => &[],
+        }
+    }
+
+    /// The value of the label `name`, if the series carries it.
+    pub fn label(&self, name: &str) -> Option<&str> {
+        self.labels().iter().find(|label| label.name == name).map(|label| label.value.as_str())
+    }
+
+    pub(crate) fn labels_mut(&mut self) -> Option<&mut Vec<MetricLabel>> {
+        match self {
+            Self::Counter { labels, .. } => Some(labels),
+            Self::Gauge { labels, .. } => Some(labels),
+            Self::Histogram { labels, .. } => Some(labels),
+            Self::GaugeHistogram { labels, .. } => Some(labels),
+            Self::NativeHistogram { labels, .. } => Some(labels),
+            Self::Summary { labels, .. } => Some(labels),
+            Self::Untyped { labels, .. } => Some(labels),
+            Self::Info { labels, .. } => Some(labels),
+            Self::StateSet { labels, .. } => Some(labels),
+            Self::UNSUPPORTED(_) => None,
         }
     }
 
     pub fn count(&self) -> u64 {
         match self {
-            Self::Counter(_, _) => 1,
-            Self::Gauge(_, _) => 1,
+            Self::Counter { .. } => 1,
+            Self::Gauge { .. } => 1,
             Self::Histogram { sample_count, .. } => (*sample_count).unwrap_or(0),
+            Self::GaugeHistogram { sample_count, .. } => (*sample_count).unwrap_or(0),
+            Self::NativeHistogram { sample_count, .. } => (*sample_count).unwrap_or(0),
+            Self::Summary { sample_count, .. } => (*sample_count).unwrap_or(0),
+            Self::Untyped { .. } => 1,
+            Self::Info { .. } => 1,
+            Self::StateSet { .. } => 1,
             Self::UNSUPPORTED(_) => 1,
         }
     }
 
     pub fn sum(&self) -> f64 {
         match self {
-            Self::Counter(_, val) => (*val).unwrap_or(0_f64),
-            Self::Gauge(_, val) => (*val).unwrap_or(0_f64),
+            Self::Counter { value, .. } => (*value).unwrap_or(0_f64),
+            Self::Gauge { value, .. } => (*value).unwrap_or(0_f64),
             Self::Histogram { sample_sum, .. } => (*sample_sum).unwrap_or(0_f64),
+            Self::GaugeHistogram { sample_sum, .. } => (*sample_sum).unwrap_or(0_f64),
+            Self::NativeHistogram { sample_sum, .. } => (*sample_sum).unwrap_or(0_f64),
+            Self::Summary { sample_sum, .. } => (*sample_sum).unwrap_or(0_f64),
+            Self::Untyped { value, .. } => (*value).unwrap_or(0_f64),
+            Self::Info { .. } => 1_f64,
+            Self::StateSet { states, .. } => states.iter().filter(|(_, enabled)| *enabled).count() as f64,
             Self::UNSUPPORTED(_) => 0_f64,
         }
     }
+
+    /// The sample value of a counter, gauge or untyped series.
+    pub fn value(&self) -> Option<f64> {
+        match self {
+            Self::Counter { value, .. } | Self::Gauge { value, .. } | Self::Untyped { value, .. } => *value,
+            _ => None,
+        }
+    }
+
+    pub fn counter_value(&self) -> Option<f64> {
+        match self {
+            Self::Counter { value, .. } => *value,
+            _ => None,
+        }
+    }
+
+    pub fn gauge_value(&self) -> Option<f64> {
+        match self {
+            Self::Gauge { value, .. } => *value,
+            _ => None,
+        }
+    }
+
+    /// The counter, gauge or untyped value as an integer, e.g. of an `IntCounter`. Exposition
+    /// formats carry values as floats, so this is `None` unless the value is a whole number below
+    /// 2^53, the range in which floats hold integers exactly.
+    pub fn as_u64(&self) -> Option<u64> {
+        self.value()
+            .filter(|value| value.fract() == 0_f64 && (0_f64..MAX_EXACT_INTEGER).contains(value))
+            .map(|value| value as u64)
+    }
+
+    /// The count, sum and buckets of a classic or gauge histogram, or of a native histogram that
+    /// also carries classic buckets.
+    pub fn histogram(&self) -> Option<HistogramValue<'_>> {
+        match self {
+            Self::Histogram { sample_count, sample_sum, buckets, .. }
+            | Self::GaugeHistogram { sample_count, sample_sum, buckets, .. } => Some(HistogramValue {
+                sample_count: *sample_count,
+                sample_sum: *sample_sum,
+                buckets,
+            }),
+            Self::NativeHistogram { sample_count, sample_sum, buckets, .. } if !buckets.is_empty() => {
+                Some(HistogramValue {
+                    sample_count: *sample_count,
+                    sample_sum: *sample_sum,
+                    buckets,
+                })
+            },
+            _ => None,
+        }
+    }
+
+    pub fn quantiles(&self) -> Option<&[Quantile]> {
+        match self {
+            Self::Summary { quantiles, .. } => Some(quantiles),
+            _ => None,
+        }
+    }
+
+    pub fn states(&self) -> Option<&[(String, bool)]> {
+        match self {
+            Self::StateSet { states, .. } => Some(states),
+            _ => None,
+        }
+    }
+
+    pub fn exemplar(&self) -> Option<&Exemplar> {
+        match self {
+            Self::Counter { exemplar, .. } => exemplar.as_ref(),
+            _ => None,
+        }
+    }
+
+    pub fn created(&self) -> Option<f64> {
+        match self {
+            Self::Counter { created, .. } => *created,
+            Self::Histogram { created, .. } => *created,
+            Self::NativeHistogram { created, .. } => *created,
+            Self::Summary { created, .. } => *created,
+            _ => None,
+        }
+    }
+
+    pub fn timestamp_ms(&self) -> Option<i64> {
+        match self {
+            Self::Counter { timestamp_ms, .. } => *timestamp_ms,
+            Self::Gauge { timestamp_ms, .. } => *timestamp_ms,
+            Self::Histogram { timestamp_ms, .. } => *timestamp_ms,
+            Self::GaugeHistogram { timestamp_ms, .. } => *timestamp_ms,
+            Self::NativeHistogram { timestamp_ms, .. } => *timestamp_ms,
+            Self::Summary { timestamp_ms, .. } => *timestamp_ms,
+            Self::Untyped { timestamp_ms, .. } => *timestamp_ms,
+            Self::Info { timestamp_ms, .. } => *timestamp_ms,
+            Self::StateSet { timestamp_ms, .. } => *timestamp_ms,
+            Self::UNSUPPORTED(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct HistogramValue<'a> {
+    pub sample_count: Option<u64>,
+    pub sample_sum: Option<f64>,
+    pub buckets: &'a [HistogramBucket],
+}
+
+#[derive(Debug, Clone)]
+pub struct HistogramBucket {
+    pub upper_bound: f64,
+    pub cumulative_count: u64,
+    pub exemplar: Option<Exemplar>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BucketSpan {
+    pub offset: i32,
+    pub length: u32,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone)]
+pub struct Exemplar {
+    pub labels: Vec<MetricLabel>,
+    pub value: f64,
+    pub timestamp: Option<f64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Quantile {
+    pub quantile: f64,
+    pub value: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct MetricLabel {
     pub name: String,
     pub value: String,
 }
 
-impl From<&str> for MetricLabel {
-    fn from(rep: &str) -> Self {
-        MetricLabel::from_str(rep).unwrap()
+impl MetricLabel {
+    /// The separators [`FromStr`] accepts between a label's name and value.
+    pub const SEPARATORS: [char; 2] = ['|', '='];
+
+    /// Parses a label split at the first of `separators`. A value in double quotes is unescaped
+    /// as in the text exposition format, so `method="GET"` and `method=GET` are the same label.
+    pub fn parse_with(rep: &str, separators: &[char]) -> Result<Self, MetricLabelParseError> {
+        let Some((name, value)) = rep.split_once(separators) else {
+            return Err(MetricLabelParseError::MissingSeparator(rep.to_string()));
+        };
+        if name.is_empty() {
+            return Err(MetricLabelParseError::EmptyName(rep.to_string()));
+        }
+        let value = match value.strip_prefix('"').and_then(|quoted| quoted.strip_suffix('"')) {
+            Some(quoted) => {
+                Self::unescape_value(quoted).ok_or_else(|| MetricLabelParseError::InvalidValue(rep.to_string()))?
+            },
+            None => value.to_string(),
+        };
+        Ok(Self { name: name.to_string(), value })
+    }
+
+    /// Escapes `value` for a quoted label value in the text exposition formats, where `\`, `"`
+    /// and newlines are written as `\\`, `\"` and `\n`.
+    pub fn escape_value(value: &str) -> String {
+        let mut escaped = String::with_capacity(value.len());
+        for c in value.chars() {
+            match c {
+                '\\' => escaped.push_str("\\\\"),
+                '"' => escaped.push_str("\\\""),
+                '\n' => escaped.push_str("\\n"),
+                c => escaped.push(c),
+            }
+        }
+        escaped
+    }
+
+    /// Reverses [`MetricLabel::escape_value`], or `None` if `escaped` holds any other escape
+    /// sequence, a trailing backslash or an unescaped `"`.
+    pub fn unescape_value(escaped: &str) -> Option<String> {
+        let mut value = String::with_capacity(escaped.len());
+        let mut chars = escaped.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next()? {
+                    '\\' => value.push('\\'),
+                    '"' => value.push('"'),
+                    'n' => value.push('\n'),
+                    _ => return None,
+                },
+                '"' => return None,
+                c => value.push(c),
+            }
+        }
+        Some(value)
     }
 }
 
-impl From<String> for MetricLabel {
-    fn from(rep: String) -> Self {
-        MetricLabel::from_str(rep.as_str()).unwrap()
+/// Builds a label set sorted by label name: `labels! {"method" => "GET", "status" => "200"}`.
+#[macro_export]
+macro_rules! labels {
+    () => {
+        ::std::vec::Vec::<$crate::MetricLabel>::new()
+    };
+    ($($name:expr => $value:expr),+ $(,)?) => {{
+        let mut labels = ::std::vec![$($crate::MetricLabel { name: ($name).to_string(), value: ($value).to_string() }),+];
+        labels.sort_by(|lhs, rhs| lhs.name.cmp(&rhs.name));
+        labels
+    }};
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum MetricLabelParseError {
+    #[error("label {0:?} is missing a separator between its name and value")]
+    MissingSeparator(String),
+
+    #[error("label {0:?} has an empty name")]
+    EmptyName(String),
+
+    #[error("label {0:?} has an invalid escape sequence in its quoted value")]
+    InvalidValue(String),
+}
+
+impl TryFrom<&str> for MetricLabel {
+    type Error = MetricLabelParseError;
+
+    fn try_from(rep: &str) -> Result<Self, Self::Error> {
+        rep.parse()
     }
 }
 
+impl TryFrom<String> for MetricLabel {
+    type Error = MetricLabelParseError;
+
+    fn try_from(rep: String) -> Result<Self, Self::Error> {
+        rep.parse()
+    }
+}
+
+/// Parses `name|value`, `name=value` or `name="value"`; see [`MetricLabel::parse_with`].
 impl FromStr for MetricLabel {
-    type Err = Infallible;
+    type Err = MetricLabelParseError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        s.split_once("|")
-            .map(|(name, value)| Ok(Self { name: name.to_string(), value: value.to_string() }))
-            .unwrap_or_else(|| Ok(Self { name: String::new(), value: String::new() }))
+        Self::parse_with(s, &Self::SEPARATORS)
     }
 }
 
+/// The family in the text exposition format, as [`render::text`] writes it.
+impl fmt::Display for MetricFamily {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(render::text(std::slice::from_ref(self)).trim_end())
+    }
+}
+
+/// The series on one line, as `{labels} value [timestamp]` without the family name it does not
+/// know. Histograms and summaries show their count and sum, state sets each state.
+impl fmt::Display for Metric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let labels = self.labels();
+        if !labels.is_empty() {
+            write!(f, "{{{}}} ", export::format_labels(labels))?;
+        }
+        f.write_str(&render::display_value(self))?;
+        if let Some(timestamp_ms) = self.timestamp_ms() {
+            write!(f, " {timestamp_ms}")?;
+        }
+        Ok(())
+    }
+}
+
+/// `name="value"`, escaped as in the text exposition format.
+impl fmt::Display for MetricLabel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&export::format_labels(std::slice::from_ref(self)))
+    }
+}
+
+/// A label set as the key identifying a series, the same whatever the order of its labels.
+pub(crate) type LabelKey = Vec<(String, String)>;
+
+pub(crate) fn label_key(labels: &[MetricLabel]) -> LabelKey {
+    label_key_of(labels.iter().map(|l| (l.name.as_str(), l.value.as_str())))
+}
+
+/// The [`label_key`] of `(name, value)` pairs.
+pub(crate) fn label_key_of<'a>(pairs: impl IntoIterator<Item = (&'a str, &'a str)>) -> LabelKey {
+    let mut key: LabelKey = pairs.into_iter().map(|(name, value)| (name.to_string(), value.to_string())).collect();
+    key.sort();
+    key
+}
+
+/// Sorts families by name and normalizes each with [`MetricFamily::normalize`].
+pub fn normalize(families: &mut [MetricFamily]) {
+    families.sort_by(|lhs, rhs| lhs.name.cmp(&rhs.name));
+    families.iter_mut().for_each(MetricFamily::normalize);
+}
+
+pub fn distill_registry(registry: &prometheus::Registry) -> Vec<MetricFamily> {
+    distill_metric_state(registry.gather())
+}
+
+/// Distills the process-wide registry the `prometheus` crate's `register_*!` macros record into.
+pub fn distill_default_registry() -> Vec<MetricFamily> {
+    distill_metric_state(prometheus::gather())
+}
+
+/// Distills gathered families. A histogram declaring a native schema distills into a
+/// [`Metric::NativeHistogram`], which keeps the classic buckets of a mixed histogram as well.
 pub fn distill_metric_state(families: impl IntoIterator<Item = prometheus::proto::MetricFamily>) -> Vec<MetricFamily> {
     families
         .into_iter()
         .map(|family| {
             let name = family.get_name().to_string();
+            let help = if family.has_help() { Some(family.get_help().to_string()) } else { None };
+            let unit = proto_ext::family_unit(&family);
+            let kind = proto_ext::family_kind(&family);
             let metrics: Vec<Metric> = family
                 .get_metric()
                 .iter()
-                .cloned()
                 .map(|m| {
                     let labels: Vec<MetricLabel> = m
                         .get_label()
@@ -100,33 +761,134 @@ pub fn distill_metric_state(families: impl IntoIterator<Item = prometheus::proto
                             value: l.get_value().to_string(),
                         })
                         .collect();
+                    let timestamp_ms = if m.has_timestamp_ms() { Some(m.get_timestamp_ms()) } else { None };
 
-                    match family.get_field_type() {
-                        prometheus::proto::MetricType::COUNTER => {
+                    match kind {
+                        MetricKind::Counter => {
                             let c = m.get_counter();
-                            let val = if c.has_value() { Some(c.get_value()) } else { None };
-                            Metric::Counter(labels, val)
+                            let value = if c.has_value() { Some(c.get_value()) } else { None };
+                            let exemplar = proto_ext::counter_exemplar(c);
+                            let created = proto_ext::counter_created(c);
+                            Metric::Counter { labels, value, exemplar, created, timestamp_ms }
                         },
-                        prometheus::proto::MetricType::GAUGE => {
+                        MetricKind::Gauge => {
                             let g = m.get_gauge();
-                            let val = if g.has_value() { Some(g.get_value()) } else { None };
-                            Metric::Gauge(labels, val)
+                            let value = if g.has_value() { Some(g.get_value()) } else { None };
+                            Metric::Gauge { labels, value, timestamp_ms }
+                        },
+                        MetricKind::Histogram => {
+                            let h = m.get_histogram();
+                            let sample_count = if h.has_sample_count() { Some(h.get_sample_count()) } else { None };
+                            let sample_sum = if h.has_sample_sum() { Some(h.get_sample_sum()) } else { None };
+                            let created = proto_ext::histogram_created(h);
+                            if let Some(native) = proto_ext::native_histogram(h) {
+                                return Metric::NativeHistogram {
+                                    labels,
+                                    sample_count,
+                                    sample_sum,
+                                    schema: native.schema,
+                                    zero_threshold: native.zero_threshold,
+                                    zero_count: native.zero_count,
+                                    positive_spans: native.positive_spans,
+                                    positive_deltas: native.positive_deltas,
+                                    negative_spans: native.negative_spans,
+                                    negative_deltas: native.negative_deltas,
+                                    buckets: distill_buckets(h),
+                                    created,
+                                    timestamp_ms,
+                                };
+                            }
+
+                            let buckets = distill_buckets(h);
+                            Metric::Histogram {
+                                labels,
+                                sample_count,
+                                sample_sum,
+                                buckets,
+                                created,
+                                timestamp_ms,
+                            }
                         },
-                        prometheus::proto::MetricType::HISTOGRAM => {
+                        MetricKind::GaugeHistogram => {
                             let h = m.get_histogram();
                             let sample_count = if h.has_sample_count() { Some(h.get_sample_count()) } else { None };
                             let sample_sum = if h.has_sample_sum() { Some(h.get_sample_sum()) } else { None };
-                            Metric::Histogram { labels, sample_count, sample_sum }
+                            let buckets = distill_buckets(h);
+                            Metric::GaugeHistogram { labels, sample_count, sample_sum, buckets, timestamp_ms }
                         },
-                        metric_type => {
-                            tracing::error!("prometheus::proto metric_type not supported: {:?}", metric_type);
-                            Metric::UNSUPPORTED(metric_type)
+                        MetricKind::Summary => {
+                            let s = m.get_summary();
+                            let sample_count = if s.has_sample_count() { Some(s.get_sample_count()) } else { None };
+                            let sample_sum = if s.has_sample_sum() { Some(s.get_sample_sum()) } else { None };
+                            let quantiles = s
+                                .get_quantile()
+                                .iter()
+                                .map(|q| Quantile { quantile: q.get_quantile(), value: q.get_value() })
+                                .collect();
+                            let created = proto_ext::summary_created(s);
+                            Metric::Summary {
+                                labels,
+                                sample_count,
+                                sample_sum,
+                                quantiles,
+                                created,
+                                timestamp_ms,
+                            }
+                        },
+                        MetricKind::Untyped => {
+                            let u = m.get_untyped();
+                            let value = if u.has_value() { Some(u.get_value()) } else { None };
+                            Metric::Untyped { labels, value, timestamp_ms }
+                        },
+                        kind @ (MetricKind::Info | MetricKind::StateSet) => {
+                            tracing::error!("prometheus::proto cannot carry {:?} metrics", kind);
+                            Metric::UNSUPPORTED(family.get_field_type())
                         },
                     }
                 })
                 .collect();
 
-            MetricFamily { name, metrics }
+            MetricFamily { name, help, unit, kind, metrics }
+        })
+        .collect()
+}
+
+fn distill_buckets(histogram: &prometheus::proto::Histogram) -> Vec<HistogramBucket> {
+    histogram
+        .get_bucket()
+        .iter()
+        .map(|b| HistogramBucket {
+            upper_bound: b.get_upper_bound(),
+            cumulative_count: b.get_cumulative_count(),
+            exemplar: proto_ext::bucket_exemplar(b),
         })
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_whole_values_below_2_pow_53_as_integers() {
+        let as_u64 = |value: f64| Metric::counter(vec![], value).as_u64();
+        assert_eq!(as_u64(0_f64), Some(0));
+        assert_eq!(as_u64(-0_f64), Some(0));
+        assert_eq!(as_u64(42_f64), Some(42));
+        assert_eq!(as_u64(MAX_EXACT_INTEGER - 1_f64), Some((1 << 53) - 1));
+        assert_eq!(as_u64(MAX_EXACT_INTEGER), None);
+        assert_eq!(as_u64(MAX_EXACT_INTEGER * 2_f64), None);
+    }
+
+    #[test]
+    fn reads_negative_fractional_and_non_finite_values_as_no_integer() {
+        let as_u64 = |value: f64| Metric::gauge(vec![], value).as_u64();
+        assert_eq!(as_u64(-1_f64), None);
+        assert_eq!(as_u64(0.5), None);
+        assert_eq!(as_u64(2.000_001), None);
+        assert_eq!(as_u64(f64::NAN), None);
+        assert_eq!(as_u64(f64::INFINITY), None);
+        assert_eq!(Metric::untyped(vec![], 7_f64).as_u64(), Some(7));
+        assert_eq!(Metric::info(vec![]).as_u64(), None);
+    }
+}
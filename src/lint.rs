@@ -0,0 +1,176 @@
+//! Opinionated checks of the Prometheus naming conventions, beyond what [`crate::validate`]
+//! reports as invalid, so teams can enforce them in tests:
+//!
+//! ```
+//! use inspect_prometheus::lint::{lint, Severity};
+//!
+//! let families = inspect_prometheus::distill_default_registry();
+//! let problems: Vec<_> = lint(&families).into_iter().filter(|finding| Severity::Warning <= finding.severity).collect();
+//! assert!(problems.is_empty(), "{problems:?}");
+//! ```
+
+use std::fmt;
+
+use crate::{MetricFamily, MetricKind};
+
+/// Name segments that denote a time unit other than seconds.
+const TIME_UNITS: [&str; 9] =
+    ["nanoseconds", "microseconds", "milliseconds", "ns", "us", "ms", "minutes", "hours", "days"];
+
+/// Name segments that denote a size unit other than bytes.
+const SIZE_UNITS: [&str; 8] = ["bits", "kilobytes", "megabytes", "gigabytes", "kb", "mb", "gb", "kib"];
+
+/// Name segments suggesting a duration; such names should carry a `seconds` unit.
+const DURATION_WORDS: [&str; 3] = ["duration", "latency", "elapsed"];
+
+/// Name segments suggesting a size; such names should carry a `bytes` unit.
+const SIZE_WORDS: [&str; 2] = ["size", "memory"];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// Probably worth changing, but the name may be fine as is.
+    Info,
+    /// Against the conventions.
+    Warning,
+    /// Misleading to anyone querying the metric.
+    Error,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Rule {
+    /// Counters end in `_total`.
+    CounterTotalSuffix,
+    /// Histograms and summaries do not end in `_total`, which their `_count` and `_sum` would carry.
+    HistogramTotalSuffix,
+    /// Durations are measured in seconds.
+    DurationSeconds,
+    /// Sizes are measured in bytes.
+    SizeBytes,
+    /// Families have HELP text.
+    MissingHelp,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub family: String,
+    pub rule: Rule,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Info => "info",
+            Self::Warning => "warning",
+            Self::Error => "error",
+        })
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}: {}", self.severity, self.family, self.message)
+    }
+}
+
+/// Every convention `families` break, in exposition order.
+pub fn lint(families: &[MetricFamily]) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    for family in families.iter() {
+        let mut finding = |rule, severity, message: String| {
+            findings.push(Finding { family: family.name.clone(), rule, severity, message });
+        };
+        let segments: Vec<&str> = family.name.split('_').collect();
+        let has = |words: &[&str]| segments.iter().find(|segment| words.contains(segment)).copied();
+
+        match family.kind {
+            MetricKind::Counter if !family.name.ends_with("_total") => {
+                finding(Rule::CounterTotalSuffix, Severity::Warning, "counter names should end in _total".to_string())
+            },
+            MetricKind::Histogram | MetricKind::GaugeHistogram | MetricKind::Summary
+                if family.name.ends_with("_total") =>
+            {
+                let message = format!("{} names should not end in _total", crate::json::kind_name(family.kind));
+                finding(Rule::HistogramTotalSuffix, Severity::Error, message)
+            },
+            _ => (),
+        }
+
+        if let Some(unit) = has(&TIME_UNITS) {
+            let message = format!("durations should be measured in seconds, not {unit}");
+            finding(Rule::DurationSeconds, Severity::Warning, message);
+        } else if has(&DURATION_WORDS).is_some() && has(&["seconds"]).is_none() {
+            finding(Rule::DurationSeconds, Severity::Info, "durations should carry a _seconds unit".to_string());
+        }
+
+        if let Some(unit) = has(&SIZE_UNITS) {
+            finding(Rule::SizeBytes, Severity::Warning, format!("sizes should be measured in bytes, not {unit}"));
+        } else if has(&SIZE_WORDS).is_some() && has(&["bytes"]).is_none() {
+            finding(Rule::SizeBytes, Severity::Info, "sizes should carry a _bytes unit".to_string());
+        }
+
+        if family.help.as_deref().is_none_or(|help| help.trim().is_empty()) {
+            finding(Rule::MissingHelp, Severity::Warning, "HELP text should not be empty".to_string());
+        }
+    }
+    findings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    fn rules(exposition: &str) -> Vec<(Rule, Severity)> {
+        let families = if exposition.ends_with("# EOF\n") {
+            parse::parse_openmetrics(exposition).unwrap()
+        } else {
+            parse::parse_text(exposition).unwrap()
+        };
+        lint(&families).into_iter().map(|finding| (finding.rule, finding.severity)).collect()
+    }
+
+    #[test]
+    fn accepts_conventional_names() {
+        assert_eq!(rules("# HELP jobs_total Jobs\n# TYPE jobs_total counter\njobs_total 3\n"), []);
+        assert_eq!(rules("# HELP jobs Jobs\n# TYPE jobs counter\njobs_total 3\n# EOF\n"), []);
+        let histogram = "# HELP request_duration_seconds Latency\n# TYPE request_duration_seconds histogram\n\
+                         request_duration_seconds_sum 1\nrequest_duration_seconds_count 1\n";
+        assert_eq!(rules(histogram), []);
+    }
+
+    #[test]
+    fn flags_counters_without_total() {
+        assert_eq!(
+            rules("# HELP jobs Jobs\n# TYPE jobs counter\njobs 3\n"),
+            [(Rule::CounterTotalSuffix, Severity::Warning)]
+        );
+    }
+
+    #[test]
+    fn flags_histograms_named_total() {
+        let rules = rules("# HELP rpc_total Rpcs\n# TYPE rpc_total summary\nrpc_total_count 1\nrpc_total_sum 1\n");
+        assert_eq!(rules, [(Rule::HistogramTotalSuffix, Severity::Error)]);
+    }
+
+    #[test]
+    fn flags_units() {
+        let mut rules = rules("request_latency_ms 1\nheap_memory 2\ncache_kb 3\nrequest_duration 4\n");
+        rules.retain(|(rule, _)| *rule != Rule::MissingHelp);
+        assert_eq!(
+            rules,
+            [
+                (Rule::DurationSeconds, Severity::Warning),
+                (Rule::SizeBytes, Severity::Info),
+                (Rule::SizeBytes, Severity::Warning),
+                (Rule::DurationSeconds, Severity::Info),
+            ]
+        );
+    }
+
+    #[test]
+    fn flags_missing_help() {
+        assert_eq!(rules("# HELP up  \nup 1\n"), [(Rule::MissingHelp, Severity::Warning)]);
+    }
+}
@@ -0,0 +1,430 @@
+//! PromQL-style label matchers and selectors for picking series out of a [`crate::MetricState`].
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::regex::Regex;
+use crate::MetricLabel;
+
+/// The label name PromQL matches the metric name under.
+pub const METRIC_NAME_LABEL: &str = "__name__";
+
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum MatcherError {
+    #[error("{0}")]
+    InvalidRegex(String),
+
+    #[error("invalid selector at offset {offset}: {message}")]
+    Syntax { offset: usize, message: String },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum MatchOp {
+    Eq,
+    NotEq,
+    Regex,
+    NotRegex,
+}
+
+impl fmt::Display for MatchOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = match self {
+            Self::Eq => "=",
+            Self::NotEq => "!=",
+            Self::Regex => "=~",
+            Self::NotRegex => "!~",
+        };
+        f.write_str(op)
+    }
+}
+
+/// Matches one label the way PromQL does: a missing label matches as the empty string, and
+/// regular expressions must match the whole value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LabelMatcher {
+    pub name: String,
+    pub op: MatchOp,
+    pub value: String,
+    regex: Option<Regex>,
+}
+
+impl LabelMatcher {
+    pub fn new(name: impl Into<String>, op: MatchOp, value: impl Into<String>) -> Result<Self, MatcherError> {
+        let value = value.into();
+        let regex = match op {
+            MatchOp::Regex | MatchOp::NotRegex => {
+                Some(Regex::new(&value).map_err(|err| MatcherError::InvalidRegex(err.to_string()))?)
+            },
+            MatchOp::Eq | MatchOp::NotEq => None,
+        };
+        Ok(Self { name: name.into(), op, value, regex })
+    }
+
+    pub fn eq(name: impl Into<String>, value: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            op: MatchOp::Eq,
+            value: value.into(),
+            regex: None,
+        }
+    }
+
+    pub fn not_eq(name: impl Into<String>, value: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            op: MatchOp::NotEq,
+            value: value.into(),
+            regex: None,
+        }
+    }
+
+    pub fn regex(name: impl Into<String>, pattern: impl Into<String>) -> Result<Self, MatcherError> {
+        Self::new(name, MatchOp::Regex, pattern)
+    }
+
+    pub fn not_regex(name: impl Into<String>, pattern: impl Into<String>) -> Result<Self, MatcherError> {
+        Self::new(name, MatchOp::NotRegex, pattern)
+    }
+
+    /// Whether a label with `value` (or no such label, when `None`) satisfies the matcher.
+    pub fn matches_value(&self, value: Option<&str>) -> bool {
+        let value = value.unwrap_or_default();
+        match (self.op, self.regex.as_ref()) {
+            (MatchOp::Eq, _) => value == self.value,
+            (MatchOp::NotEq, _) => value != self.value,
+            (MatchOp::Regex, Some(regex)) => regex.is_full_match(value),
+            (MatchOp::NotRegex, Some(regex)) => !regex.is_full_match(value),
+            (MatchOp::Regex | MatchOp::NotRegex, None) => unreachable!("regex matchers are compiled on construction"),
+        }
+    }
+
+    pub fn matches(&self, labels: &[MetricLabel]) -> bool {
+        self.matches_value(labels.iter().find(|l| l.name == self.name).map(|l| l.value.as_str()))
+    }
+}
+
+impl fmt::Display for LabelMatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}\"{}\"", self.name, self.op, crate::MetricLabel::escape_value(&self.value))
+    }
+}
+
+/// A PromQL instant vector selector such as `http_requests_total{method="GET",status!~"5.."}`,
+/// compiled into label matchers with the metric name as an `__name__` equality matcher.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Selector {
+    matchers: Vec<LabelMatcher>,
+}
+
+impl Selector {
+    pub fn new(matchers: Vec<LabelMatcher>) -> Self {
+        Self { matchers }
+    }
+
+    pub fn matchers(&self) -> &[LabelMatcher] {
+        &self.matchers
+    }
+
+    pub fn metric_name(&self) -> Option<&str> {
+        self.matchers
+            .iter()
+            .find(|matcher| matcher.name == METRIC_NAME_LABEL && matcher.op == MatchOp::Eq)
+            .map(|matcher| matcher.value.as_str())
+    }
+
+    pub fn into_matchers(self) -> Vec<LabelMatcher> {
+        self.matchers
+    }
+}
+
+impl AsRef<[LabelMatcher]> for Selector {
+    fn as_ref(&self) -> &[LabelMatcher] {
+        &self.matchers
+    }
+}
+
+impl FromStr for Selector {
+    type Err = MatcherError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut scanner = Scanner { input: s, pos: 0 };
+        let mut matchers = Vec::new();
+
+        scanner.skip_whitespace();
+        let name = scanner
+            .take_while(|idx, c| c.is_ascii_alphabetic() || c == '_' || c == ':' || (0 < idx && c.is_ascii_digit()));
+        if !name.is_empty() {
+            matchers.push(LabelMatcher::eq(METRIC_NAME_LABEL, name));
+        }
+
+        scanner.skip_whitespace();
+        if scanner.eat('{') {
+            loop {
+                scanner.skip_whitespace();
+                if scanner.eat('}') {
+                    break;
+                }
+
+                let start = scanner.pos;
+                let label =
+                    scanner.take_while(|idx, c| c.is_ascii_alphabetic() || c == '_' || (0 < idx && c.is_ascii_digit()));
+                if label.is_empty() {
+                    return Err(scanner.error("expected a label name"));
+                }
+                if label == METRIC_NAME_LABEL && !name.is_empty() {
+                    return Err(MatcherError::Syntax { offset: start, message: "metric name set twice".to_string() });
+                }
+
+                scanner.skip_whitespace();
+                let op = if scanner.eat_str("=~") {
+                    MatchOp::Regex
+                } else if scanner.eat_str("!~") {
+                    MatchOp::NotRegex
+                } else if scanner.eat_str("!=") {
+                    MatchOp::NotEq
+                } else if scanner.eat('=') {
+                    MatchOp::Eq
+                } else {
+                    return Err(scanner.error("expected one of `=`, `!=`, `=~` or `!~`"));
+                };
+
+                scanner.skip_whitespace();
+                let value = scanner.quoted()?;
+                matchers.push(LabelMatcher::new(label, op, value)?);
+
+                scanner.skip_whitespace();
+                if scanner.eat('}') {
+                    break;
+                }
+                if !scanner.eat(',') {
+                    return Err(scanner.error("expected `,` or `}`"));
+                }
+            }
+        } else if name.is_empty() {
+            return Err(scanner.error("expected a metric name or `{`"));
+        }
+
+        scanner.skip_whitespace();
+        if scanner.pos < s.len() {
+            return Err(scanner.error("unexpected trailing input"));
+        }
+        Ok(Self { matchers })
+    }
+}
+
+impl fmt::Display for Selector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = self.metric_name();
+        let matchers: Vec<String> = self
+            .matchers
+            .iter()
+            .filter(|matcher| name.is_none() || !(matcher.name == METRIC_NAME_LABEL && matcher.op == MatchOp::Eq))
+            .map(|matcher| matcher.to_string())
+            .collect();
+        if let Some(name) = name {
+            f.write_str(name)?;
+        }
+        if !matchers.is_empty() || name.is_none() {
+            write!(f, "{{{}}}", matchers.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+struct Scanner<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl<'a> Scanner<'a> {
+    fn rest(&self) -> &'a str {
+        &self.input[self.pos..]
+    }
+
+    fn error(&self, message: &str) -> MatcherError {
+        MatcherError::Syntax { offset: self.pos, message: message.to_string() }
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        self.eat_str(c.encode_utf8(&mut [0; 4]))
+    }
+
+    fn eat_str(&mut self, token: &str) -> bool {
+        let matched = self.rest().starts_with(token);
+        if matched {
+            self.pos += token.len();
+        }
+        matched
+    }
+
+    fn take_while(&mut self, pred: impl Fn(usize, char) -> bool) -> &'a str {
+        let rest = self.rest();
+        let end = rest.char_indices().find(|(idx, c)| !pred(*idx, *c)).map(|(idx, _)| idx).unwrap_or(rest.len());
+        self.pos += end;
+        &rest[..end]
+    }
+
+    /// Reads a double-quoted, single-quoted or backtick raw string, as PromQL accepts them.
+    fn quoted(&mut self) -> Result<String, MatcherError> {
+        let start = self.pos;
+        let quote = match self.rest().chars().next() {
+            Some(quote @ ('"' | '\'' | '`')) => quote,
+            _ => return Err(self.error("expected a quoted label value")),
+        };
+        self.pos += 1;
+
+        let mut value = String::new();
+        let mut chars = self.rest().char_indices();
+        while let Some((idx, c)) = chars.next() {
+            match c {
+                c if c == quote => {
+                    self.pos += idx + 1;
+                    return Ok(value);
+                },
+                '\\' if quote != '`' => match chars.next().map(|(_, c)| c) {
+                    Some('\\') => value.push('\\'),
+                    Some('"') => value.push('"'),
+                    Some('\'') => value.push('\''),
+                    Some('n') => value.push('\n'),
+                    Some('t') => value.push('\t'),
+                    Some('r') => value.push('\r'),
+                    _ => {
+                        return Err(MatcherError::Syntax {
+                            offset: self.pos + idx,
+                            message: "invalid escape sequence".to_string(),
+                        })
+                    },
+                },
+                '\n' if quote != '`' => break,
+                c => value.push(c),
+            }
+        }
+        Err(MatcherError::Syntax {
+            offset: start,
+            message: "unterminated label value".to_string(),
+        })
+    }
+}
+
+/// Matches family names, for narrowing large registries before diffing or rendering.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NamePattern(Pattern);
+
+#[derive(Debug, Clone, PartialEq)]
+enum Pattern {
+    Exact(String),
+    Prefix(String),
+    Suffix(String),
+    Regex(Regex),
+}
+
+impl NamePattern {
+    pub fn exact(name: impl Into<String>) -> Self {
+        Self(Pattern::Exact(name.into()))
+    }
+
+    pub fn prefix(prefix: impl Into<String>) -> Self {
+        Self(Pattern::Prefix(prefix.into()))
+    }
+
+    pub fn suffix(suffix: impl Into<String>) -> Self {
+        Self(Pattern::Suffix(suffix.into()))
+    }
+
+    /// A regular expression that must match the whole name, as in a `__name__=~` matcher.
+    pub fn regex(pattern: &str) -> Result<Self, MatcherError> {
+        let regex = Regex::new(pattern).map_err(|err| MatcherError::InvalidRegex(err.to_string()))?;
+        Ok(Self(Pattern::Regex(regex)))
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        match &self.0 {
+            Pattern::Exact(exact) => name == exact,
+            Pattern::Prefix(prefix) => name.starts_with(prefix.as_str()),
+            Pattern::Suffix(suffix) => name.ends_with(suffix.as_str()),
+            Pattern::Regex(regex) => regex.is_full_match(name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::labels;
+
+    #[test]
+    fn matches_missing_labels_as_empty() {
+        let labels = labels! {"code" => "200"};
+        assert!(LabelMatcher::eq("method", "").matches(&labels));
+        assert!(LabelMatcher::not_eq("method", "GET").matches(&labels));
+        assert!(!LabelMatcher::eq("method", "GET").matches(&labels));
+        assert!(LabelMatcher::regex("method", "GET|").unwrap().matches(&labels));
+        assert!(LabelMatcher::not_regex("code", "5..").unwrap().matches(&labels));
+        assert!(!LabelMatcher::regex("code", "2").unwrap().matches(&labels));
+    }
+
+    #[test]
+    fn parses_selectors() {
+        let selector: Selector = r#"http_requests_total{method="GET", status!~'5..',path=~`/api/.*`}"#.parse().unwrap();
+        assert_eq!(selector.metric_name(), Some("http_requests_total"));
+        let matchers: Vec<_> = selector.matchers().iter().map(|m| (m.name.as_str(), m.op, m.value.as_str())).collect();
+        assert_eq!(
+            matchers,
+            vec![
+                (METRIC_NAME_LABEL, MatchOp::Eq, "http_requests_total"),
+                ("method", MatchOp::Eq, "GET"),
+                ("status", MatchOp::NotRegex, "5.."),
+                ("path", MatchOp::Regex, "/api/.*"),
+            ]
+        );
+    }
+
+    #[test]
+    fn displays_selectors_that_parse_back() {
+        for selector in ["up", r#"up{job="api"}"#, r#"{__name__=~"up|down",job!="a\"b\\c"}"#, "{}"] {
+            let parsed: Selector = selector.parse().unwrap();
+            assert_eq!(parsed.to_string(), selector);
+            assert_eq!(parsed.to_string().parse::<Selector>().unwrap(), parsed);
+        }
+    }
+
+    #[test]
+    fn unescapes_label_values() {
+        let selector: Selector = r#"{msg="a\nb\t\"c\""}"#.parse().unwrap();
+        assert_eq!(selector.matchers()[0].value, "a\nb\t\"c\"");
+        let raw: Selector = r"{path=`C:\dir`}".parse().unwrap();
+        assert_eq!(raw.matchers()[0].value, r"C:\dir");
+    }
+
+    #[test]
+    fn reports_selector_syntax_errors() {
+        let offset = |selector: &str| match selector.parse::<Selector>() {
+            Err(MatcherError::Syntax { offset, .. }) => offset,
+            outcome => panic!("{selector}: {outcome:?}"),
+        };
+        assert_eq!(offset(""), 0);
+        assert_eq!(offset("up{job}"), 6);
+        assert_eq!(offset(r#"up{job="a" code="b"}"#), 11);
+        assert_eq!(offset(r#"up{job="a}"#), 7);
+        assert_eq!(offset(r#"up{__name__="up"}"#), 3);
+        assert_eq!(offset(r#"up{job="a"} x"#), 12);
+        assert_eq!(offset(r#"up{job="\q"}"#), 8);
+        assert!(matches!("up{job=~\"(\"}".parse::<Selector>(), Err(MatcherError::InvalidRegex(_))));
+    }
+
+    #[test]
+    fn matches_family_names() {
+        assert!(NamePattern::exact("up").matches("up"));
+        assert!(!NamePattern::exact("up").matches("upstream"));
+        assert!(NamePattern::prefix("http_").matches("http_requests_total"));
+        assert!(NamePattern::suffix("_seconds").matches("latency_seconds"));
+        assert!(NamePattern::regex("process_.+").unwrap().matches("process_cpu_seconds_total"));
+        assert!(!NamePattern::regex("process").unwrap().matches("process_cpu_seconds_total"));
+        assert!(NamePattern::regex("(").is_err());
+    }
+}
@@ -0,0 +1,60 @@
+//! Support for the `metrics` facade's `metrics-exporter-prometheus` recorder, whose
+//! `PrometheusHandle` only hands out the rendered text exposition format. The crate isn't a
+//! dependency, so the rendered output is passed in.
+
+use crate::parse::{self, ParseError};
+use crate::MetricFamily;
+
+/// Distills the output of `PrometheusHandle::render()`, with families and series in
+/// [`crate::normalize`] order, the same as distilling a `prometheus::Registry`:
+///
+/// ```ignore
+/// let families = metrics_exporter::distill_rendered(&handle.render())?;
+/// ```
+pub fn distill_rendered(rendered: &str) -> Result<Vec<MetricFamily>, ParseError> {
+    let mut families = parse::parse_text(rendered)?;
+    crate::normalize(&mut families);
+    Ok(families)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{labels, MetricKind};
+
+    /// Output as `PrometheusHandle::render()` writes it for a described counter and a histogram
+    /// recorded as a summary, the exporter's default, with a blank line after each family.
+    const RENDERED: &str = r#"# HELP requests_total Handled requests.
+# TYPE requests_total counter
+requests_total{path="/b",method="GET"} 2
+requests_total{path="/a",method="GET"} 1
+
+# TYPE latency_seconds summary
+latency_seconds{quantile="0"} 0.25
+latency_seconds{quantile="0.5"} 0.25
+latency_seconds{quantile="1"} 0.5
+latency_seconds_sum 0.75
+latency_seconds_count 2
+
+"#;
+
+    #[test]
+    fn distills_rendered_output_in_normal_order() {
+        let families = distill_rendered(RENDERED).unwrap();
+        let names: Vec<_> = families.iter().map(|family| (family.name.as_str(), family.kind)).collect();
+        assert_eq!(names, vec![("latency_seconds", MetricKind::Summary), ("requests_total", MetricKind::Counter)]);
+        assert_eq!(families[0].metrics[0].quantiles().map(<[_]>::len), Some(3));
+        assert_eq!(families[1].help.as_deref(), Some("Handled requests."));
+        let labels: Vec<_> = families[1].metrics.iter().map(|m| m.labels().to_vec()).collect();
+        assert_eq!(
+            labels,
+            vec![labels! {"method" => "GET", "path" => "/a"}, labels! {"method" => "GET", "path" => "/b"}]
+        );
+    }
+
+    #[test]
+    fn reports_malformed_output() {
+        assert!(matches!(distill_rendered("requests_total{path=\"/a\" 1\n"), Err(ParseError::Syntax { line: 1, .. })));
+    }
+}
@@ -0,0 +1,311 @@
+//! Equality, ordering and hashing of the float-bearing distilled types, which are total so that
+//! families can be put in sets and maps or sorted.
+
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
+
+use crate::{BucketSpan, Exemplar, HistogramBucket, Metric, MetricFamily, MetricKind, MetricLabel, Quantile};
+
+/// A label set in canonical order.
+type Labels<'a> = Vec<&'a MetricLabel>;
+
+fn sorted(labels: &[MetricLabel]) -> Labels<'_> {
+    let mut labels: Labels<'_> = labels.iter().collect();
+    labels.sort();
+    labels
+}
+
+/// A float's position in the [`f64::total_cmp`] order, with `-0.0` folded into `0.0` and every
+/// NaN into one, as they are the same sample value.
+fn total(value: f64) -> i64 {
+    let value = if value == 0_f64 {
+        0_f64
+    } else if value.is_nan() {
+        f64::NAN
+    } else {
+        value
+    };
+    let bits = value.to_bits() as i64;
+    bits ^ (((bits >> 63) as u64) >> 1) as i64
+}
+
+/// Derives the comparison traits of `$type` from the totally ordered key `$key` returns.
+macro_rules! total_order {
+    ($type:ty, $key:path) => {
+        impl PartialEq for $type {
+            fn eq(&self, other: &Self) -> bool {
+                $key(self) == $key(other)
+            }
+        }
+
+        impl Eq for $type {}
+
+        impl PartialOrd for $type {
+            fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+                Some(self.cmp(other))
+            }
+        }
+
+        /// Floats compare by [`f64::total_cmp`] once zeros and NaNs are folded, so unlike with `==`
+        /// on `f64` NaN equals NaN and `-0.0` equals `0.0`, and label sets regardless of their
+        /// order, as registries may list them in any.
+        impl Ord for $type {
+            fn cmp(&self, other: &Self) -> Ordering {
+                $key(self).cmp(&$key(other))
+            }
+        }
+
+        impl Hash for $type {
+            fn hash<H: Hasher>(&self, state: &mut H) {
+                $key(self).hash(state)
+            }
+        }
+    };
+}
+
+total_order!(MetricFamily, family_key);
+total_order!(Metric, MetricKey::of);
+total_order!(HistogramBucket, bucket_key);
+total_order!(Exemplar, exemplar_key);
+total_order!(Quantile, quantile_key);
+
+type FamilyKey<'a> = (&'a str, Option<&'a str>, Option<&'a str>, MetricKind, &'a [Metric]);
+
+fn family_key(family: &MetricFamily) -> FamilyKey<'_> {
+    (&family.name, family.help.as_deref(), family.unit.as_deref(), family.kind, &family.metrics)
+}
+
+fn bucket_key(bucket: &HistogramBucket) -> (i64, u64, Option<&Exemplar>) {
+    (total(bucket.upper_bound), bucket.cumulative_count, bucket.exemplar.as_ref())
+}
+
+fn exemplar_key(exemplar: &Exemplar) -> (Labels<'_>, i64, Option<i64>) {
+    (sorted(&exemplar.labels), total(exemplar.value), exemplar.timestamp.map(total))
+}
+
+fn quantile_key(quantile: &Quantile) -> (i64, i64) {
+    (total(quantile.quantile), total(quantile.value))
+}
+
+/// [`Metric`] with its floats replaced by their [`total`] order.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum MetricKey<'a> {
+    Counter {
+        labels: Labels<'a>,
+        value: Option<i64>,
+        exemplar: Option<&'a Exemplar>,
+        created: Option<i64>,
+        timestamp_ms: Option<i64>,
+    },
+    Gauge {
+        labels: Labels<'a>,
+        value: Option<i64>,
+        timestamp_ms: Option<i64>,
+    },
+    Histogram {
+        labels: Labels<'a>,
+        sample_count: Option<u64>,
+        sample_sum: Option<i64>,
+        buckets: &'a [HistogramBucket],
+        created: Option<i64>,
+        timestamp_ms: Option<i64>,
+    },
+    GaugeHistogram {
+        labels: Labels<'a>,
+        sample_count: Option<u64>,
+        sample_sum: Option<i64>,
+        buckets: &'a [HistogramBucket],
+        timestamp_ms: Option<i64>,
+    },
+    NativeHistogram {
+        labels: Labels<'a>,
+        sample_count: Option<u64>,
+        sample_sum: Option<i64>,
+        schema: i32,
+        zero_threshold: i64,
+        zero_count: u64,
+        positive_spans: &'a [BucketSpan],
+        positive_deltas: &'a [i64],
+        negative_spans: &'a [BucketSpan],
+        negative_deltas: &'a [i64],
+        buckets: &'a [HistogramBucket],
+        created: Option<i64>,
+        timestamp_ms: Option<i64>,
+    },
+    Summary {
+        labels: Labels<'a>,
+        sample_count: Option<u64>,
+        sample_sum: Option<i64>,
+        quantiles: &'a [Quantile],
+        created: Option<i64>,
+        timestamp_ms: Option<i64>,
+    },
+    Untyped {
+        labels: Labels<'a>,
+        value: Option<i64>,
+        timestamp_ms: Option<i64>,
+    },
+    Info {
+        labels: Labels<'a>,
+        timestamp_ms: Option<i64>,
+    },
+    StateSet {
+        labels: Labels<'a>,
+        states: &'a [(String, bool)],
+        timestamp_ms: Option<i64>,
+    },
+    Unsupported(i32),
+}
+
+impl<'a> MetricKey<'a> {
+    fn of(metric: &'a Metric) -> Self {
+        match metric {
+            Metric::Counter { labels, value, exemplar, created, timestamp_ms } => Self::Counter {
+                labels: sorted(labels),
+                value: value.map(total),
+                exemplar: exemplar.as_ref(),
+                created: created.map(total),
+                timestamp_ms: *timestamp_ms,
+            },
+            Metric::Gauge { labels, value, timestamp_ms } => Self::Gauge {
+                labels: sorted(labels),
+                value: value.map(total),
+                timestamp_ms: *timestamp_ms,
+            },
+            Metric::Histogram {
+                labels,
+                sample_count,
+                sample_sum,
+                buckets,
+                created,
+                timestamp_ms,
+            } => Self::Histogram {
+                labels: sorted(labels),
+                sample_count: *sample_count,
+                sample_sum: sample_sum.map(total),
+                buckets,
+                created: created.map(total),
+                timestamp_ms: *timestamp_ms,
+            },
+            Metric::GaugeHistogram { labels, sample_count, sample_sum, buckets, timestamp_ms } => {
+                Self::GaugeHistogram {
+                    labels: sorted(labels),
+                    sample_count: *sample_count,
+                    sample_sum: sample_sum.map(total),
+                    buckets,
+                    timestamp_ms: *timestamp_ms,
+                }
+            },
+            Metric::NativeHistogram {
+                labels,
+                sample_count,
+                sample_sum,
+                schema,
+                zero_threshold,
+                zero_count,
+                positive_spans,
+                positive_deltas,
+                negative_spans,
+                negative_deltas,
+                buckets,
+                created,
+                timestamp_ms,
+            } => Self::NativeHistogram {
+                labels: sorted(labels),
+                sample_count: *sample_count,
+                sample_sum: sample_sum.map(total),
+                schema: *schema,
+                zero_threshold: total(*zero_threshold),
+                zero_count: *zero_count,
+                positive_spans,
+                positive_deltas,
+                negative_spans,
+                negative_deltas,
+                buckets,
+                created: created.map(total),
+                timestamp_ms: *timestamp_ms,
+            },
+            Metric::Summary {
+                labels,
+                sample_count,
+                sample_sum,
+                quantiles,
+                created,
+                timestamp_ms,
+            } => Self::Summary {
+                labels: sorted(labels),
+                sample_count: *sample_count,
+                sample_sum: sample_sum.map(total),
+                quantiles,
+                created: created.map(total),
+                timestamp_ms: *timestamp_ms,
+            },
+            Metric::Untyped { labels, value, timestamp_ms } => Self::Untyped {
+                labels: sorted(labels),
+                value: value.map(total),
+                timestamp_ms: *timestamp_ms,
+            },
+            Metric::Info { labels, timestamp_ms } => Self::Info { labels: sorted(labels), timestamp_ms: *timestamp_ms },
+            Metric::StateSet { labels, states, timestamp_ms } => {
+                Self::StateSet { labels: sorted(labels), states, timestamp_ms: *timestamp_ms }
+            },
+            Metric::UNSUPPORTED(metric_type) => Self::Unsupported(*metric_type as i32),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::hash_map::DefaultHasher;
+
+    use crate::labels;
+
+    fn gauge(value: f64) -> Metric {
+        Metric::Gauge {
+            labels: labels! {"pool" => "a"},
+            value: Some(value),
+            timestamp_ms: None,
+        }
+    }
+
+    fn hashed(metric: &Metric) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        metric.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn folds_negative_zero_into_zero() {
+        assert_eq!(gauge(-0_f64), gauge(0_f64));
+        assert_eq!(hashed(&gauge(-0_f64)), hashed(&gauge(0_f64)));
+        assert_eq!(gauge(-0_f64).cmp(&gauge(0_f64)), Ordering::Equal);
+    }
+
+    #[test]
+    fn treats_every_nan_as_equal() {
+        let negative_nan = -f64::NAN;
+        let payload_nan = f64::from_bits(f64::NAN.to_bits() | 1);
+        assert_eq!(gauge(f64::NAN), gauge(f64::NAN));
+        assert_eq!(gauge(negative_nan), gauge(payload_nan));
+        assert_eq!(hashed(&gauge(negative_nan)), hashed(&gauge(f64::NAN)));
+        assert_ne!(gauge(f64::NAN), gauge(1_f64));
+    }
+
+    #[test]
+    fn sorts_values_totally() {
+        let mut metrics = [gauge(f64::NAN), gauge(1_f64), gauge(f64::NEG_INFINITY), gauge(-0_f64), gauge(-1_f64)];
+        metrics.sort();
+        let values: Vec<_> = metrics.iter().filter_map(Metric::gauge_value).map(|value| value.to_string()).collect();
+        assert_eq!(values, vec!["-inf", "-1", "-0", "1", "NaN"]);
+    }
+
+    #[test]
+    fn compares_label_sets_regardless_of_order() {
+        let lhs = Metric::Info { labels: labels! {"a" => "1", "b" => "2"}, timestamp_ms: None };
+        let mut rhs = lhs.clone();
+        rhs.labels_mut().unwrap().reverse();
+        assert_eq!(lhs, rhs);
+        assert_eq!(hashed(&lhs), hashed(&rhs));
+    }
+}
@@ -0,0 +1,48 @@
+//! Support for families exported by the opentelemetry SDK's Prometheus exporter, which registers
+//! into a `prometheus::Registry` and exposes the OTel resource as a separate `target_info` series.
+
+use crate::{MetricFamily, MetricKind, MetricLabel};
+
+const TARGET_INFO: &str = "target_info";
+
+/// Distills a registry fed by the opentelemetry Prometheus exporter, with the resource attributes
+/// moved onto every series; see [`apply_resource_attributes`].
+pub fn distill_exporter_registry(registry: &prometheus::Registry) -> Vec<MetricFamily> {
+    apply_resource_attributes(crate::distill_registry(registry))
+}
+
+/// The OTel resource attributes, as labels of the `target_info` series.
+pub fn resource_attributes(families: &[MetricFamily]) -> Vec<MetricLabel> {
+    families
+        .iter()
+        .find(|family| is_target_info(family))
+        .and_then(|family| family.metrics.first())
+        .map(|metric| metric.labels().to_vec())
+        .unwrap_or_default()
+}
+
+/// Removes the `target_info` family and adds its resource attributes to the labels of every other
+/// series, leaving labels the series already carries untouched.
+pub fn apply_resource_attributes(families: Vec<MetricFamily>) -> Vec<MetricFamily> {
+    let attributes = resource_attributes(&families);
+
+    families
+        .into_iter()
+        .filter(|family| !is_target_info(family))
+        .map(|mut family| {
+            for labels in family.metrics.iter_mut().filter_map(|m| m.labels_mut()) {
+                for attribute in attributes.iter() {
+                    if !labels.iter().any(|l| l.name == attribute.name) {
+                        labels.push(attribute.clone());
+                    }
+                }
+            }
+            family
+        })
+        .collect()
+}
+
+/// The exporter writes `target_info` as a gauge; OpenMetrics expositions declare it as info `target`.
+pub(crate) fn is_target_info(family: &MetricFamily) -> bool {
+    family.name == TARGET_INFO || (family.name == "target" && family.kind == MetricKind::Info)
+}
@@ -0,0 +1,692 @@
+//! Conversion of families into the OTLP metrics data model, so snapshots can be forwarded to an
+//! OpenTelemetry collector. The types are a hand-rolled mirror of `opentelemetry_proto`'s
+//! `ResourceMetrics` tree rather than that crate's types, which would pull in `prost` and `tonic`;
+//! they are encoded into `ExportMetricsServiceRequest` bytes field by field.
+//!
+//! Counters become monotonic sums named without `_total`, gauges and untyped series gauges,
+//! histograms explicit-bucket histograms, native histograms exponential histograms and summaries
+//! summaries, all cumulative; a histogram family mixing both becomes one metric of each kind under
+//! its name. Info and state set families become non-monotonic sums as the OTel Prometheus receiver
+//! converts them. Gauge histograms have no OTLP counterpart and are left out, as are exemplars. The
+//! `target_info` family becomes the resource, see [`crate::otel`].
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::proto_ext::encoded;
+use crate::render::with_label;
+use crate::{otel, BucketSpan, MetricFamily, MetricKind, MetricLabel, Quantile, Snapshot};
+
+const SCOPE_NAME: &str = env!("CARGO_PKG_NAME");
+const CUMULATIVE: i32 = 2;
+
+const REQUEST_RESOURCE_METRICS: u32 = 1;
+
+const RESOURCE_METRICS_RESOURCE: u32 = 1;
+const RESOURCE_METRICS_SCOPE_METRICS: u32 = 2;
+const RESOURCE_ATTRIBUTES: u32 = 1;
+
+const SCOPE_METRICS_SCOPE: u32 = 1;
+const SCOPE_METRICS_METRICS: u32 = 2;
+const SCOPE_NAME_FIELD: u32 = 1;
+const SCOPE_VERSION: u32 = 2;
+
+const METRIC_NAME: u32 = 1;
+const METRIC_DESCRIPTION: u32 = 2;
+const METRIC_UNIT: u32 = 3;
+const METRIC_GAUGE: u32 = 5;
+const METRIC_SUM: u32 = 7;
+const METRIC_HISTOGRAM: u32 = 9;
+const METRIC_EXPONENTIAL_HISTOGRAM: u32 = 10;
+const METRIC_SUMMARY: u32 = 11;
+
+const DATA_POINTS: u32 = 1;
+const AGGREGATION_TEMPORALITY: u32 = 2;
+const SUM_IS_MONOTONIC: u32 = 3;
+
+const POINT_START_TIME: u32 = 2;
+const POINT_TIME: u32 = 3;
+const POINT_COUNT: u32 = 4;
+const POINT_SUM: u32 = 5;
+
+const NUMBER_AS_DOUBLE: u32 = 4;
+const NUMBER_ATTRIBUTES: u32 = 7;
+
+const HISTOGRAM_BUCKET_COUNTS: u32 = 6;
+const HISTOGRAM_EXPLICIT_BOUNDS: u32 = 7;
+const HISTOGRAM_ATTRIBUTES: u32 = 9;
+
+const EXPONENTIAL_ATTRIBUTES: u32 = 1;
+const EXPONENTIAL_SCALE: u32 = 6;
+const EXPONENTIAL_ZERO_COUNT: u32 = 7;
+const EXPONENTIAL_POSITIVE: u32 = 8;
+const EXPONENTIAL_NEGATIVE: u32 = 9;
+const EXPONENTIAL_ZERO_THRESHOLD: u32 = 14;
+const BUCKETS_OFFSET: u32 = 1;
+const BUCKETS_COUNTS: u32 = 2;
+
+const SUMMARY_QUANTILE_VALUES: u32 = 6;
+const SUMMARY_ATTRIBUTES: u32 = 7;
+const QUANTILE_QUANTILE: u32 = 1;
+const QUANTILE_VALUE: u32 = 2;
+
+const KEY_VALUE_KEY: u32 = 1;
+const KEY_VALUE_VALUE: u32 = 2;
+const ANY_VALUE_STRING: u32 = 1;
+
+/// The metrics of one resource. Attributes are string-valued, as Prometheus labels are.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResourceMetrics {
+    pub resource: Vec<MetricLabel>,
+    pub scope_metrics: Vec<ScopeMetrics>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScopeMetrics {
+    pub scope_name: String,
+    pub scope_version: String,
+    pub metrics: Vec<Metric>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metric {
+    pub name: String,
+    pub description: String,
+    pub unit: String,
+    pub data: Data,
+}
+
+/// All sums, histograms and exponential histograms are cumulative, as Prometheus series are.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Data {
+    Gauge(Vec<NumberDataPoint>),
+    Sum { data_points: Vec<NumberDataPoint>, is_monotonic: bool },
+    Histogram(Vec<HistogramDataPoint>),
+    ExponentialHistogram(Vec<ExponentialHistogramDataPoint>),
+    Summary(Vec<SummaryDataPoint>),
+}
+
+/// Times are nanoseconds since the epoch; a start time of 0 means unknown.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NumberDataPoint {
+    pub attributes: Vec<MetricLabel>,
+    pub start_time_unix_nano: u64,
+    pub time_unix_nano: u64,
+    pub value: f64,
+}
+
+/// `bucket_counts` holds one more count than `explicit_bounds`, for the bucket above the last
+/// bound, and unlike Prometheus buckets the counts are not cumulative.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistogramDataPoint {
+    pub attributes: Vec<MetricLabel>,
+    pub start_time_unix_nano: u64,
+    pub time_unix_nano: u64,
+    pub count: u64,
+    pub sum: Option<f64>,
+    pub bucket_counts: Vec<u64>,
+    pub explicit_bounds: Vec<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExponentialHistogramDataPoint {
+    pub attributes: Vec<MetricLabel>,
+    pub start_time_unix_nano: u64,
+    pub time_unix_nano: u64,
+    pub count: u64,
+    pub sum: Option<f64>,
+    pub scale: i32,
+    pub zero_count: u64,
+    pub zero_threshold: f64,
+    pub positive: Buckets,
+    pub negative: Buckets,
+}
+
+/// Dense bucket counts starting at bucket index `offset`. Bucket `i` covers
+/// `(base^i, base^(i + 1)]`, one index below the Prometheus bucket of the same bounds.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Buckets {
+    pub offset: i32,
+    pub bucket_counts: Vec<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SummaryDataPoint {
+    pub attributes: Vec<MetricLabel>,
+    pub start_time_unix_nano: u64,
+    pub time_unix_nano: u64,
+    pub count: u64,
+    pub sum: f64,
+    pub quantile_values: Vec<Quantile>,
+}
+
+/// Converts families into OTLP resource metrics. Data points are stamped with their own
+/// timestamp or else `observed_at`, and start at their created timestamp when known.
+pub fn to_resource_metrics(families: &[MetricFamily], observed_at: SystemTime) -> ResourceMetrics {
+    let observed_ns = nanos(observed_at.duration_since(UNIX_EPOCH).unwrap_or_default());
+    let metrics =
+        families.iter().filter(|family| !otel::is_target_info(family)).flat_map(|f| metrics(f, observed_ns)).collect();
+    ResourceMetrics {
+        resource: otel::resource_attributes(families),
+        scope_metrics: vec![ScopeMetrics {
+            scope_name: SCOPE_NAME.to_string(),
+            scope_version: env!("CARGO_PKG_VERSION").to_string(),
+            metrics,
+        }],
+    }
+}
+
+/// Encodes a snapshot as an `ExportMetricsServiceRequest`, the body OTLP/HTTP expects.
+pub fn export_request_bytes(snapshot: &Snapshot) -> Vec<u8> {
+    let resource_metrics = to_resource_metrics(&snapshot.families, snapshot.captured_at);
+    encoded(|os| os.write_bytes(REQUEST_RESOURCE_METRICS, &resource_metrics_bytes(&resource_metrics)))
+}
+
+/// POSTs each snapshot to an OTLP/HTTP metrics endpoint (usually `http://collector:4318/v1/metrics`)
+/// as its own request, oldest first, stopping at the first rejected one.
+#[cfg(feature = "scrape")]
+pub fn push(url: &str, snapshots: &[Snapshot]) -> Result<(), crate::scrape::ScrapeError> {
+    for snapshot in snapshots {
+        let body = export_request_bytes(snapshot);
+        let response = crate::http::request("POST", url, &[("Content-Type", "application/x-protobuf")], &body)?;
+        if !(200..300).contains(&response.status) {
+            return Err(crate::scrape::ScrapeError::Status(response.status));
+        }
+    }
+    Ok(())
+}
+
+/// The OTLP metrics of a family: one, except for a histogram family mixing native and classic
+/// series, which yields an explicit-bucket histogram beside the exponential one.
+fn metrics(family: &MetricFamily, observed_ns: u64) -> Vec<Metric> {
+    let times = |metric: &crate::Metric| {
+        let time = metric.timestamp_ms().map_or(observed_ns, |ms| (ms.max(0) as u64).saturating_mul(1_000_000));
+        let start =
+            metric.created().map_or(0, |created| nanos(Duration::try_from_secs_f64(created).unwrap_or_default()));
+        (start, time)
+    };
+    let number_point = |metric: &crate::Metric, attributes: Vec<MetricLabel>, value: f64| {
+        let (start_time_unix_nano, time_unix_nano) = times(metric);
+        NumberDataPoint { attributes, start_time_unix_nano, time_unix_nano, value }
+    };
+    let number_points = || {
+        let values = family.metrics.iter().filter_map(|m| m.value().map(|value| (m, value)));
+        values.map(|(m, value)| number_point(m, m.labels().to_vec(), value)).collect()
+    };
+
+    let data = match family.kind {
+        MetricKind::Counter => vec![Data::Sum { data_points: number_points(), is_monotonic: true }],
+        MetricKind::Gauge | MetricKind::Untyped => vec![Data::Gauge(number_points())],
+        MetricKind::Info => {
+            let data_points = family.metrics.iter().map(|m| number_point(m, m.labels().to_vec(), 1_f64)).collect();
+            vec![Data::Sum { data_points, is_monotonic: false }]
+        },
+        MetricKind::StateSet => {
+            let data_points = family
+                .metrics
+                .iter()
+                .flat_map(|m| {
+                    let states = m.states().unwrap_or_default().iter();
+                    states.map(move |(state, enabled)| {
+                        let attributes = with_label(m.labels(), &family.name, state.clone());
+                        number_point(m, attributes, if *enabled { 1_f64 } else { 0_f64 })
+                    })
+                })
+                .collect();
+            vec![Data::Sum { data_points, is_monotonic: false }]
+        },
+        MetricKind::Histogram => {
+            let exponential: Vec<_> = family.metrics.iter().filter_map(|m| exponential_point(m, times(m))).collect();
+            let classic: Vec<_> = family
+                .metrics
+                .iter()
+                .filter(|m| !matches!(m, crate::Metric::NativeHistogram { .. }))
+                .filter_map(|m| histogram_point(m, times(m)))
+                .collect();
+            match (classic.is_empty(), exponential.is_empty()) {
+                (_, true) => vec![Data::Histogram(classic)],
+                (true, false) => vec![Data::ExponentialHistogram(exponential)],
+                (false, false) => vec![Data::Histogram(classic), Data::ExponentialHistogram(exponential)],
+            }
+        },
+        MetricKind::Summary => {
+            let data_points = family
+                .metrics
+                .iter()
+                .filter_map(|m| {
+                    let quantile_values = m.quantiles()?.to_vec();
+                    let (start_time_unix_nano, time_unix_nano) = times(m);
+                    Some(SummaryDataPoint {
+                        attributes: m.labels().to_vec(),
+                        start_time_unix_nano,
+                        time_unix_nano,
+                        count: m.count(),
+                        sum: m.sum(),
+                        quantile_values,
+                    })
+                })
+                .collect();
+            vec![Data::Summary(data_points)]
+        },
+        MetricKind::GaugeHistogram => Vec::new(),
+    };
+
+    let name = match family.kind {
+        MetricKind::Counter => family.name.strip_suffix("_total").unwrap_or(&family.name),
+        _ => &family.name,
+    };
+    data.into_iter()
+        .map(|data| Metric {
+            name: name.to_string(),
+            description: family.help.clone().unwrap_or_default(),
+            unit: family.unit.clone().unwrap_or_default(),
+            data,
+        })
+        .collect()
+}
+
+/// The explicit-bucket point of a classic histogram series, or of a native one's classic buckets.
+fn histogram_point(metric: &crate::Metric, (start, time): (u64, u64)) -> Option<HistogramDataPoint> {
+    let histogram = metric.histogram()?;
+    let finite = histogram.buckets.iter().filter(|bucket| bucket.upper_bound.is_finite());
+    let (mut bucket_counts, mut explicit_bounds, mut below) = (Vec::new(), Vec::new(), 0);
+    for bucket in finite {
+        bucket_counts.push(bucket.cumulative_count.saturating_sub(below));
+        explicit_bounds.push(bucket.upper_bound);
+        below = bucket.cumulative_count;
+    }
+    bucket_counts.push(metric.count().saturating_sub(below));
+    Some(HistogramDataPoint {
+        attributes: metric.labels().to_vec(),
+        start_time_unix_nano: start,
+        time_unix_nano: time,
+        count: metric.count(),
+        sum: histogram.sample_sum,
+        bucket_counts,
+        explicit_bounds,
+    })
+}
+
+fn exponential_point(metric: &crate::Metric, (start, time): (u64, u64)) -> Option<ExponentialHistogramDataPoint> {
+    let crate::Metric::NativeHistogram {
+        labels,
+        sample_sum,
+        schema,
+        zero_threshold,
+        zero_count,
+        positive_spans,
+        positive_deltas,
+        negative_spans,
+        negative_deltas,
+        ..
+    } = metric
+    else {
+        return None;
+    };
+    Some(ExponentialHistogramDataPoint {
+        attributes: labels.clone(),
+        start_time_unix_nano: start,
+        time_unix_nano: time,
+        count: metric.count(),
+        sum: *sample_sum,
+        scale: *schema,
+        zero_count: *zero_count,
+        zero_threshold: *zero_threshold,
+        positive: buckets(positive_spans, positive_deltas),
+        negative: buckets(negative_spans, negative_deltas),
+    })
+}
+
+/// Expands Prometheus spans and delta-encoded counts into dense OTLP buckets. The first span
+/// offset is the index of its first bucket, later ones the number of empty buckets skipped.
+fn buckets(spans: &[BucketSpan], deltas: &[i64]) -> Buckets {
+    let mut result = Buckets::default();
+    let (mut deltas, mut count) = (deltas.iter(), 0_i64);
+    for (position, span) in spans.iter().enumerate() {
+        if position == 0 {
+            result.offset = span.offset - 1;
+        } else {
+            result.bucket_counts.extend(std::iter::repeat_n(0, span.offset.max(0) as usize));
+        }
+        for delta in deltas.by_ref().take(span.length as usize) {
+            count += delta;
+            result.bucket_counts.push(count.max(0) as u64);
+        }
+    }
+    result
+}
+
+fn nanos(duration: Duration) -> u64 {
+    u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX)
+}
+
+fn resource_metrics_bytes(resource_metrics: &ResourceMetrics) -> Vec<u8> {
+    encoded(|os| {
+        let resource = encoded(|os| {
+            resource_metrics
+                .resource
+                .iter()
+                .try_for_each(|attribute| os.write_bytes(RESOURCE_ATTRIBUTES, &key_value_bytes(attribute)))
+        });
+        os.write_bytes(RESOURCE_METRICS_RESOURCE, &resource)?;
+        for scope_metrics in resource_metrics.scope_metrics.iter() {
+            let scope = encoded(|os| {
+                os.write_string(SCOPE_NAME_FIELD, &scope_metrics.scope_name)?;
+                os.write_string(SCOPE_VERSION, &scope_metrics.scope_version)
+            });
+            let bytes = encoded(|os| {
+                os.write_bytes(SCOPE_METRICS_SCOPE, &scope)?;
+                scope_metrics.metrics.iter().try_for_each(|m| os.write_bytes(SCOPE_METRICS_METRICS, &metric_bytes(m)))
+            });
+            os.write_bytes(RESOURCE_METRICS_SCOPE_METRICS, &bytes)?;
+        }
+        Ok(())
+    })
+}
+
+fn metric_bytes(metric: &Metric) -> Vec<u8> {
+    encoded(|os| {
+        os.write_string(METRIC_NAME, &metric.name)?;
+        if !metric.description.is_empty() {
+            os.write_string(METRIC_DESCRIPTION, &metric.description)?;
+        }
+        if !metric.unit.is_empty() {
+            os.write_string(METRIC_UNIT, &metric.unit)?;
+        }
+        match &metric.data {
+            Data::Gauge(points) => {
+                let gauge = encoded(|os| points.iter().try_for_each(|p| os.write_bytes(DATA_POINTS, &number_bytes(p))));
+                os.write_bytes(METRIC_GAUGE, &gauge)
+            },
+            Data::Sum { data_points, is_monotonic } => {
+                let sum = encoded(|os| {
+                    data_points.iter().try_for_each(|p| os.write_bytes(DATA_POINTS, &number_bytes(p)))?;
+                    os.write_enum(AGGREGATION_TEMPORALITY, CUMULATIVE)?;
+                    os.write_bool(SUM_IS_MONOTONIC, *is_monotonic)
+                });
+                os.write_bytes(METRIC_SUM, &sum)
+            },
+            Data::Histogram(points) => {
+                let histogram = encoded(|os| {
+                    points.iter().try_for_each(|p| os.write_bytes(DATA_POINTS, &histogram_bytes(p)))?;
+                    os.write_enum(AGGREGATION_TEMPORALITY, CUMULATIVE)
+                });
+                os.write_bytes(METRIC_HISTOGRAM, &histogram)
+            },
+            Data::ExponentialHistogram(points) => {
+                let histogram = encoded(|os| {
+                    points.iter().try_for_each(|p| os.write_bytes(DATA_POINTS, &exponential_bytes(p)))?;
+                    os.write_enum(AGGREGATION_TEMPORALITY, CUMULATIVE)
+                });
+                os.write_bytes(METRIC_EXPONENTIAL_HISTOGRAM, &histogram)
+            },
+            Data::Summary(points) => {
+                let summary =
+                    encoded(|os| points.iter().try_for_each(|p| os.write_bytes(DATA_POINTS, &summary_bytes(p))));
+                os.write_bytes(METRIC_SUMMARY, &summary)
+            },
+        }
+    })
+}
+
+fn number_bytes(point: &NumberDataPoint) -> Vec<u8> {
+    encoded(|os| {
+        os.write_fixed64(POINT_START_TIME, point.start_time_unix_nano)?;
+        os.write_fixed64(POINT_TIME, point.time_unix_nano)?;
+        os.write_double(NUMBER_AS_DOUBLE, point.value)?;
+        point.attributes.iter().try_for_each(|a| os.write_bytes(NUMBER_ATTRIBUTES, &key_value_bytes(a)))
+    })
+}
+
+fn histogram_bytes(point: &HistogramDataPoint) -> Vec<u8> {
+    encoded(|os| {
+        os.write_fixed64(POINT_START_TIME, point.start_time_unix_nano)?;
+        os.write_fixed64(POINT_TIME, point.time_unix_nano)?;
+        os.write_fixed64(POINT_COUNT, point.count)?;
+        if let Some(sum) = point.sum {
+            os.write_double(POINT_SUM, sum)?;
+        }
+        let counts = encoded(|os| point.bucket_counts.iter().try_for_each(|c| os.write_fixed64_no_tag(*c)));
+        os.write_bytes(HISTOGRAM_BUCKET_COUNTS, &counts)?;
+        let bounds = encoded(|os| point.explicit_bounds.iter().try_for_each(|b| os.write_double_no_tag(*b)));
+        os.write_bytes(HISTOGRAM_EXPLICIT_BOUNDS, &bounds)?;
+        point.attributes.iter().try_for_each(|a| os.write_bytes(HISTOGRAM_ATTRIBUTES, &key_value_bytes(a)))
+    })
+}
+
+fn exponential_bytes(point: &ExponentialHistogramDataPoint) -> Vec<u8> {
+    let buckets_bytes = |buckets: &Buckets| {
+        encoded(|os| {
+            os.write_sint32(BUCKETS_OFFSET, buckets.offset)?;
+            let counts = encoded(|os| buckets.bucket_counts.iter().try_for_each(|c| os.write_uint64_no_tag(*c)));
+            os.write_bytes(BUCKETS_COUNTS, &counts)
+        })
+    };
+    encoded(|os| {
+        point.attributes.iter().try_for_each(|a| os.write_bytes(EXPONENTIAL_ATTRIBUTES, &key_value_bytes(a)))?;
+        os.write_fixed64(POINT_START_TIME, point.start_time_unix_nano)?;
+        os.write_fixed64(POINT_TIME, point.time_unix_nano)?;
+        os.write_fixed64(POINT_COUNT, point.count)?;
+        if let Some(sum) = point.sum {
+            os.write_double(POINT_SUM, sum)?;
+        }
+        os.write_sint32(EXPONENTIAL_SCALE, point.scale)?;
+        os.write_fixed64(EXPONENTIAL_ZERO_COUNT, point.zero_count)?;
+        os.write_bytes(EXPONENTIAL_POSITIVE, &buckets_bytes(&point.positive))?;
+        os.write_bytes(EXPONENTIAL_NEGATIVE, &buckets_bytes(&point.negative))?;
+        os.write_double(EXPONENTIAL_ZERO_THRESHOLD, point.zero_threshold)
+    })
+}
+
+fn summary_bytes(point: &SummaryDataPoint) -> Vec<u8> {
+    encoded(|os| {
+        os.write_fixed64(POINT_START_TIME, point.start_time_unix_nano)?;
+        os.write_fixed64(POINT_TIME, point.time_unix_nano)?;
+        os.write_fixed64(POINT_COUNT, point.count)?;
+        os.write_double(POINT_SUM, point.sum)?;
+        for quantile in point.quantile_values.iter() {
+            let value = encoded(|os| {
+                os.write_double(QUANTILE_QUANTILE, quantile.quantile)?;
+                os.write_double(QUANTILE_VALUE, quantile.value)
+            });
+            os.write_bytes(SUMMARY_QUANTILE_VALUES, &value)?;
+        }
+        point.attributes.iter().try_for_each(|a| os.write_bytes(SUMMARY_ATTRIBUTES, &key_value_bytes(a)))
+    })
+}
+
+fn key_value_bytes(label: &MetricLabel) -> Vec<u8> {
+    encoded(|os| {
+        os.write_string(KEY_VALUE_KEY, &label.name)?;
+        os.write_bytes(KEY_VALUE_VALUE, &encoded(|os| os.write_string(ANY_VALUE_STRING, &label.value)))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protobuf::wire_format::WireType;
+    use protobuf::CodedInputStream;
+
+    use crate::{labels, HistogramBucket};
+
+    fn at(seconds: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(seconds)
+    }
+
+    fn convert(family: MetricFamily) -> Metric {
+        let mut resource_metrics = to_resource_metrics(&[family], at(10));
+        resource_metrics.scope_metrics.remove(0).metrics.remove(0)
+    }
+
+    /// The length-delimited values of field `number`, e.g. the nested messages of a repeated field.
+    fn messages(bytes: &[u8], number: u32) -> Vec<Vec<u8>> {
+        let mut is = CodedInputStream::from_bytes(bytes);
+        let mut messages = Vec::new();
+        while !is.eof().unwrap() {
+            match is.read_tag_unpack().unwrap() {
+                (field, WireType::WireTypeLengthDelimited) if field == number => {
+                    messages.push(is.read_bytes().unwrap())
+                },
+                (_, wire_type) => is.skip_field(wire_type).unwrap(),
+            }
+        }
+        messages
+    }
+
+    #[test]
+    fn converts_counters_into_monotonic_sums() {
+        let mut family =
+            MetricFamily::builder("requests_total").help("Requests.").counter(labels! {"code" => "200"}, 3_f64).build();
+        if let crate::Metric::Counter { created, .. } = &mut family.metrics[0] {
+            *created = Some(4.5);
+        }
+        let expected = Metric {
+            name: "requests".to_string(),
+            description: "Requests.".to_string(),
+            unit: String::new(),
+            data: Data::Sum {
+                data_points: vec![NumberDataPoint {
+                    attributes: labels! {"code" => "200"},
+                    start_time_unix_nano: 4_500_000_000,
+                    time_unix_nano: 10_000_000_000,
+                    value: 3_f64,
+                }],
+                is_monotonic: true,
+            },
+        };
+        assert_eq!(convert(family), expected);
+    }
+
+    #[test]
+    fn converts_gauges_and_stamps_their_own_timestamps() {
+        let mut family = MetricFamily::builder("temperature").gauge(vec![], 21.5).build();
+        family.metrics[0] = family.metrics[0].clone().with_timestamp_ms(2_000);
+        let Data::Gauge(points) = convert(family).data else { panic!("not a gauge") };
+        assert_eq!((points[0].time_unix_nano, points[0].value), (2_000_000_000, 21.5));
+    }
+
+    #[test]
+    fn converts_cumulative_buckets_into_bucket_counts() {
+        let family = MetricFamily::builder("latency_seconds")
+            .histogram(vec![], &[(0.1, 2), (1_f64, 5), (f64::INFINITY, 6)], 2.5)
+            .build();
+        let Data::Histogram(points) = convert(family).data else { panic!("not a histogram") };
+        assert_eq!(points[0].explicit_bounds, vec![0.1, 1_f64]);
+        assert_eq!(points[0].bucket_counts, vec![2, 3, 1]);
+        assert_eq!((points[0].count, points[0].sum), (6, Some(2.5)));
+    }
+
+    #[test]
+    fn converts_native_histograms_into_exponential_histograms() {
+        let family = MetricFamily::builder("native_seconds")
+            .metric(crate::Metric::NativeHistogram {
+                labels: vec![],
+                sample_count: Some(9),
+                sample_sum: Some(1.5),
+                schema: 3,
+                zero_threshold: 1e-128,
+                zero_count: 1,
+                positive_spans: vec![BucketSpan { offset: -2, length: 2 }, BucketSpan { offset: 1, length: 1 }],
+                positive_deltas: vec![2, -1, 3],
+                negative_spans: vec![],
+                negative_deltas: vec![],
+                buckets: vec![HistogramBucket { upper_bound: 1_f64, cumulative_count: 9, exemplar: None }],
+                created: None,
+                timestamp_ms: None,
+            })
+            .kind(MetricKind::Histogram)
+            .build();
+        let Data::ExponentialHistogram(points) = convert(family).data else { panic!("not exponential") };
+        assert_eq!(points[0].positive, Buckets { offset: -3, bucket_counts: vec![2, 1, 0, 4] });
+        assert_eq!(points[0].negative, Buckets::default());
+        assert_eq!((points[0].scale, points[0].zero_count, points[0].count), (3, 1, 9));
+    }
+
+    #[test]
+    fn keeps_classic_series_beside_native_ones() {
+        let native = crate::Metric::NativeHistogram {
+            labels: labels! {"path" => "/native"},
+            sample_count: Some(1),
+            sample_sum: Some(0.5),
+            schema: 0,
+            zero_threshold: 0_f64,
+            zero_count: 0,
+            positive_spans: vec![BucketSpan { offset: 0, length: 1 }],
+            positive_deltas: vec![1],
+            negative_spans: vec![],
+            negative_deltas: vec![],
+            buckets: vec![],
+            created: None,
+            timestamp_ms: None,
+        };
+        let family = MetricFamily::builder("latency_seconds")
+            .histogram(labels! {"path" => "/classic"}, &[(1_f64, 2), (f64::INFINITY, 3)], 2_f64)
+            .metric(native)
+            .build();
+        let metrics = to_resource_metrics(&[family], at(10)).scope_metrics.remove(0).metrics;
+        assert_eq!(metrics.len(), 2);
+        assert!(metrics.iter().all(|metric| metric.name == "latency_seconds"));
+        let Data::Histogram(classic) = &metrics[0].data else { panic!("not a histogram") };
+        assert_eq!(classic.len(), 1);
+        assert_eq!(classic[0].attributes, labels! {"path" => "/classic"});
+        assert_eq!(classic[0].bucket_counts, vec![2, 1]);
+        let Data::ExponentialHistogram(exponential) = &metrics[1].data else { panic!("not exponential") };
+        assert_eq!(exponential.len(), 1);
+        assert_eq!(exponential[0].attributes, labels! {"path" => "/native"});
+    }
+
+    #[test]
+    fn saturates_timestamps_beyond_the_nanosecond_range() {
+        let mut family = MetricFamily::builder("temperature").gauge(vec![], 21.5).build();
+        family.metrics[0] = family.metrics[0].clone().with_timestamp_ms(i64::MAX);
+        let Data::Gauge(points) = convert(family).data else { panic!("not a gauge") };
+        assert_eq!(points[0].time_unix_nano, u64::MAX);
+    }
+
+    #[test]
+    fn converts_state_sets_into_a_point_per_state() {
+        let family = MetricFamily::builder("mode").state_set(vec![], &[("on", true), ("off", false)]).build();
+        let Data::Sum { data_points, is_monotonic: false } = convert(family).data else { panic!("not a sum") };
+        let states: Vec<_> = data_points.iter().map(|p| (p.attributes[0].value.as_str(), p.value)).collect();
+        assert_eq!(states, vec![("on", 1_f64), ("off", 0_f64)]);
+    }
+
+    #[test]
+    fn moves_target_info_into_the_resource_and_leaves_out_gauge_histograms() {
+        let families = vec![
+            MetricFamily::builder("target_info").gauge(labels! {"service_name" => "api"}, 1_f64).build(),
+            MetricFamily::builder("queue_seconds").gauge_histogram(vec![], &[(1_f64, 2)], 1_f64).build(),
+            MetricFamily::builder("up").gauge(vec![], 1_f64).build(),
+        ];
+        let resource_metrics = to_resource_metrics(&families, at(10));
+        assert_eq!(resource_metrics.resource, labels! {"service_name" => "api"});
+        let names: Vec<_> = resource_metrics.scope_metrics[0].metrics.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, vec!["up"]);
+    }
+
+    #[test]
+    fn encodes_export_metrics_service_requests() {
+        let families = vec![
+            MetricFamily::builder("target_info").gauge(labels! {"service_name" => "api"}, 1_f64).build(),
+            MetricFamily::builder("requests_total").counter(vec![], 3_f64).build(),
+        ];
+        let bytes = export_request_bytes(&Snapshot { captured_at: at(10), families });
+
+        let resource_metrics = messages(&bytes, REQUEST_RESOURCE_METRICS);
+        assert_eq!(resource_metrics.len(), 1);
+        let resource = &messages(&resource_metrics[0], RESOURCE_METRICS_RESOURCE)[0];
+        let attribute = &messages(resource, RESOURCE_ATTRIBUTES)[0];
+        assert_eq!(messages(attribute, KEY_VALUE_KEY), vec![b"service_name".to_vec()]);
+        assert_eq!(messages(&messages(attribute, KEY_VALUE_VALUE)[0], ANY_VALUE_STRING), vec![b"api".to_vec()]);
+
+        let scope_metrics = &messages(&resource_metrics[0], RESOURCE_METRICS_SCOPE_METRICS)[0];
+        let metric = &messages(scope_metrics, SCOPE_METRICS_METRICS)[0];
+        assert_eq!(messages(metric, METRIC_NAME), vec![b"requests".to_vec()]);
+        let sum = &messages(metric, METRIC_SUM)[0];
+        let point = &messages(sum, DATA_POINTS)[0];
+        // start time, time and the double value are fixed-width: 1 + 8 bytes each
+        assert_eq!(point.len(), 27);
+        assert_eq!(&point[19..], &3_f64.to_le_bytes());
+        assert_eq!(&sum[sum.len() - 4..], &[0x10, CUMULATIVE as u8, 0x18, 1]);
+    }
+}
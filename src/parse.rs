@@ -0,0 +1,1157 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead};
+
+use crate::export::format_float;
+use crate::validate::{is_valid_label_name, is_valid_metric_name};
+use crate::{Exemplar, HistogramBucket, Metric, MetricFamily, MetricKind, MetricLabel, Quantile};
+
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum ParseError {
+    #[error("line {line}: {message}")]
+    Syntax { line: usize, message: String },
+
+    #[error("OpenMetrics exposition is missing the terminating # EOF line")]
+    MissingEof,
+
+    #[error("invalid protobuf exposition: {0}")]
+    Protobuf(String),
+
+    #[error("text exposition is not valid UTF-8")]
+    Utf8,
+
+    #[error("failed to read exposition: {0}")]
+    Io(String),
+
+    #[error("invalid snappy compressed payload: {0}")]
+    Snappy(String),
+
+    #[error("invalid gzip compressed payload: {0}")]
+    Gzip(String),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Format {
+    Text,
+    OpenMetrics,
+    Protobuf,
+}
+
+impl Format {
+    /// Identifies the exposition format from an HTTP `Content-Type`, defaulting to the classic
+    /// text format as Prometheus does.
+    pub fn from_content_type(content_type: &str) -> Self {
+        let content_type = content_type.to_ascii_lowercase();
+        if content_type.starts_with("application/vnd.google.protobuf") && content_type.contains("encoding=delimited") {
+            Self::Protobuf
+        } else if content_type.starts_with("application/openmetrics-text") {
+            Self::OpenMetrics
+        } else {
+            Self::Text
+        }
+    }
+}
+
+impl Format {
+    /// Guesses the exposition format of a saved dump. Text is valid UTF-8 without control
+    /// characters, whereas delimited protobuf is dominated by length and tag bytes.
+    pub fn sniff(bytes: &[u8]) -> Self {
+        match std::str::from_utf8(bytes) {
+            Ok(text) if !text.chars().any(|c| c.is_control() && !matches!(c, '\n' | '\r' | '\t')) => {
+                if text.trim_end().ends_with("# EOF") {
+                    Self::OpenMetrics
+                } else {
+                    Self::Text
+                }
+            },
+            _ => Self::Protobuf,
+        }
+    }
+}
+
+pub fn parse_bytes(bytes: &[u8], format: Format) -> Result<Vec<MetricFamily>, ParseError> {
+    parse_bytes_with(bytes, format, ParseOptions::default())
+}
+
+/// [`parse_bytes`] with `options` for the text formats.
+pub fn parse_bytes_with(bytes: &[u8], format: Format, options: ParseOptions) -> Result<Vec<MetricFamily>, ParseError> {
+    let text = || std::str::from_utf8(bytes).map_err(|_| ParseError::Utf8);
+    match format {
+        Format::Protobuf => parse_protobuf(bytes),
+        Format::Text => parse_text_with(text()?, options),
+        Format::OpenMetrics => parse_openmetrics_with(text()?, options),
+    }
+}
+
+/// Parsing other than the defaults, for [`parse_text_with`] and [`parse_openmetrics_with`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct ParseOptions {
+    /// Rejects samples carrying two labels of the same name, which are otherwise kept as is.
+    pub reject_duplicate_labels: bool,
+    /// Accepts the quoted UTF-8 metric and label names of Prometheus 3, as in
+    /// `{"my.metric","my label"="x"} 1` and `# TYPE "my.metric" counter`.
+    pub utf8_names: bool,
+}
+
+/// Parses the Prometheus text exposition format. Samples of a family that are not contiguous
+/// are merged into the family where it first appeared, parts of the same series into one series.
+pub fn parse_text(input: &str) -> Result<Vec<MetricFamily>, ParseError> {
+    parse_text_with(input, ParseOptions::default())
+}
+
+pub fn parse_text_with(input: &str, options: ParseOptions) -> Result<Vec<MetricFamily>, ParseError> {
+    parse_lines(input, TextParser::new(Dialect::Prometheus).with_options(options))
+}
+
+/// Parses the OpenMetrics text format (`application/openmetrics-text`), including units,
+/// exemplars, `_created` samples and the terminating `# EOF` marker. Counter families are named
+/// after their `_total` samples, as in the Prometheus text format.
+pub fn parse_openmetrics(input: &str) -> Result<Vec<MetricFamily>, ParseError> {
+    parse_openmetrics_with(input, ParseOptions::default())
+}
+
+pub fn parse_openmetrics_with(input: &str, options: ParseOptions) -> Result<Vec<MetricFamily>, ParseError> {
+    parse_lines(input, TextParser::new(Dialect::OpenMetrics).with_options(options))
+}
+
+/// Decodes the length-delimited `io.prometheus.client.MetricFamily` stream served for the
+/// `application/vnd.google.protobuf; proto=io.prometheus.client.MetricFamily; encoding=delimited`
+/// content type.
+pub fn parse_protobuf(bytes: &[u8]) -> Result<Vec<MetricFamily>, ParseError> {
+    let mut is = protobuf::CodedInputStream::from_bytes(bytes);
+    let mut families = Vec::new();
+    while !is.eof().map_err(|err| ParseError::Protobuf(err.to_string()))? {
+        let family: prometheus::proto::MetricFamily =
+            is.read_message().map_err(|err| ParseError::Protobuf(err.to_string()))?;
+        families.push(family);
+    }
+    Ok(crate::distill_metric_state(families))
+}
+
+/// Streams families from a text exposition without buffering the whole payload. Each family is
+/// yielded once a line of the next family is read, so a family whose samples are not contiguous
+/// is yielded more than once.
+pub fn stream_text<R: BufRead>(reader: R) -> TextStream<R> {
+    TextStream::new(reader, Dialect::Prometheus)
+}
+
+/// Streaming counterpart of [`parse_openmetrics`].
+pub fn stream_openmetrics<R: BufRead>(reader: R) -> TextStream<R> {
+    TextStream::new(reader, Dialect::OpenMetrics)
+}
+
+#[derive(Debug)]
+pub struct TextStream<R> {
+    reader: R,
+    parser: TextParser,
+    line: String,
+    line_nr: usize,
+    done: bool,
+}
+
+impl<R: BufRead> TextStream<R> {
+    fn new(reader: R, dialect: Dialect) -> Self {
+        Self {
+            reader,
+            parser: TextParser::new(dialect),
+            line: String::new(),
+            line_nr: 0,
+            done: false,
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for TextStream<R> {
+    type Item = Result<MetricFamily, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            self.line.clear();
+            let outcome = match self.reader.read_line(&mut self.line) {
+                Ok(0) => {
+                    self.done = true;
+                    self.parser.finish()
+                },
+                Ok(_) => {
+                    self.line_nr += 1;
+                    self.parser.line(self.line_nr, self.line.trim_end_matches(['\r', '\n']))
+                },
+                Err(err) if err.kind() == io::ErrorKind::InvalidData => Err(ParseError::Utf8),
+                Err(err) => Err(ParseError::Io(err.to_string())),
+            };
+
+            match outcome {
+                Ok(Some(family)) => return Some(Ok(family)),
+                Ok(None) => (),
+                Err(err) => {
+                    self.done = true;
+                    return Some(Err(err));
+                },
+            }
+        }
+
+        None
+    }
+}
+
+fn parse_lines(input: &str, parser: TextParser) -> Result<Vec<MetricFamily>, ParseError> {
+    let mut parser = parser.merging();
+    for (idx, line) in input.lines().enumerate() {
+        parser.line(idx + 1, line)?;
+    }
+    parser.finish()?;
+    Ok(parser.into_families())
+}
+
+/// Families a merging [`TextParser`] has moved on from, kept where each name first appeared so
+/// their parts can be resumed when the name reappears.
+#[derive(Debug, Default)]
+struct FamilyCollector {
+    families: Vec<Option<FamilyBuilder>>,
+    positions: HashMap<String, usize>,
+}
+
+impl FamilyCollector {
+    fn park(&mut self, family: FamilyBuilder) {
+        match self.positions.get(&family.name) {
+            Some(pos) => self.families[*pos] = Some(family),
+            None => {
+                self.positions.insert(family.name.clone(), self.families.len());
+                self.families.push(Some(family));
+            },
+        }
+    }
+
+    fn resume(&mut self, name: &str) -> Option<FamilyBuilder> {
+        self.positions.get(name).and_then(|pos| self.families[*pos].take())
+    }
+
+    fn into_families(self) -> Vec<MetricFamily> {
+        self.families.into_iter().flatten().map(FamilyBuilder::build).collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Dialect {
+    Prometheus,
+    OpenMetrics,
+}
+
+/// Line oriented parser that emits each family once a line belonging to another family is seen,
+/// or, when merging, holds on to every family until [`TextParser::into_families`].
+#[derive(Debug)]
+pub(crate) struct TextParser {
+    dialect: Dialect,
+    options: ParseOptions,
+    current: Option<FamilyBuilder>,
+    merged: Option<FamilyCollector>,
+    declared: HashMap<String, MetricKind>,
+    eof: bool,
+}
+
+impl TextParser {
+    pub(crate) fn new(dialect: Dialect) -> Self {
+        Self {
+            dialect,
+            options: ParseOptions::default(),
+            current: None,
+            merged: None,
+            declared: HashMap::new(),
+            eof: false,
+        }
+    }
+
+    pub(crate) fn with_options(mut self, options: ParseOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Merges the parts of a family that are not contiguous through the same series, rather than
+    /// emitting each part as it completes.
+    pub(crate) fn merging(mut self) -> Self {
+        self.merged = Some(FamilyCollector::default());
+        self
+    }
+
+    /// Every family seen by a merging parser, in the order each name first appeared.
+    pub(crate) fn into_families(mut self) -> Vec<MetricFamily> {
+        let mut merged = self.merged.take().unwrap_or_default();
+        if let Some(family) = self.current.take() {
+            merged.park(family);
+        }
+        merged.into_families()
+    }
+
+    pub(crate) fn line(&mut self, line_nr: usize, line: &str) -> Result<Option<MetricFamily>, ParseError> {
+        let syntax = |message: String| ParseError::Syntax { line: line_nr, message };
+
+        let line = line.trim_start_matches([' ', '\t']);
+        if line.trim().is_empty() {
+            return Ok(None);
+        }
+        if self.eof {
+            return Err(syntax("unexpected content after # EOF".to_string()));
+        }
+
+        if let Some(comment) = line.strip_prefix('#') {
+            let mut tokens = comment.trim_start_matches([' ', '\t']).splitn(2, [' ', '\t']);
+            let keyword = tokens.next();
+            let (name, rest) = match tokens.next() {
+                Some(rest) if self.options.utf8_names && rest.starts_with('"') => {
+                    let (name, rest) = split_quoted_name(rest).map_err(syntax)?;
+                    (Some(name), rest)
+                },
+                Some(rest) => {
+                    let mut tokens = rest.splitn(2, [' ', '\t']);
+                    (tokens.next().map(str::to_string), tokens.next())
+                },
+                None => (None, None),
+            };
+            let utf8_names = self.options.utf8_names;
+            let validate_name = |name: &str| {
+                if utf8_names {
+                    validate_utf8_name(name, "metric")
+                } else {
+                    validate_metric_name(name)
+                }
+            };
+            return match (keyword, name.as_deref(), rest) {
+                (Some("EOF"), None, None) if self.dialect == Dialect::OpenMetrics => {
+                    self.eof = true;
+                    Ok(self.finish_current())
+                },
+                (Some("HELP"), Some(name), help) => {
+                    validate_name(name).map_err(syntax)?;
+                    let dialect = self.dialect;
+                    let completed = self.switch_to(name);
+                    let family = self.current.as_mut().expect("current family");
+                    if family.help.is_some() {
+                        return Err(syntax(format!("second HELP line for metric name {}", name)));
+                    }
+                    family.help = Some(unescape_help(help.unwrap_or_default(), dialect));
+                    Ok(completed)
+                },
+                (Some("UNIT"), Some(name), unit) if self.dialect == Dialect::OpenMetrics => {
+                    validate_name(name).map_err(syntax)?;
+                    let completed = self.switch_to(name);
+                    let family = self.current.as_mut().expect("current family");
+                    if family.unit.is_some() {
+                        return Err(syntax(format!("second UNIT line for metric name {}", name)));
+                    }
+                    family.unit = unit.map(str::trim).filter(|unit| !unit.is_empty()).map(str::to_string);
+                    Ok(completed)
+                },
+                (Some("TYPE"), Some(name), Some(kind)) => {
+                    validate_name(name).map_err(syntax)?;
+                    let kind = parse_kind(kind.trim(), self.dialect).map_err(syntax)?;
+                    if self.declared.contains_key(name) {
+                        return Err(syntax(format!("second TYPE line for metric name {}", name)));
+                    }
+                    let completed = self.switch_to(name);
+                    let family = self.current.as_mut().expect("current family");
+                    if !family.series.is_empty() {
+                        return Err(syntax(format!("TYPE line for metric name {} follows its samples", name)));
+                    }
+                    family.kind = kind;
+                    self.declared.insert(name.to_string(), kind);
+                    Ok(completed)
+                },
+                (Some("TYPE"), _, None) => Err(syntax("TYPE line is missing the metric type".to_string())),
+                _ => Ok(None),
+            };
+        }
+
+        let sample = parse_sample(line, self.dialect, self.options.utf8_names).map_err(syntax)?;
+        if self.options.reject_duplicate_labels {
+            if let Some(label) = duplicate_label_name(&sample.labels) {
+                return Err(syntax(format!("sample {} has more than one label named {}", sample.name, label)));
+            }
+        }
+        self.sample(sample).map_err(syntax)
+    }
+
+    /// Declares the type of a family ahead of its samples, as a TYPE line would.
+    pub(crate) fn declare(&mut self, name: &str, kind: MetricKind) {
+        self.declared.insert(name.to_string(), kind);
+    }
+
+    pub(crate) fn sample(&mut self, sample: Sample) -> Result<Option<MetricFamily>, String> {
+        let family_name = self.family_name_for(&sample.name);
+        let completed = self.switch_to(&family_name);
+        self.current.as_mut().expect("current family").add(sample)?;
+        Ok(completed)
+    }
+
+    pub(crate) fn finish(&mut self) -> Result<Option<MetricFamily>, ParseError> {
+        if self.dialect == Dialect::OpenMetrics && !self.eof {
+            return Err(ParseError::MissingEof);
+        }
+        Ok(self.finish_current())
+    }
+
+    fn finish_current(&mut self) -> Option<MetricFamily> {
+        let family = self.current.take()?;
+        match self.merged.as_mut() {
+            Some(merged) => {
+                merged.park(family);
+                None
+            },
+            None => Some(family.build()),
+        }
+    }
+
+    /// Makes `name` the current family, returning the previous family if it is complete. A
+    /// merging parser resumes the family of that name it moved on from, if any.
+    fn switch_to(&mut self, name: &str) -> Option<MetricFamily> {
+        if self.current.as_ref().map(|family| family.name == name).unwrap_or(false) {
+            return None;
+        }
+
+        let completed = self.finish_current();
+        let resumed = self.merged.as_mut().and_then(|merged| merged.resume(name));
+        let kind = self.declared.get(name).copied().unwrap_or(MetricKind::Untyped);
+        self.current = Some(resumed.unwrap_or_else(|| FamilyBuilder::new(name, kind, self.dialect)));
+        completed
+    }
+
+    fn family_name_for(&self, sample_name: &str) -> String {
+        if let Some(family) = self.current.as_ref() {
+            if family.role_of(sample_name).is_some() {
+                return family.name.clone();
+            }
+        }
+
+        if self.declared.contains_key(sample_name) {
+            return sample_name.to_string();
+        }
+
+        for suffix in SUFFIXES {
+            if let Some(base) = sample_name.strip_suffix(suffix) {
+                if let Some(kind) = self.declared.get(base) {
+                    if role_for(*kind, self.dialect, suffix).is_some() {
+                        return base.to_string();
+                    }
+                }
+            }
+        }
+
+        sample_name.to_string()
+    }
+}
+
+const SUFFIXES: [&str; 8] = ["_total", "_created", "_bucket", "_count", "_sum", "_gcount", "_gsum", "_info"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Role {
+    Value,
+    Bucket,
+    Quantile,
+    Count,
+    Sum,
+    Created,
+    State,
+}
+
+/// The part a sample plays in its family, identified by the suffix following the family name.
+fn role_for(kind: MetricKind, dialect: Dialect, suffix: &str) -> Option<Role> {
+    match (kind, suffix) {
+        (MetricKind::Counter, "_total") if dialect == Dialect::OpenMetrics => Some(Role::Value),
+        (MetricKind::Counter | MetricKind::Histogram | MetricKind::Summary, "_created")
+            if dialect == Dialect::OpenMetrics =>
+        {
+            Some(Role::Created)
+        },
+        (MetricKind::Histogram | MetricKind::GaugeHistogram, "_bucket") => Some(Role::Bucket),
+        (MetricKind::Histogram | MetricKind::Summary, "_count") => Some(Role::Count),
+        (MetricKind::Histogram | MetricKind::Summary, "_sum") => Some(Role::Sum),
+        (MetricKind::GaugeHistogram, "_gcount") => Some(Role::Count),
+        (MetricKind::GaugeHistogram, "_gsum") => Some(Role::Sum),
+        (MetricKind::Summary, "") => Some(Role::Quantile),
+        (MetricKind::Info, "_info") => Some(Role::Value),
+        (MetricKind::StateSet, "") => Some(Role::State),
+        (MetricKind::Histogram | MetricKind::GaugeHistogram | MetricKind::Summary | MetricKind::Info, _) => None,
+        (_, "") => Some(Role::Value),
+        _ => None,
+    }
+}
+
+#[derive(Debug)]
+struct FamilyBuilder {
+    name: String,
+    help: Option<String>,
+    unit: Option<String>,
+    kind: MetricKind,
+    dialect: Dialect,
+    series: Vec<SeriesBuilder>,
+    index: HashMap<Vec<(String, String)>, usize>,
+}
+
+impl FamilyBuilder {
+    fn new(name: &str, kind: MetricKind, dialect: Dialect) -> Self {
+        Self {
+            name: name.to_string(),
+            help: None,
+            unit: None,
+            kind,
+            dialect,
+            series: Vec::new(),
+            index: HashMap::new(),
+        }
+    }
+
+    fn role_of(&self, sample_name: &str) -> Option<Role> {
+        let suffix = sample_name.strip_prefix(self.name.as_str())?;
+        role_for(self.kind, self.dialect, suffix)
+    }
+
+    fn add(&mut self, sample: Sample) -> Result<(), String> {
+        let role = self.role_of(&sample.name).unwrap_or(Role::Value);
+        let Sample { name, mut labels, value, timestamp_ms, exemplar } = sample;
+
+        let bound_label = match role {
+            Role::Bucket => Some("le"),
+            Role::Quantile => Some("quantile"),
+            _ => None,
+        };
+        let bound = bound_label
+            .map(|label| {
+                let bound =
+                    take_label(&mut labels, label).ok_or_else(|| format!("{} is missing the {} label", name, label))?;
+                parse_bound(&bound).ok_or_else(|| format!("{} has an invalid {} label value {:?}", name, label, bound))
+            })
+            .transpose()?;
+        let state = match role {
+            Role::State => Some(
+                take_label(&mut labels, &self.name)
+                    .ok_or_else(|| format!("stateset {} is missing its {} label", name, self.name))?,
+            ),
+            _ => None,
+        };
+
+        let key: Vec<(String, String)> = labels.iter().map(|l| (l.name.clone(), l.value.clone())).collect();
+        let pos = match self.index.get(&key) {
+            Some(pos) => *pos,
+            None => {
+                self.index.insert(key, self.series.len());
+                self.series.push(SeriesBuilder { labels, ..SeriesBuilder::default() });
+                self.series.len() - 1
+            },
+        };
+
+        let count = match role {
+            Role::Bucket | Role::Count => Some(
+                parse_count(value).ok_or_else(|| format!("{} has an invalid count {}", name, format_float(value)))?,
+            ),
+            _ => None,
+        };
+
+        let series = &mut self.series[pos];
+        if series.timestamp_ms.is_none() {
+            series.timestamp_ms = timestamp_ms;
+        }
+        match (role, bound, state) {
+            (Role::Bucket, Some(upper_bound), _) => series.buckets.push(HistogramBucket {
+                upper_bound,
+                cumulative_count: count.unwrap_or_default(),
+                exemplar,
+            }),
+            (Role::Quantile, Some(quantile), _) => series.quantiles.push(Quantile { quantile, value }),
+            (Role::Count, _, _) => series.sample_count = count,
+            (Role::Sum, _, _) => series.sample_sum = Some(value),
+            (Role::Created, _, _) => series.created = Some(value),
+            (Role::State, _, Some(state)) => series.states.push((state, value != 0_f64)),
+            _ => {
+                series.value = Some(value);
+                series.exemplar = exemplar;
+            },
+        }
+
+        Ok(())
+    }
+
+    /// The family, an OpenMetrics counter named with the `_total` suffix of its samples so that
+    /// both text formats distill a counter into the same name.
+    fn build(self) -> MetricFamily {
+        let kind = self.kind;
+        let name = match (self.dialect, kind) {
+            (Dialect::OpenMetrics, MetricKind::Counter) if !self.name.ends_with("_total") => {
+                format!("{}_total", self.name)
+            },
+            _ => self.name,
+        };
+        let metrics = self.series.into_iter().map(|series| series.build(kind)).collect();
+        MetricFamily { name, help: self.help, unit: self.unit, kind, metrics }
+    }
+}
+
+#[derive(Debug, Default)]
+struct SeriesBuilder {
+    labels: Vec<MetricLabel>,
+    value: Option<f64>,
+    exemplar: Option<Exemplar>,
+    sample_count: Option<u64>,
+    sample_sum: Option<f64>,
+    buckets: Vec<HistogramBucket>,
+    quantiles: Vec<Quantile>,
+    states: Vec<(String, bool)>,
+    created: Option<f64>,
+    timestamp_ms: Option<i64>,
+}
+
+impl SeriesBuilder {
+    fn build(self, kind: MetricKind) -> Metric {
+        let Self {
+            labels,
+            value,
+            exemplar,
+            sample_count,
+            sample_sum,
+            buckets,
+            quantiles,
+            states,
+            created,
+            timestamp_ms,
+        } = self;
+        match kind {
+            MetricKind::Counter => Metric::Counter { labels, value, exemplar, created, timestamp_ms },
+            MetricKind::Gauge => Metric::Gauge { labels, value, timestamp_ms },
+            MetricKind::Histogram => Metric::Histogram {
+                labels,
+                sample_count,
+                sample_sum,
+                buckets,
+                created,
+                timestamp_ms,
+            },
+            MetricKind::GaugeHistogram => {
+                Metric::GaugeHistogram { labels, sample_count, sample_sum, buckets, timestamp_ms }
+            },
+            MetricKind::Summary => Metric::Summary {
+                labels,
+                sample_count,
+                sample_sum,
+                quantiles,
+                created,
+                timestamp_ms,
+            },
+            MetricKind::Untyped => Metric::Untyped { labels, value, timestamp_ms },
+            MetricKind::Info => Metric::Info { labels, timestamp_ms },
+            MetricKind::StateSet => Metric::StateSet { labels, states, timestamp_ms },
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Sample {
+    pub name: String,
+    pub labels: Vec<MetricLabel>,
+    pub value: f64,
+    pub timestamp_ms: Option<i64>,
+    pub exemplar: Option<Exemplar>,
+}
+
+fn parse_kind(kind: &str, dialect: Dialect) -> Result<MetricKind, String> {
+    match (kind, dialect) {
+        ("counter", _) => Ok(MetricKind::Counter),
+        ("gauge", _) => Ok(MetricKind::Gauge),
+        ("histogram", _) => Ok(MetricKind::Histogram),
+        ("summary", _) => Ok(MetricKind::Summary),
+        ("untyped", Dialect::Prometheus) => Ok(MetricKind::Untyped),
+        ("unknown", Dialect::OpenMetrics) => Ok(MetricKind::Untyped),
+        ("gaugehistogram", Dialect::OpenMetrics) => Ok(MetricKind::GaugeHistogram),
+        ("info", Dialect::OpenMetrics) => Ok(MetricKind::Info),
+        ("stateset", Dialect::OpenMetrics) => Ok(MetricKind::StateSet),
+        (unknown, _) => Err(format!("unknown metric type {:?}", unknown)),
+    }
+}
+
+fn parse_sample(line: &str, dialect: Dialect, utf8_names: bool) -> Result<Sample, String> {
+    let mut cursor = Cursor::new(line);
+
+    let mut name = cursor.take_while(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':').to_string();
+    if !(utf8_names && name.is_empty() && cursor.rest().starts_with('{')) {
+        validate_metric_name(&name)?;
+    }
+
+    cursor.skip_blanks();
+    let labels = if cursor.eat('{') {
+        let (quoted_name, labels) = parse_labels(&mut cursor, utf8_names)?;
+        match quoted_name {
+            Some(_) if !name.is_empty() => return Err(format!("sample {} has a second, quoted name", name)),
+            Some(quoted_name) => name = quoted_name,
+            None if name.is_empty() => return Err("sample is missing a metric name".to_string()),
+            None => (),
+        }
+        labels
+    } else {
+        Vec::new()
+    };
+
+    cursor.skip_blanks();
+    let value = cursor.take_while(|c| c != ' ' && c != '\t');
+    if value.is_empty() {
+        return Err(format!("sample {} is missing a value", name));
+    }
+    let value = parse_float(value).map_err(|_| format!("sample {} has an invalid value {:?}", name, value))?;
+
+    cursor.skip_blanks();
+    let timestamp_ms = if cursor.rest().starts_with('#') {
+        None
+    } else {
+        parse_timestamp(&mut cursor, dialect)
+            .map_err(|rep| format!("sample {} has an invalid timestamp {:?}", name, rep))?
+    };
+
+    cursor.skip_blanks();
+    let exemplar = if dialect == Dialect::OpenMetrics && cursor.eat('#') {
+        Some(
+            parse_exemplar(&mut cursor, utf8_names)
+                .map_err(|message| format!("sample {} has an invalid exemplar: {}", name, message))?,
+        )
+    } else {
+        None
+    };
+
+    cursor.skip_blanks();
+    if !cursor.is_empty() {
+        return Err(format!("unexpected trailing content {:?} after sample {}", cursor.rest(), name));
+    }
+
+    Ok(Sample { name, labels, value, timestamp_ms, exemplar })
+}
+
+/// Prometheus timestamps are integer milliseconds; OpenMetrics timestamps are float seconds.
+fn parse_timestamp(cursor: &mut Cursor, dialect: Dialect) -> Result<Option<i64>, String> {
+    let rep = cursor.take_while(|c| c != ' ' && c != '\t');
+    if rep.is_empty() {
+        return Ok(None);
+    }
+
+    match dialect {
+        Dialect::Prometheus => rep.parse::<i64>().map(Some).map_err(|_| rep.to_string()),
+        Dialect::OpenMetrics => match rep.parse::<f64>() {
+            Ok(seconds) if seconds.is_finite() => Ok(Some((seconds * 1000_f64).round() as i64)),
+            _ => Err(rep.to_string()),
+        },
+    }
+}
+
+/// Parses an OpenMetrics exemplar following its `#` marker: `{labels} value [timestamp]`.
+fn parse_exemplar(cursor: &mut Cursor, utf8_names: bool) -> Result<Exemplar, String> {
+    cursor.skip_blanks();
+    if !cursor.eat('{') {
+        return Err("expected exemplar labels".to_string());
+    }
+    let (name, labels) = parse_labels(cursor, utf8_names)?;
+    if let Some(name) = name {
+        return Err(format!("unexpected name {:?} in exemplar labels", name));
+    }
+
+    cursor.skip_blanks();
+    let value = cursor.take_while(|c| c != ' ' && c != '\t');
+    let value = parse_float(value).map_err(|_| format!("invalid value {:?}", value))?;
+
+    cursor.skip_blanks();
+    let timestamp = cursor.take_while(|c| c != ' ' && c != '\t');
+    let timestamp = if timestamp.is_empty() {
+        None
+    } else {
+        Some(timestamp.parse::<f64>().map_err(|_| format!("invalid timestamp {:?}", timestamp))?)
+    };
+
+    Ok(Exemplar { labels, value, timestamp })
+}
+
+/// Parses the label pairs following an opening brace through the closing brace. With
+/// `utf8_names`, label names may be quoted and a quoted string without a value is the metric
+/// name, which is returned alongside the labels.
+fn parse_labels(cursor: &mut Cursor, utf8_names: bool) -> Result<(Option<String>, Vec<MetricLabel>), String> {
+    let mut metric_name = None;
+    let mut labels = Vec::new();
+
+    loop {
+        cursor.skip_blanks();
+        if cursor.eat('}') {
+            return Ok((metric_name, labels));
+        }
+
+        let name = if utf8_names && cursor.eat('"') {
+            let name = cursor.take_quoted().ok_or_else(|| "unterminated or invalid quoted name".to_string())?;
+            cursor.skip_blanks();
+            if !cursor.rest().starts_with('=') {
+                validate_utf8_name(&name, "metric")?;
+                if metric_name.replace(name).is_some() {
+                    return Err("more than one quoted metric name".to_string());
+                }
+                if cursor.eat(',') {
+                    continue;
+                }
+                if cursor.eat('}') {
+                    return Ok((metric_name, labels));
+                }
+                return Err("expected ',' or '}' after the quoted metric name".to_string());
+            }
+            validate_utf8_name(&name, "label")?;
+            name
+        } else {
+            let name = cursor.take_while(|c| c.is_ascii_alphanumeric() || c == '_');
+            validate_label_name(name)?;
+            name.to_string()
+        };
+
+        cursor.skip_blanks();
+        if !cursor.eat('=') {
+            return Err(format!("expected '=' after label name {}", name));
+        }
+        cursor.skip_blanks();
+        if !cursor.eat('"') {
+            return Err(format!("expected quoted value for label {}", name));
+        }
+        let value = cursor.take_quoted().ok_or_else(|| format!("unterminated or invalid value for label {}", name))?;
+        labels.push(MetricLabel { name: name.clone(), value });
+
+        cursor.skip_blanks();
+        if cursor.eat(',') {
+            continue;
+        }
+        if cursor.eat('}') {
+            return Ok((metric_name, labels));
+        }
+        return Err(format!("expected ',' or '}}' after label {}", name));
+    }
+}
+
+/// The first label name that appears more than once in `labels`.
+pub(crate) fn duplicate_label_name(labels: &[MetricLabel]) -> Option<&str> {
+    labels
+        .iter()
+        .enumerate()
+        .find(|(idx, label)| labels[..*idx].iter().any(|earlier| earlier.name == label.name))
+        .map(|(_, label)| label.name.as_str())
+}
+
+fn take_label(labels: &mut Vec<MetricLabel>, name: &str) -> Option<String> {
+    let pos = labels.iter().position(|l| l.name == name)?;
+    Some(labels.remove(pos).value)
+}
+
+pub(crate) fn parse_float(rep: &str) -> Result<f64, std::num::ParseFloatError> {
+    match rep {
+        "+Inf" | "Inf" => Ok(f64::INFINITY),
+        "-Inf" => Ok(f64::NEG_INFINITY),
+        rep => rep.parse(),
+    }
+}
+
+/// A bucket bound or quantile, which unlike sample values may not be `NaN`.
+fn parse_bound(rep: &str) -> Option<f64> {
+    parse_float(rep).ok().filter(|bound| !bound.is_nan())
+}
+
+/// A bucket or sample count, which must be a whole, non-negative number.
+fn parse_count(value: f64) -> Option<u64> {
+    let in_range = (0_f64..u64::MAX as f64).contains(&value) && value.fract() == 0_f64;
+    in_range.then_some(value as u64)
+}
+
+fn validate_metric_name(name: &str) -> Result<(), String> {
+    if is_valid_metric_name(name) {
+        Ok(())
+    } else {
+        Err(format!("invalid metric name {:?}", name))
+    }
+}
+
+/// Quoted UTF-8 names may hold anything but must not be empty.
+fn validate_utf8_name(name: &str, what: &str) -> Result<(), String> {
+    if name.is_empty() {
+        Err(format!("empty {} name", what))
+    } else {
+        Ok(())
+    }
+}
+
+/// Splits a quoted name from the start of `rest`, returning the unescaped name and what follows
+/// the blank after it.
+fn split_quoted_name(rest: &str) -> Result<(String, Option<&str>), String> {
+    let mut cursor = Cursor::new(&rest[1..]);
+    let name = cursor.take_quoted().ok_or_else(|| "unterminated or invalid quoted metric name".to_string())?;
+    let rest = cursor.rest();
+    if rest.is_empty() {
+        return Ok((name, None));
+    }
+    match rest.strip_prefix([' ', '\t']) {
+        Some(rest) => Ok((name, Some(rest))),
+        None => Err(format!("expected a blank after the quoted metric name {:?}", name)),
+    }
+}
+
+fn validate_label_name(name: &str) -> Result<(), String> {
+    if is_valid_label_name(name) {
+        Ok(())
+    } else {
+        Err(format!("invalid label name {:?}", name))
+    }
+}
+
+/// Prometheus HELP text only escapes backslash and newline, OpenMetrics also escapes double
+/// quotes; other sequences are kept verbatim.
+fn unescape_help(help: &str, dialect: Dialect) -> String {
+    let mut result = String::with_capacity(help.len());
+    let mut chars = help.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('\\')) => {
+                chars.next();
+                result.push('\\');
+            },
+            ('\\', Some('n')) => {
+                chars.next();
+                result.push('\n');
+            },
+            ('\\', Some('"')) if dialect == Dialect::OpenMetrics => {
+                chars.next();
+                result.push('"');
+            },
+            (c, _) => result.push(c),
+        }
+    }
+    result
+}
+
+struct Cursor<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(input: &'a str) -> Self {
+        Self { rest: input }
+    }
+
+    fn rest(&self) -> &'a str {
+        self.rest
+    }
+
+    fn is_empty(&self) -> bool {
+        self.rest.is_empty()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        match self.rest.strip_prefix(c) {
+            Some(rest) => {
+                self.rest = rest;
+                true
+            },
+            None => false,
+        }
+    }
+
+    fn skip_blanks(&mut self) {
+        self.rest = self.rest.trim_start_matches([' ', '\t']);
+    }
+
+    fn take_while(&mut self, pred: impl Fn(char) -> bool) -> &'a str {
+        let end = self.rest.find(|c| !pred(c)).unwrap_or(self.rest.len());
+        let (taken, rest) = self.rest.split_at(end);
+        self.rest = rest;
+        taken
+    }
+
+    /// Reads an escaped label value up to and including its closing quote.
+    fn take_quoted(&mut self) -> Option<String> {
+        let mut chars = self.rest.char_indices();
+        while let Some((idx, c)) = chars.next() {
+            match c {
+                '"' => {
+                    let value = MetricLabel::unescape_value(&self.rest[..idx])?;
+                    self.rest = &self.rest[idx + 1..];
+                    return Some(value);
+                },
+                '\\' => {
+                    chars.next()?;
+                },
+                _ => (),
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use prometheus::{Encoder, Histogram, HistogramOpts, IntCounterVec, Opts, Registry};
+
+    use super::*;
+    use crate::labels;
+
+    fn registry() -> Registry {
+        let registry = Registry::new();
+        let requests = IntCounterVec::new(Opts::new("requests_total", "Requests served."), &["code"]).unwrap();
+        requests.with_label_values(&["200"]).inc_by(3);
+        requests.with_label_values(&["500"]).inc();
+        let latency =
+            Histogram::with_opts(HistogramOpts::new("latency_seconds", "Latency.").buckets(vec![0.1, 1_f64])).unwrap();
+        latency.observe(0.05);
+        latency.observe(0.5);
+        registry.register(Box::new(requests)).unwrap();
+        registry.register(Box::new(latency)).unwrap();
+        registry
+    }
+
+    fn syntax_error(line: usize, message: &str) -> ParseError {
+        ParseError::Syntax { line, message: message.to_string() }
+    }
+
+    #[test]
+    fn parses_what_the_prometheus_text_encoder_writes() {
+        let registry = registry();
+        let mut text = Vec::new();
+        prometheus::TextEncoder::new().encode(&registry.gather(), &mut text).unwrap();
+        let parsed = parse_text(std::str::from_utf8(&text).unwrap()).unwrap();
+        let distilled = crate::distill_registry(&registry);
+        assert_eq!(parsed[1], distilled[1]);
+
+        // the text format lists the +Inf bucket the protobuf exposition leaves implicit
+        let (parsed, distilled) =
+            (parsed[0].metrics[0].histogram().unwrap(), distilled[0].metrics[0].histogram().unwrap());
+        assert_eq!((parsed.sample_count, parsed.sample_sum), (distilled.sample_count, distilled.sample_sum));
+        assert_eq!(
+            parsed.buckets.split_last().unwrap(),
+            (
+                &HistogramBucket {
+                    upper_bound: f64::INFINITY,
+                    cumulative_count: 2,
+                    exemplar: None
+                },
+                distilled.buckets
+            )
+        );
+    }
+
+    #[test]
+    fn parses_what_the_prometheus_protobuf_encoder_writes() {
+        let registry = registry();
+        let mut bytes = Vec::new();
+        prometheus::ProtobufEncoder::new().encode(&registry.gather(), &mut bytes).unwrap();
+        assert_eq!(Format::sniff(&bytes), Format::Protobuf);
+        assert_eq!(parse_bytes(&bytes, Format::Protobuf).unwrap(), crate::distill_registry(&registry));
+        assert!(matches!(parse_protobuf(&bytes[..bytes.len() - 1]), Err(ParseError::Protobuf(_))));
+    }
+
+    #[test]
+    fn round_trips_rendered_text() {
+        let families = vec![
+            MetricFamily::builder("requests_total")
+                .help("Requests \"served\"\n")
+                .counter(labels! {"code" => "200"}, 3_f64)
+                .build(),
+            MetricFamily::builder("temperature").gauge(labels! {"room" => "a\\b"}, -0.5).build(),
+            MetricFamily::builder("latency_seconds")
+                .histogram(vec![], &[(0.1, 2), (1_f64, 5), (f64::INFINITY, 6)], 2.5)
+                .build(),
+            MetricFamily::builder("rpc_seconds").summary(vec![], &[(0.5, 0.2), (0.99, 0.9)], 10, 4_f64).build(),
+            MetricFamily::builder("queue_depth").untyped(vec![], 7_f64).build(),
+        ];
+        let text = crate::render::text(&families);
+        assert_eq!(parse_text(&text).unwrap(), families);
+        assert_eq!(crate::render::text(&parse_text(&text).unwrap()), text);
+    }
+
+    #[test]
+    fn merges_families_that_are_not_contiguous() {
+        let text = "# TYPE a_total counter\na_total{x=\"1\"} 1\n# TYPE b gauge\nb 2\na_total{x=\"2\"} 3\n";
+        let families = parse_text(text).unwrap();
+        let names: Vec<_> = families.iter().map(|family| family.name.as_str()).collect();
+        assert_eq!(names, vec!["a_total", "b"]);
+        assert_eq!(families[0].metrics.len(), 2);
+        assert_eq!(families[0].kind, MetricKind::Counter);
+    }
+
+    #[test]
+    fn streams_families_that_are_not_contiguous_more_than_once() {
+        let text = "a 1\nb 2\na{x=\"2\"} 3\n";
+        let names: Vec<_> = stream_text(text.as_bytes()).map(|family| family.unwrap().name).collect();
+        assert_eq!(names, vec!["a", "b", "a"]);
+    }
+
+    #[test]
+    fn parses_openmetrics_units_exemplars_and_created_samples() {
+        let text = "# TYPE requests counter\n# UNIT requests requests\n\
+                    requests_total{code=\"200\"} 3 # {trace_id=\"abc\"} 1 1700000000.5\n\
+                    requests_created{code=\"200\"} 1700000000\n# EOF\n";
+        let families = parse_openmetrics(text).unwrap();
+        assert_eq!(families.len(), 1);
+        let family = &families[0];
+        assert_eq!((family.name.as_str(), family.unit.as_deref()), ("requests_total", Some("requests")));
+        let metric = &family.metrics[0];
+        assert_eq!(metric.value(), Some(3_f64));
+        assert_eq!(metric.created(), Some(1_700_000_000_f64));
+        let exemplar = metric.exemplar().unwrap();
+        assert_eq!(
+            (exemplar.labels.clone(), exemplar.value, exemplar.timestamp),
+            (labels! {"trace_id" => "abc"}, 1_f64, Some(1_700_000_000.5))
+        );
+    }
+
+    #[test]
+    fn requires_the_openmetrics_eof_marker() {
+        assert_eq!(parse_openmetrics("up 1\n"), Err(ParseError::MissingEof));
+        assert_eq!(parse_openmetrics("up 1\n# EOF\nup 2\n"), Err(syntax_error(3, "unexpected content after # EOF")));
+        assert_eq!(Format::sniff(b"up 1\n# EOF\n"), Format::OpenMetrics);
+        assert_eq!(Format::sniff(b"up 1\n"), Format::Text);
+    }
+
+    #[test]
+    fn identifies_formats_from_content_types() {
+        let protobuf = "application/vnd.google.protobuf; proto=io.prometheus.client.MetricFamily; encoding=delimited";
+        assert_eq!(Format::from_content_type(protobuf), Format::Protobuf);
+        assert_eq!(Format::from_content_type("application/openmetrics-text; version=1.0.0"), Format::OpenMetrics);
+        assert_eq!(Format::from_content_type("text/plain; version=0.0.4"), Format::Text);
+        assert_eq!(Format::from_content_type(""), Format::Text);
+    }
+
+    #[test]
+    fn rejects_invalid_counts() {
+        let histogram =
+            |count: &str| format!("# TYPE h histogram\nh_bucket{{le=\"+Inf\"}} 1\nh_sum 1\nh_count {count}\n");
+        assert_eq!(parse_text(&histogram("2")).unwrap()[0].metrics[0].count(), 2);
+        assert_eq!(parse_text(&histogram("-1")), Err(syntax_error(4, "h_count has an invalid count -1")));
+        assert_eq!(parse_text(&histogram("1.5")), Err(syntax_error(4, "h_count has an invalid count 1.5")));
+        assert_eq!(parse_text(&histogram("NaN")), Err(syntax_error(4, "h_count has an invalid count NaN")));
+        assert!(matches!(parse_text(&histogram("1e30")), Err(ParseError::Syntax { line: 4, .. })));
+    }
+
+    #[test]
+    fn reports_syntax_errors_by_line() {
+        assert_eq!(parse_text("up 1\nup\n"), Err(syntax_error(2, "sample up is missing a value")));
+        assert_eq!(parse_text("up one\n"), Err(syntax_error(1, "sample up has an invalid value \"one\"")));
+        assert_eq!(parse_text("# TYPE up sideways\n"), Err(syntax_error(1, "unknown metric type \"sideways\"")));
+        assert!(matches!(parse_text("up{job=\"a} 1\n"), Err(ParseError::Syntax { line: 1, .. })));
+        assert_eq!(
+            parse_text("up 1\n# TYPE up gauge\n"),
+            Err(syntax_error(2, "TYPE line for metric name up follows its samples"))
+        );
+        assert_eq!(
+            parse_text("# TYPE h histogram\nh_bucket{le=\"x\"} 1\n"),
+            Err(syntax_error(2, "h_bucket has an invalid le label value \"x\""))
+        );
+        assert_eq!(parse_bytes(&[0xff, b'\n'], Format::Text), Err(ParseError::Utf8));
+    }
+
+    #[test]
+    fn rejects_duplicate_labels_on_request() {
+        let text = "up{job=\"a\",job=\"b\"} 1\n";
+        assert_eq!(parse_text(text).unwrap()[0].metrics[0].labels().len(), 2);
+        let options = ParseOptions { reject_duplicate_labels: true, ..ParseOptions::default() };
+        assert_eq!(parse_text_with(text, options), Err(syntax_error(1, "sample up has more than one label named job")));
+    }
+
+    #[test]
+    fn accepts_quoted_utf8_names_on_request() {
+        let text = "# TYPE \"my.metric\" gauge\n{\"my.metric\",\"my label\"=\"x\"} 1\n";
+        assert!(parse_text(text).is_err());
+        let options = ParseOptions { utf8_names: true, ..ParseOptions::default() };
+        let families = parse_text_with(text, options).unwrap();
+        assert_eq!((families[0].name.as_str(), families[0].kind), ("my.metric", MetricKind::Gauge));
+        assert_eq!(families[0].metrics[0].label("my label"), Some("x"));
+    }
+}
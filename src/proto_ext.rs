@@ -0,0 +1,298 @@
+//! Recovers fields added to the `io.prometheus.client` model after the `prometheus` crate's
+//! generated proto types. protobuf keeps them as unknown fields when decoding exposition bytes.
+
+use protobuf::well_known_types::Timestamp;
+use protobuf::wire_format::WireType;
+use protobuf::{CodedInputStream, CodedOutputStream, Message, ProtobufResult, UnknownFields};
+
+use crate::{BucketSpan, Exemplar, MetricKind, MetricLabel};
+
+const FAMILY_TYPE: u32 = 3;
+const GAUGE_HISTOGRAM_TYPE: u64 = 5;
+const FAMILY_UNIT: u32 = 5;
+
+const COUNTER_EXEMPLAR: u32 = 2;
+const COUNTER_CREATED: u32 = 3;
+const SUMMARY_CREATED: u32 = 4;
+const HISTOGRAM_CREATED: u32 = 15;
+const BUCKET_EXEMPLAR: u32 = 3;
+
+const HISTOGRAM_SCHEMA: u32 = 5;
+const HISTOGRAM_ZERO_THRESHOLD: u32 = 6;
+const HISTOGRAM_ZERO_COUNT: u32 = 7;
+const HISTOGRAM_NEGATIVE_SPAN: u32 = 9;
+const HISTOGRAM_NEGATIVE_DELTA: u32 = 10;
+const HISTOGRAM_POSITIVE_SPAN: u32 = 12;
+const HISTOGRAM_POSITIVE_DELTA: u32 = 13;
+
+const SPAN_OFFSET: u32 = 1;
+const SPAN_LENGTH: u32 = 2;
+
+const EXEMPLAR_LABEL: u32 = 1;
+const EXEMPLAR_VALUE: u32 = 2;
+const EXEMPLAR_TIMESTAMP: u32 = 3;
+
+/// Metric types unknown to the generated `MetricType` enum are retained as unknown varints.
+pub(crate) fn family_kind(family: &prometheus::proto::MetricFamily) -> MetricKind {
+    let unknown_type = family.get_unknown_fields().get(FAMILY_TYPE).and_then(|values| values.varint.last()).copied();
+    match unknown_type {
+        Some(GAUGE_HISTOGRAM_TYPE) if !family.has_field_type() => MetricKind::GaugeHistogram,
+        _ => MetricKind::from(family.get_field_type()),
+    }
+}
+
+pub(crate) fn family_unit(family: &prometheus::proto::MetricFamily) -> Option<String> {
+    last_message(family.get_unknown_fields(), FAMILY_UNIT)
+        .map(|bytes| String::from_utf8_lossy(bytes).into_owned())
+        .filter(|unit| !unit.is_empty())
+}
+
+pub(crate) fn counter_exemplar(counter: &prometheus::proto::Counter) -> Option<Exemplar> {
+    last_message(counter.get_unknown_fields(), COUNTER_EXEMPLAR)
+        .and_then(|bytes| decoded("exemplar", decode_exemplar(bytes)))
+}
+
+pub(crate) fn bucket_exemplar(bucket: &prometheus::proto::Bucket) -> Option<Exemplar> {
+    last_message(bucket.get_unknown_fields(), BUCKET_EXEMPLAR)
+        .and_then(|bytes| decoded("exemplar", decode_exemplar(bytes)))
+}
+
+pub(crate) struct NativeHistogram {
+    pub schema: i32,
+    pub zero_threshold: f64,
+    pub zero_count: u64,
+    pub positive_spans: Vec<BucketSpan>,
+    pub positive_deltas: Vec<i64>,
+    pub negative_spans: Vec<BucketSpan>,
+    pub negative_deltas: Vec<i64>,
+}
+
+pub(crate) fn counter_created(counter: &prometheus::proto::Counter) -> Option<f64> {
+    created_timestamp(counter.get_unknown_fields(), COUNTER_CREATED)
+}
+
+pub(crate) fn summary_created(summary: &prometheus::proto::Summary) -> Option<f64> {
+    created_timestamp(summary.get_unknown_fields(), SUMMARY_CREATED)
+}
+
+pub(crate) fn histogram_created(histogram: &prometheus::proto::Histogram) -> Option<f64> {
+    created_timestamp(histogram.get_unknown_fields(), HISTOGRAM_CREATED)
+}
+
+fn created_timestamp(fields: &UnknownFields, field_number: u32) -> Option<f64> {
+    last_message(fields, field_number)
+        .and_then(|bytes| decoded("created timestamp", Timestamp::parse_from_bytes(bytes)))
+        .map(|ts| seconds_from(&ts))
+}
+
+/// A histogram is native (sparse) when it declares a bucket schema.
+pub(crate) fn native_histogram(histogram: &prometheus::proto::Histogram) -> Option<NativeHistogram> {
+    let fields = histogram.get_unknown_fields();
+    let schema = fields.get(HISTOGRAM_SCHEMA).and_then(|values| values.varint.last()).map(|v| zigzag32(*v))?;
+
+    let spans = |field_number| {
+        fields
+            .get(field_number)
+            .map(|values| {
+                values.length_delimited.iter().filter_map(|bytes| decoded("bucket span", decode_span(bytes))).collect()
+            })
+            .unwrap_or_default()
+    };
+
+    Some(NativeHistogram {
+        schema,
+        zero_threshold: fields
+            .get(HISTOGRAM_ZERO_THRESHOLD)
+            .and_then(|values| values.fixed64.last())
+            .map(|bits| f64::from_bits(*bits))
+            .unwrap_or(0_f64),
+        zero_count: fields.get(HISTOGRAM_ZERO_COUNT).and_then(|values| values.varint.last()).copied().unwrap_or(0),
+        positive_spans: spans(HISTOGRAM_POSITIVE_SPAN),
+        positive_deltas: sint64s(fields, HISTOGRAM_POSITIVE_DELTA),
+        negative_spans: spans(HISTOGRAM_NEGATIVE_SPAN),
+        negative_deltas: sint64s(fields, HISTOGRAM_NEGATIVE_DELTA),
+    })
+}
+
+/// Repeated scalars may arrive packed (length delimited) or one varint per element.
+fn sint64s(fields: &UnknownFields, field_number: u32) -> Vec<i64> {
+    let mut result = Vec::new();
+    if let Some(values) = fields.get(field_number) {
+        for packed in values.length_delimited.iter() {
+            let mut is = CodedInputStream::from_bytes(packed);
+            if let Some(mut unpacked) = decoded("packed deltas", read_packed_sint64(&mut is)) {
+                result.append(&mut unpacked);
+            }
+        }
+        result.extend(values.varint.iter().map(|v| zigzag64(*v)));
+    }
+    result
+}
+
+fn read_packed_sint64(is: &mut CodedInputStream) -> ProtobufResult<Vec<i64>> {
+    let mut values = Vec::new();
+    while !is.eof()? {
+        values.push(is.read_sint64()?);
+    }
+    Ok(values)
+}
+
+fn decode_span(bytes: &[u8]) -> ProtobufResult<BucketSpan> {
+    let mut is = CodedInputStream::from_bytes(bytes);
+    let mut span = BucketSpan { offset: 0, length: 0 };
+
+    while !is.eof()? {
+        match is.read_tag_unpack()? {
+            (SPAN_OFFSET, WireType::WireTypeVarint) => span.offset = is.read_sint32()?,
+            (SPAN_LENGTH, WireType::WireTypeVarint) => span.length = is.read_uint32()?,
+            (_, wire_type) => is.skip_field(wire_type)?,
+        }
+    }
+
+    Ok(span)
+}
+
+fn zigzag32(value: u64) -> i32 {
+    let value = value as u32;
+    ((value >> 1) as i32) ^ -((value & 1) as i32)
+}
+
+fn zigzag64(value: u64) -> i64 {
+    ((value >> 1) as i64) ^ -((value & 1) as i64)
+}
+
+fn last_message(fields: &UnknownFields, field_number: u32) -> Option<&[u8]> {
+    fields.get(field_number).and_then(|values| values.length_delimited.last()).map(|bytes| bytes.as_slice())
+}
+
+fn decoded<T>(what: &str, result: ProtobufResult<T>) -> Option<T> {
+    match result {
+        Ok(value) => Some(value),
+        Err(err) => {
+            tracing::warn!("failed to decode prometheus::proto {}: {}", what, err);
+            None
+        },
+    }
+}
+
+fn decode_exemplar(bytes: &[u8]) -> ProtobufResult<Exemplar> {
+    let mut is = CodedInputStream::from_bytes(bytes);
+    let mut exemplar = Exemplar { labels: Vec::new(), value: 0_f64, timestamp: None };
+
+    while !is.eof()? {
+        match is.read_tag_unpack()? {
+            (EXEMPLAR_LABEL, WireType::WireTypeLengthDelimited) => {
+                let label: prometheus::proto::LabelPair = is.read_message()?;
+                exemplar.labels.push(MetricLabel {
+                    name: label.get_name().to_string(),
+                    value: label.get_value().to_string(),
+                });
+            },
+            (EXEMPLAR_VALUE, WireType::WireTypeFixed64) => exemplar.value = is.read_double()?,
+            (EXEMPLAR_TIMESTAMP, WireType::WireTypeLengthDelimited) => {
+                let ts: Timestamp = is.read_message()?;
+                exemplar.timestamp = Some(seconds_from(&ts));
+            },
+            (_, wire_type) => is.skip_field(wire_type)?,
+        }
+    }
+
+    Ok(exemplar)
+}
+
+fn seconds_from(ts: &Timestamp) -> f64 {
+    ts.seconds as f64 + f64::from(ts.nanos) / 1e9
+}
+
+pub(crate) fn set_family_unit(family: &mut prometheus::proto::MetricFamily, unit: &str) {
+    family.mut_unknown_fields().add_length_delimited(FAMILY_UNIT, unit.as_bytes().to_vec());
+}
+
+/// `GAUGE_HISTOGRAM` is written as an unknown type varint in place of the generated enum field.
+pub(crate) fn set_gauge_histogram(family: &mut prometheus::proto::MetricFamily) {
+    family.clear_field_type();
+    family.mut_unknown_fields().add_varint(FAMILY_TYPE, GAUGE_HISTOGRAM_TYPE);
+}
+
+pub(crate) fn set_counter_exemplar(counter: &mut prometheus::proto::Counter, exemplar: &Exemplar) {
+    counter.mut_unknown_fields().add_length_delimited(COUNTER_EXEMPLAR, encode_exemplar(exemplar));
+}
+
+pub(crate) fn set_bucket_exemplar(bucket: &mut prometheus::proto::Bucket, exemplar: &Exemplar) {
+    bucket.mut_unknown_fields().add_length_delimited(BUCKET_EXEMPLAR, encode_exemplar(exemplar));
+}
+
+pub(crate) fn set_counter_created(counter: &mut prometheus::proto::Counter, created: f64) {
+    counter.mut_unknown_fields().add_length_delimited(COUNTER_CREATED, encode_timestamp(created));
+}
+
+pub(crate) fn set_summary_created(summary: &mut prometheus::proto::Summary, created: f64) {
+    summary.mut_unknown_fields().add_length_delimited(SUMMARY_CREATED, encode_timestamp(created));
+}
+
+pub(crate) fn set_histogram_created(histogram: &mut prometheus::proto::Histogram, created: f64) {
+    histogram.mut_unknown_fields().add_length_delimited(HISTOGRAM_CREATED, encode_timestamp(created));
+}
+
+pub(crate) fn set_native_histogram(histogram: &mut prometheus::proto::Histogram, native: &NativeHistogram) {
+    let fields = histogram.mut_unknown_fields();
+    fields.add_varint(HISTOGRAM_SCHEMA, u64::from(((native.schema << 1) ^ (native.schema >> 31)) as u32));
+    fields.add_fixed64(HISTOGRAM_ZERO_THRESHOLD, native.zero_threshold.to_bits());
+    fields.add_varint(HISTOGRAM_ZERO_COUNT, native.zero_count);
+    for (span_field, delta_field, spans, deltas) in [
+        (HISTOGRAM_NEGATIVE_SPAN, HISTOGRAM_NEGATIVE_DELTA, &native.negative_spans, &native.negative_deltas),
+        (HISTOGRAM_POSITIVE_SPAN, HISTOGRAM_POSITIVE_DELTA, &native.positive_spans, &native.positive_deltas),
+    ] {
+        for span in spans.iter() {
+            fields.add_length_delimited(
+                span_field,
+                encoded(|os| {
+                    os.write_sint32(SPAN_OFFSET, span.offset)?;
+                    os.write_uint32(SPAN_LENGTH, span.length)
+                }),
+            );
+        }
+        if !deltas.is_empty() {
+            fields.add_length_delimited(
+                delta_field,
+                encoded(|os| deltas.iter().try_for_each(|d| os.write_sint64_no_tag(*d))),
+            );
+        }
+    }
+}
+
+fn encode_exemplar(exemplar: &Exemplar) -> Vec<u8> {
+    encoded(|os| {
+        for label in exemplar.labels.iter() {
+            let mut pair = prometheus::proto::LabelPair::new();
+            pair.set_name(label.name.clone());
+            pair.set_value(label.value.clone());
+            os.write_message(EXEMPLAR_LABEL, &pair)?;
+        }
+        os.write_double(EXEMPLAR_VALUE, exemplar.value)?;
+        match exemplar.timestamp {
+            Some(timestamp) => os.write_message(EXEMPLAR_TIMESTAMP, &timestamp_from(timestamp)),
+            None => Ok(()),
+        }
+    })
+}
+
+fn encode_timestamp(seconds: f64) -> Vec<u8> {
+    encoded(|os| timestamp_from(seconds).write_to(os))
+}
+
+pub(crate) fn encoded(write: impl FnOnce(&mut CodedOutputStream) -> ProtobufResult<()>) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut os = CodedOutputStream::vec(&mut bytes);
+    // writing into a Vec cannot fail
+    write(&mut os).and_then(|_| os.flush()).expect("in-memory protobuf encoding");
+    drop(os);
+    bytes
+}
+
+fn timestamp_from(seconds: f64) -> Timestamp {
+    let mut ts = Timestamp::new();
+    ts.seconds = seconds.floor() as i64;
+    ts.nanos = ((seconds - seconds.floor()) * 1e9).round().min(999_999_999_f64) as i32;
+    ts
+}
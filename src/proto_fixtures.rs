@@ -0,0 +1,159 @@
+//! Builders for synthetic `prometheus::proto` families, for testing [`crate::distill_metric_state`]
+//! and tools built on it without assembling the generated types by hand:
+//!
+//! ```
+//! use inspect_prometheus::proto_fixtures;
+//!
+//! let families = vec![
+//!     proto_fixtures::counter("http_requests_total").help("Requests").counter(&[("code", "200")], 7.0).build(),
+//!     proto_fixtures::histogram("latency_seconds").histogram(&[], &[(0.1, 4), (1.0, 9)], 10, 3.5).build(),
+//! ];
+//! let state = inspect_prometheus::MetricState::distill_from(families);
+//! assert_eq!(state.counter("http_requests_total", &[("code", "200")]), Some(7.0));
+//! ```
+//!
+//! Series are added to the family whatever its type, so fixtures may also hold the mismatched
+//! metrics real exporters occasionally produce.
+
+use prometheus::proto::{self, MetricType};
+
+use crate::proto_ext;
+
+pub fn counter(name: &str) -> ProtoFamilyBuilder {
+    ProtoFamilyBuilder::new(name, MetricType::COUNTER)
+}
+
+pub fn gauge(name: &str) -> ProtoFamilyBuilder {
+    ProtoFamilyBuilder::new(name, MetricType::GAUGE)
+}
+
+pub fn histogram(name: &str) -> ProtoFamilyBuilder {
+    ProtoFamilyBuilder::new(name, MetricType::HISTOGRAM)
+}
+
+/// A gauge histogram family, encoded in the newer field the generated `MetricType` lacks.
+pub fn gauge_histogram(name: &str) -> ProtoFamilyBuilder {
+    let mut builder = ProtoFamilyBuilder::new(name, MetricType::HISTOGRAM);
+    proto_ext::set_gauge_histogram(&mut builder.family);
+    builder
+}
+
+pub fn summary(name: &str) -> ProtoFamilyBuilder {
+    ProtoFamilyBuilder::new(name, MetricType::SUMMARY)
+}
+
+pub fn untyped(name: &str) -> ProtoFamilyBuilder {
+    ProtoFamilyBuilder::new(name, MetricType::UNTYPED)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProtoFamilyBuilder {
+    family: proto::MetricFamily,
+}
+
+impl ProtoFamilyBuilder {
+    fn new(name: &str, metric_type: MetricType) -> Self {
+        let mut family = proto::MetricFamily::new();
+        family.set_name(name.to_string());
+        family.set_field_type(metric_type);
+        Self { family }
+    }
+
+    pub fn help(mut self, help: &str) -> Self {
+        self.family.set_help(help.to_string());
+        self
+    }
+
+    pub fn unit(mut self, unit: &str) -> Self {
+        proto_ext::set_family_unit(&mut self.family, unit);
+        self
+    }
+
+    pub fn counter(self, labels: &[(&str, &str)], value: f64) -> Self {
+        self.push(labels, |metric| metric.mut_counter().set_value(value))
+    }
+
+    pub fn gauge(self, labels: &[(&str, &str)], value: f64) -> Self {
+        self.push(labels, |metric| metric.mut_gauge().set_value(value))
+    }
+
+    pub fn untyped(self, labels: &[(&str, &str)], value: f64) -> Self {
+        self.push(labels, |metric| metric.mut_untyped().set_value(value))
+    }
+
+    /// A classic histogram series from its `(upper_bound, cumulative_count)` buckets. As in the
+    /// `prometheus` crate's own output, the `+Inf` bucket is implied by `count`.
+    pub fn histogram(self, labels: &[(&str, &str)], buckets: &[(f64, u64)], count: u64, sum: f64) -> Self {
+        self.push(labels, |metric| {
+            let histogram = metric.mut_histogram();
+            histogram.set_sample_count(count);
+            histogram.set_sample_sum(sum);
+            for (upper_bound, cumulative_count) in buckets.iter() {
+                let mut bucket = proto::Bucket::new();
+                bucket.set_upper_bound(*upper_bound);
+                bucket.set_cumulative_count(*cumulative_count);
+                histogram.mut_bucket().push(bucket);
+            }
+        })
+    }
+
+    /// A summary series from its `(quantile, value)` pairs.
+    pub fn summary(self, labels: &[(&str, &str)], quantiles: &[(f64, f64)], count: u64, sum: f64) -> Self {
+        self.push(labels, |metric| {
+            let summary = metric.mut_summary();
+            summary.set_sample_count(count);
+            summary.set_sample_sum(sum);
+            for (quantile, value) in quantiles.iter() {
+                let mut q = proto::Quantile::new();
+                q.set_quantile(*quantile);
+                q.set_value(*value);
+                summary.mut_quantile().push(q);
+            }
+        })
+    }
+
+    /// Adds an already assembled metric.
+    pub fn metric(mut self, metric: proto::Metric) -> Self {
+        self.family.mut_metric().push(metric);
+        self
+    }
+
+    /// Sets the timestamp of the last added series.
+    pub fn timestamp_ms(mut self, timestamp_ms: i64) -> Self {
+        if let Some(metric) = self.family.mut_metric().last_mut() {
+            metric.set_timestamp_ms(timestamp_ms);
+        }
+        self
+    }
+
+    /// Sets the created timestamp, in seconds, of the last added counter, histogram or summary.
+    pub fn created(mut self, created: f64) -> Self {
+        if let Some(metric) = self.family.mut_metric().last_mut() {
+            if metric.has_counter() {
+                proto_ext::set_counter_created(metric.mut_counter(), created);
+            } else if metric.has_histogram() {
+                proto_ext::set_histogram_created(metric.mut_histogram(), created);
+            } else if metric.has_summary() {
+                proto_ext::set_summary_created(metric.mut_summary(), created);
+            }
+        }
+        self
+    }
+
+    pub fn build(self) -> proto::MetricFamily {
+        self.family
+    }
+
+    fn push(mut self, labels: &[(&str, &str)], set_value: impl FnOnce(&mut proto::Metric)) -> Self {
+        let mut metric = proto::Metric::new();
+        for (name, value) in labels.iter() {
+            let mut pair = proto::LabelPair::new();
+            pair.set_name(name.to_string());
+            pair.set_value(value.to_string());
+            metric.mut_label().push(pair);
+        }
+        set_value(&mut metric);
+        self.family.mut_metric().push(metric);
+        self
+    }
+}
@@ -0,0 +1,145 @@
+//! A chainable query over one family of a [`MetricState`], composing label matchers with a
+//! terminal aggregation:
+//!
+//! ```
+//! # let state = inspect_prometheus::MetricState::default();
+//! let errors = state.query("http_requests_total").label_eq("method", "GET").label_regex("status", "5..").sum();
+//! ```
+
+use crate::matcher::LabelMatcher;
+use crate::{Metric, MetricState};
+
+#[derive(Debug, Clone)]
+pub struct Query<'a> {
+    state: &'a MetricState,
+    name: String,
+    matchers: Vec<LabelMatcher>,
+}
+
+impl<'a> Query<'a> {
+    pub(crate) fn new(state: &'a MetricState, name: impl Into<String>) -> Self {
+        Self { state, name: name.into(), matchers: Vec::new() }
+    }
+
+    pub fn label_eq(self, name: &str, value: &str) -> Self {
+        self.matching(LabelMatcher::eq(name, value))
+    }
+
+    pub fn label_ne(self, name: &str, value: &str) -> Self {
+        self.matching(LabelMatcher::not_eq(name, value))
+    }
+
+    /// Keeps series whose label fully matches `pattern`, as PromQL's `=~` does.
+    ///
+    /// # Panics
+    /// If `pattern` is not a valid regular expression; use [`Query::matching`] with
+    /// [`LabelMatcher::regex`] to handle that case.
+    pub fn label_regex(self, name: &str, pattern: &str) -> Self {
+        self.matching(LabelMatcher::regex(name, pattern).unwrap_or_else(|err| panic!("{err}")))
+    }
+
+    /// Keeps series whose label does not fully match `pattern`, as PromQL's `!~` does.
+    ///
+    /// # Panics
+    /// If `pattern` is not a valid regular expression.
+    pub fn label_not_regex(self, name: &str, pattern: &str) -> Self {
+        self.matching(LabelMatcher::not_regex(name, pattern).unwrap_or_else(|err| panic!("{err}")))
+    }
+
+    pub fn matching(mut self, matcher: LabelMatcher) -> Self {
+        self.matchers.push(matcher);
+        self
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &'a Metric> + '_ {
+        self.state.family(&self.name).into_iter().flat_map(|family| family.metrics.iter()).filter(|metric| {
+            let labels = metric.labels();
+            self.matchers.iter().all(|matcher| matcher.matches(labels))
+        })
+    }
+
+    pub fn series(&self) -> Vec<&'a Metric> {
+        self.iter().collect()
+    }
+
+    pub fn first(&self) -> Option<&'a Metric> {
+        self.iter().next()
+    }
+
+    pub fn exists(&self) -> bool {
+        self.first().is_some()
+    }
+
+    pub fn count(&self) -> usize {
+        self.iter().count()
+    }
+
+    /// The summed value of the selected series; histograms and summaries contribute their sum.
+    pub fn sum(&self) -> f64 {
+        self.iter().map(Metric::sum).sum()
+    }
+
+    pub fn avg(&self) -> Option<f64> {
+        let count = self.count();
+        (0 < count).then(|| self.sum() / count as f64)
+    }
+
+    pub fn min(&self) -> Option<f64> {
+        self.iter().map(Metric::sum).reduce(f64::min)
+    }
+
+    pub fn max(&self) -> Option<f64> {
+        self.iter().map(Metric::sum).reduce(f64::max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{labels, MetricFamily};
+
+    fn state() -> MetricState {
+        MetricState::from(vec![MetricFamily::builder("http_requests_total")
+            .counter(labels! {"method" => "GET", "status" => "200"}, 10_f64)
+            .counter(labels! {"method" => "GET", "status" => "503"}, 2_f64)
+            .counter(labels! {"method" => "GET", "status" => "500"}, 1_f64)
+            .counter(labels! {"method" => "POST", "status" => "502"}, 4_f64)
+            .build()])
+    }
+
+    #[test]
+    fn composes_matchers() {
+        let state = state();
+        let errors = state.query("http_requests_total").label_eq("method", "GET").label_regex("status", "5..");
+        assert_eq!((errors.count(), errors.sum()), (2, 3_f64));
+        assert_eq!((errors.min(), errors.max(), errors.avg()), (Some(1_f64), Some(2_f64), Some(1.5)));
+        assert_eq!(errors.first().and_then(|m| m.label("status")), Some("503"));
+
+        let others = state.query("http_requests_total").label_ne("method", "GET").label_not_regex("status", "2..");
+        let statuses: Vec<_> = others.series().iter().filter_map(|m| m.label("status")).collect();
+        assert_eq!(statuses, vec!["502"]);
+    }
+
+    #[test]
+    fn anchors_regexes_like_promql() {
+        let state = state();
+        assert!(!state.query("http_requests_total").label_regex("status", "5").exists());
+        assert_eq!(state.query("http_requests_total").label_regex("status", "50[03]|200").count(), 3);
+    }
+
+    #[test]
+    fn selects_nothing_from_missing_families() {
+        let state = state();
+        let missing = state.query("errors_total");
+        assert!(!missing.exists());
+        assert_eq!((missing.count(), missing.sum()), (0, 0_f64));
+        assert_eq!((missing.avg(), missing.min(), missing.max()), (None, None, None));
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid regex \"5(\" at offset 1: unclosed group")]
+    fn panics_on_invalid_patterns() {
+        state().query("http_requests_total").label_regex("status", "5(");
+    }
+}
@@ -0,0 +1,368 @@
+//! Periodic capture of metric snapshots into an in-memory history, e.g. over a soak test.
+
+use std::collections::VecDeque;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
+
+use crate::{MetricFamily, Snapshot};
+
+#[derive(Debug, thiserror::Error)]
+pub enum RecordError {
+    #[cfg(feature = "scrape")]
+    #[error("failed to scrape metrics: {0}")]
+    Scrape(#[from] crate::scrape::ScrapeError),
+
+    #[error("metric source failed: {0}")]
+    Source(String),
+}
+
+/// Bounds the recorded history; the oldest snapshots are evicted after each capture once either
+/// limit is exceeded.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct Retention {
+    pub max_snapshots: Option<usize>,
+    pub max_age: Option<Duration>,
+}
+
+impl Retention {
+    pub fn unbounded() -> Self {
+        Self::default()
+    }
+
+    pub fn max_snapshots(max_snapshots: usize) -> Self {
+        Self { max_snapshots: Some(max_snapshots), ..Self::default() }
+    }
+
+    pub fn max_age(max_age: Duration) -> Self {
+        Self { max_age: Some(max_age), ..Self::default() }
+    }
+
+    fn evict(&self, history: &mut VecDeque<Snapshot>) {
+        if let Some(max_snapshots) = self.max_snapshots {
+            while max_snapshots < history.len() {
+                history.pop_front();
+            }
+        }
+        if let Some(max_age) = self.max_age {
+            let now = SystemTime::now();
+            while history
+                .front()
+                .is_some_and(|oldest| now.duration_since(oldest.captured_at).is_ok_and(|age| max_age < age))
+            {
+                history.pop_front();
+            }
+        }
+    }
+}
+
+type Source = Box<dyn FnMut() -> Result<Vec<MetricFamily>, RecordError> + Send>;
+
+/// Captures snapshots from a source every `interval` on a background thread once started, or on
+/// demand with [`MetricRecorder::capture`], keeping them as the [`Retention`] allows. Failed
+/// captures are logged and skipped.
+pub struct MetricRecorder {
+    source: Arc<Mutex<Source>>,
+    interval: Duration,
+    retention: Retention,
+    history: Arc<Mutex<VecDeque<Snapshot>>>,
+    worker: Option<(Sender<()>, JoinHandle<()>)>,
+}
+
+impl MetricRecorder {
+    pub fn new(
+        source: impl FnMut() -> Result<Vec<MetricFamily>, RecordError> + Send + 'static,
+        interval: Duration,
+    ) -> Self {
+        Self {
+            source: Arc::new(Mutex::new(Box::new(source))),
+            interval,
+            retention: Retention::default(),
+            history: Arc::new(Mutex::new(VecDeque::new())),
+            worker: None,
+        }
+    }
+
+    pub fn from_fn(mut source: impl FnMut() -> Vec<MetricFamily> + Send + 'static, interval: Duration) -> Self {
+        Self::new(move || Ok(source()), interval)
+    }
+
+    pub fn for_registry(registry: &prometheus::Registry, interval: Duration) -> Self {
+        let registry = registry.clone();
+        Self::from_fn(move || crate::distill_registry(&registry), interval)
+    }
+
+    #[cfg(feature = "scrape")]
+    pub fn for_url(url: impl Into<String>, interval: Duration) -> Self {
+        let url = url.into();
+        Self::new(move || Ok(crate::scrape::scrape_url(&url)?), interval)
+    }
+
+    pub fn with_retention(mut self, retention: Retention) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    pub fn retention(&self) -> Retention {
+        self.retention
+    }
+
+    /// Captures a snapshot right away, whether or not the recorder is running.
+    pub fn capture(&self) -> Result<(), RecordError> {
+        capture(&self.source, &self.history, self.retention)
+    }
+
+    /// Starts capturing every interval on a background thread, beginning immediately.
+    pub fn start(&mut self) {
+        if self.worker.is_some() {
+            return;
+        }
+
+        let (stop, stopped) = mpsc::channel();
+        let (source, history) = (Arc::clone(&self.source), Arc::clone(&self.history));
+        let (interval, retention) = (self.interval, self.retention);
+        let handle = thread::spawn(move || loop {
+            if let Err(err) = capture(&source, &history, retention) {
+                tracing::warn!(error = %err, "failed to capture metric snapshot");
+            }
+            match stopped.recv_timeout(interval) {
+                Err(RecvTimeoutError::Timeout) => continue,
+                Ok(()) | Err(RecvTimeoutError::Disconnected) => break,
+            }
+        });
+        self.worker = Some((stop, handle));
+    }
+
+    pub fn stop(&mut self) {
+        if let Some((stop, handle)) = self.worker.take() {
+            let _ = stop.send(());
+            let _ = handle.join();
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.worker.is_some()
+    }
+
+    /// The captured snapshots, oldest first.
+    pub fn history(&self) -> Vec<Snapshot> {
+        lock(&self.history).iter().cloned().collect()
+    }
+
+    pub fn latest(&self) -> Option<Snapshot> {
+        lock(&self.history).back().cloned()
+    }
+
+    pub fn len(&self) -> usize {
+        lock(&self.history).len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        lock(&self.history).is_empty()
+    }
+
+    pub fn clear(&self) {
+        lock(&self.history).clear();
+    }
+
+    /// Ships the recorded history to a remote-write endpoint; see [`crate::remote_write::push`].
+    #[cfg(feature = "remote-write")]
+    pub fn push_remote_write(&self, url: &str) -> Result<(), crate::scrape::ScrapeError> {
+        crate::remote_write::push(url, &self.history())
+    }
+}
+
+impl Drop for MetricRecorder {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+impl std::fmt::Debug for MetricRecorder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MetricRecorder")
+            .field("interval", &self.interval)
+            .field("retention", &self.retention)
+            .field("snapshots", &self.len())
+            .field("running", &self.is_running())
+            .finish()
+    }
+}
+
+fn capture(
+    source: &Mutex<Source>,
+    history: &Mutex<VecDeque<Snapshot>>,
+    retention: Retention,
+) -> Result<(), RecordError> {
+    let families = (lock(source))()?;
+    let mut history = lock(history);
+    history.push_back(Snapshot::new(families));
+    retention.evict(&mut history);
+    Ok(())
+}
+
+fn lock<T: ?Sized>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::time::Instant;
+
+    /// A recorder whose source counts its captures in a `captures_total` counter.
+    fn counting() -> MetricRecorder {
+        let captures = AtomicU64::new(0);
+        let source = move || {
+            let count = captures.fetch_add(1, Ordering::SeqCst) + 1;
+            vec![MetricFamily::builder("captures_total").counter(vec![], count as f64).build()]
+        };
+        MetricRecorder::from_fn(source, Duration::from_millis(5))
+    }
+
+    fn counts(snapshots: &[Snapshot]) -> Vec<f64> {
+        snapshots.iter().filter_map(|snapshot| snapshot.families[0].metrics[0].counter_value()).collect()
+    }
+
+    #[test]
+    fn captures_on_demand() {
+        let recorder = counting();
+        assert!(recorder.is_empty());
+        recorder.capture().unwrap();
+        recorder.capture().unwrap();
+        assert_eq!(counts(&recorder.history()), vec![1_f64, 2_f64]);
+        assert_eq!(recorder.latest().map(|latest| counts(&[latest])), Some(vec![2_f64]));
+        recorder.clear();
+        assert_eq!((recorder.len(), recorder.latest()), (0, None));
+    }
+
+    #[test]
+    fn skips_failed_captures() {
+        let recorder = MetricRecorder::new(|| Err(RecordError::Source("down".to_string())), Duration::from_secs(1));
+        let err = recorder.capture().unwrap_err();
+        assert_eq!(err.to_string(), "metric source failed: down");
+        assert!(recorder.is_empty());
+    }
+
+    #[test]
+    fn records_in_the_background_until_stopped() {
+        let mut recorder = counting();
+        recorder.start();
+        assert!(recorder.is_running());
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while recorder.len() < 3 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(1));
+        }
+        recorder.stop();
+        assert!(!recorder.is_running());
+
+        let recorded = recorder.len();
+        assert!(3 <= recorded, "recorded {recorded} snapshots");
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(recorder.len(), recorded);
+        assert_eq!(counts(&recorder.history()), (1..=recorded).map(|count| count as f64).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn records_registries() {
+        let registry = prometheus::Registry::new();
+        let counter = prometheus::IntCounter::new("jobs_total", "Jobs.").unwrap();
+        registry.register(Box::new(counter.clone())).unwrap();
+        let recorder = MetricRecorder::for_registry(&registry, Duration::from_secs(1));
+        counter.inc();
+        recorder.capture().unwrap();
+        counter.inc();
+        recorder.capture().unwrap();
+        assert_eq!(counts(&recorder.history()), vec![1_f64, 2_f64]);
+    }
+
+    #[test]
+    fn drops_the_oldest_snapshots_beyond_the_limit() {
+        let recorder = counting().with_retention(Retention::max_snapshots(3));
+        for _ in 0..4 {
+            recorder.capture().unwrap();
+        }
+        assert_eq!(counts(&recorder.history()), vec![2_f64, 3_f64, 4_f64]);
+    }
+
+    #[test]
+    fn evicts_snapshots_older_than_the_max_age() {
+        let now = SystemTime::now();
+        let snapshot = |age: Duration| Snapshot { captured_at: now - age, families: Vec::new() };
+        let mut history: VecDeque<_> =
+            [Duration::from_secs(3_600), Duration::from_secs(120), Duration::ZERO].map(snapshot).into();
+        Retention::max_age(Duration::from_secs(600)).evict(&mut history);
+        let captured: Vec<_> = history.iter().map(|snapshot| snapshot.captured_at).collect();
+        assert_eq!(captured, vec![now - Duration::from_secs(120), now]);
+
+        Retention::unbounded().evict(&mut history);
+        assert_eq!(history.len(), 2);
+    }
+
+    /// Answers `requests` HTTP requests with `status`, returning the bodies they carried.
+    #[cfg(feature = "remote-write")]
+    fn serve(status: u16, requests: usize) -> (String, JoinHandle<Vec<Vec<u8>>>) {
+        use std::io::{BufRead, BufReader, Read, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/api/v1/write", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let mut bodies = Vec::new();
+            for stream in listener.incoming().take(requests) {
+                let mut reader = BufReader::new(stream.unwrap());
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    match line.to_ascii_lowercase().trim_end().strip_prefix("content-length: ") {
+                        Some(value) => length = value.parse().unwrap(),
+                        None if line.trim_end().is_empty() => break,
+                        None => (),
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                bodies.push(body);
+                write!(reader.get_mut(), "HTTP/1.1 {status} Status\r\nContent-Length: 0\r\n\r\n").unwrap();
+            }
+            bodies
+        });
+        (url, server)
+    }
+
+    #[cfg(feature = "remote-write")]
+    #[test]
+    fn pushes_the_history_to_remote_write() {
+        let recorder = counting();
+        recorder.capture().unwrap();
+        recorder.capture().unwrap();
+        let (url, server) = serve(204, 2);
+        recorder.push_remote_write(&url).unwrap();
+
+        let pushed: Vec<_> = server
+            .join()
+            .unwrap()
+            .iter()
+            .map(|body| crate::remote_write::decode_write_request(body).unwrap()[0].metrics[0].counter_value())
+            .collect();
+        assert_eq!(pushed, vec![Some(1_f64), Some(2_f64)]);
+    }
+
+    #[cfg(feature = "remote-write")]
+    #[test]
+    fn stops_pushing_at_a_rejected_snapshot() {
+        let recorder = counting();
+        recorder.capture().unwrap();
+        recorder.capture().unwrap();
+        let (url, server) = serve(500, 1);
+        let err = recorder.push_remote_write(&url).unwrap_err();
+        assert!(matches!(err, crate::scrape::ScrapeError::Status(500)));
+        assert_eq!(server.join().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,617 @@
+//! A small RE2-style regular expression engine for label matchers, compiled to a Thompson NFA and
+//! run as a Pike VM, so matching is linear in the input.
+//!
+//! Supports literals, `.`, classes (`[a-z]`, `[^0-9]`), `\d \w \s` and their negations, groups
+//! (`(...)`, `(?:...)`), alternation, `* + ?` and `{n}`, `{n,}`, `{n,m}` repetition, the `^` and
+//! `$` anchors and a leading `(?i)` for case-insensitive matching. Lazy quantifiers are accepted
+//! and match like their greedy forms, since only whether a match exists is reported. Counts go up
+//! to 1000, and patterns whose program would exceed 100 000 instructions are rejected.
+
+use std::fmt;
+
+const MAX_REPEAT: u32 = 1000;
+/// Repetition copies its operand, so nested counted repetitions multiply; like RE2, patterns are
+/// bounded by the size of their compiled program rather than by each count alone.
+const MAX_PROGRAM: u64 = 100_000;
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("invalid regex {pattern:?} at offset {offset}: {message}")]
+pub struct RegexError {
+    pub pattern: String,
+    pub offset: usize,
+    pub message: String,
+}
+
+#[derive(Clone)]
+pub struct Regex {
+    pattern: String,
+    program: Vec<Inst>,
+    case_insensitive: bool,
+}
+
+impl fmt::Debug for Regex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Regex").field(&self.pattern).finish()
+    }
+}
+
+impl PartialEq for Regex {
+    fn eq(&self, other: &Self) -> bool {
+        self.pattern == other.pattern
+    }
+}
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Self, RegexError> {
+        let (body, case_insensitive) = match pattern.strip_prefix("(?i)") {
+            Some(body) => (body, true),
+            None => (pattern, false),
+        };
+        let offset = pattern.len() - body.len();
+
+        let mut parser = Parser { chars: body.chars().collect(), pos: 0 };
+        let node = parser.alternation().map_err(|(at, message)| RegexError {
+            pattern: pattern.to_string(),
+            offset: offset + at,
+            message: message.to_string(),
+        })?;
+        if parser.pos < parser.chars.len() {
+            return Err(RegexError {
+                pattern: pattern.to_string(),
+                offset: offset + parser.pos,
+                message: "unmatched `)`".to_string(),
+            });
+        }
+
+        if MAX_PROGRAM < program_size(&node) {
+            return Err(RegexError {
+                pattern: pattern.to_string(),
+                offset: 0,
+                message: format!("pattern compiles to more than {MAX_PROGRAM} instructions"),
+            });
+        }
+
+        let mut program = Vec::new();
+        compile(&node, &mut program);
+        program.push(Inst::Match);
+        Ok(Self { pattern: pattern.to_string(), program, case_insensitive })
+    }
+
+    /// Whether the whole of `text` matches, as PromQL label matchers and RE2's full match do.
+    pub fn is_full_match(&self, text: &str) -> bool {
+        self.run(text, true)
+    }
+
+    fn run(&self, text: &str, anchored: bool) -> bool {
+        let chars: Vec<char> = text.chars().collect();
+        let mut current = Threads::new(self.program.len());
+        let mut next = Threads::new(self.program.len());
+        self.add_thread(&mut current, 0, 0, chars.len());
+
+        for (pos, c) in chars.iter().enumerate() {
+            if !anchored && current.contains_match(&self.program) {
+                return true;
+            }
+            for idx in 0..current.len() {
+                let pc = current.pcs[idx];
+                let matched = match &self.program[pc] {
+                    Inst::Char(expected) => self.char_eq(*expected, *c),
+                    Inst::Any => *c != '\n',
+                    Inst::Class(class) => self.class_matches(class, *c),
+                    _ => false,
+                };
+                if matched {
+                    self.add_thread(&mut next, pc + 1, pos + 1, chars.len());
+                }
+            }
+            if !anchored {
+                self.add_thread(&mut next, 0, pos + 1, chars.len());
+            }
+            std::mem::swap(&mut current, &mut next);
+            next.clear();
+            if current.is_empty() {
+                return false;
+            }
+        }
+        current.contains_match(&self.program)
+    }
+
+    /// Follows jumps, splits and satisfied assertions from `pc` with an explicit stack, as a
+    /// program may chain more splits than the call stack could recurse through.
+    fn add_thread(&self, threads: &mut Threads, pc: usize, pos: usize, len: usize) {
+        let mut stack = std::mem::take(&mut threads.stack);
+        stack.push(pc);
+        while let Some(pc) = stack.pop() {
+            if !threads.insert(pc) {
+                continue;
+            }
+            match &self.program[pc] {
+                Inst::Jmp(to) => stack.push(*to),
+                Inst::Split(first, second) => stack.extend([*second, *first]),
+                Inst::AssertStart if pos == 0 => stack.push(pc + 1),
+                Inst::AssertEnd if pos == len => stack.push(pc + 1),
+                _ => (),
+            }
+        }
+        threads.stack = stack;
+    }
+
+    fn char_eq(&self, expected: char, c: char) -> bool {
+        expected == c || (self.case_insensitive && fold(expected) == fold(c))
+    }
+
+    fn class_matches(&self, class: &Class, c: char) -> bool {
+        let contains = |c: char| class.ranges.iter().any(|(lo, hi)| *lo <= c && c <= *hi);
+        let found = contains(c)
+            || (self.case_insensitive && (contains(fold(c)) || c.to_uppercase().next().is_some_and(contains)));
+        found != class.negated
+    }
+}
+
+fn fold(c: char) -> char {
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+/// A sparse set of program counters, in insertion (priority) order.
+struct Threads {
+    pcs: Vec<usize>,
+    seen: Vec<bool>,
+    /// Scratch space for [`Regex::add_thread`], kept to reuse its allocation.
+    stack: Vec<usize>,
+}
+
+impl Threads {
+    fn new(size: usize) -> Self {
+        Self {
+            pcs: Vec::with_capacity(size),
+            seen: vec![false; size],
+            stack: Vec::new(),
+        }
+    }
+
+    fn insert(&mut self, pc: usize) -> bool {
+        if self.seen[pc] {
+            return false;
+        }
+        self.seen[pc] = true;
+        self.pcs.push(pc);
+        true
+    }
+
+    fn len(&self) -> usize {
+        self.pcs.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pcs.is_empty()
+    }
+
+    fn contains_match(&self, program: &[Inst]) -> bool {
+        self.pcs.iter().any(|pc| matches!(program[*pc], Inst::Match))
+    }
+
+    fn clear(&mut self) {
+        for pc in self.pcs.drain(..) {
+            self.seen[pc] = false;
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Class {
+    ranges: Vec<(char, char)>,
+    negated: bool,
+}
+
+#[derive(Debug, Clone)]
+enum Inst {
+    Char(char),
+    Any,
+    Class(Class),
+    Split(usize, usize),
+    Jmp(usize),
+    AssertStart,
+    AssertEnd,
+    Match,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Empty,
+    Char(char),
+    Any,
+    Class(Class),
+    Start,
+    End,
+    Concat(Vec<Node>),
+    Alternate(Vec<Node>),
+    Repeat { node: Box<Node>, min: u32, max: Option<u32> },
+}
+
+/// The number of instructions [`compile`] emits for `node`, saturating rather than overflowing.
+fn program_size(node: &Node) -> u64 {
+    match node {
+        Node::Empty => 0,
+        Node::Char(_) | Node::Any | Node::Class(_) | Node::Start | Node::End => 1,
+        Node::Concat(nodes) => nodes.iter().fold(0, |size, node| size.saturating_add(program_size(node))),
+        Node::Alternate(branches) => branches
+            .iter()
+            .fold(2 * (branches.len() as u64).saturating_sub(1), |size, node| size.saturating_add(program_size(node))),
+        Node::Repeat { node, min, max } => {
+            let size = program_size(node);
+            let optional = match max {
+                None => size.saturating_add(2),
+                Some(max) => u64::from(max - min).saturating_mul(size.saturating_add(1)),
+            };
+            u64::from(*min).saturating_mul(size).saturating_add(optional)
+        },
+    }
+}
+
+fn compile(node: &Node, program: &mut Vec<Inst>) {
+    match node {
+        Node::Empty => (),
+        Node::Char(c) => program.push(Inst::Char(*c)),
+        Node::Any => program.push(Inst::Any),
+        Node::Class(class) => program.push(Inst::Class(class.clone())),
+        Node::Start => program.push(Inst::AssertStart),
+        Node::End => program.push(Inst::AssertEnd),
+        Node::Concat(nodes) => nodes.iter().for_each(|node| compile(node, program)),
+        Node::Alternate(branches) => {
+            // split to the first branch or the rest; every branch jumps past the alternation
+            let mut jumps = Vec::new();
+            for (idx, branch) in branches.iter().enumerate() {
+                if idx + 1 < branches.len() {
+                    let split = program.len();
+                    program.push(Inst::Split(split + 1, 0));
+                    compile(branch, program);
+                    jumps.push(program.len());
+                    program.push(Inst::Jmp(0));
+                    let next = program.len();
+                    program[split] = Inst::Split(split + 1, next);
+                } else {
+                    compile(branch, program);
+                }
+            }
+            let end = program.len();
+            for jump in jumps {
+                program[jump] = Inst::Jmp(end);
+            }
+        },
+        Node::Repeat { node, min, max } => {
+            for _ in 0..*min {
+                compile(node, program);
+            }
+            match max {
+                None => {
+                    let split = program.len();
+                    program.push(Inst::Split(split + 1, 0));
+                    compile(node, program);
+                    program.push(Inst::Jmp(split));
+                    let end = program.len();
+                    program[split] = Inst::Split(split + 1, end);
+                },
+                Some(max) => {
+                    let mut splits = Vec::new();
+                    for _ in *min..*max {
+                        splits.push(program.len());
+                        program.push(Inst::Split(program.len() + 1, 0));
+                        compile(node, program);
+                    }
+                    let end = program.len();
+                    for split in splits {
+                        program[split] = Inst::Split(split + 1, end);
+                    }
+                },
+            }
+        },
+    }
+}
+
+type ParseResult<T> = Result<T, (usize, &'static str)>;
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> ParseResult<char> {
+        let c = self.peek().ok_or((self.pos, "unexpected end of pattern"))?;
+        self.pos += 1;
+        Ok(c)
+    }
+
+    fn eat(&mut self, expected: char) -> bool {
+        let matched = self.peek() == Some(expected);
+        if matched {
+            self.pos += 1;
+        }
+        matched
+    }
+
+    fn alternation(&mut self) -> ParseResult<Node> {
+        let mut branches = vec![self.concatenation()?];
+        while self.eat('|') {
+            branches.push(self.concatenation()?);
+        }
+        Ok(if branches.len() == 1 { branches.remove(0) } else { Node::Alternate(branches) })
+    }
+
+    fn concatenation(&mut self) -> ParseResult<Node> {
+        let mut nodes = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let atom = self.atom()?;
+            nodes.push(self.repetition(atom)?);
+        }
+        Ok(match nodes.len() {
+            0 => Node::Empty,
+            1 => nodes.remove(0),
+            _ => Node::Concat(nodes),
+        })
+    }
+
+    fn repetition(&mut self, atom: Node) -> ParseResult<Node> {
+        let start = self.pos;
+        let (min, max) = match self.peek() {
+            Some('*') => (0, None),
+            Some('+') => (1, None),
+            Some('?') => (0, Some(1)),
+            Some('{') => match self.counted()? {
+                Some(bounds) => bounds,
+                None => return Ok(atom),
+            },
+            _ => return Ok(atom),
+        };
+        if self.pos == start {
+            self.pos += 1;
+        }
+        self.eat('?');
+        if matches!(atom, Node::Start | Node::End | Node::Empty) {
+            return Err((start, "nothing to repeat"));
+        }
+        if matches!(self.peek(), Some('*' | '+' | '?')) {
+            return Err((self.pos, "nested repetition"));
+        }
+        Ok(Node::Repeat { node: Box::new(atom), min, max })
+    }
+
+    /// Parses `{n}`, `{n,}` or `{n,m}`; a `{` that does not start a valid count is a literal.
+    fn counted(&mut self) -> ParseResult<Option<(u32, Option<u32>)>> {
+        let start = self.pos;
+        self.pos += 1;
+        let min = self.number();
+        let bounds = match (min, self.peek()) {
+            (Some(min), Some('}')) => Some((min, Some(min))),
+            (Some(min), Some(',')) => {
+                self.pos += 1;
+                match (self.number(), self.peek()) {
+                    (None, Some('}')) => Some((min, None)),
+                    (Some(max), Some('}')) => Some((min, Some(max))),
+                    _ => None,
+                }
+            },
+            _ => None,
+        };
+        match bounds {
+            Some((min, max)) => {
+                self.pos += 1;
+                if max.is_some_and(|max| max < min) {
+                    return Err((start, "invalid repetition range"));
+                }
+                if MAX_REPEAT < min || max.is_some_and(|max| MAX_REPEAT < max) {
+                    return Err((start, "repetition count too large"));
+                }
+                Ok(Some((min, max)))
+            },
+            None => {
+                self.pos = start;
+                Ok(None)
+            },
+        }
+    }
+
+    fn number(&mut self) -> Option<u32> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos].iter().collect::<String>().parse().ok()
+    }
+
+    fn atom(&mut self) -> ParseResult<Node> {
+        let start = self.pos;
+        match self.next()? {
+            '(' => {
+                if self.eat('?') && !self.eat(':') {
+                    return Err((start, "unsupported group flags"));
+                }
+                let node = self.alternation()?;
+                if !self.eat(')') {
+                    return Err((start, "unclosed group"));
+                }
+                Ok(node)
+            },
+            '[' => self.class().map(Node::Class),
+            '.' => Ok(Node::Any),
+            '^' => Ok(Node::Start),
+            '$' => Ok(Node::End),
+            '\\' => self.escape(false).map(|escaped| match escaped {
+                Escaped::Char(c) => Node::Char(c),
+                Escaped::Class(class) => Node::Class(class),
+            }),
+            '*' | '+' | '?' => Err((start, "nothing to repeat")),
+            c => Ok(Node::Char(c)),
+        }
+    }
+
+    fn class(&mut self) -> ParseResult<Class> {
+        let start = self.pos - 1;
+        let negated = self.eat('^');
+        let mut ranges = Vec::new();
+        let mut first = true;
+        loop {
+            let c = self.next().map_err(|_| (start, "unclosed character class"))?;
+            if c == ']' && !first {
+                break;
+            }
+            first = false;
+
+            let low = match c {
+                '\\' => match self.escape(true)? {
+                    Escaped::Char(c) => c,
+                    Escaped::Class(class) => {
+                        ranges.extend(class.ranges);
+                        continue;
+                    },
+                },
+                c => c,
+            };
+            if self.peek() == Some('-') && self.chars.get(self.pos + 1).is_some_and(|c| *c != ']') {
+                self.pos += 1;
+                let high = match self.next()? {
+                    '\\' => match self.escape(true)? {
+                        Escaped::Char(c) => c,
+                        Escaped::Class(_) => return Err((self.pos, "invalid class range")),
+                    },
+                    c => c,
+                };
+                if high < low {
+                    return Err((self.pos, "invalid class range"));
+                }
+                ranges.push((low, high));
+            } else {
+                ranges.push((low, low));
+            }
+        }
+        Ok(Class { ranges, negated })
+    }
+
+    fn escape(&mut self, in_class: bool) -> ParseResult<Escaped> {
+        let start = self.pos - 1;
+        let class = |ranges: &[(char, char)], negated: bool| Class { ranges: ranges.to_vec(), negated };
+        let escaped = match self.next()? {
+            'd' => Escaped::Class(class(DIGIT, false)),
+            'D' if !in_class => Escaped::Class(class(DIGIT, true)),
+            'w' => Escaped::Class(class(WORD, false)),
+            'W' if !in_class => Escaped::Class(class(WORD, true)),
+            's' => Escaped::Class(class(SPACE, false)),
+            'S' if !in_class => Escaped::Class(class(SPACE, true)),
+            'n' => Escaped::Char('\n'),
+            't' => Escaped::Char('\t'),
+            'r' => Escaped::Char('\r'),
+            c if c.is_ascii_punctuation() => Escaped::Char(c),
+            _ => return Err((start, "unsupported escape sequence")),
+        };
+        Ok(escaped)
+    }
+}
+
+enum Escaped {
+    Char(char),
+    Class(Class),
+}
+
+const DIGIT: &[(char, char)] = &[('0', '9')];
+const WORD: &[(char, char)] = &[('0', '9'), ('A', 'Z'), ('_', '_'), ('a', 'z')];
+const SPACE: &[(char, char)] = &[('\t', '\n'), ('\u{c}', '\r'), (' ', ' ')];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn full_match(pattern: &str, text: &str) -> bool {
+        Regex::new(pattern).unwrap().is_full_match(text)
+    }
+
+    #[test]
+    fn matches_the_whole_text_only() {
+        assert!(full_match("5..", "503"));
+        assert!(!full_match("5..", "5030"));
+        assert!(!full_match("0", "503"));
+        assert!(full_match("", ""));
+        assert!(!full_match("", "a"));
+    }
+
+    #[test]
+    fn matches_classes_escapes_and_alternation() {
+        assert!(full_match("[a-c]+[^0-9]", "abcx"));
+        assert!(!full_match("[a-c]+[^0-9]", "abc1"));
+        assert!(full_match(r"\d{3}\s\w+", "404 not_found"));
+        assert!(!full_match(r"\D", "4"));
+        assert!(full_match("GET|POST", "POST"));
+        assert!(full_match("(?:api|web)-(east|west)", "web-west"));
+        assert!(!full_match("(?:api|web)-(east|west)", "web-north"));
+        assert!(full_match(r"a\.b", "a.b"));
+        assert!(!full_match(r"a\.b", "axb"));
+    }
+
+    #[test]
+    fn matches_bounded_repetition() {
+        assert!(full_match("a{2,3}", "aa"));
+        assert!(full_match("a{2,3}", "aaa"));
+        assert!(!full_match("a{2,3}", "aaaa"));
+        assert!(full_match("a{2}b?", "aab"));
+        assert!(full_match("a{2,}", "aaaaa"));
+        assert!(full_match("x*?y+?", "xxy"));
+    }
+
+    #[test]
+    fn honours_case_insensitivity_and_anchors() {
+        assert!(full_match("(?i)get", "GeT"));
+        assert!(!full_match("get", "GET"));
+        assert!(full_match("^api.*$", "api_server"));
+        assert!(full_match("é+", "éé"));
+    }
+
+    #[test]
+    fn matches_pathological_patterns_in_linear_time() {
+        let text = "a".repeat(5_000);
+        assert!(!full_match("(a*)*b", &text));
+        assert!(full_match("(a|aa)*", &text));
+    }
+
+    #[test]
+    fn rejects_patterns_whose_program_is_too_large() {
+        for pattern in ["((a?){1000}){100}", "((a{1000}){1000}){1000}", "(((a|b){1000}){1000}){1000}"] {
+            let err = Regex::new(pattern).unwrap_err();
+            assert_eq!(err.message, "pattern compiles to more than 100000 instructions", "{pattern}");
+        }
+    }
+
+    #[test]
+    fn follows_long_chains_of_splits_without_recursing() {
+        let regex = Regex::new("((a?){100}){200}").unwrap();
+        assert!(regex.is_full_match(""));
+        assert!(regex.is_full_match(&"a".repeat(150)));
+        assert!(!regex.is_full_match("b"));
+    }
+
+    #[test]
+    fn sizes_programs_as_they_compile() {
+        for pattern in ["", "a", "ab|c|d", "(a|b)*c+", "a{2,5}", "(?:x{3}){2,}", "^[a-z]?$"] {
+            let regex = Regex::new(pattern).unwrap();
+            let node = Parser { chars: pattern.chars().collect(), pos: 0 }.alternation().unwrap();
+            assert_eq!(program_size(&node) + 1, regex.program.len() as u64, "{pattern}");
+        }
+    }
+
+    #[test]
+    fn reports_the_offset_of_invalid_patterns() {
+        let err = |pattern: &str| Regex::new(pattern).unwrap_err();
+        assert_eq!(err("ab)").offset, 2);
+        assert_eq!(err("(?i)a)").offset, 5);
+        assert!(Regex::new("(ab").is_err());
+        assert!(Regex::new("[z-a]").is_err());
+        assert!(Regex::new("*a").is_err());
+        assert!(Regex::new("a{1001}").is_err());
+        assert!(err("ab)").to_string().starts_with("invalid regex \"ab)\" at offset 2"));
+    }
+}
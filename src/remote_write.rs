@@ -0,0 +1,472 @@
+//! Decoding of Prometheus remote-write `prometheus.WriteRequest` payloads back into families,
+//! so tests can inspect what an application ships to a remote-write receiver, and encoding of
+//! snapshots into them, to ship recorded metrics to Prometheus or Mimir.
+
+use std::collections::HashMap;
+
+use std::time::UNIX_EPOCH;
+
+use protobuf::wire_format::WireType;
+use protobuf::{CodedInputStream, ProtobufResult};
+
+use crate::export::format_float;
+use crate::parse::{Dialect, ParseError, Sample, TextParser};
+use crate::proto_ext::encoded;
+use crate::render::{with_inf_bucket, with_label};
+use crate::{snappy, Exemplar, Metric, MetricFamily, MetricKind, MetricLabel, Snapshot};
+
+const REQUEST_TIMESERIES: u32 = 1;
+const REQUEST_METADATA: u32 = 3;
+
+const SERIES_LABELS: u32 = 1;
+const SERIES_SAMPLES: u32 = 2;
+const SERIES_EXEMPLARS: u32 = 3;
+
+const SAMPLE_VALUE: u32 = 1;
+const SAMPLE_TIMESTAMP: u32 = 2;
+
+const EXEMPLAR_LABELS: u32 = 1;
+const EXEMPLAR_VALUE: u32 = 2;
+const EXEMPLAR_TIMESTAMP: u32 = 3;
+
+const METADATA_TYPE: u32 = 1;
+const METADATA_FAMILY_NAME: u32 = 2;
+const METADATA_HELP: u32 = 4;
+const METADATA_UNIT: u32 = 5;
+
+const METRIC_NAME_LABEL: &str = "__name__";
+
+/// Decodes a snappy-compressed `WriteRequest` body as sent over the wire.
+pub fn decode_write_request(compressed: &[u8]) -> Result<Vec<MetricFamily>, ParseError> {
+    let bytes = snappy::decompress(compressed).map_err(|err| ParseError::Snappy(err.to_string()))?;
+    parse_write_request(&bytes)
+}
+
+/// Reconstructs families from an uncompressed `WriteRequest`. Series are typed from the request
+/// metadata when present, and the samples of a family or series are gathered wherever they appear
+/// in the request; a series carrying several samples is distilled from its latest one.
+pub fn parse_write_request(bytes: &[u8]) -> Result<Vec<MetricFamily>, ParseError> {
+    let request = read_request(bytes).map_err(|err| ParseError::Protobuf(err.to_string()))?;
+
+    let mut parser = TextParser::new(Dialect::OpenMetrics).merging();
+    for metadata in request.metadata.iter() {
+        parser.declare(&metadata.family_name, metadata.kind);
+    }
+    for series in request.timeseries {
+        let Some(sample) = series.into_sample() else { continue };
+        parser.sample(sample).map_err(ParseError::Protobuf)?;
+    }
+
+    let metadata: HashMap<String, Metadata> =
+        request.metadata.into_iter().map(|metadata| (metadata.family_name.clone(), metadata)).collect();
+    let mut families = parser.into_families();
+    for family in families.iter_mut() {
+        // counter families are named after their samples, which metadata may name without `_total`
+        let counter_base = family.name.strip_suffix("_total").filter(|_| family.kind == MetricKind::Counter);
+        if let Some(metadata) = metadata.get(&family.name).or_else(|| counter_base.and_then(|base| metadata.get(base)))
+        {
+            family.help = metadata.help.clone();
+            family.unit = metadata.unit.clone();
+        }
+    }
+
+    Ok(families)
+}
+
+/// Encodes a snapshot as a snappy-compressed `WriteRequest` body, ready to be POSTed.
+pub fn encode_write_request(snapshot: &Snapshot) -> Vec<u8> {
+    snappy::compress(&write_request_bytes(snapshot))
+}
+
+/// POSTs each snapshot to a remote-write receiver (Prometheus, Mimir, ...) as its own request,
+/// oldest first, stopping at the first rejected one.
+#[cfg(feature = "remote-write")]
+pub fn push(url: &str, snapshots: &[Snapshot]) -> Result<(), crate::scrape::ScrapeError> {
+    for snapshot in snapshots {
+        let body = encode_write_request(snapshot);
+        let headers = [
+            ("Content-Type", "application/x-protobuf"),
+            ("Content-Encoding", "snappy"),
+            ("X-Prometheus-Remote-Write-Version", "0.1.0"),
+        ];
+        let response = crate::http::request("POST", url, &headers, &body)?;
+        if !(200..300).contains(&response.status) {
+            return Err(crate::scrape::ScrapeError::Status(response.status));
+        }
+    }
+    Ok(())
+}
+
+/// Encodes a snapshot as an uncompressed `WriteRequest`, with one series per exposed sample
+/// (`_bucket`, `_sum` and `_count` for histograms, as scraped) plus metadata for each family.
+/// Samples are stamped with their own timestamp or else the snapshot's capture time.
+pub fn write_request_bytes(snapshot: &Snapshot) -> Vec<u8> {
+    let captured_ms = snapshot.captured_at.duration_since(UNIX_EPOCH).map(|d| d.as_millis() as i64).unwrap_or(0);
+    encoded(|os| {
+        for family in snapshot.families.iter() {
+            for metric in family.metrics.iter() {
+                let timestamp_ms = metric.timestamp_ms().unwrap_or(captured_ms);
+                for (suffix, labels, value) in expand(family, metric) {
+                    let mut labels = with_label(&labels, METRIC_NAME_LABEL, format!("{}{suffix}", family.name));
+                    labels.sort_by(|lhs, rhs| lhs.name.cmp(&rhs.name));
+                    os.write_bytes(REQUEST_TIMESERIES, &series_bytes(&labels, value, timestamp_ms))?;
+                }
+            }
+        }
+        for family in snapshot.families.iter() {
+            os.write_bytes(REQUEST_METADATA, &metadata_bytes(family))?;
+        }
+        Ok(())
+    })
+}
+
+fn expand(family: &MetricFamily, metric: &Metric) -> Vec<(&'static str, Vec<MetricLabel>, f64)> {
+    let flag = |enabled: bool| if enabled { 1_f64 } else { 0_f64 };
+    match metric {
+        Metric::Counter { labels, value, .. }
+        | Metric::Gauge { labels, value, .. }
+        | Metric::Untyped { labels, value, .. } => {
+            value.map(|value| vec![("", labels.clone(), value)]).unwrap_or_default()
+        },
+        Metric::Histogram { labels, buckets, .. } | Metric::GaugeHistogram { labels, buckets, .. } => {
+            let mut samples: Vec<_> = with_inf_bucket(buckets, metric.count())
+                .into_iter()
+                .map(|(upper_bound, count)| ("_bucket", with_label(labels, "le", format_float(upper_bound)), count))
+                .collect();
+            samples.push(("_sum", labels.clone(), metric.sum()));
+            samples.push(("_count", labels.clone(), metric.count() as f64));
+            samples
+        },
+        Metric::NativeHistogram { labels, buckets, .. } => {
+            // a mixed histogram ships its classic buckets as well, a purely native one only its totals
+            let mut samples: Vec<_> =
+                if buckets.is_empty() { Vec::new() } else { with_inf_bucket(buckets, metric.count()) }
+                    .into_iter()
+                    .map(|(upper_bound, count)| ("_bucket", with_label(labels, "le", format_float(upper_bound)), count))
+                    .collect();
+            samples.push(("_sum", labels.clone(), metric.sum()));
+            samples.push(("_count", labels.clone(), metric.count() as f64));
+            samples
+        },
+        Metric::Summary { labels, quantiles, .. } => {
+            let mut samples: Vec<_> = quantiles
+                .iter()
+                .map(|q| ("", with_label(labels, "quantile", format_float(q.quantile)), q.value))
+                .collect();
+            samples.push(("_sum", labels.clone(), metric.sum()));
+            samples.push(("_count", labels.clone(), metric.count() as f64));
+            samples
+        },
+        Metric::Info { labels, .. } => {
+            let suffix = if family.name.ends_with("_info") { "" } else { "_info" };
+            vec![(suffix, labels.clone(), 1_f64)]
+        },
+        Metric::StateSet { labels, states, .. } => states
+            .iter()
+            .map(|(state, enabled)| ("", with_label(labels, &family.name, state.clone()), flag(*enabled)))
+            .collect(),
+        Metric::UNSUPPORTED(_) => Vec::new(),
+    }
+}
+
+fn series_bytes(labels: &[MetricLabel], value: f64, timestamp_ms: i64) -> Vec<u8> {
+    encoded(|os| {
+        for label in labels {
+            let pair = encoded(|os| {
+                os.write_string(1, &label.name)?;
+                os.write_string(2, &label.value)
+            });
+            os.write_bytes(SERIES_LABELS, &pair)?;
+        }
+        let sample = encoded(|os| {
+            os.write_double(SAMPLE_VALUE, value)?;
+            os.write_int64(SAMPLE_TIMESTAMP, timestamp_ms)
+        });
+        os.write_bytes(SERIES_SAMPLES, &sample)
+    })
+}
+
+fn metadata_bytes(family: &MetricFamily) -> Vec<u8> {
+    encoded(|os| {
+        os.write_uint32(METADATA_TYPE, kind_to(family.kind))?;
+        os.write_string(METADATA_FAMILY_NAME, &family.name)?;
+        if let Some(help) = family.help.as_ref() {
+            os.write_string(METADATA_HELP, help)?;
+        }
+        if let Some(unit) = family.unit.as_ref() {
+            os.write_string(METADATA_UNIT, unit)?;
+        }
+        Ok(())
+    })
+}
+
+#[derive(Debug, Default)]
+struct WriteRequest {
+    timeseries: Vec<TimeSeries>,
+    metadata: Vec<Metadata>,
+}
+
+#[derive(Debug, Default)]
+struct TimeSeries {
+    labels: Vec<MetricLabel>,
+    samples: Vec<(f64, i64)>,
+    exemplars: Vec<Exemplar>,
+}
+
+impl TimeSeries {
+    fn into_sample(self) -> Option<Sample> {
+        let (value, timestamp_ms) = self.samples.into_iter().max_by_key(|(_, timestamp_ms)| *timestamp_ms)?;
+
+        let mut name = None;
+        let labels = self
+            .labels
+            .into_iter()
+            .filter_map(|label| {
+                if label.name == METRIC_NAME_LABEL {
+                    name = Some(label.value);
+                    None
+                } else {
+                    Some(label)
+                }
+            })
+            .collect();
+
+        Some(Sample {
+            name: name?,
+            labels,
+            value,
+            timestamp_ms: Some(timestamp_ms),
+            exemplar: self.exemplars.into_iter().last(),
+        })
+    }
+}
+
+#[derive(Debug)]
+struct Metadata {
+    kind: MetricKind,
+    family_name: String,
+    help: Option<String>,
+    unit: Option<String>,
+}
+
+fn read_request(bytes: &[u8]) -> ProtobufResult<WriteRequest> {
+    let mut is = CodedInputStream::from_bytes(bytes);
+    let mut request = WriteRequest::default();
+    while !is.eof()? {
+        match is.read_tag_unpack()? {
+            (REQUEST_TIMESERIES, WireType::WireTypeLengthDelimited) => {
+                request.timeseries.push(read_series(&is.read_bytes()?)?)
+            },
+            (REQUEST_METADATA, WireType::WireTypeLengthDelimited) => {
+                request.metadata.push(read_metadata(&is.read_bytes()?)?)
+            },
+            (_, wire_type) => is.skip_field(wire_type)?,
+        }
+    }
+    Ok(request)
+}
+
+fn read_series(bytes: &[u8]) -> ProtobufResult<TimeSeries> {
+    let mut is = CodedInputStream::from_bytes(bytes);
+    let mut series = TimeSeries::default();
+    while !is.eof()? {
+        match is.read_tag_unpack()? {
+            (SERIES_LABELS, WireType::WireTypeLengthDelimited) => series.labels.push(read_label(&mut is)?),
+            (SERIES_SAMPLES, WireType::WireTypeLengthDelimited) => series.samples.push(read_sample(&is.read_bytes()?)?),
+            (SERIES_EXEMPLARS, WireType::WireTypeLengthDelimited) => {
+                series.exemplars.push(read_exemplar(&is.read_bytes()?)?)
+            },
+            (_, wire_type) => is.skip_field(wire_type)?,
+        }
+    }
+    Ok(series)
+}
+
+/// Remote-write labels share the `name = 1, value = 2` layout of `io.prometheus.client.LabelPair`.
+fn read_label(is: &mut CodedInputStream) -> ProtobufResult<MetricLabel> {
+    let label: prometheus::proto::LabelPair = is.read_message()?;
+    Ok(MetricLabel {
+        name: label.get_name().to_string(),
+        value: label.get_value().to_string(),
+    })
+}
+
+fn read_sample(bytes: &[u8]) -> ProtobufResult<(f64, i64)> {
+    let mut is = CodedInputStream::from_bytes(bytes);
+    let (mut value, mut timestamp_ms) = (0_f64, 0_i64);
+    while !is.eof()? {
+        match is.read_tag_unpack()? {
+            (SAMPLE_VALUE, WireType::WireTypeFixed64) => value = is.read_double()?,
+            (SAMPLE_TIMESTAMP, WireType::WireTypeVarint) => timestamp_ms = is.read_int64()?,
+            (_, wire_type) => is.skip_field(wire_type)?,
+        }
+    }
+    Ok((value, timestamp_ms))
+}
+
+fn read_exemplar(bytes: &[u8]) -> ProtobufResult<Exemplar> {
+    let mut is = CodedInputStream::from_bytes(bytes);
+    let mut exemplar = Exemplar { labels: Vec::new(), value: 0_f64, timestamp: None };
+    while !is.eof()? {
+        match is.read_tag_unpack()? {
+            (EXEMPLAR_LABELS, WireType::WireTypeLengthDelimited) => exemplar.labels.push(read_label(&mut is)?),
+            (EXEMPLAR_VALUE, WireType::WireTypeFixed64) => exemplar.value = is.read_double()?,
+            (EXEMPLAR_TIMESTAMP, WireType::WireTypeVarint) => {
+                exemplar.timestamp = Some(is.read_int64()? as f64 / 1000_f64)
+            },
+            (_, wire_type) => is.skip_field(wire_type)?,
+        }
+    }
+    Ok(exemplar)
+}
+
+fn read_metadata(bytes: &[u8]) -> ProtobufResult<Metadata> {
+    let mut is = CodedInputStream::from_bytes(bytes);
+    let mut metadata = Metadata {
+        kind: MetricKind::Untyped,
+        family_name: String::new(),
+        help: None,
+        unit: None,
+    };
+    while !is.eof()? {
+        match is.read_tag_unpack()? {
+            (METADATA_TYPE, WireType::WireTypeVarint) => metadata.kind = kind_from(is.read_uint32()?),
+            (METADATA_FAMILY_NAME, WireType::WireTypeLengthDelimited) => metadata.family_name = is.read_string()?,
+            (METADATA_HELP, WireType::WireTypeLengthDelimited) => {
+                metadata.help = Some(is.read_string()?).filter(|help| !help.is_empty())
+            },
+            (METADATA_UNIT, WireType::WireTypeLengthDelimited) => {
+                metadata.unit = Some(is.read_string()?).filter(|unit| !unit.is_empty())
+            },
+            (_, wire_type) => is.skip_field(wire_type)?,
+        }
+    }
+    Ok(metadata)
+}
+
+fn kind_to(kind: MetricKind) -> u32 {
+    match kind {
+        MetricKind::Counter => 1,
+        MetricKind::Gauge => 2,
+        MetricKind::Histogram => 3,
+        MetricKind::GaugeHistogram => 4,
+        MetricKind::Summary => 5,
+        MetricKind::Info => 6,
+        MetricKind::StateSet => 7,
+        MetricKind::Untyped => 0,
+    }
+}
+
+/// `prometheus.MetricMetadata.MetricType` numbering differs from `io.prometheus.client.MetricType`.
+fn kind_from(metric_type: u32) -> MetricKind {
+    match metric_type {
+        1 => MetricKind::Counter,
+        2 => MetricKind::Gauge,
+        3 => MetricKind::Histogram,
+        4 => MetricKind::GaugeHistogram,
+        5 => MetricKind::Summary,
+        6 => MetricKind::Info,
+        7 => MetricKind::StateSet,
+        _ => MetricKind::Untyped,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MetricFamily;
+
+    fn label(name: &str, value: &str) -> MetricLabel {
+        MetricLabel { name: name.to_string(), value: value.to_string() }
+    }
+
+    fn request(series: &[(&[MetricLabel], f64)], metadata: &[MetricFamily]) -> Vec<u8> {
+        encoded(|os| {
+            for (labels, value) in series {
+                os.write_bytes(REQUEST_TIMESERIES, &series_bytes(labels, *value, 1_000))?;
+            }
+            for family in metadata {
+                os.write_bytes(REQUEST_METADATA, &metadata_bytes(family))?;
+            }
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn merges_series_parts_out_of_shard_order() {
+        let name = |name: &str| label(METRIC_NAME_LABEL, name);
+        let bytes = request(
+            &[
+                (&[name("h_bucket"), label("a", "1"), label("le", "1")], 1_f64),
+                (&[name("up")], 1_f64),
+                (&[name("h_bucket"), label("a", "1"), label("le", "+Inf")], 2_f64),
+                (&[name("h_count"), label("a", "1")], 2_f64),
+                (&[name("h_sum"), label("a", "1")], 3.5),
+            ],
+            &[MetricFamily::builder("h").help("Latency").kind(MetricKind::Histogram).build()],
+        );
+
+        let families = parse_write_request(&bytes).unwrap();
+        let names: Vec<&str> = families.iter().map(|family| family.name.as_str()).collect();
+        assert_eq!(names, ["h", "up"]);
+        assert_eq!(families[0].help.as_deref(), Some("Latency"));
+        assert_eq!(families[0].metrics.len(), 1);
+        match &families[0].metrics[0] {
+            Metric::Histogram { labels, sample_count, sample_sum, buckets, .. } => {
+                assert_eq!(labels, &[label("a", "1")]);
+                assert_eq!((*sample_count, *sample_sum), (Some(2), Some(3.5)));
+                assert_eq!(buckets.len(), 2);
+            },
+            other => panic!("expected a histogram, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn names_counters_after_their_samples() {
+        let bytes = request(
+            &[(&[label(METRIC_NAME_LABEL, "jobs_total")], 3_f64)],
+            &[MetricFamily::builder("jobs").help("Jobs").kind(MetricKind::Counter).build()],
+        );
+        let families = parse_write_request(&bytes).unwrap();
+        assert_eq!((families[0].name.as_str(), families[0].help.as_deref()), ("jobs_total", Some("Jobs")));
+        assert_eq!(families[0].metrics[0].counter_value(), Some(3_f64));
+    }
+
+    #[test]
+    fn round_trips_encoded_snapshots() {
+        let families = vec![
+            MetricFamily::builder("requests_total").help("Requests").counter(vec![label("code", "200")], 7_f64).build(),
+            MetricFamily::builder("latency_seconds").histogram(vec![], &[(0.5, 3), (f64::INFINITY, 4)], 1.25).build(),
+        ];
+        let decoded = decode_write_request(&encode_write_request(&Snapshot::new(families))).unwrap();
+
+        assert_eq!(decoded.len(), 2);
+        assert_eq!(decoded[0].kind, MetricKind::Counter);
+        assert_eq!(decoded[0].help.as_deref(), Some("Requests"));
+        assert_eq!(decoded[0].metrics[0].counter_value(), Some(7_f64));
+        assert_eq!(decoded[1].kind, MetricKind::Histogram);
+        assert_eq!((decoded[1].metrics[0].count(), decoded[1].metrics[0].sum()), (4, 1.25));
+    }
+
+    #[test]
+    fn suffixes_info_series_only_when_the_family_lacks_it() {
+        let families = vec![
+            MetricFamily::builder("build_info").info(vec![label("version", "1.2")]).build(),
+            MetricFamily::builder("target").info(vec![label("service", "api")]).build(),
+        ];
+        let request = read_request(&write_request_bytes(&Snapshot::new(families))).unwrap();
+        let names: Vec<_> = request
+            .timeseries
+            .iter()
+            .flat_map(|series| series.labels.iter().filter(|l| l.name == METRIC_NAME_LABEL))
+            .map(|l| l.value.as_str())
+            .collect();
+        assert_eq!(names, ["build_info", "target_info"]);
+    }
+
+    #[test]
+    fn rejects_invalid_payloads() {
+        assert!(matches!(decode_write_request(&[0x05, 0x00]), Err(ParseError::Snappy(_))));
+        assert!(matches!(parse_write_request(&[0x0a, 0x05, 0x01]), Err(ParseError::Protobuf(_))));
+    }
+}
@@ -0,0 +1,408 @@
+//! Rendering of distilled families back into expositions and human-readable dumps.
+
+use std::fmt::Write;
+
+use crate::export::{format_float, format_labels, format_metric_name, format_series};
+use crate::{BucketSpan, Exemplar, HistogramBucket, Metric, MetricFamily, MetricKind, MetricLabel};
+
+/// Encodes families in the Prometheus text exposition format (version 0.0.4).
+///
+/// The text format has no exemplars, created timestamps, units or OpenMetrics-only types, so
+/// gauge histograms are written as histograms, info and state set families as gauges, and native
+/// histograms only by their classic buckets, count and sum. Counters are named with the `_total` suffix of their
+/// samples even where the family name lacks it, and series without a value are left out.
+pub fn text(families: &[MetricFamily]) -> String {
+    let mut out = String::new();
+    for family in families {
+        let name = &match family.kind {
+            MetricKind::Counter if !family.name.ends_with("_total") => format!("{}_total", family.name),
+            MetricKind::Info if !family.name.ends_with("_info") => format!("{}_info", family.name),
+            _ => family.name.clone(),
+        };
+        let header = format_metric_name(name);
+        if let Some(help) = family.help.as_ref() {
+            let _ = writeln!(out, "# HELP {header} {}", help.replace('\\', "\\\\").replace('\n', "\\n"));
+        }
+        let _ = writeln!(out, "# TYPE {header} {}", text_type(family.kind));
+
+        for metric in family.metrics.iter() {
+            let timestamp_ms = metric.timestamp_ms();
+            let mut sample = |suffix: &str, labels: &[MetricLabel], value: f64| {
+                out.push_str(&format_series(&format!("{name}{suffix}"), labels));
+                let _ = write!(out, " {}", format_float(value));
+                if let Some(timestamp_ms) = timestamp_ms {
+                    let _ = write!(out, " {timestamp_ms}");
+                }
+                out.push('\n');
+            };
+
+            match metric {
+                Metric::Counter { labels, value, .. }
+                | Metric::Gauge { labels, value, .. }
+                | Metric::Untyped { labels, value, .. } => {
+                    if let Some(value) = value {
+                        sample("", labels, *value);
+                    }
+                },
+                Metric::Histogram { labels, buckets, .. }
+                | Metric::GaugeHistogram { labels, buckets, .. }
+                | Metric::NativeHistogram { labels, buckets, .. } => {
+                    for (upper_bound, count) in with_inf_bucket(buckets, metric.count()) {
+                        sample("_bucket", &with_label(labels, "le", format_float(upper_bound)), count);
+                    }
+                    sample("_sum", labels, metric.sum());
+                    sample("_count", labels, metric.count() as f64);
+                },
+                Metric::Summary { labels, quantiles, .. } => {
+                    for q in quantiles.iter() {
+                        sample("", &with_label(labels, "quantile", format_float(q.quantile)), q.value);
+                    }
+                    sample("_sum", labels, metric.sum());
+                    sample("_count", labels, metric.count() as f64);
+                },
+                Metric::Info { labels, .. } => sample("", labels, 1_f64),
+                Metric::StateSet { labels, states, .. } => {
+                    for (state, enabled) in states.iter() {
+                        sample("", &with_label(labels, name, state.clone()), if *enabled { 1_f64 } else { 0_f64 });
+                    }
+                },
+                Metric::UNSUPPORTED(metric_type) => {
+                    tracing::warn!("cannot render unsupported {:?} metric of {}", metric_type, name);
+                },
+            }
+        }
+    }
+    out
+}
+
+fn text_type(kind: MetricKind) -> &'static str {
+    match kind {
+        MetricKind::Counter => "counter",
+        MetricKind::Histogram | MetricKind::GaugeHistogram => "histogram",
+        MetricKind::Summary => "summary",
+        MetricKind::Untyped => "untyped",
+        MetricKind::Gauge | MetricKind::Info | MetricKind::StateSet => "gauge",
+    }
+}
+
+/// The text format requires a `+Inf` bucket, which protobuf expositions leave implicit.
+pub(crate) fn with_inf_bucket(buckets: &[HistogramBucket], count: u64) -> Vec<(f64, f64)> {
+    let mut rendered: Vec<(f64, f64)> = buckets.iter().map(|b| (b.upper_bound, b.cumulative_count as f64)).collect();
+    if rendered.last().is_none_or(|(upper_bound, _)| *upper_bound != f64::INFINITY) {
+        rendered.push((f64::INFINITY, count as f64));
+    }
+    rendered
+}
+
+pub(crate) fn with_label(labels: &[MetricLabel], name: &str, value: String) -> Vec<MetricLabel> {
+    let mut labels = labels.to_vec();
+    labels.push(MetricLabel { name: name.to_string(), value });
+    labels
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct TableOptions {
+    /// Colors the type column with ANSI escapes, for terminals.
+    pub color: bool,
+}
+
+/// Renders families as an aligned `FAMILY TYPE LABELS VALUE` table, one row per series.
+pub fn table(families: &[MetricFamily]) -> String {
+    table_with_options(families, &TableOptions::default())
+}
+
+pub fn table_with_options(families: &[MetricFamily], options: &TableOptions) -> String {
+    let header = (None, ["FAMILY", "TYPE", "LABELS", "VALUE"].map(String::from));
+    let rows: Vec<(Option<MetricKind>, [String; 4])> = families
+        .iter()
+        .flat_map(|family| {
+            family.metrics.iter().map(move |metric| {
+                let row = [
+                    family.name.clone(),
+                    crate::json::kind_name(family.kind).to_string(),
+                    format_labels(metric.labels()),
+                    display_value(metric),
+                ];
+                (Some(family.kind), row)
+            })
+        })
+        .collect();
+
+    let mut widths = [0_usize; 4];
+    for (_, row) in std::iter::once(&header).chain(rows.iter()) {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let mut out = String::new();
+    for (kind, row) in std::iter::once(&header).chain(rows.iter()) {
+        for (column, cell) in row.iter().enumerate() {
+            match kind.filter(|_| options.color && column == 1) {
+                Some(kind) => {
+                    let _ = write!(out, "\x1b[{}m{cell}\x1b[0m", ansi_color(kind));
+                },
+                None => out.push_str(cell),
+            }
+            if column + 1 < row.len() {
+                out.extend(std::iter::repeat_n(' ', widths[column] - cell.chars().count() + 2));
+            }
+        }
+        out.push('\n');
+    }
+    out
+}
+
+fn ansi_color(kind: MetricKind) -> u8 {
+    match kind {
+        MetricKind::Counter => 32,
+        MetricKind::Gauge => 36,
+        MetricKind::Histogram | MetricKind::GaugeHistogram => 35,
+        MetricKind::Summary => 33,
+        MetricKind::Untyped | MetricKind::Info | MetricKind::StateSet => 34,
+    }
+}
+
+/// A short, human-readable summary of a series' value.
+pub(crate) fn display_value(metric: &Metric) -> String {
+    match metric {
+        Metric::Counter { value, .. } | Metric::Gauge { value, .. } | Metric::Untyped { value, .. } => {
+            value.map(format_float).unwrap_or_else(|| "-".to_string())
+        },
+        Metric::Histogram { .. }
+        | Metric::GaugeHistogram { .. }
+        | Metric::NativeHistogram { .. }
+        | Metric::Summary { .. } => format!("count={} sum={}", metric.count(), format_float(metric.sum())),
+        Metric::Info { .. } => "1".to_string(),
+        Metric::StateSet { states, .. } => states
+            .iter()
+            .map(|(state, enabled)| format!("{state}={}", if *enabled { 1 } else { 0 }))
+            .collect::<Vec<_>>()
+            .join(" "),
+        Metric::UNSUPPORTED(metric_type) => format!("unsupported {metric_type:?}"),
+    }
+}
+
+/// A normalized rendering of families for snapshot tests, e.g. with `insta::assert_snapshot!`.
+///
+/// Families and series are sorted as [`crate::normalize`] sorts them, timestamps and created
+/// timestamps are dropped (exemplars keep their value but not their timestamp), histograms always
+/// end in a `+Inf` bucket, and floats are written in their shortest round-tripping form. Every kind is kept as distilled, so unlike
+/// [`text`] nothing is folded into another type.
+///
+/// # Stability
+/// The format is stable across patch releases, so committed snapshots only change when the
+/// metrics do.
+pub fn snapshot_string(families: &[MetricFamily]) -> String {
+    let mut families = families.to_vec();
+    crate::normalize(&mut families);
+
+    let mut out = String::new();
+    for family in families.iter() {
+        let name = &family.name;
+        let header = format_metric_name(name);
+        if let Some(help) = family.help.as_ref() {
+            let _ = writeln!(out, "# HELP {header} {}", help.replace('\\', "\\\\").replace('\n', "\\n"));
+        }
+        let _ = writeln!(out, "# TYPE {header} {}", crate::json::kind_name(family.kind));
+        if let Some(unit) = family.unit.as_ref() {
+            let _ = writeln!(out, "# UNIT {header} {unit}");
+        }
+
+        for metric in family.metrics.iter() {
+            let mut sample = |suffix: &str, labels: &[MetricLabel], value: String| {
+                out.push_str(&format_series(&format!("{name}{suffix}"), labels));
+                let _ = writeln!(out, " {value}");
+            };
+
+            match metric {
+                Metric::Counter { labels, value, exemplar, .. } => {
+                    let mut value = value.map(format_float).unwrap_or_else(|| "-".to_string());
+                    if let Some(exemplar) = exemplar {
+                        value = format!("{value} # {}", snapshot_exemplar(exemplar));
+                    }
+                    sample("", labels, value)
+                },
+                Metric::Gauge { labels, value, .. } | Metric::Untyped { labels, value, .. } => {
+                    sample("", labels, value.map(format_float).unwrap_or_else(|| "-".to_string()))
+                },
+                Metric::Histogram { labels, buckets, .. } | Metric::GaugeHistogram { labels, buckets, .. } => {
+                    for bucket in buckets.iter() {
+                        let mut value = bucket.cumulative_count.to_string();
+                        if let Some(exemplar) = bucket.exemplar.as_ref() {
+                            value = format!("{value} # {}", snapshot_exemplar(exemplar));
+                        }
+                        sample("_bucket", &with_label(labels, "le", format_float(bucket.upper_bound)), value);
+                    }
+                    if buckets.last().is_none_or(|bucket| bucket.upper_bound != f64::INFINITY) {
+                        sample("_bucket", &with_label(labels, "le", "+Inf".to_string()), metric.count().to_string());
+                    }
+                    sample("_sum", labels, format_float(metric.sum()));
+                    sample("_count", labels, metric.count().to_string());
+                },
+                Metric::NativeHistogram {
+                    labels,
+                    schema,
+                    zero_threshold,
+                    zero_count,
+                    positive_spans,
+                    positive_deltas,
+                    negative_spans,
+                    negative_deltas,
+                    buckets,
+                    ..
+                } => {
+                    let spans = |spans: &[BucketSpan], deltas: &[i64]| {
+                        let spans: Vec<String> = spans.iter().map(|s| format!("{}:{}", s.offset, s.length)).collect();
+                        let deltas: Vec<String> = deltas.iter().map(i64::to_string).collect();
+                        format!("[{}] [{}]", spans.join(" "), deltas.join(" "))
+                    };
+                    let value = format!(
+                        "schema={schema} zero_threshold={} zero_count={zero_count} positive={} negative={}",
+                        format_float(*zero_threshold),
+                        spans(positive_spans, positive_deltas),
+                        spans(negative_spans, negative_deltas),
+                    );
+                    sample("", labels, value);
+                    for bucket in buckets.iter() {
+                        let value = bucket.cumulative_count.to_string();
+                        sample("_bucket", &with_label(labels, "le", format_float(bucket.upper_bound)), value);
+                    }
+                    sample("_sum", labels, format_float(metric.sum()));
+                    sample("_count", labels, metric.count().to_string());
+                },
+                Metric::Summary { labels, quantiles, .. } => {
+                    for q in quantiles.iter() {
+                        sample("", &with_label(labels, "quantile", format_float(q.quantile)), format_float(q.value));
+                    }
+                    sample("_sum", labels, format_float(metric.sum()));
+                    sample("_count", labels, metric.count().to_string());
+                },
+                Metric::Info { labels, .. } => sample("", labels, "1".to_string()),
+                Metric::StateSet { labels, states, .. } => {
+                    for (state, enabled) in states.iter() {
+                        let value = if *enabled { "1" } else { "0" }.to_string();
+                        sample("", &with_label(labels, name, state.clone()), value);
+                    }
+                },
+                Metric::UNSUPPORTED(metric_type) => {
+                    let _ = writeln!(out, "{name} unsupported {metric_type:?}");
+                },
+            }
+        }
+    }
+    out
+}
+
+fn snapshot_exemplar(exemplar: &Exemplar) -> String {
+    let mut labels = exemplar.labels.clone();
+    labels.sort_by(|lhs, rhs| (&lhs.name, &lhs.value).cmp(&(&rhs.name, &rhs.value)));
+    format!("{{{}}} {}", format_labels(&labels), format_float(exemplar.value))
+}
+
+/// Renders a markdown section per family, with its HELP text and a table of its series.
+pub fn markdown(families: &[MetricFamily]) -> String {
+    let mut out = String::new();
+    for family in families {
+        let _ = writeln!(out, "### `{}` ({})\n", family.name, crate::json::kind_name(family.kind));
+        if let Some(help) = family.help.as_ref().filter(|help| !help.is_empty()) {
+            let _ = writeln!(out, "{}\n", help.replace('\n', " "));
+        }
+        if family.metrics.is_empty() {
+            out.push_str("_no series_\n\n");
+            continue;
+        }
+
+        out.push_str("| Labels | Value |\n|---|---|\n");
+        for metric in family.metrics.iter() {
+            let labels = format_labels(metric.labels());
+            let labels = if labels.is_empty() { String::new() } else { format!("`{}`", markdown_cell(&labels)) };
+            let _ = writeln!(out, "| {labels} | {} |", markdown_cell(&display_value(metric)));
+        }
+        out.push('\n');
+    }
+    out
+}
+
+fn markdown_cell(cell: &str) -> String {
+    cell.replace('|', "\\|").replace('\n', " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{labels, parse};
+
+    #[test]
+    fn text_round_trips_through_the_parser() {
+        let families = vec![
+            MetricFamily::builder("requests_total")
+                .help("Requests \\ served\nper code")
+                .counter(labels! {"code" => "200", "path" => "/a\"b\\c\nd"}, 7_f64)
+                .counter(labels! {"code" => "500"}, 0.5)
+                .build(),
+            MetricFamily::builder("temperature").gauge(vec![], f64::NEG_INFINITY).build(),
+            MetricFamily::builder("latency_seconds")
+                .histogram(labels! {"route" => "a"}, &[(0.1, 2), (1_f64, 5), (f64::INFINITY, 6)], 2.5)
+                .build(),
+            MetricFamily::builder("rpc_seconds").summary(vec![], &[(0.5, 0.25), (0.99, 0.75)], 10, 4_f64).build(),
+            MetricFamily::builder("anything").untyped(labels! {"a" => "b"}, 3_f64).build(),
+        ];
+        let families: Vec<MetricFamily> = families
+            .into_iter()
+            .map(|mut family| {
+                family.metrics = family.metrics.into_iter().map(|m| m.with_timestamp_ms(1_700_000_000_000)).collect();
+                family
+            })
+            .collect();
+
+        assert_eq!(parse::parse_text(&text(&families)).unwrap(), families);
+    }
+
+    #[test]
+    fn text_names_counters_with_their_total_suffix() {
+        let openmetrics = parse::parse_openmetrics("# TYPE jobs counter\njobs_total 3\n# EOF\n").unwrap();
+        let rendered = text(&openmetrics);
+        assert_eq!(rendered, "# TYPE jobs_total counter\njobs_total 3\n");
+
+        let built = MetricFamily::builder("jobs").counter(vec![], 3_f64).build();
+        assert_eq!(text(&[built]), rendered);
+    }
+
+    #[test]
+    fn text_leaves_out_series_without_a_value() {
+        let family = MetricFamily::builder("up")
+            .metric(Metric::Gauge { labels: vec![], value: None, timestamp_ms: None })
+            .kind(MetricKind::Gauge)
+            .build();
+        assert_eq!(text(&[family]), "# TYPE up gauge\n");
+    }
+
+    #[test]
+    fn text_folds_openmetrics_types() {
+        let families = vec![
+            MetricFamily::builder("build").info(labels! {"version" => "1.0"}).build(),
+            MetricFamily::builder("mode").state_set(vec![], &[("on", true), ("off", false)]).build(),
+            MetricFamily::builder("queue").gauge_histogram(vec![], &[(1_f64, 2)], 0.5).build(),
+        ];
+        let expected = "# TYPE build_info gauge\nbuild_info{version=\"1.0\"} 1\n\
+                        # TYPE mode gauge\nmode{mode=\"on\"} 1\nmode{mode=\"off\"} 0\n\
+                        # TYPE queue histogram\nqueue_bucket{le=\"1\"} 2\nqueue_bucket{le=\"+Inf\"} 2\n\
+                        queue_sum 0.5\nqueue_count 2\n";
+        assert_eq!(text(&families), expected);
+    }
+
+    #[test]
+    fn snapshot_string_is_normalized() {
+        let family = |order: [&str; 2]| {
+            let mut builder = MetricFamily::builder("hits_total");
+            for code in order {
+                builder = builder.counter(labels! {"code" => code}, 1_f64);
+            }
+            builder.build()
+        };
+        let snapshot = snapshot_string(&[family(["500", "200"])]);
+        assert_eq!(snapshot, snapshot_string(&[family(["200", "500"])]));
+        assert_eq!(snapshot, "# TYPE hits_total counter\nhits_total{code=\"200\"} 1\nhits_total{code=\"500\"} 1\n");
+    }
+}
@@ -0,0 +1,106 @@
+//! Self-contained HTML reports of metric state, e.g. to attach to CI artifacts.
+
+use std::fmt::Write;
+
+use crate::export::{format_float, format_labels};
+use crate::render::display_value;
+use crate::{HistogramBucket, MetricFamily};
+
+const STYLE: &str = "\
+body { font-family: sans-serif; margin: 2em; color: #222; }
+details { margin: 0.5em 0; border: 1px solid #ddd; border-radius: 4px; padding: 0.5em 1em; }
+summary { cursor: pointer; font-weight: bold; }
+.kind { font-weight: normal; color: #666; margin-left: 0.5em; }
+.help { color: #555; font-style: italic; }
+table { border-collapse: collapse; margin: 0.5em 0; }
+th, td { border: 1px solid #ddd; padding: 0.25em 0.75em; text-align: left; font-family: monospace; }
+th { cursor: pointer; background: #f4f4f4; }
+.buckets { margin: 0.25em 0 1em; }
+.bar { display: flex; align-items: center; font-family: monospace; font-size: 0.85em; }
+.bar .le { width: 8em; text-align: right; padding-right: 0.5em; }
+.bar .fill { background: #5b8def; height: 0.9em; margin-right: 0.5em; }
+";
+
+const SCRIPT: &str = "\
+document.querySelectorAll('table.sortable th').forEach(function (th, column) {
+  th.addEventListener('click', function () {
+    var body = th.closest('table').tBodies[0];
+    var ascending = th.dataset.order !== 'asc';
+    th.dataset.order = ascending ? 'asc' : 'desc';
+    var rows = Array.prototype.slice.call(body.rows);
+    rows.sort(function (a, b) {
+      var x = a.cells[column].textContent, y = b.cells[column].textContent;
+      var order = isNaN(x) || isNaN(y) ? x.localeCompare(y) : x - y;
+      return ascending ? order : -order;
+    });
+    rows.forEach(function (row) { body.appendChild(row); });
+  });
+});
+";
+
+/// Renders a single-file HTML report with a collapsible section per family, a sortable table of
+/// its series and a bar chart of each classic histogram's buckets.
+pub fn html(families: &[MetricFamily]) -> String {
+    let series: usize = families.iter().map(|family| family.metrics.len()).sum();
+
+    let mut out = String::new();
+    out.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Metric report</title>\n");
+    let _ = writeln!(out, "<style>\n{STYLE}</style>\n</head>\n<body>\n<h1>Metric report</h1>");
+    let _ = writeln!(out, "<p>{} families, {series} series</p>", families.len());
+
+    for family in families {
+        let _ = writeln!(
+            out,
+            "<details open>\n<summary><code>{}</code><span class=\"kind\">{} &middot; {} series</span></summary>",
+            escape(&family.name),
+            crate::json::kind_name(family.kind),
+            family.metrics.len()
+        );
+        if let Some(help) = family.help.as_ref().filter(|help| !help.is_empty()) {
+            let _ = writeln!(out, "<p class=\"help\">{}</p>", escape(help));
+        }
+
+        out.push_str("<table class=\"sortable\">\n<thead><tr><th>Labels</th><th>Value</th></tr></thead>\n<tbody>\n");
+        for metric in family.metrics.iter() {
+            let _ = writeln!(
+                out,
+                "<tr><td>{}</td><td>{}</td></tr>",
+                escape(&format_labels(metric.labels())),
+                escape(&display_value(metric))
+            );
+        }
+        out.push_str("</tbody>\n</table>\n");
+
+        for metric in family.metrics.iter() {
+            if let Some(histogram) = metric.histogram() {
+                write_buckets(&mut out, &format_labels(metric.labels()), histogram.buckets);
+            }
+        }
+        out.push_str("</details>\n");
+    }
+
+    let _ = write!(out, "<script>\n{SCRIPT}</script>\n</body>\n</html>\n");
+    out
+}
+
+fn write_buckets(out: &mut String, labels: &str, buckets: &[HistogramBucket]) {
+    let max = buckets.iter().map(|b| b.cumulative_count).max().unwrap_or(0).max(1);
+    out.push_str("<div class=\"buckets\">\n");
+    if !labels.is_empty() {
+        let _ = writeln!(out, "<div><code>{}</code></div>", escape(labels));
+    }
+    for bucket in buckets.iter() {
+        let width = bucket.cumulative_count as f64 / max as f64 * 100_f64;
+        let _ = writeln!(
+            out,
+            "<div class=\"bar\"><span class=\"le\">le {}</span><span class=\"fill\" style=\"width: {width:.1}%\"></span>{}</div>",
+            escape(&format_float(bucket.upper_bound)),
+            bucket.cumulative_count
+        );
+    }
+    out.push_str("</div>\n");
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
@@ -0,0 +1,71 @@
+use crate::parse::{self, Format, ParseError};
+use crate::{http, MetricFamily};
+
+#[derive(Debug, thiserror::Error)]
+pub enum ScrapeError {
+    #[error("invalid scrape url {0}")]
+    InvalidUrl(String),
+
+    #[error("failed to reach metrics endpoint: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("malformed HTTP response: {0}")]
+    Http(String),
+
+    #[error("metrics endpoint responded with HTTP status {0}")]
+    Status(u16),
+
+    #[error("failed to parse scraped metrics: {0}")]
+    Parse(#[from] ParseError),
+}
+
+const ACCEPT: &str = "application/vnd.google.protobuf;proto=io.prometheus.client.MetricFamily;encoding=delimited,\
+                      application/openmetrics-text;version=1.0.0;q=0.8,\
+                      text/plain;version=0.0.4;q=0.5,\
+                      */*;q=0.1";
+
+#[cfg(feature = "gzip")]
+const ACCEPT_ENCODING: &str = "gzip, identity;q=0.5";
+#[cfg(not(feature = "gzip"))]
+const ACCEPT_ENCODING: &str = "identity";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scrape {
+    pub format: Format,
+    pub families: Vec<MetricFamily>,
+}
+
+/// Scrapes a plain `http://` metrics endpoint, negotiating protobuf or OpenMetrics with a
+/// fallback to the text format, and distills the response with the matching parser. With the
+/// `gzip` feature the endpoint may also compress its response.
+pub fn scrape(url: &str) -> Result<Scrape, ScrapeError> {
+    let response = http::get(url, &[("Accept", ACCEPT), ("Accept-Encoding", ACCEPT_ENCODING)])?;
+    if !(200..300).contains(&response.status) {
+        return Err(ScrapeError::Status(response.status));
+    }
+
+    let body = match response.header("Content-Encoding").map(str::trim) {
+        None | Some("identity") => response.body.clone(),
+        #[cfg(feature = "gzip")]
+        Some(encoding) if encoding.eq_ignore_ascii_case("gzip") => {
+            crate::gzip::decompress(&response.body).map_err(|err| ParseError::Gzip(err.to_string()))?
+        },
+        Some(encoding) => return Err(ScrapeError::Http(format!("unsupported Content-Encoding {encoding}"))),
+    };
+
+    let format = response.header("Content-Type").map(Format::from_content_type).unwrap_or(Format::Text);
+    let families = parse::parse_bytes(&body, format)?;
+    Ok(Scrape { format, families })
+}
+
+pub fn scrape_url(url: &str) -> Result<Vec<MetricFamily>, ScrapeError> {
+    scrape(url).map(|scrape| scrape.families)
+}
+
+/// Async counterpart of [`scrape_url`]. The scrape runs on its own thread, so the returned
+/// future can be awaited from any executor without blocking it.
+#[cfg(feature = "scrape-async")]
+pub async fn scrape_async(url: &str) -> Result<Vec<MetricFamily>, ScrapeError> {
+    let url = url.to_string();
+    crate::blocking::spawn(move || scrape_url(&url)).await
+}
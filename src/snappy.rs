@@ -0,0 +1,213 @@
+//! Snappy block format, as used to compress Prometheus remote-write payloads.
+
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+#[error("{0}")]
+pub(crate) struct SnappyError(&'static str);
+
+const LITERAL: u8 = 0;
+const COPY_1: u8 = 1;
+const COPY_2: u8 = 2;
+
+const OVERRUN: SnappyError = SnappyError("decompressed data exceeds the declared length");
+
+pub(crate) fn decompress(input: &[u8]) -> Result<Vec<u8>, SnappyError> {
+    let mut pos = 0;
+    let expected = read_varint(input, &mut pos)?;
+    let mut output = Vec::with_capacity(expected.min(64 * 1024 * 1024));
+
+    while pos < input.len() {
+        let tag = input[pos];
+        pos += 1;
+
+        let (length, offset) = match tag & 0x03 {
+            LITERAL => {
+                let length = match tag >> 2 {
+                    small @ 0..=59 => usize::from(small) + 1,
+                    width => read_le(input, &mut pos, usize::from(width - 59))? + 1,
+                };
+                if expected - output.len() < length {
+                    return Err(OVERRUN);
+                }
+                let literal = input.get(pos..pos + length).ok_or(SnappyError("truncated literal"))?;
+                output.extend_from_slice(literal);
+                pos += length;
+                continue;
+            },
+            COPY_1 => {
+                let low = *input.get(pos).ok_or(SnappyError("truncated copy"))?;
+                pos += 1;
+                (usize::from((tag >> 2) & 0x07) + 4, (usize::from(tag >> 5) << 8) | usize::from(low))
+            },
+            COPY_2 => (usize::from(tag >> 2) + 1, read_le(input, &mut pos, 2)?),
+            _ => (usize::from(tag >> 2) + 1, read_le(input, &mut pos, 4)?),
+        };
+
+        if offset == 0 || offset > output.len() {
+            return Err(SnappyError("copy offset out of range"));
+        }
+        if expected - output.len() < length {
+            return Err(OVERRUN);
+        }
+        let start = output.len() - offset;
+        for idx in 0..length {
+            output.push(output[start + idx]);
+        }
+    }
+
+    if output.len() != expected {
+        return Err(SnappyError("decompressed length does not match the declared length"));
+    }
+    Ok(output)
+}
+
+const BLOCK_SIZE: usize = 1 << 16;
+const HASH_BITS: u32 = 14;
+
+/// Compresses `input` greedily with a hash table of 4-byte sequences, in independent 64 KiB
+/// blocks like the reference encoder.
+pub(crate) fn compress(input: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(input.len() / 2 + 16);
+    let mut length = input.len();
+    loop {
+        let byte = (length & 0x7f) as u8;
+        length >>= 7;
+        if length == 0 {
+            output.push(byte);
+            break;
+        }
+        output.push(byte | 0x80);
+    }
+
+    for block in input.chunks(BLOCK_SIZE) {
+        compress_block(block, &mut output);
+    }
+    output
+}
+
+fn compress_block(block: &[u8], output: &mut Vec<u8>) {
+    let mut table = vec![0_u16; 1 << HASH_BITS];
+    let (mut pos, mut literal_start) = (0, 0);
+    while pos + 4 <= block.len() {
+        let word = u32::from_le_bytes([block[pos], block[pos + 1], block[pos + 2], block[pos + 3]]);
+        let slot = (word.wrapping_mul(0x1e35_a7bd) >> (32 - HASH_BITS)) as usize;
+        let candidate = usize::from(table[slot]);
+        table[slot] = pos as u16;
+
+        if candidate < pos && block[candidate..candidate + 4] == block[pos..pos + 4] {
+            emit_literal(&block[literal_start..pos], output);
+            let mut length = 4;
+            while pos + length < block.len() && block[candidate + length] == block[pos + length] {
+                length += 1;
+            }
+            emit_copy(pos - candidate, length, output);
+            pos += length;
+            literal_start = pos;
+        } else {
+            pos += 1;
+        }
+    }
+    emit_literal(&block[literal_start..], output);
+}
+
+fn emit_literal(literal: &[u8], output: &mut Vec<u8>) {
+    if literal.is_empty() {
+        return;
+    }
+    let n = literal.len() - 1;
+    if n < 60 {
+        output.push((n as u8) << 2 | LITERAL);
+    } else {
+        let width = (usize::BITS - n.leading_zeros()).div_ceil(8) as usize;
+        output.push(((59 + width) as u8) << 2 | LITERAL);
+        output.extend_from_slice(&n.to_le_bytes()[..width]);
+    }
+    output.extend_from_slice(literal);
+}
+
+fn emit_copy(offset: usize, mut length: usize, output: &mut Vec<u8>) {
+    // copies of 2-byte offsets carry at most 64 bytes; keep the remainder at least 4 long
+    while 68 <= length {
+        push_copy_2(offset, 64, output);
+        length -= 64;
+    }
+    if 64 < length {
+        push_copy_2(offset, 60, output);
+        length -= 60;
+    }
+    if length < 12 && offset < 2048 {
+        output.push(((offset >> 8) as u8) << 5 | ((length - 4) as u8) << 2 | COPY_1);
+        output.push(offset as u8);
+    } else {
+        push_copy_2(offset, length, output);
+    }
+}
+
+fn push_copy_2(offset: usize, length: usize, output: &mut Vec<u8>) {
+    output.push(((length - 1) as u8) << 2 | COPY_2);
+    output.extend_from_slice(&(offset as u16).to_le_bytes());
+}
+
+fn read_varint(input: &[u8], pos: &mut usize) -> Result<usize, SnappyError> {
+    let mut result = 0_usize;
+    for shift in (0..35).step_by(7) {
+        let byte = *input.get(*pos).ok_or(SnappyError("truncated length"))?;
+        *pos += 1;
+        result |= usize::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(result);
+        }
+    }
+    Err(SnappyError("length varint is too long"))
+}
+
+fn read_le(input: &[u8], pos: &mut usize, width: usize) -> Result<usize, SnappyError> {
+    let bytes = input.get(*pos..*pos + width).ok_or(SnappyError("truncated element"))?;
+    *pos += width;
+    Ok(bytes.iter().rev().fold(0, |acc, byte| (acc << 8) | usize::from(*byte)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_compressible_and_random_input() {
+        let repetitive = "http_requests_total{method=\"GET\"} 1\n".repeat(5_000).into_bytes();
+        let compressed = compress(&repetitive);
+        assert!(compressed.len() < repetitive.len() / 10);
+        assert_eq!(decompress(&compressed).unwrap(), repetitive);
+
+        let mut state = 0x2545_f491_u32;
+        let noise: Vec<u8> = (0..200_000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect();
+        assert_eq!(decompress(&compress(&noise)).unwrap(), noise);
+        assert_eq!(decompress(&compress(&[])).unwrap(), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn decodes_reference_encoding() {
+        // "abcabcabc" as a 3 byte literal followed by a 6 byte copy at offset 3
+        let compressed = [0x09, 0x08, b'a', b'b', b'c', 0x09, 0x03];
+        assert_eq!(decompress(&compressed).unwrap(), b"abcabcabc");
+    }
+
+    #[test]
+    fn rejects_output_beyond_the_declared_length() {
+        assert_eq!(decompress(&[0x02, 0x08, b'a', b'b', b'c']), Err(OVERRUN));
+        assert_eq!(decompress(&[0x04, 0x00, b'a', 0xfe, 0x01, 0x00]), Err(OVERRUN));
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        assert!(decompress(&[]).is_err());
+        assert!(decompress(&[0x03, 0x08, b'a']).is_err());
+        assert!(decompress(&[0x04, 0x01, 0x01]).is_err());
+        assert!(decompress(&[0x05, 0x00, b'a']).is_err());
+    }
+}
@@ -0,0 +1,531 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use crate::export::{format_float, format_labels};
+use crate::json::{self, JsonError};
+use crate::matcher::{LabelMatcher, NamePattern, METRIC_NAME_LABEL};
+use crate::parse::{self, Format, ParseError, ParseOptions};
+use crate::query::Query;
+use crate::render::{self, display_value};
+use crate::{label_key, label_key_of, LabelKey, Metric, MetricFamily, MetricKind, MetricLabel};
+
+#[derive(Debug, thiserror::Error)]
+pub enum LoadError {
+    #[error("failed to read metrics: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("failed to parse metrics: {0}")]
+    Parse(#[from] ParseError),
+
+    #[error("failed to load JSON metrics: {0}")]
+    Json(#[from] JsonError),
+
+    #[error("{0} metric snapshots are not supported")]
+    UnsupportedFormat(&'static str),
+}
+
+/// Distilled families indexed by family name and, within each family, by label set.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct MetricState {
+    families: Vec<MetricFamily>,
+    family_index: HashMap<String, usize>,
+    series_index: Vec<HashMap<LabelKey, usize>>,
+}
+
+impl MetricState {
+    pub fn distill_from(families: impl IntoIterator<Item = prometheus::proto::MetricFamily>) -> Self {
+        Self::from(crate::distill_metric_state(families))
+    }
+
+    pub fn families(&self) -> &[MetricFamily] {
+        &self.families
+    }
+
+    pub fn family(&self, name: &str) -> Option<&MetricFamily> {
+        self.family_index.get(name).map(|idx| &self.families[*idx])
+    }
+
+    /// The series of family `name` with exactly `labels`, in any order.
+    pub fn series(&self, name: &str, labels: &[(&str, &str)]) -> Option<&Metric> {
+        let family = *self.family_index.get(name)?;
+        let key = label_key_of(labels.iter().copied());
+        self.series_index[family].get(&key).map(|idx| &self.families[family].metrics[*idx])
+    }
+
+    /// The distinct values `label` takes across every family.
+    pub fn label_values(&self, label: &str) -> BTreeSet<String> {
+        self.families.iter().flat_map(|family| family.label_values(label)).collect()
+    }
+
+    /// Every label name used by any series of any family.
+    pub fn label_names(&self) -> BTreeSet<String> {
+        self.families.iter().flat_map(|family| family.label_names()).collect()
+    }
+
+    /// Every sample of every family as the text exposition format would list it, histograms and
+    /// summaries expanded into their `_bucket` or quantile, `_sum` and `_count` samples. Native
+    /// histograms only yield the classic buckets of a mixed histogram with their `_sum` and `_count`;
+    /// created timestamps are left out.
+    pub fn samples(&self) -> impl Iterator<Item = Sample<'_>> + '_ {
+        self.families
+            .iter()
+            .flat_map(|family| family.metrics.iter().flat_map(move |metric| series_samples(family, metric)))
+    }
+
+    /// The value of the counter series of family `name` with exactly `labels`.
+    pub fn counter(&self, name: &str, labels: &[(&str, &str)]) -> Option<f64> {
+        self.series(name, labels)?.counter_value()
+    }
+
+    /// The value of the gauge series of family `name` with exactly `labels`.
+    pub fn gauge(&self, name: &str, labels: &[(&str, &str)]) -> Option<f64> {
+        self.series(name, labels)?.gauge_value()
+    }
+
+    /// The first series of family `name` carrying at least `labels`.
+    pub fn find(&self, name: &str, labels: &[(&str, &str)]) -> Option<&Metric> {
+        self.family(name)?.metrics.iter().find(|metric| has_labels(metric, labels))
+    }
+
+    /// Every series of family `name` carrying at least `labels`, in exposition order.
+    pub fn find_all<'a>(&'a self, name: &str, labels: &'a [(&str, &str)]) -> impl Iterator<Item = &'a Metric> + 'a {
+        self.family(name)
+            .into_iter()
+            .flat_map(|family| family.metrics.iter())
+            .filter(move |metric| has_labels(metric, labels))
+    }
+
+    /// Every series satisfying all `matchers`, with its family. A `__name__` matcher is tested
+    /// against the family name.
+    pub fn select<'a>(
+        &'a self,
+        matchers: &'a [LabelMatcher],
+    ) -> impl Iterator<Item = (&'a MetricFamily, &'a Metric)> + 'a {
+        let (name_matchers, label_matchers): (Vec<_>, Vec<_>) =
+            matchers.iter().partition(|matcher| matcher.name == METRIC_NAME_LABEL);
+        self.families
+            .iter()
+            .filter(move |family| name_matchers.iter().all(|matcher| matcher.matches_value(Some(&family.name))))
+            .flat_map(|family| family.metrics.iter().map(move |metric| (family, metric)))
+            .filter(move |(_, metric)| {
+                let labels = metric.labels();
+                label_matchers.iter().all(|matcher| matcher.matches(labels))
+            })
+    }
+
+    /// Starts a chainable [`Query`] over the series of family `name`.
+    pub fn query(&self, name: &str) -> Query<'_> {
+        Query::new(self, name)
+    }
+
+    /// The `k` highest-valued counter, gauge and untyped series across the families matching
+    /// `pattern`, highest first. NaN values are skipped.
+    pub fn top_k(&self, k: usize, pattern: NamePattern) -> Vec<(&MetricFamily, &Metric)> {
+        let mut series = self.valued_series(&pattern);
+        series.sort_by(|(_, _, lhs), (_, _, rhs)| rhs.total_cmp(lhs));
+        series.into_iter().take(k).map(|(family, metric, _)| (family, metric)).collect()
+    }
+
+    /// The `k` lowest-valued counter, gauge and untyped series across the families matching
+    /// `pattern`, lowest first. NaN values are skipped.
+    pub fn bottom_k(&self, k: usize, pattern: NamePattern) -> Vec<(&MetricFamily, &Metric)> {
+        let mut series = self.valued_series(&pattern);
+        series.sort_by(|(_, _, lhs), (_, _, rhs)| lhs.total_cmp(rhs));
+        series.into_iter().take(k).map(|(family, metric, _)| (family, metric)).collect()
+    }
+
+    fn valued_series(&self, pattern: &NamePattern) -> Vec<(&MetricFamily, &Metric, f64)> {
+        self.families
+            .iter()
+            .filter(|family| pattern.matches(&family.name))
+            .flat_map(|family| family.metrics.iter().map(move |metric| (family, metric)))
+            .filter_map(|(family, metric)| Some((family, metric, metric.value().filter(|v| !v.is_nan())?)))
+            .collect()
+    }
+
+    /// Combines the values of families `left` and `right` series by series, pairing series that
+    /// agree on `on_labels` as PromQL's `on (...)` does, into a gauge family named after `left`
+    /// whose series carry just `on_labels`. Left series without a partner are dropped, and a left
+    /// series pairs with the first matching right series.
+    pub fn join(
+        &self,
+        left: &str,
+        right: &str,
+        on_labels: &[&str],
+        op: impl Fn(f64, f64) -> f64,
+    ) -> Option<MetricFamily> {
+        let (left, right) = (self.family(left)?, self.family(right)?);
+        let on = |metric: &Metric| -> Vec<MetricLabel> {
+            metric
+                .labels()
+                .iter()
+                .filter(|l| on_labels.contains(&l.name.as_str()) && !l.value.is_empty())
+                .cloned()
+                .collect()
+        };
+
+        let mut partners: HashMap<LabelKey, f64> = HashMap::new();
+        for metric in right.metrics.iter() {
+            if let Some(value) = metric.value() {
+                partners.entry(label_key(&on(metric))).or_insert(value);
+            }
+        }
+
+        let metrics = left
+            .metrics
+            .iter()
+            .filter_map(|metric| {
+                let labels = on(metric);
+                let key = label_key(&labels);
+                let value = op(metric.value()?, *partners.get(&key)?);
+                Some(Metric::Gauge { labels, value: Some(value), timestamp_ms: None })
+            })
+            .collect();
+
+        Some(MetricFamily {
+            name: left.name.clone(),
+            help: None,
+            unit: None,
+            kind: MetricKind::Gauge,
+            metrics,
+        })
+    }
+
+    /// A new state holding only the families whose name matches `pattern`.
+    pub fn filter_families(&self, pattern: NamePattern) -> Self {
+        let families = self.families.iter().filter(|family| pattern.matches(&family.name)).cloned().collect::<Vec<_>>();
+        Self::from(families)
+    }
+
+    /// Drops the families whose name does not match `pattern`.
+    pub fn retain_families(&mut self, pattern: NamePattern) {
+        let mut families = std::mem::take(&mut self.families);
+        families.retain(|family| pattern.matches(&family.name));
+        *self = Self::from(families);
+    }
+
+    /// Sorts families, series and labels into a canonical order; see [`crate::normalize`].
+    pub fn normalize(&mut self) {
+        let mut families = std::mem::take(&mut self.families);
+        crate::normalize(&mut families);
+        *self = Self::from(families);
+    }
+
+    /// Whether this state includes `expected`: each expected family is present with the same kind,
+    /// and each expected series matches a series carrying at least its labels with the same value.
+    /// Extra families, series and labels are tolerated, as are histogram buckets, summary
+    /// quantiles and states the expected series leaves out.
+    pub fn includes(&self, expected: &MetricState) -> bool {
+        self.missing(expected).is_empty()
+    }
+
+    /// Describes each way this state falls short of including `expected`.
+    pub(crate) fn missing(&self, expected: &MetricState) -> Vec<String> {
+        let mut missing = Vec::new();
+        for expected in expected.families.iter() {
+            let Some(family) = self.family(&expected.name) else {
+                missing.push(format!("no family {}", expected.name));
+                continue;
+            };
+            if family.kind != expected.kind {
+                let (kind, expected_kind) = (json::kind_name(family.kind), json::kind_name(expected.kind));
+                missing.push(format!("family {} is of kind {kind}, not {expected_kind}", family.name));
+                continue;
+            }
+
+            for metric in expected.metrics.iter() {
+                let labels = metric.labels();
+                let candidates: Vec<&Metric> =
+                    family.metrics.iter().filter(|actual| includes_labels(actual, labels)).collect();
+                if candidates.iter().any(|actual| includes_value(actual, metric)) {
+                    continue;
+                }
+
+                let series = if labels.is_empty() {
+                    family.name.clone()
+                } else {
+                    format!("{}{{{}}}", family.name, format_labels(labels))
+                };
+                if candidates.is_empty() {
+                    missing.push(format!("no series {series}"));
+                } else {
+                    let found: Vec<String> = candidates.iter().map(|actual| display_value(actual)).collect();
+                    let found = found.join(", ");
+                    missing.push(format!("{series} expected {}, found {found}", display_value(metric)));
+                }
+            }
+        }
+        missing
+    }
+
+    pub fn into_families(self) -> Vec<MetricFamily> {
+        self.families
+    }
+
+    /// Loads a saved exposition dump, sniffing whether it holds text, OpenMetrics, protobuf or the
+    /// [`crate::json`] format. Text may use the quoted UTF-8 names of Prometheus 3. Gzip compressed
+    /// dumps are decompressed first with the `gzip` feature.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, LoadError> {
+        Self::from_reader(File::open(path)?)
+    }
+
+    pub fn from_reader(mut reader: impl Read) -> Result<Self, LoadError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        Self::from_bytes(&bytes)
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, LoadError> {
+        if is_gzip(bytes) {
+            #[cfg(feature = "gzip")]
+            return crate::gzip::decompress(bytes)
+                .map_err(|err| LoadError::Parse(ParseError::Gzip(err.to_string())))
+                .and_then(|bytes| Self::from_bytes(&bytes));
+            #[cfg(not(feature = "gzip"))]
+            return Err(LoadError::UnsupportedFormat("gzip compressed"));
+        }
+
+        if is_json(bytes) {
+            let json = std::str::from_utf8(bytes).map_err(|_| ParseError::Utf8)?;
+            return Ok(Self::from(json::from_json(json)?));
+        }
+
+        let options = ParseOptions { utf8_names: true, ..ParseOptions::default() };
+        let families = parse::parse_bytes_with(bytes, Format::sniff(bytes), options)?;
+        Ok(Self::from(families))
+    }
+}
+
+/// A single sample of a series, see [`MetricState::samples`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample<'a> {
+    pub family: &'a str,
+    pub kind: MetricKind,
+    /// What the sample name adds to the family name, e.g. `_bucket`.
+    pub suffix: &'static str,
+    /// The labels of the series.
+    pub labels: &'a [MetricLabel],
+    /// The `le`, `quantile` or state label the sample carries besides the series labels.
+    pub extra_label: Option<MetricLabel>,
+    pub value: f64,
+    pub timestamp_ms: Option<i64>,
+}
+
+impl Sample<'_> {
+    /// The sample name, the family name with its suffix.
+    pub fn name(&self) -> String {
+        format!("{}{}", self.family, self.suffix)
+    }
+
+    /// The series labels followed by the extra label.
+    pub fn all_labels(&self) -> Vec<MetricLabel> {
+        self.labels.iter().cloned().chain(self.extra_label.clone()).collect()
+    }
+}
+
+fn series_samples<'a>(family: &'a MetricFamily, metric: &'a Metric) -> Vec<Sample<'a>> {
+    let labels = metric.labels();
+    let sample = |suffix, extra_label, value| Sample {
+        family: &family.name,
+        kind: family.kind,
+        suffix,
+        labels,
+        extra_label,
+        value,
+        timestamp_ms: metric.timestamp_ms(),
+    };
+    let label = |name: &str, value: String| Some(MetricLabel { name: name.to_string(), value });
+    let totals = |samples: &mut Vec<Sample<'a>>, sum_suffix, count_suffix| {
+        samples.push(sample(sum_suffix, None, metric.sum()));
+        samples.push(sample(count_suffix, None, metric.count() as f64));
+    };
+
+    let mut samples = Vec::new();
+    match metric {
+        Metric::Counter { value, .. } | Metric::Gauge { value, .. } | Metric::Untyped { value, .. } => {
+            samples.extend(value.map(|value| sample("", None, value)))
+        },
+        Metric::NativeHistogram { buckets, .. } if buckets.is_empty() => totals(&mut samples, "_sum", "_count"),
+        Metric::Histogram { buckets, .. }
+        | Metric::GaugeHistogram { buckets, .. }
+        | Metric::NativeHistogram { buckets, .. } => {
+            for (upper_bound, count) in render::with_inf_bucket(buckets, metric.count()) {
+                samples.push(sample("_bucket", label("le", format_float(upper_bound)), count));
+            }
+            match metric {
+                Metric::GaugeHistogram { .. } => totals(&mut samples, "_gsum", "_gcount"),
+                _ => totals(&mut samples, "_sum", "_count"),
+            }
+        },
+        Metric::Summary { quantiles, .. } => {
+            for quantile in quantiles.iter() {
+                samples.push(sample("", label("quantile", format_float(quantile.quantile)), quantile.value));
+            }
+            totals(&mut samples, "_sum", "_count");
+        },
+        Metric::Info { .. } => {
+            let suffix = if family.name.ends_with("_info") { "" } else { "_info" };
+            samples.push(sample(suffix, None, 1_f64));
+        },
+        Metric::StateSet { states, .. } => {
+            for (state, enabled) in states.iter() {
+                let value = if *enabled { 1_f64 } else { 0_f64 };
+                samples.push(sample("", label(&family.name, state.clone()), value));
+            }
+        },
+        Metric::UNSUPPORTED(_) => (),
+    }
+    samples
+}
+
+impl AsRef<[MetricFamily]> for MetricState {
+    fn as_ref(&self) -> &[MetricFamily] {
+        &self.families
+    }
+}
+
+impl From<Vec<MetricFamily>> for MetricState {
+    fn from(families: Vec<MetricFamily>) -> Self {
+        let mut family_index = HashMap::with_capacity(families.len());
+        for (idx, family) in families.iter().enumerate() {
+            family_index.entry(family.name.clone()).or_insert(idx);
+        }
+
+        let series_index = families
+            .iter()
+            .map(|family| {
+                let mut series = HashMap::with_capacity(family.metrics.len());
+                for (idx, metric) in family.metrics.iter().enumerate() {
+                    let key = label_key(metric.labels());
+                    series.entry(key).or_insert(idx);
+                }
+                series
+            })
+            .collect();
+
+        Self { families, family_index, series_index }
+    }
+}
+
+/// JSON snapshots are an array of families, whereas a text exposition may open with the `{` of a
+/// sample with a quoted UTF-8 name but never with `[`.
+fn is_json(bytes: &[u8]) -> bool {
+    bytes.iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'[')
+}
+
+fn is_gzip(bytes: &[u8]) -> bool {
+    bytes.starts_with(&[0x1f, 0x8b])
+}
+
+fn has_labels(metric: &Metric, labels: &[(&str, &str)]) -> bool {
+    let metric_labels = metric.labels();
+    labels.iter().all(|(name, value)| metric_labels.iter().any(|l| l.name == *name && l.value == *value))
+}
+
+fn includes_labels(metric: &Metric, labels: &[MetricLabel]) -> bool {
+    let metric_labels = metric.labels();
+    labels.iter().all(|label| metric_labels.contains(label))
+}
+
+/// Whether `actual` has `expected`'s value, and at least its buckets, quantiles or states.
+fn includes_value(actual: &Metric, expected: &Metric) -> bool {
+    let same = |actual: Option<f64>, expected: Option<f64>| expected.is_none_or(|expected| actual == Some(expected));
+    match (actual, expected) {
+        (Metric::Counter { value: actual, .. }, Metric::Counter { value: expected, .. })
+        | (Metric::Gauge { value: actual, .. }, Metric::Gauge { value: expected, .. })
+        | (Metric::Untyped { value: actual, .. }, Metric::Untyped { value: expected, .. }) => same(*actual, *expected),
+        (
+            Metric::Histogram { sample_count, sample_sum, buckets, .. },
+            Metric::Histogram {
+                sample_count: expected_count,
+                sample_sum: expected_sum,
+                buckets: expected_buckets,
+                ..
+            },
+        )
+        | (
+            Metric::GaugeHistogram { sample_count, sample_sum, buckets, .. },
+            Metric::GaugeHistogram {
+                sample_count: expected_count,
+                sample_sum: expected_sum,
+                buckets: expected_buckets,
+                ..
+            },
+        )
+        | (
+            Metric::NativeHistogram { sample_count, sample_sum, buckets, .. },
+            Metric::NativeHistogram {
+                sample_count: expected_count,
+                sample_sum: expected_sum,
+                buckets: expected_buckets,
+                ..
+            },
+        ) => {
+            expected_count.is_none_or(|count| *sample_count == Some(count))
+                && same(*sample_sum, *expected_sum)
+                && expected_buckets.iter().all(|expected| {
+                    buckets.iter().any(|bucket| {
+                        bucket.upper_bound == expected.upper_bound
+                            && bucket.cumulative_count == expected.cumulative_count
+                    })
+                })
+        },
+        (
+            Metric::Summary { sample_count, sample_sum, quantiles, .. },
+            Metric::Summary {
+                sample_count: expected_count,
+                sample_sum: expected_sum,
+                quantiles: expected_quantiles,
+                ..
+            },
+        ) => {
+            expected_count.is_none_or(|count| *sample_count == Some(count))
+                && same(*sample_sum, *expected_sum)
+                && expected_quantiles.iter().all(|expected| quantiles.contains(expected))
+        },
+        (Metric::Info { .. }, Metric::Info { .. }) => true,
+        (Metric::StateSet { states, .. }, Metric::StateSet { states: expected_states, .. }) => {
+            expected_states.iter().all(|expected| states.contains(expected))
+        },
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loads_each_sniffed_format() {
+        let text = "# TYPE up gauge\nup{job=\"a\"} 1\n";
+        let state = MetricState::from_reader(text.as_bytes()).unwrap();
+        assert_eq!(state.gauge("up", &[("job", "a")]), Some(1_f64));
+
+        let openmetrics = "# TYPE jobs counter\njobs_total 3\n# EOF\n";
+        assert_eq!(MetricState::from_reader(openmetrics.as_bytes()).unwrap().counter("jobs_total", &[]), Some(3_f64));
+
+        let json = json::to_json_pretty(state.families());
+        assert_eq!(MetricState::from_reader(json.as_bytes()).unwrap(), state);
+    }
+
+    #[test]
+    fn loads_text_opening_with_a_quoted_name() {
+        let state = MetricState::from_reader(&b"  {\"my.metric\",code=\"200\"} 1\n"[..]).unwrap();
+        assert_eq!(state.series("my.metric", &[("code", "200")]).and_then(Metric::value), Some(1_f64));
+    }
+
+    #[test]
+    fn reports_invalid_json_snapshots() {
+        assert!(matches!(MetricState::from_reader(&b"[{]"[..]), Err(LoadError::Json(_))));
+        assert!(matches!(MetricState::from_reader(&b"{\"a\": 1}"[..]), Err(LoadError::Parse(_))));
+    }
+
+    #[test]
+    fn finds_series_regardless_of_label_order() {
+        let state = MetricState::from(parse::parse_text("m{a=\"1\",b=\"2\"} 5\n").unwrap());
+        assert_eq!(state.series("m", &[("b", "2"), ("a", "1")]).and_then(Metric::value), Some(5_f64));
+        assert_eq!(state.series("m", &[("a", "1")]), None);
+    }
+}
@@ -0,0 +1,486 @@
+//! Checks for families Prometheus would reject on scrape or that break its naming rules, so
+//! exporters can self-check in unit tests and CI.
+
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::export::{format_float, format_labels};
+use crate::parse::duplicate_label_name;
+use crate::{HistogramValue, Metric, MetricFamily, MetricLabel, Quantile};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Violation {
+    /// More than one family is exposed under the same name.
+    DuplicateFamily { family: String },
+    /// A family holds more than one series with the same label set.
+    DuplicateSeries { family: String, labels: Vec<MetricLabel> },
+    /// The family name does not match `[a-zA-Z_:][a-zA-Z0-9_:]*`, or is empty when UTF-8 names
+    /// are allowed.
+    InvalidMetricName { family: String },
+    /// A label name does not match `[a-zA-Z_][a-zA-Z0-9_]*`, or is empty when UTF-8 names are
+    /// allowed.
+    InvalidLabelName { family: String, label: String },
+    /// A label name starts with `__`, which Prometheus reserves for internal use.
+    ReservedLabelName { family: String, label: String },
+    /// A series carries more than one label named `label`.
+    DuplicateLabelName { family: String, labels: Vec<MetricLabel>, label: String },
+    /// A histogram bucket's upper bound is not above the bound of the bucket before it.
+    UnorderedBuckets { family: String, labels: Vec<MetricLabel>, upper_bound: f64 },
+    /// A histogram bucket counts fewer observations than the bucket before it. A missing `+Inf`
+    /// bucket stands for `sample_count`, so it is reported here when a bucket exceeds the count.
+    DecreasingBucketCount { family: String, labels: Vec<MetricLabel>, upper_bound: f64 },
+    /// A histogram's `+Inf` bucket disagrees with its `sample_count`.
+    InfBucketMismatch {
+        family: String,
+        labels: Vec<MetricLabel>,
+        bucket_count: u64,
+        sample_count: u64,
+    },
+    /// A summary quantile lies outside `[0, 1]`.
+    QuantileOutOfRange { family: String, labels: Vec<MetricLabel>, quantile: f64 },
+    /// A summary quantile is not above the quantile before it.
+    UnorderedQuantiles { family: String, labels: Vec<MetricLabel>, quantile: f64 },
+    /// A summary quantile's value is below the value of the quantile before it.
+    DecreasingQuantileValue { family: String, labels: Vec<MetricLabel>, quantile: f64 },
+    /// A value, sum or quantile is NaN or infinite, reported by [`non_finite`] only.
+    NonFiniteValue {
+        family: String,
+        sample: String,
+        labels: Vec<MetricLabel>,
+        value: f64,
+    },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DuplicateFamily { family } => write!(f, "family {family} is exposed more than once"),
+            Self::DuplicateSeries { family, labels } => {
+                write!(f, "family {family} has more than one series {{{}}}", format_labels(labels))
+            },
+            Self::InvalidMetricName { family } => write!(f, "{family:?} is not a valid metric name"),
+            Self::InvalidLabelName { family, label } => {
+                write!(f, "family {family} uses {label:?}, which is not a valid label name")
+            },
+            Self::ReservedLabelName { family, label } => {
+                write!(f, "family {family} uses the reserved label name {label}")
+            },
+            Self::DuplicateLabelName { family, labels, label } => {
+                write!(f, "series {family}{{{}}} has more than one label named {label}", format_labels(labels))
+            },
+            Self::UnorderedBuckets { family, labels, upper_bound } => write!(
+                f,
+                "series {family}{{{}}} has bucket le=\"{}\" out of order",
+                format_labels(labels),
+                format_float(*upper_bound)
+            ),
+            Self::DecreasingBucketCount { family, labels, upper_bound } => write!(
+                f,
+                "series {family}{{{}}} counts fewer observations in bucket le=\"{}\" than in the bucket before it",
+                format_labels(labels),
+                format_float(*upper_bound)
+            ),
+            Self::InfBucketMismatch { family, labels, bucket_count, sample_count } => write!(
+                f,
+                "series {family}{{{}}} counts {bucket_count} observations in its +Inf bucket but {sample_count} in total",
+                format_labels(labels)
+            ),
+            Self::QuantileOutOfRange { family, labels, quantile } => write!(
+                f,
+                "series {family}{{{}}} has quantile {} outside [0, 1]",
+                format_labels(labels),
+                format_float(*quantile)
+            ),
+            Self::UnorderedQuantiles { family, labels, quantile } => write!(
+                f,
+                "series {family}{{{}}} has quantile {} out of order",
+                format_labels(labels),
+                format_float(*quantile)
+            ),
+            Self::DecreasingQuantileValue { family, labels, quantile } => write!(
+                f,
+                "series {family}{{{}}} has a smaller value at quantile {} than at the quantile before it",
+                format_labels(labels),
+                format_float(*quantile)
+            ),
+            Self::NonFiniteValue { family: _, sample, labels, value } => {
+                write!(f, "sample {sample}{{{}}} is {}", format_labels(labels), format_float(*value))
+            },
+        }
+    }
+}
+
+/// Rules other than the defaults, for [`validate_with`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct ValidateOptions {
+    /// Accepts any non-empty UTF-8 metric and label names, as Prometheus 3 does.
+    pub utf8_names: bool,
+}
+
+/// Every violation in `families`, in exposition order. Each duplicate or bad label name is
+/// reported once per family however often it repeats.
+pub fn validate(families: &[MetricFamily]) -> Vec<Violation> {
+    validate_with(families, ValidateOptions::default())
+}
+
+pub fn validate_with(families: &[MetricFamily], options: ValidateOptions) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    let mut family_names = HashSet::new();
+    let mut duplicate_families = HashSet::new();
+    for family in families.iter() {
+        if !family_names.insert(family.name.as_str()) && duplicate_families.insert(family.name.as_str()) {
+            violations.push(Violation::DuplicateFamily { family: family.name.clone() });
+        }
+        if !(is_valid_metric_name(&family.name) || options.utf8_names && !family.name.is_empty()) {
+            violations.push(Violation::InvalidMetricName { family: family.name.clone() });
+        }
+        violations.extend(label_names(family, options));
+        for metric in family.metrics.iter() {
+            let labels = metric.labels();
+            if let Some(label) = duplicate_label_name(labels) {
+                let (labels, label) = (labels.to_vec(), label.to_string());
+                violations.push(Violation::DuplicateLabelName { family: family.name.clone(), labels, label });
+            }
+            if let Some(histogram) = metric.histogram() {
+                violations.extend(buckets(&family.name, labels.to_vec(), histogram));
+            }
+            if let Metric::Summary { labels, quantiles, .. } = metric {
+                violations.extend(self::quantiles(&family.name, labels, quantiles));
+            }
+        }
+        violations.extend(duplicate_series(family));
+    }
+    violations
+}
+
+/// Every counter, gauge or untyped value, histogram or summary sum and quantile value in
+/// `families` that is NaN or infinite. Prometheus accepts these, so [`validate`] does not report
+/// them, but they usually come from a division by zero in a collector.
+pub fn non_finite(families: &[MetricFamily]) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    for family in families.iter() {
+        for metric in family.metrics.iter() {
+            let mut report = |suffix: &str, labels: Vec<MetricLabel>, value: f64| {
+                if !value.is_finite() {
+                    let (family, sample) = (family.name.clone(), format!("{}{suffix}", family.name));
+                    violations.push(Violation::NonFiniteValue { family, sample, labels, value });
+                }
+            };
+            match metric {
+                Metric::Counter { labels, value: Some(value), .. }
+                | Metric::Gauge { labels, value: Some(value), .. }
+                | Metric::Untyped { labels, value: Some(value), .. } => report("", labels.clone(), *value),
+                Metric::Histogram { labels, sample_sum: Some(sum), .. }
+                | Metric::NativeHistogram { labels, sample_sum: Some(sum), .. } => report("_sum", labels.clone(), *sum),
+                Metric::GaugeHistogram { labels, sample_sum: Some(sum), .. } => report("_gsum", labels.clone(), *sum),
+                Metric::Summary { labels, sample_sum, quantiles, .. } => {
+                    for quantile in quantiles.iter() {
+                        let mut labels = labels.clone();
+                        labels.push(MetricLabel {
+                            name: "quantile".to_string(),
+                            value: format_float(quantile.quantile),
+                        });
+                        report("", labels, quantile.value);
+                    }
+                    if let Some(sum) = sample_sum {
+                        report("_sum", labels.clone(), *sum);
+                    }
+                },
+                _ => (),
+            }
+        }
+    }
+    violations
+}
+
+pub fn is_valid_metric_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == ':')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+}
+
+pub fn is_valid_label_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn label_names(family: &MetricFamily, options: ValidateOptions) -> Vec<Violation> {
+    let mut checked = HashSet::new();
+    let mut violations = Vec::new();
+    for label in family.metrics.iter().flat_map(|metric| metric.labels()) {
+        if !checked.insert(label.name.clone()) {
+            continue;
+        }
+        if !(is_valid_label_name(&label.name) || options.utf8_names && !label.name.is_empty()) {
+            violations.push(Violation::InvalidLabelName { family: family.name.clone(), label: label.name.clone() });
+        } else if label.name.starts_with("__") {
+            violations.push(Violation::ReservedLabelName { family: family.name.clone(), label: label.name.clone() });
+        }
+    }
+    violations
+}
+
+fn buckets(family: &str, labels: Vec<MetricLabel>, histogram: HistogramValue<'_>) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    for pair in histogram.buckets.windows(2) {
+        let (previous, bucket) = (&pair[0], &pair[1]);
+        if previous.upper_bound.partial_cmp(&bucket.upper_bound) != Some(Ordering::Less) {
+            violations.push(Violation::UnorderedBuckets {
+                family: family.to_string(),
+                labels: labels.clone(),
+                upper_bound: bucket.upper_bound,
+            });
+        }
+        if bucket.cumulative_count < previous.cumulative_count {
+            violations.push(Violation::DecreasingBucketCount {
+                family: family.to_string(),
+                labels: labels.clone(),
+                upper_bound: bucket.upper_bound,
+            });
+        }
+    }
+
+    if let (Some(sample_count), Some(last)) = (histogram.sample_count, histogram.buckets.last()) {
+        let family = family.to_string();
+        if last.upper_bound == f64::INFINITY {
+            if last.cumulative_count != sample_count {
+                let bucket_count = last.cumulative_count;
+                violations.push(Violation::InfBucketMismatch { family, labels, bucket_count, sample_count });
+            }
+        } else if sample_count < last.cumulative_count {
+            violations.push(Violation::DecreasingBucketCount { family, labels, upper_bound: f64::INFINITY });
+        }
+    }
+    violations
+}
+
+fn quantiles(family: &str, labels: &[MetricLabel], quantiles: &[Quantile]) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    let violation = |quantile: &Quantile| (family.to_string(), labels.to_vec(), quantile.quantile);
+    for quantile in quantiles.iter().filter(|q| !(0.0..=1.0).contains(&q.quantile)) {
+        let (family, labels, quantile) = violation(quantile);
+        violations.push(Violation::QuantileOutOfRange { family, labels, quantile });
+    }
+    for pair in quantiles.windows(2) {
+        let (previous, quantile) = (&pair[0], &pair[1]);
+        if previous.quantile.partial_cmp(&quantile.quantile) != Some(Ordering::Less) {
+            let (family, labels, quantile) = violation(quantile);
+            violations.push(Violation::UnorderedQuantiles { family, labels, quantile });
+        } else if quantile.value < previous.value {
+            let (family, labels, quantile) = violation(quantile);
+            violations.push(Violation::DecreasingQuantileValue { family, labels, quantile });
+        }
+    }
+    violations
+}
+
+fn duplicate_series(family: &MetricFamily) -> Vec<Violation> {
+    let mut seen = HashSet::new();
+    let mut reported = HashSet::new();
+    let mut violations = Vec::new();
+    for metric in family.metrics.iter() {
+        let labels = metric.labels();
+        let key = crate::label_key(labels);
+        if !seen.insert(key.clone()) && reported.insert(key) {
+            violations.push(Violation::DuplicateSeries { family: family.name.clone(), labels: labels.to_vec() });
+        }
+    }
+    violations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::labels;
+
+    /// A histogram family over `buckets` claiming `sample_count` observations.
+    fn histogram(buckets: &[(f64, u64)], sample_count: u64) -> MetricFamily {
+        let mut family = MetricFamily::builder("latency_seconds").histogram(vec![], buckets, 1_f64).build();
+        if let Metric::Histogram { sample_count: count, .. } = &mut family.metrics[0] {
+            *count = Some(sample_count);
+        }
+        family
+    }
+
+    #[test]
+    fn accepts_well_formed_families() {
+        let families = vec![
+            MetricFamily::builder("requests_total").counter(labels! {"method" => "GET"}, 1_f64).build(),
+            histogram(&[(0.5, 1), (1_f64, 3), (f64::INFINITY, 4)], 4),
+            MetricFamily::builder("rpc_seconds").summary(vec![], &[(0.5, 0.1), (0.99, 0.4)], 4, 1_f64).build(),
+        ];
+        assert_eq!(validate(&families), vec![]);
+    }
+
+    #[test]
+    fn reports_duplicate_families_and_series_once() {
+        let requests = MetricFamily::builder("requests_total")
+            .counter(labels! {"method" => "GET"}, 1_f64)
+            .counter(labels! {"method" => "GET"}, 2_f64)
+            .counter(labels! {"method" => "GET"}, 3_f64)
+            .build();
+        let violations = validate(&[requests.clone(), requests]);
+        let duplicate_series = Violation::DuplicateSeries {
+            family: "requests_total".to_string(),
+            labels: labels! {"method" => "GET"},
+        };
+        assert_eq!(
+            violations,
+            vec![
+                duplicate_series.clone(),
+                Violation::DuplicateFamily { family: "requests_total".to_string() },
+                duplicate_series,
+            ]
+        );
+        assert_eq!(violations[0].to_string(), "family requests_total has more than one series {method=\"GET\"}");
+    }
+
+    #[test]
+    fn reports_reserved_and_invalid_names() {
+        let family = MetricFamily::builder("http-requests")
+            .counter(labels! {"__name" => "a"}, 1_f64)
+            .counter(labels! {"__name" => "b", "0code" => "200"}, 1_f64)
+            .build();
+        let violations = validate(&[family]);
+        assert_eq!(
+            violations,
+            vec![
+                Violation::InvalidMetricName { family: "http-requests".to_string() },
+                Violation::ReservedLabelName {
+                    family: "http-requests".to_string(),
+                    label: "__name".to_string()
+                },
+                Violation::InvalidLabelName {
+                    family: "http-requests".to_string(),
+                    label: "0code".to_string()
+                },
+            ]
+        );
+        assert_eq!(violations[1].to_string(), "family http-requests uses the reserved label name __name");
+
+        let options = ValidateOptions { utf8_names: true };
+        let family = MetricFamily::builder("http-requests").counter(labels! {"__name" => "a"}, 1_f64).build();
+        assert_eq!(
+            validate_with(&[family], options),
+            vec![Violation::ReservedLabelName {
+                family: "http-requests".to_string(),
+                label: "__name".to_string()
+            }]
+        );
+    }
+
+    #[test]
+    fn reports_unordered_buckets() {
+        let violations = validate(&[histogram(&[(1_f64, 1), (0.5, 2), (f64::INFINITY, 2)], 2)]);
+        assert_eq!(
+            violations,
+            vec![Violation::UnorderedBuckets {
+                family: "latency_seconds".to_string(),
+                labels: vec![],
+                upper_bound: 0.5
+            }]
+        );
+        assert_eq!(violations[0].to_string(), "series latency_seconds{} has bucket le=\"0.5\" out of order");
+    }
+
+    #[test]
+    fn reports_decreasing_bucket_counts() {
+        let violations = validate(&[histogram(&[(0.5, 3), (1_f64, 2), (f64::INFINITY, 3)], 3)]);
+        assert_eq!(
+            violations,
+            vec![Violation::DecreasingBucketCount {
+                family: "latency_seconds".to_string(),
+                labels: vec![],
+                upper_bound: 1_f64,
+            }]
+        );
+    }
+
+    #[test]
+    fn reports_counts_below_the_last_bucket_without_an_inf_bucket() {
+        let violations = validate(&[histogram(&[(0.5, 1), (1_f64, 5)], 3)]);
+        assert_eq!(
+            violations,
+            vec![Violation::DecreasingBucketCount {
+                family: "latency_seconds".to_string(),
+                labels: vec![],
+                upper_bound: f64::INFINITY,
+            }]
+        );
+        assert_eq!(
+            violations[0].to_string(),
+            "series latency_seconds{} counts fewer observations in bucket le=\"+Inf\" than in the bucket before it"
+        );
+        assert_eq!(validate(&[histogram(&[(0.5, 1), (1_f64, 5)], 7)]), vec![]);
+    }
+
+    #[test]
+    fn reports_inf_buckets_disagreeing_with_the_count() {
+        let violations = validate(&[histogram(&[(0.5, 1), (f64::INFINITY, 4)], 5)]);
+        assert_eq!(
+            violations,
+            vec![Violation::InfBucketMismatch {
+                family: "latency_seconds".to_string(),
+                labels: vec![],
+                bucket_count: 4,
+                sample_count: 5,
+            }]
+        );
+        assert_eq!(
+            violations[0].to_string(),
+            "series latency_seconds{} counts 4 observations in its +Inf bucket but 5 in total"
+        );
+    }
+
+    #[test]
+    fn reports_quantiles_out_of_range_and_order() {
+        let families = vec![MetricFamily::builder("rpc_seconds")
+            .summary(vec![], &[(0.5, 0.2), (0.25, 0.3), (0.9, 0.1), (1.5, 0.4)], 4, 1_f64)
+            .build()];
+        let (family, labels) = ("rpc_seconds".to_string(), Vec::new());
+        let violations = validate(&families);
+        assert_eq!(
+            violations,
+            vec![
+                Violation::QuantileOutOfRange {
+                    family: family.clone(),
+                    labels: labels.clone(),
+                    quantile: 1.5
+                },
+                Violation::UnorderedQuantiles {
+                    family: family.clone(),
+                    labels: labels.clone(),
+                    quantile: 0.25
+                },
+                Violation::DecreasingQuantileValue { family, labels, quantile: 0.9 },
+            ]
+        );
+        assert_eq!(violations[0].to_string(), "series rpc_seconds{} has quantile 1.5 outside [0, 1]");
+    }
+
+    #[test]
+    fn reports_non_finite_values_apart_from_validate() {
+        let families = vec![
+            MetricFamily::builder("ratio").gauge(labels! {"pool" => "a"}, f64::INFINITY).build(),
+            MetricFamily::builder("latency_seconds").histogram(vec![], &[(f64::INFINITY, 1)], f64::NAN).build(),
+            MetricFamily::builder("rpc_seconds").summary(vec![], &[(0.5, f64::NAN)], 1, 1_f64).build(),
+        ];
+        assert_eq!(validate(&families), vec![]);
+
+        let violations = non_finite(&families);
+        let described: Vec<_> = violations.iter().map(Violation::to_string).collect();
+        assert_eq!(
+            described,
+            vec![
+                "sample ratio{pool=\"a\"} is +Inf",
+                "sample latency_seconds_sum{} is NaN",
+                "sample rpc_seconds{quantile=\"0.5\"} is NaN"
+            ]
+        );
+        let Violation::NonFiniteValue { family, sample, .. } = &violations[1] else { panic!("not a non-finite value") };
+        assert_eq!((family.as_str(), sample.as_str()), ("latency_seconds", "latency_seconds_sum"));
+    }
+}
@@ -0,0 +1,242 @@
+//! Change notifications for a registry's distilled state, so tests can wait for a metric to reach
+//! a value instead of sleeping and polling.
+//!
+//! A background thread distills the registry every interval and publishes the state only when it
+//! differs from the last one. [`Receiver::changed`] is a plain future, so it can be awaited from
+//! tokio or any other executor, and [`Receiver::changed_timeout`] blocks for synchronous tests.
+//!
+//! [`Receiver`] follows the API of `tokio::sync::watch::Receiver` rather than being one: tokio
+//! isn't a dependency of this crate, and a watch channel would still need a task or thread to
+//! poll the registry, which only exposes its state through `gather`.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::MetricState;
+
+struct Published {
+    state: MetricState,
+    version: u64,
+    wakers: Vec<Waker>,
+}
+
+struct Shared {
+    published: Mutex<Published>,
+    condvar: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Published> {
+        self.published.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Watches `registry`, publishing a new [`MetricState`] whenever its distilled families change.
+/// The watcher thread exits once every receiver is dropped.
+pub fn watch_registry(registry: &prometheus::Registry, interval: Duration) -> Receiver {
+    let registry = registry.clone();
+    watch(move || MetricState::from(crate::distill_registry(&registry)), interval)
+}
+
+/// Watches an arbitrary source of state, polled every `interval`.
+pub fn watch(mut source: impl FnMut() -> MetricState + Send + 'static, interval: Duration) -> Receiver {
+    let shared = Arc::new(Shared {
+        published: Mutex::new(Published { state: source(), version: 0, wakers: Vec::new() }),
+        condvar: Condvar::new(),
+    });
+
+    let watcher = Arc::downgrade(&shared);
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        let Some(shared) = watcher.upgrade() else { break };
+        let state = source();
+        let mut published = shared.lock();
+        if published.state != state {
+            published.state = state;
+            published.version += 1;
+            published.wakers.drain(..).for_each(Waker::wake);
+            shared.condvar.notify_all();
+        }
+    });
+
+    Receiver { shared, seen: 0 }
+}
+
+/// Receives published states; each receiver tracks which version it has seen.
+#[derive(Clone)]
+pub struct Receiver {
+    shared: Arc<Shared>,
+    seen: u64,
+}
+
+impl Receiver {
+    /// The latest published state, which this receiver now counts as seen.
+    pub fn borrow_and_update(&mut self) -> MetricState {
+        let published = self.shared.lock();
+        self.seen = published.version;
+        published.state.clone()
+    }
+
+    /// The latest published state, without marking it seen.
+    pub fn current(&self) -> MetricState {
+        self.shared.lock().state.clone()
+    }
+
+    pub fn has_changed(&self) -> bool {
+        self.seen < self.shared.lock().version
+    }
+
+    /// Resolves once a state newer than the last seen one is published, and marks it seen.
+    pub fn changed(&mut self) -> Changed<'_> {
+        Changed { receiver: self }
+    }
+
+    /// Blocks until a newer state is published or `timeout` passes, returning whether one was.
+    pub fn changed_timeout(&mut self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut published = self.shared.lock();
+        while published.version <= self.seen {
+            let Some(remaining) = deadline.checked_duration_since(Instant::now()) else { return false };
+            published = self
+                .shared
+                .condvar
+                .wait_timeout(published, remaining)
+                .map(|(guard, _)| guard)
+                .unwrap_or_else(|poisoned| poisoned.into_inner().0);
+        }
+        self.seen = published.version;
+        true
+    }
+
+    /// Resolves with the first published state, the current one included, satisfying `predicate`.
+    pub async fn wait_for(&mut self, mut predicate: impl FnMut(&MetricState) -> bool) -> MetricState {
+        loop {
+            let state = self.borrow_and_update();
+            if predicate(&state) {
+                return state;
+            }
+            self.changed().await;
+        }
+    }
+}
+
+impl std::fmt::Debug for Receiver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Receiver").field("seen", &self.seen).finish()
+    }
+}
+
+/// Future returned by [`Receiver::changed`].
+#[derive(Debug)]
+pub struct Changed<'a> {
+    receiver: &'a mut Receiver,
+}
+
+impl Future for Changed<'_> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let shared = Arc::clone(&self.receiver.shared);
+        let mut published = shared.lock();
+        if self.receiver.seen < published.version {
+            self.receiver.seen = published.version;
+            Poll::Ready(())
+        } else {
+            if !published.wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+                published.wakers.push(cx.waker().clone());
+            }
+            Poll::Pending
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::task::Wake;
+
+    use crate::MetricFamily;
+
+    const INTERVAL: Duration = Duration::from_millis(2);
+
+    /// Watches a `queue_depth` gauge reading the returned depth.
+    fn watch_depth() -> (Arc<AtomicU64>, Receiver) {
+        let depth = Arc::new(AtomicU64::new(0));
+        let source = Arc::clone(&depth);
+        let receiver = watch(
+            move || {
+                let depth = source.load(Ordering::SeqCst) as f64;
+                MetricState::from(vec![MetricFamily::builder("queue_depth").gauge(vec![], depth).build()])
+            },
+            INTERVAL,
+        );
+        (depth, receiver)
+    }
+
+    fn depth_of(state: &MetricState) -> Option<f64> {
+        state.family("queue_depth").and_then(|family| family.metrics[0].gauge_value())
+    }
+
+    struct Unpark(thread::Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = std::pin::pin!(future);
+        let waker = Waker::from(Arc::new(Unpark(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+            thread::park();
+        }
+    }
+
+    #[test]
+    fn does_not_publish_unchanged_states() {
+        let (_depth, mut receiver) = watch_depth();
+        assert!(!receiver.changed_timeout(INTERVAL * 20));
+        assert!(!receiver.has_changed());
+        assert_eq!(depth_of(&receiver.current()), Some(0_f64));
+    }
+
+    #[test]
+    fn publishes_changed_states_once() {
+        let (depth, mut receiver) = watch_depth();
+        depth.store(3, Ordering::SeqCst);
+        assert!(receiver.changed_timeout(Duration::from_secs(5)));
+        assert_eq!(depth_of(&receiver.borrow_and_update()), Some(3_f64));
+        assert!(!receiver.has_changed());
+        assert!(!receiver.changed_timeout(INTERVAL * 20));
+    }
+
+    #[test]
+    fn resolves_futures_on_changes() {
+        let (depth, mut receiver) = watch_depth();
+        let mut early = receiver.clone();
+        assert_eq!(depth_of(&block_on(receiver.wait_for(|state| depth_of(state) == Some(0_f64)))), Some(0_f64));
+
+        let writer = thread::spawn(move || {
+            for next in 1..=5 {
+                thread::sleep(INTERVAL * 3);
+                depth.store(next, Ordering::SeqCst);
+            }
+        });
+        let state = block_on(receiver.wait_for(|state| depth_of(state) == Some(5_f64)));
+        assert_eq!(depth_of(&state), Some(5_f64));
+        writer.join().unwrap();
+        // a receiver that never looked resolves at once, on the latest state
+        block_on(early.changed());
+        assert_eq!(depth_of(&early.current()), Some(5_f64));
+    }
+}
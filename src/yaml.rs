@@ -0,0 +1,419 @@
+//! YAML export and import with the [`crate::json`] schema, for hand-edited fixtures.
+//!
+//! `to_yaml` writes block style with every string double-quoted. `from_yaml` reads the block
+//! subset of YAML fixtures are written in: nested mappings and `- ` sequences, quoted or plain
+//! scalars, flow collections in JSON syntax (`[]`, `{"code": "200"}`), `#` comments and a
+//! leading `---`.
+
+use crate::json::{self, JsonError, Value};
+use crate::MetricFamily;
+
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum YamlError {
+    #[error("invalid YAML at line {line}: {message}")]
+    Syntax { line: usize, message: String },
+
+    #[error("YAML does not match the metric family schema: {0}")]
+    Schema(String),
+}
+
+pub fn to_yaml(families: &[MetricFamily]) -> String {
+    let document = Value::Array(families.iter().map(json::encode_family).collect());
+    let mut out = String::new();
+    match &document {
+        Value::Array(items) if items.is_empty() => out.push_str("[]\n"),
+        document => write_block(document, 0, &mut out),
+    }
+    out
+}
+
+pub fn from_yaml(yaml: &str) -> Result<Vec<MetricFamily>, YamlError> {
+    let lines = yaml
+        .lines()
+        .enumerate()
+        .filter_map(|(idx, text)| {
+            let text = strip_comment(text).trim_end();
+            let content = text.trim_start();
+            (!content.is_empty() && content != "---").then(|| Line {
+                number: idx + 1,
+                indent: text.len() - content.len(),
+                text: content.to_string(),
+            })
+        })
+        .collect();
+
+    let mut parser = Parser { lines, idx: 0 };
+    let document = parser.block(0)?;
+    if let Some(line) = parser.lines.get(parser.idx) {
+        return Err(YamlError::Syntax {
+            line: line.number,
+            message: "unexpected indentation".to_string(),
+        });
+    }
+
+    match document {
+        Value::Array(families) => families
+            .iter()
+            .map(|family| {
+                json::decode_family(family).map_err(|err| match err {
+                    JsonError::Schema(message) => YamlError::Schema(message),
+                    err => YamlError::Schema(err.to_string()),
+                })
+            })
+            .collect(),
+        _ => Err(YamlError::Schema("document must be a sequence of families".to_string())),
+    }
+}
+
+fn write_block(value: &Value, indent: usize, out: &mut String) {
+    let pad = " ".repeat(indent);
+    match value {
+        Value::Array(items) => {
+            for item in items {
+                out.push_str(&pad);
+                out.push('-');
+                match item {
+                    Value::Object(fields) if !fields.is_empty() => {
+                        // the first field shares the `- ` line, the rest align with it
+                        let mut nested = String::new();
+                        write_block(item, indent + 2, &mut nested);
+                        out.push(' ');
+                        out.push_str(&nested[indent + 2..]);
+                    },
+                    Value::Array(items) if !items.is_empty() => {
+                        out.push('\n');
+                        write_block(item, indent + 2, out);
+                    },
+                    scalar => {
+                        out.push(' ');
+                        write_scalar(scalar, out);
+                        out.push('\n');
+                    },
+                }
+            }
+        },
+        Value::Object(fields) => {
+            for (name, field) in fields {
+                out.push_str(&pad);
+                write_key(name, out);
+                out.push(':');
+                match field {
+                    Value::Object(nested) if !nested.is_empty() => {
+                        out.push('\n');
+                        write_block(field, indent + 2, out);
+                    },
+                    Value::Array(nested) if !nested.is_empty() => {
+                        out.push('\n');
+                        write_block(field, indent + 2, out);
+                    },
+                    scalar => {
+                        out.push(' ');
+                        write_scalar(scalar, out);
+                        out.push('\n');
+                    },
+                }
+            }
+        },
+        scalar => {
+            out.push_str(&pad);
+            write_scalar(scalar, out);
+            out.push('\n');
+        },
+    }
+}
+
+fn write_key(name: &str, out: &mut String) {
+    let plain = name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !matches!(name, "null" | "true" | "false" | "y" | "n" | "yes" | "no" | "on" | "off");
+    if plain {
+        out.push_str(name);
+    } else {
+        json::write_string(name, out);
+    }
+}
+
+fn write_scalar(value: &Value, out: &mut String) {
+    match value {
+        Value::Null => out.push_str("null"),
+        Value::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+        Value::Number(n) => out.push_str(n),
+        Value::String(s) => json::write_string(s, out),
+        Value::Array(_) => out.push_str("[]"),
+        Value::Object(_) => out.push_str("{}"),
+    }
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut scan = QuoteScan::default();
+    let mut previous = ' ';
+    for (idx, c) in line.char_indices() {
+        if !scan.step(c, previous) && c == '#' && previous.is_whitespace() {
+            return &line[..idx];
+        }
+        previous = c;
+    }
+    line
+}
+
+/// Tracks whether a scan is inside a quoted scalar; quotes only open at the start of a token.
+#[derive(Default)]
+struct QuoteScan {
+    quote: Option<char>,
+    escaped: bool,
+}
+
+impl QuoteScan {
+    /// Advances over `c`, returning whether it is part of a quoted scalar.
+    fn step(&mut self, c: char, previous: char) -> bool {
+        match self.quote {
+            Some('"') => {
+                if self.escaped {
+                    self.escaped = false;
+                } else if c == '\\' {
+                    self.escaped = true;
+                } else if c == '"' {
+                    self.quote = None;
+                }
+                true
+            },
+            Some(_) => {
+                if c == '\'' {
+                    self.quote = None;
+                }
+                true
+            },
+            None if (c == '"' || c == '\'') && (previous.is_whitespace() || "[{,:-".contains(previous)) => {
+                self.quote = Some(c);
+                true
+            },
+            None => false,
+        }
+    }
+}
+
+struct Line {
+    number: usize,
+    indent: usize,
+    text: String,
+}
+
+struct Parser {
+    lines: Vec<Line>,
+    idx: usize,
+}
+
+impl Parser {
+    fn error(&self, message: &str) -> YamlError {
+        let line = self.lines.get(self.idx).or(self.lines.last()).map(|line| line.number).unwrap_or(1);
+        YamlError::Syntax { line, message: message.to_string() }
+    }
+
+    fn block(&mut self, min_indent: usize) -> Result<Value, YamlError> {
+        let Some(line) = self.lines.get(self.idx) else { return Ok(Value::Null) };
+        if line.indent < min_indent {
+            return Ok(Value::Null);
+        }
+
+        let indent = line.indent;
+        if is_sequence_item(&line.text) {
+            self.sequence(indent)
+        } else if split_key(&line.text).is_some() {
+            self.mapping(indent)
+        } else {
+            let text = line.text.clone();
+            let value = self.scalar(&text)?;
+            self.idx += 1;
+            Ok(value)
+        }
+    }
+
+    fn sequence(&mut self, indent: usize) -> Result<Value, YamlError> {
+        let mut items = Vec::new();
+        while let Some(line) = self.lines.get(self.idx).filter(|l| l.indent == indent && is_sequence_item(&l.text)) {
+            let rest = line.text[1..].trim_start();
+            if rest.is_empty() {
+                self.idx += 1;
+                items.push(self.block(indent + 1)?);
+            } else {
+                // reparse the item's content as if it started on its own, further indented line
+                let item_indent = indent + line.text.len() - rest.len();
+                let rest = rest.to_string();
+                self.lines[self.idx].indent = item_indent;
+                self.lines[self.idx].text = rest;
+                items.push(self.block(item_indent)?);
+            }
+        }
+        Ok(Value::Array(items))
+    }
+
+    fn mapping(&mut self, indent: usize) -> Result<Value, YamlError> {
+        let mut fields = Vec::new();
+        while let Some(line) = self.lines.get(self.idx).filter(|l| l.indent == indent && !is_sequence_item(&l.text)) {
+            let Some((key, rest)) = split_key(&line.text) else {
+                return Err(self.error("expected a `key: value` entry"));
+            };
+            let (key, rest) = (key.to_string(), rest.to_string());
+            let key = match self.scalar(&key)? {
+                Value::String(key) | Value::Number(key) => key,
+                Value::Bool(b) => b.to_string(),
+                _ => return Err(self.error("mapping keys must be scalars")),
+            };
+
+            let value = if rest.is_empty() {
+                self.idx += 1;
+                match self.lines.get(self.idx) {
+                    Some(next) if next.indent > indent => self.block(next.indent)?,
+                    Some(next) if next.indent == indent && is_sequence_item(&next.text) => self.sequence(indent)?,
+                    _ => Value::Null,
+                }
+            } else {
+                let value = self.scalar(&rest)?;
+                self.idx += 1;
+                value
+            };
+            fields.push((key, value));
+        }
+        Ok(Value::Object(fields))
+    }
+
+    fn scalar(&self, text: &str) -> Result<Value, YamlError> {
+        let value = match text {
+            "null" | "~" => Value::Null,
+            "true" => Value::Bool(true),
+            "false" => Value::Bool(false),
+            ".inf" | "+.inf" | ".Inf" | "+.Inf" => Value::String("+Inf".to_string()),
+            "-.inf" | "-.Inf" => Value::String("-Inf".to_string()),
+            ".nan" | ".NaN" => Value::String("NaN".to_string()),
+            text if text.starts_with(['"', '[', '{']) => json::parse_document(text).map_err(|err| match err {
+                JsonError::Syntax { message, .. } => self.error(&message),
+                err => self.error(&err.to_string()),
+            })?,
+            text if text.starts_with('\'') => {
+                let inner = text
+                    .strip_prefix('\'')
+                    .and_then(|t| t.strip_suffix('\''))
+                    .ok_or_else(|| self.error("unterminated single-quoted string"))?;
+                Value::String(inner.replace("''", "'"))
+            },
+            text if is_number(text) => Value::Number(text.to_string()),
+            text => Value::String(text.to_string()),
+        };
+        Ok(value)
+    }
+}
+
+fn is_sequence_item(text: &str) -> bool {
+    text == "-" || text.starts_with("- ")
+}
+
+fn is_number(text: &str) -> bool {
+    let digits = text.strip_prefix('-').unwrap_or(text);
+    digits.starts_with(|c: char| c.is_ascii_digit()) && text.parse::<f64>().is_ok()
+}
+
+/// Splits `key: value` at the first `:` outside quotes that is followed by a space or ends the line.
+fn split_key(text: &str) -> Option<(&str, &str)> {
+    if text.starts_with(['[', '{']) {
+        return None;
+    }
+    let mut scan = QuoteScan::default();
+    let mut previous = ' ';
+    for (idx, c) in text.char_indices() {
+        let rest = &text[idx + c.len_utf8()..];
+        if !scan.step(c, previous) && c == ':' && (rest.is_empty() || rest.starts_with(' ')) {
+            return Some((text[..idx].trim_end(), rest.trim_start()));
+        }
+        previous = c;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{labels, MetricKind};
+
+    fn families() -> Vec<MetricFamily> {
+        vec![
+            MetricFamily::builder("requests_total")
+                .help("Requests \"served\": all of them\n")
+                .counter(labels! {"code" => "200", "path" => "/a: b # c"}, 3_f64)
+                .build(),
+            MetricFamily::builder("temperature").unit("celsius").gauge(vec![], -0.5).build(),
+            MetricFamily::builder("latency_seconds")
+                .histogram(vec![], &[(0.1, 2), (1_f64, 5), (f64::INFINITY, 6)], 2.5)
+                .build(),
+            MetricFamily::builder("rpc_seconds").summary(vec![], &[(0.5, 0.2), (0.99, 0.9)], 10, 4_f64).build(),
+            MetricFamily::builder("mode").state_set(vec![], &[("on", true), ("off", false)]).build(),
+        ]
+    }
+
+    #[test]
+    fn round_trips_families() {
+        let yaml = to_yaml(&families());
+        assert_eq!(from_yaml(&yaml).unwrap(), families());
+        assert_eq!(to_yaml(&from_yaml(&yaml).unwrap()), yaml);
+    }
+
+    #[test]
+    fn round_trips_no_families() {
+        assert_eq!(to_yaml(&[]), "[]\n");
+        assert_eq!(from_yaml("[]\n").unwrap(), vec![]);
+    }
+
+    #[test]
+    fn writes_block_style_with_quoted_strings() {
+        let yaml = to_yaml(&families()[..1]);
+        assert!(
+            yaml.starts_with("- name: \"requests_total\"\n  help: \"Requests \\\"served\\\": all of them\\n\"\n"),
+            "{yaml}"
+        );
+        assert!(yaml.contains("      labels:\n        code: \"200\"\n        path: \"/a: b # c\"\n"), "{yaml}");
+    }
+
+    #[test]
+    fn reads_hand_written_fixtures() {
+        let yaml = r#"---
+# recorded by hand
+- name: requests_total
+  help: 'Requests.'
+  unit: null
+  kind: counter
+  metrics:
+    - type: counter   # a single series
+      labels: {"code": "200"}
+      value: 3
+      exemplar: null
+      created: null
+      timestamp_ms: null
+"#;
+        let families = from_yaml(yaml).unwrap();
+        let expected = vec![MetricFamily::builder("requests_total")
+            .help("Requests.")
+            .counter(labels! {"code" => "200"}, 3_f64)
+            .build()];
+        assert_eq!(families, expected);
+        assert_eq!(families[0].kind, MetricKind::Counter);
+    }
+
+    #[test]
+    fn reports_syntax_errors_by_line() {
+        let err = from_yaml("- name: up\n  kind gauge\n").unwrap_err();
+        assert_eq!(
+            err,
+            YamlError::Syntax {
+                line: 2,
+                message: "expected a `key: value` entry".to_string()
+            }
+        );
+        assert!(matches!(from_yaml("- name: up\n    kind: gauge\n"), Err(YamlError::Syntax { line: 2, .. })));
+        assert!(matches!(from_yaml("- labels: {\"a\": \n"), Err(YamlError::Syntax { line: 1, .. })));
+    }
+
+    #[test]
+    fn reports_schema_errors() {
+        assert!(matches!(from_yaml("name: up\n"), Err(YamlError::Schema(_))));
+        assert!(matches!(from_yaml("- name: up\n  kind: sideways\n  metrics: []\n"), Err(YamlError::Schema(_))));
+    }
+}